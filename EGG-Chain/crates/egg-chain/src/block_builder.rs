@@ -1,12 +1,16 @@
 #![forbid(unsafe_code)]
 
-use egg_crypto::{merkle::merkle_root_txids, tx_id_from_payload, validate_tx_id};
-use egg_types::{Block, BlockHeader, Hash256, Height, Transaction};
+use std::collections::HashSet;
+
+use egg_crypto::{hash_tx, merkle::merkle_root_txids, validate_tx_id};
+use egg_types::{canonical, Block, BlockHeader, Hash256, Height, Transaction};
 use thiserror::Error;
 
 use crate::mempool::Mempool;
+use crate::pow_valid;
 
 const MAX_TXS_PER_BLOCK: usize = 10_000;
+const MAX_BLOCK_BYTES: usize = 4 * 1024 * 1024; // 4 MiB (canonical encoded)
 
 #[derive(Debug, Error)]
 pub enum BlockBuildError {
@@ -19,14 +23,42 @@ pub enum BlockBuildError {
 
     #[error("merkle mismatch: expected {expected:?}, got {got:?}")]
     MerkleMismatch { expected: Hash256, got: Hash256 },
+
+    #[error("invalid pow for block header")]
+    InvalidPow,
+
+    #[error("duplicate tx at index {index}: {id:?}")]
+    DuplicateTx { index: usize, id: Hash256 },
+
+    #[error("too many txs in block: {count} > {max}")]
+    TooManyTxs { count: usize, max: usize },
+
+    #[error("block too large: {size} bytes > {max}")]
+    BlockTooLarge { size: usize, max: usize },
 }
 
 pub type Result<T> = std::result::Result<T, BlockBuildError>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockLimits {
+    pub max_txs: usize,
+    pub max_block_bytes: usize,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        Self {
+            max_txs: MAX_TXS_PER_BLOCK,
+            max_block_bytes: MAX_BLOCK_BYTES,
+        }
+    }
+}
+
+/// Merkle root theo `egg_crypto::merkle::MERKLE_VERSION` hiện tại (lá/node trong tách domain).
 pub fn compute_merkle_root_from_txs(txs: &[Transaction]) -> Result<Hash256> {
     for (i, tx) in txs.iter().enumerate() {
         if !validate_tx_id(tx) {
-            let expected = tx_id_from_payload(&tx.payload);
+            let expected = hash_tx(tx);
             return Err(BlockBuildError::InvalidTxId {
                 index: i,
                 expected,
@@ -49,7 +81,48 @@ pub fn verify_block_merkle(block: &Block) -> Result<()> {
     Ok(())
 }
 
-/// Build block template từ mempool (FIFO), set merkle_root đúng chuẩn.
+/// Kiểm tra block không cần context (không cần parent trong store):
+/// PoW, số tx, kích thước, txid hợp lệ, không trùng tx, merkle khớp.
+/// Là điều kiện cần trước khi `ChainState::ingest_block` kiểm tra theo context.
+pub fn validate_block_standalone(block: &Block, limits: &BlockLimits) -> Result<()> {
+    if !pow_valid(&block.header) {
+        return Err(BlockBuildError::InvalidPow);
+    }
+
+    validate_block_limits(block, limits)?;
+
+    // txid + merkle
+    verify_block_merkle(block)
+}
+
+/// Phần rẻ của `validate_block_standalone`: số tx, kích thước, không trùng tx.
+/// Không kiểm PoW / txid / merkle (dùng cho block dưới assume-valid).
+pub fn validate_block_limits(block: &Block, limits: &BlockLimits) -> Result<()> {
+    if block.txs.len() > limits.max_txs {
+        return Err(BlockBuildError::TooManyTxs {
+            count: block.txs.len(),
+            max: limits.max_txs,
+        });
+    }
+
+    let size = canonical::encode_block(block).len();
+    if size > limits.max_block_bytes {
+        return Err(BlockBuildError::BlockTooLarge {
+            size,
+            max: limits.max_block_bytes,
+        });
+    }
+
+    let mut seen = HashSet::with_capacity(block.txs.len());
+    for (i, tx) in block.txs.iter().enumerate() {
+        if !seen.insert(tx.id) {
+            return Err(BlockBuildError::DuplicateTx { index: i, id: tx.id });
+        }
+    }
+    Ok(())
+}
+
+/// Build block template từ mempool (FIFO, cha trước con nếu có `deps_extractor`), set merkle_root đúng chuẩn.
 /// Nonce mặc định = 0 (mining xử lý ở bước sau).
 pub fn build_block_template_from_mempool(
     mempool: &mut Mempool,
@@ -58,7 +131,26 @@ pub fn build_block_template_from_mempool(
     timestamp_utc: i64,
     pow_difficulty_bits: u32,
 ) -> Result<Block> {
-    let txs = mempool.drain_fifo(MAX_TXS_PER_BLOCK);
+    build_block_template_with_limits(
+        mempool,
+        parent,
+        height,
+        timestamp_utc,
+        pow_difficulty_bits,
+        &BlockLimits::default(),
+    )
+}
+
+/// Như `build_block_template_from_mempool`, lấy tối đa `limits.max_txs` tx.
+pub fn build_block_template_with_limits(
+    mempool: &mut Mempool,
+    parent: Hash256,
+    height: Height,
+    timestamp_utc: i64,
+    pow_difficulty_bits: u32,
+    limits: &BlockLimits,
+) -> Result<Block> {
+    let txs = mempool.drain_topological(limits.max_txs);
     let merkle_root = compute_merkle_root_from_txs(&txs)?;
 
     let header = BlockHeader {
@@ -77,11 +169,15 @@ pub fn build_block_template_from_mempool(
 mod tests {
     use super::*;
     use egg_crypto::tx_id_from_payload;
-    use egg_types::Hash256;
+    use egg_types::{Hash256, CONTENT_TAG_OPAQUE};
 
     fn mk_tx(payload: &[u8]) -> Transaction {
         let id = tx_id_from_payload(payload);
-        Transaction { id, payload: payload.to_vec() }
+        Transaction {
+            id,
+            payload: payload.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        }
     }
 
     #[test]
@@ -136,4 +232,98 @@ mod tests {
         // mempool drained
         assert_eq!(mp.len(), 0);
     }
+
+    fn mk_block(txs: Vec<Transaction>) -> Block {
+        let merkle_root = compute_merkle_root_from_txs(&txs).unwrap();
+        Block {
+            header: BlockHeader {
+                parent: Hash256::zero(),
+                height: Height(1),
+                timestamp_utc: 1_700_000_000,
+                nonce: 0,
+                merkle_root,
+                pow_difficulty_bits: 0,
+            },
+            txs,
+        }
+    }
+
+    #[test]
+    fn validate_block_standalone_accepts_good_block() {
+        let blk = mk_block(vec![mk_tx(b"a"), mk_tx(b"b")]);
+        validate_block_standalone(&blk, &BlockLimits::default()).unwrap();
+    }
+
+    #[test]
+    fn validate_block_standalone_failure_modes() {
+        let limits = BlockLimits::default();
+
+        let mut blk = mk_block(vec![mk_tx(b"a")]);
+        blk.header.pow_difficulty_bits = 255;
+        let err = validate_block_standalone(&blk, &limits).unwrap_err();
+        assert!(matches!(err, BlockBuildError::InvalidPow));
+
+        let mut blk = mk_block(vec![mk_tx(b"a")]);
+        blk.header.merkle_root = Hash256([9u8; 32]);
+        let err = validate_block_standalone(&blk, &limits).unwrap_err();
+        assert!(matches!(err, BlockBuildError::MerkleMismatch { .. }));
+
+        let mut blk = mk_block(vec![mk_tx(b"a")]);
+        blk.txs[0].id = Hash256([1u8; 32]);
+        let err = validate_block_standalone(&blk, &limits).unwrap_err();
+        assert!(matches!(err, BlockBuildError::InvalidTxId { .. }));
+
+        let blk = mk_block(vec![mk_tx(b"a"), mk_tx(b"b"), mk_tx(b"a")]);
+        let err = validate_block_standalone(&blk, &limits).unwrap_err();
+        assert!(matches!(err, BlockBuildError::DuplicateTx { index: 2, .. }));
+
+        let blk = mk_block(vec![mk_tx(b"a"), mk_tx(b"b"), mk_tx(b"c")]);
+        let small = BlockLimits { max_txs: 2, ..limits };
+        let err = validate_block_standalone(&blk, &small).unwrap_err();
+        assert!(matches!(err, BlockBuildError::TooManyTxs { count: 3, max: 2 }));
+
+        let blk = mk_block(vec![mk_tx(&[0u8; 1024])]);
+        let tiny = BlockLimits { max_block_bytes: 512, ..limits };
+        let err = validate_block_standalone(&blk, &tiny).unwrap_err();
+        assert!(matches!(err, BlockBuildError::BlockTooLarge { max: 512, .. }));
+    }
+
+    #[test]
+    fn merkle_root_not_forgeable_from_internal_nodes() {
+        let txs: Vec<Transaction> = [b"a", b"b", b"c", b"d"].iter().map(|p| mk_tx(*p)).collect();
+        let ids: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        let root = compute_merkle_root_from_txs(&txs).unwrap();
+
+        // block giả chỉ có 2 "tx" mang id = 2 node trong: trước đây cho cùng root
+        let left = merkle_root_txids(&ids[..2]);
+        let right = merkle_root_txids(&ids[2..]);
+        assert_ne!(merkle_root_txids(&[left, right]), root);
+    }
+
+    #[test]
+    fn tx_with_64_byte_payload_cannot_stand_in_for_internal_node() {
+        let (a, b) = (mk_tx(b"a"), mk_tx(b"b"));
+        let header = BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: 0,
+            nonce: 0,
+            merkle_root: compute_merkle_root_from_txs(&[a.clone(), b.clone()]).unwrap(),
+            pow_difficulty_bits: 0,
+        };
+
+        // tx thật có payload 64 byte = 2 con của root: block chỉ chứa nó không được cùng root
+        let mut payload = a.id.0.to_vec();
+        payload.extend_from_slice(&b.id.0);
+        let forged = mk_tx(&payload);
+        assert_eq!(forged.payload.len(), 64);
+        let fake = Block {
+            header,
+            txs: vec![forged],
+        };
+        assert!(matches!(
+            verify_block_merkle(&fake),
+            Err(BlockBuildError::MerkleMismatch { .. })
+        ));
+    }
 }
@@ -152,6 +152,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn genesis_pow_valid_when_difficulty_zero() {
         let spec = mk_spec();
         assert_eq!(genesis_pow_valid(&spec).unwrap(), true);
@@ -0,0 +1,88 @@
+#![forbid(unsafe_code)]
+
+use egg_crypto::tx_id_from_payload;
+use egg_types::{Height, Transaction, CONTENT_TAG_OPAQUE};
+
+/// Payload coinbase: magic || height(u64 BE) || reward(u64 BE).
+/// Height trong payload làm coinbase mỗi block có txid khác nhau.
+pub const COINBASE_MAGIC: [u8; 8] = *b"EGG_CB0\0";
+const COINBASE_PAYLOAD_LEN: usize = 8 + 8 + 8;
+
+/// Phần thưởng block: `initial_reward`, giảm một nửa mỗi `halving_interval` block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewardPolicy {
+    pub initial_reward: u64,
+    pub halving_interval: u64,
+}
+
+impl RewardPolicy {
+    pub fn reward_at(&self, height: Height) -> u64 {
+        if self.halving_interval == 0 {
+            return self.initial_reward;
+        }
+        let halvings = height.0 / self.halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        self.initial_reward >> halvings
+    }
+}
+
+pub fn coinbase_payload(height: Height, reward: u64) -> Vec<u8> {
+    let mut out = Vec::with_capacity(COINBASE_PAYLOAD_LEN);
+    out.extend_from_slice(&COINBASE_MAGIC);
+    out.extend_from_slice(&height.0.to_be_bytes());
+    out.extend_from_slice(&reward.to_be_bytes());
+    out
+}
+
+pub fn coinbase_tx(height: Height, reward: u64) -> Transaction {
+    let payload = coinbase_payload(height, reward);
+    Transaction {
+        id: tx_id_from_payload(&payload),
+        payload,
+        content_tag: CONTENT_TAG_OPAQUE,
+    }
+}
+
+/// `(height, reward)` nếu tx là coinbase đúng format; `None` nếu không phải.
+pub fn parse_coinbase(tx: &Transaction) -> Option<(Height, u64)> {
+    if tx.content_tag != CONTENT_TAG_OPAQUE
+        || tx.payload.len() != COINBASE_PAYLOAD_LEN
+        || tx.payload[..8] != COINBASE_MAGIC
+    {
+        return None;
+    }
+    let height = u64::from_be_bytes(tx.payload[8..16].try_into().ok()?);
+    let reward = u64::from_be_bytes(tx.payload[16..24].try_into().ok()?);
+    Some((Height(height), reward))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coinbase_roundtrip_and_unique_per_height() {
+        let a = coinbase_tx(Height(7), 50);
+        assert_eq!(parse_coinbase(&a), Some((Height(7), 50)));
+        assert_ne!(a.id, coinbase_tx(Height(8), 50).id);
+
+        let mut not_cb = a.clone();
+        not_cb.payload[0] ^= 1;
+        assert_eq!(parse_coinbase(&not_cb), None);
+    }
+
+    #[test]
+    fn reward_halves_each_interval() {
+        let p = RewardPolicy {
+            initial_reward: 100,
+            halving_interval: 10,
+        };
+        assert_eq!(p.reward_at(Height(0)), 100);
+        assert_eq!(p.reward_at(Height(9)), 100);
+        assert_eq!(p.reward_at(Height(10)), 50);
+        assert_eq!(p.reward_at(Height(25)), 25);
+        assert_eq!(p.reward_at(Height(10 * 64)), 0);
+    }
+}
@@ -0,0 +1,196 @@
+#![forbid(unsafe_code)]
+
+use egg_types::{Block, BlockHeader, Hash256, Transaction};
+use thiserror::Error;
+
+use crate::block_builder::{verify_block_merkle, BlockBuildError};
+use crate::header_id;
+use crate::mempool::Mempool;
+
+#[derive(Debug, Error)]
+pub enum CompactError {
+    #[error("tx index {index} out of range (block has {count} txs)")]
+    IndexOutOfRange { index: u32, count: usize },
+
+    #[error("tx at index {index} has id {got:?}, compact block announced {expected:?}")]
+    TxIdMismatch {
+        index: u32,
+        expected: Hash256,
+        got: Hash256,
+    },
+
+    #[error("got {got} txs for {requested} requested indexes")]
+    TxCountMismatch { requested: usize, got: usize },
+
+    #[error("block still missing {missing} txs")]
+    Incomplete { missing: usize },
+
+    #[error("reconstructed block invalid: {0}")]
+    Invalid(#[from] BlockBuildError),
+}
+
+pub type Result<T> = std::result::Result<T, CompactError>;
+
+/// Header + danh sách txid của block (phần gửi đi trong CompactBlock).
+pub fn compact_of(block: &Block) -> (BlockHeader, Vec<Hash256>) {
+    (block.header.clone(), block.txs.iter().map(|t| t.id).collect())
+}
+
+/// Lấy tx theo index trong block (trả lời GetBlockTxn).
+pub fn txs_at(block: &Block, indexes: &[u32]) -> Result<Vec<Transaction>> {
+    let mut out = Vec::with_capacity(indexes.len());
+    for &i in indexes {
+        let tx = block.txs.get(i as usize).ok_or(CompactError::IndexOutOfRange {
+            index: i,
+            count: block.txs.len(),
+        })?;
+        out.push(tx.clone());
+    }
+    Ok(out)
+}
+
+/// Block đang dựng lại từ CompactBlock: tx lấy từ mempool, phần thiếu xin peer theo index.
+#[derive(Clone, Debug)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txids: Vec<Hash256>,
+    txs: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    pub fn from_mempool(header: BlockHeader, txids: Vec<Hash256>, mempool: &Mempool) -> Self {
+        let txs = txids.iter().map(|id| mempool.get(*id).cloned()).collect();
+        Self { header, txids, txs }
+    }
+
+    pub fn id(&self) -> Hash256 {
+        header_id(&self.header)
+    }
+
+    pub fn missing_indexes(&self) -> Vec<u32> {
+        self.txs
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.is_none())
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.txs.iter().all(Option::is_some)
+    }
+
+    /// Điền tx nhận từ BlockTxn; `txs[k]` ứng với `indexes[k]`, txid phải khớp CompactBlock.
+    pub fn fill(&mut self, indexes: &[u32], txs: Vec<Transaction>) -> Result<()> {
+        if indexes.len() != txs.len() {
+            return Err(CompactError::TxCountMismatch {
+                requested: indexes.len(),
+                got: txs.len(),
+            });
+        }
+        for (&i, tx) in indexes.iter().zip(txs) {
+            let Some(&expected) = self.txids.get(i as usize) else {
+                return Err(CompactError::IndexOutOfRange {
+                    index: i,
+                    count: self.txids.len(),
+                });
+            };
+            if tx.id != expected {
+                return Err(CompactError::TxIdMismatch {
+                    index: i,
+                    expected,
+                    got: tx.id,
+                });
+            }
+            self.txs[i as usize] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Ghép block hoàn chỉnh và kiểm merkle root (txid thật) khớp header.
+    pub fn into_block(self) -> Result<Block> {
+        let missing = self.txs.iter().filter(|t| t.is_none()).count();
+        if missing > 0 {
+            return Err(CompactError::Incomplete { missing });
+        }
+        let block = Block {
+            header: self.header,
+            txs: self.txs.into_iter().flatten().collect(),
+        };
+        verify_block_merkle(&block)?;
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egg_crypto::merkle::merkle_root_txids;
+    use egg_crypto::tx_id_from_payload;
+    use egg_types::{Height, CONTENT_TAG_OPAQUE};
+
+    fn mk_tx(payload: &[u8]) -> Transaction {
+        Transaction {
+            id: tx_id_from_payload(payload),
+            payload: payload.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        }
+    }
+
+    fn mk_block(txs: Vec<Transaction>) -> Block {
+        let leaves: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        Block {
+            header: BlockHeader {
+                parent: Hash256::zero(),
+                height: Height(1),
+                timestamp_utc: 1_700_000_000,
+                nonce: 0,
+                merkle_root: merkle_root_txids(&leaves),
+                pow_difficulty_bits: 0,
+            },
+            txs,
+        }
+    }
+
+    #[test]
+    fn reconstruct_fetching_only_missing_txs() {
+        let txs: Vec<Transaction> = (0u8..6).map(|i| mk_tx(&[i; 3])).collect();
+        let block = mk_block(txs.clone());
+
+        let mut mp = Mempool::new();
+        for (i, tx) in txs.iter().enumerate() {
+            if i != 1 && i != 4 {
+                mp.add_tx(tx.clone()).unwrap();
+            }
+        }
+
+        let (header, txids) = compact_of(&block);
+        let mut pb = PartialBlock::from_mempool(header, txids, &mp);
+        assert_eq!(pb.id(), header_id(&block.header));
+        let missing = pb.missing_indexes();
+        assert_eq!(missing, vec![1, 4]);
+        assert!(matches!(
+            pb.clone().into_block(),
+            Err(CompactError::Incomplete { missing: 2 })
+        ));
+
+        let fetched = txs_at(&block, &missing).unwrap();
+        pb.fill(&missing, fetched).unwrap();
+        assert!(pb.is_complete());
+        assert_eq!(pb.into_block().unwrap(), block);
+    }
+
+    #[test]
+    fn fill_rejects_wrong_tx() {
+        let block = mk_block(vec![mk_tx(b"a"), mk_tx(b"b")]);
+        let (header, txids) = compact_of(&block);
+        let mut pb = PartialBlock::from_mempool(header, txids, &Mempool::new());
+
+        let err = pb.fill(&[0], vec![mk_tx(b"zzz")]).unwrap_err();
+        assert!(matches!(err, CompactError::TxIdMismatch { index: 0, .. }));
+        assert!(matches!(
+            txs_at(&block, &[2]),
+            Err(CompactError::IndexOutOfRange { index: 2, count: 2 })
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+#![forbid(unsafe_code)]
+
+use egg_db::store::{ChainStore, ChainTip};
+use egg_types::{Block, BlockHeader, Hash256, Height, Transaction};
+
+use crate::state::{ChainState, Result};
+
+/// Kết quả `Explorer::search`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchResult {
+    Block(Block),
+    Tx(Transaction),
+    /// Không khớp gì, kể cả input không phải height / hex 64 ký tự.
+    NotFound,
+}
+
+/// Gom các truy vấn đọc hay dùng cho explorer/UI; chỉ xem canonical chain.
+pub struct Explorer<'a, S: ChainStore + Clone> {
+    state: &'a ChainState<S>,
+}
+
+impl<'a, S: ChainStore + Clone> Explorer<'a, S> {
+    pub fn new(state: &'a ChainState<S>) -> Self {
+        Self { state }
+    }
+
+    pub fn tip(&self) -> ChainTip {
+        self.state.tip
+    }
+
+    pub fn block_by_height(&self, height: Height) -> Result<Option<Block>> {
+        self.state.block_at_height(height)
+    }
+
+    /// Block bất kỳ đã lưu (kể cả nhánh phụ); `None` nếu chưa có body.
+    pub fn block_by_hash(&self, id: Hash256) -> Result<Option<Block>> {
+        Ok(self.state.get_block_with_meta(id)?.map(|(b, _)| b))
+    }
+
+    pub fn tx(&self, txid: Hash256) -> Result<Option<Transaction>> {
+        self.state.get_tx(txid)
+    }
+
+    /// Tối đa `count` header canonical từ height `from` trở lên (tăng dần).
+    pub fn headers_page(&self, from: Height, count: usize) -> Result<Vec<BlockHeader>> {
+        let mut out = Vec::new();
+        let mut h = from.0;
+        while out.len() < count {
+            let Some(hdr) = self.state.header_at_height(Height(h))? else {
+                break;
+            };
+            out.push(hdr);
+            let Some(next) = h.checked_add(1) else {
+                break;
+            };
+            h = next;
+        }
+        Ok(out)
+    }
+
+    /// Số thập phân => block theo height; hex 64 ký tự (có thể có `0x`) => block hash, rồi txid.
+    pub fn search(&self, query: &str) -> Result<SearchResult> {
+        let q = query.trim();
+        if let Some(id) = parse_hash_hex(q) {
+            if let Some(b) = self.block_by_hash(id)? {
+                return Ok(SearchResult::Block(b));
+            }
+            if let Some(tx) = self.tx(id)? {
+                return Ok(SearchResult::Tx(tx));
+            }
+            return Ok(SearchResult::NotFound);
+        }
+        if !q.is_empty() && q.bytes().all(|c| c.is_ascii_digit()) {
+            if let Ok(h) = q.parse::<u64>() {
+                if let Some(b) = self.block_by_height(Height(h))? {
+                    return Ok(SearchResult::Block(b));
+                }
+            }
+        }
+        Ok(SearchResult::NotFound)
+    }
+}
+
+fn parse_hash_hex(s: &str) -> Option<Hash256> {
+    let s = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    Hash256::from_hex(s).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header_id;
+    use egg_crypto::merkle::merkle_root_txids;
+    use egg_crypto::tx_id_from_payload;
+    use egg_db::store::DbChainStore;
+    use egg_db::MemKv;
+    use egg_types::{ChainParams, ChainSpec, GenesisSpec, CONTENT_TAG_OPAQUE};
+
+    fn mk_spec() -> ChainSpec {
+        ChainSpec {
+            spec_version: 1,
+            chain: ChainParams {
+                chain_name: "EGG-MAINNET".to_string(),
+                chain_id: 1,
+            },
+            genesis: GenesisSpec {
+                timestamp_utc: 1_700_000_000,
+                pow_difficulty_bits: 0,
+                nonce: 0,
+            },
+        }
+    }
+
+    fn mk_block(parent: Hash256, height: Height, txs: Vec<Transaction>) -> Block {
+        let ids: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        let header = BlockHeader {
+            parent,
+            height,
+            timestamp_utc: 1_700_000_000,
+            nonce: height.0,
+            merkle_root: merkle_root_txids(&ids),
+            pow_difficulty_bits: 0,
+        };
+        Block { header, txs }
+    }
+
+    fn mk_tx(payload: &[u8]) -> Transaction {
+        Transaction {
+            id: tx_id_from_payload(payload),
+            payload: payload.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        }
+    }
+
+    /// genesis + 3 block; tx nằm ở block 2.
+    fn mk_chain() -> (ChainState<DbChainStore<MemKv>>, Vec<Block>, Transaction) {
+        let mut st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec()).unwrap();
+        let tx = mk_tx(b"explorer");
+        let mut parent = st.tip.hash;
+        let mut blocks = Vec::new();
+        for h in 1..=3u64 {
+            let txs = if h == 2 { vec![tx.clone()] } else { vec![] };
+            let b = mk_block(parent, Height(h), txs);
+            parent = st.ingest_block(b.clone()).unwrap().0;
+            blocks.push(b);
+        }
+        (st, blocks, tx)
+    }
+
+    #[test]
+    fn tip_and_block_lookups() {
+        let (st, blocks, _) = mk_chain();
+        let ex = st.explorer();
+        assert_eq!(ex.tip(), st.tip);
+        assert_eq!(ex.tip().height, Height(3));
+
+        assert_eq!(ex.block_by_height(Height(2)).unwrap(), Some(blocks[1].clone()));
+        assert_eq!(ex.block_by_height(Height(4)).unwrap(), None);
+        let id = header_id(&blocks[2].header);
+        assert_eq!(ex.block_by_hash(id).unwrap(), Some(blocks[2].clone()));
+        assert_eq!(ex.block_by_hash(Hash256([7u8; 32])).unwrap(), None);
+    }
+
+    #[test]
+    fn tx_lookup_sees_mined_tx_only() {
+        let (st, _, tx) = mk_chain();
+        let ex = st.explorer();
+        assert_eq!(ex.tx(tx.id).unwrap(), Some(tx));
+        assert_eq!(ex.tx(mk_tx(b"missing").id).unwrap(), None);
+    }
+
+    #[test]
+    fn headers_page_is_bounded_by_count_and_tip() {
+        let (st, blocks, _) = mk_chain();
+        let ex = st.explorer();
+        let page = ex.headers_page(Height(1), 2).unwrap();
+        assert_eq!(page, vec![blocks[0].header.clone(), blocks[1].header.clone()]);
+        assert_eq!(ex.headers_page(Height(2), 10).unwrap().len(), 2);
+        assert_eq!(ex.headers_page(Height(0), 10).unwrap().len(), 4);
+        assert!(ex.headers_page(Height(4), 10).unwrap().is_empty());
+        assert!(ex.headers_page(Height(0), 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_routes_hex_and_height() {
+        let (st, blocks, tx) = mk_chain();
+        let ex = st.explorer();
+
+        // 64 ký tự hex => block hash
+        let id = header_id(&blocks[0].header);
+        assert_eq!(ex.search(&id.to_hex()).unwrap(), SearchResult::Block(blocks[0].clone()));
+        assert_eq!(
+            ex.search(&format!(" 0x{} ", id.to_hex().to_uppercase())).unwrap(),
+            SearchResult::Block(blocks[0].clone())
+        );
+        // hex không phải block => thử txid
+        assert_eq!(ex.search(&tx.id.to_hex()).unwrap(), SearchResult::Tx(tx));
+
+        // số => height
+        assert_eq!(ex.search("3").unwrap(), SearchResult::Block(blocks[2].clone()));
+        assert_eq!(ex.search("99").unwrap(), SearchResult::NotFound);
+
+        // 64 chữ số thập phân vẫn là hash, không phải height
+        assert_eq!(ex.search(&"1".repeat(64)).unwrap(), SearchResult::NotFound);
+        assert_eq!(ex.search("").unwrap(), SearchResult::NotFound);
+        assert_eq!(ex.search("xyz").unwrap(), SearchResult::NotFound);
+        assert_eq!(ex.search(&"g".repeat(64)).unwrap(), SearchResult::NotFound);
+    }
+}
@@ -1,10 +1,15 @@
 #![forbid(unsafe_code)]
 
-use egg_crypto::{hash_header, leading_zero_bits};
+use egg_crypto::{hash_header, meets_target, Target};
 use egg_types::{BlockHeader, Hash256};
 
+use crate::block_builder::BlockLimits;
+
 pub mod block_builder;
 pub mod chainspec;
+pub mod coinbase;
+pub mod compact;
+pub mod explorer;
 pub mod mempool;
 pub mod miner;
 pub mod state;
@@ -20,13 +25,208 @@ impl PowPolicy {
     }
 }
 
+/// Hệ số chỉnh target tối đa mỗi lần retarget (`RetargetMode::Proportional`).
+pub const MAX_RETARGET_FACTOR: i64 = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetargetMode {
+    /// Nhanh hơn 1/2 target => +1 bit; chậm hơn 2x target => -1 bit (kẹp theo giá trị bits).
+    #[default]
+    StepBits,
+    /// Target mới tỉ lệ thời gian thực / kỳ vọng (`retarget_difficulty`), kẹp theo work.
+    Proportional,
+}
+
+/// Retarget độ khó theo cửa sổ `window` header gần nhất (cũ -> mới).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetargetPolicy {
+    pub target_block_secs: i64,
+    pub window: usize,
+    pub min_bits: u32,
+    pub max_bits: u32,
+    pub mode: RetargetMode,
+}
+
+impl RetargetPolicy {
+    /// Độ khó cho block con của header cuối `window_headers`.
+    /// Chưa đủ `window` header => giữ nguyên độ khó của parent.
+    pub fn next_bits(&self, window_headers: &[BlockHeader]) -> u32 {
+        let Some(last) = window_headers.last() else {
+            return self.min_bits;
+        };
+        let cur = last.pow_difficulty_bits;
+        if self.window < 2 || window_headers.len() < self.window {
+            return cur;
+        }
+        if self.mode == RetargetMode::Proportional {
+            let next = retarget_difficulty(window_headers, self.target_block_secs, self.window);
+            let work = work_of_pow_bits(next);
+            if work <= work_of_pow_bits(self.min_bits) {
+                return self.min_bits;
+            }
+            if work >= work_of_pow_bits(self.max_bits) {
+                return self.max_bits;
+            }
+            return next;
+        }
+
+        let first = &window_headers[window_headers.len() - self.window];
+        let actual = last.timestamp_utc.saturating_sub(first.timestamp_utc);
+        let expected = self.target_block_secs.saturating_mul(self.window as i64 - 1);
+
+        let next = if actual.saturating_mul(2) < expected {
+            cur.saturating_add(1)
+        } else if actual > expected.saturating_mul(2) {
+            cur.saturating_sub(1)
+        } else {
+            cur
+        };
+        next.clamp(self.min_bits, self.max_bits)
+    }
+}
+
+/// Compact target cho block con của header cuối `window`: target của nó nhân
+/// (thời gian `window_len` header cuối thực mất) / (`target_spacing_secs` * (window_len - 1)),
+/// tỉ lệ kẹp trong [1/4, 4]. Chưa đủ `window_len` header => giữ nguyên bits của header cuối.
+pub fn retarget_difficulty(window: &[BlockHeader], target_spacing_secs: i64, window_len: usize) -> u32 {
+    let Some(last) = window.last() else {
+        return 0;
+    };
+    let expected = target_spacing_secs.saturating_mul(window_len as i64 - 1);
+    if window_len < 2 || window.len() < window_len || expected <= 0 {
+        return last.pow_difficulty_bits;
+    }
+    let first = &window[window.len() - window_len];
+    let actual = last
+        .timestamp_utc
+        .saturating_sub(first.timestamp_utc)
+        .clamp(expected / MAX_RETARGET_FACTOR, expected.saturating_mul(MAX_RETARGET_FACTOR))
+        .max(1);
+
+    let old = Target::from_pow_bits(last.pow_difficulty_bits);
+    let mut next = scale_target(&old, actual as u64, expected as u64);
+    if next == Target::ZERO {
+        // compact 0 sẽ bị hiểu là "0 bit" (dễ nhất)
+        next.0[31] = 1;
+    }
+    next.to_compact()
+}
+
+/// `target * num / den` (256-bit), bão hoà ở target lớn nhất.
+fn scale_target(target: &Target, num: u64, den: u64) -> Target {
+    // nhân: 32 byte * u64 => tối đa 40 byte (big-endian)
+    let mut wide = [0u8; 40];
+    let mut carry: u128 = 0;
+    for i in (0..32).rev() {
+        let v = u128::from(target.0[i]) * u128::from(num) + carry;
+        wide[i + 8] = v as u8;
+        carry = v >> 8;
+    }
+    for i in (0..8).rev() {
+        wide[i] = carry as u8;
+        carry >>= 8;
+    }
+
+    // chia từ byte cao xuống
+    let mut quot = [0u8; 40];
+    let mut rem: u128 = 0;
+    for i in 0..40 {
+        rem = (rem << 8) | u128::from(wide[i]);
+        quot[i] = (rem / u128::from(den)) as u8;
+        rem %= u128::from(den);
+    }
+    if quot[..8].iter().any(|&b| b != 0) {
+        return Target([0xff; 32]);
+    }
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&quot[8..]);
+    Target(out)
+}
+
+/// Số header tổ tiên dùng tính median time past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Timestamp header được vượt "now" của node tối đa bao nhiêu giây.
+pub const MAX_FUTURE_DRIFT_SECS: i64 = 2 * 60 * 60;
+
+/// Tham số consensus gom về 1 chỗ (chain test / mạng khác chỉ cần đổi struct này).
+/// `Default` = giá trị hiện tại của mainnet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsensusRules {
+    /// Số tx / kích thước canonical tối đa của 1 block.
+    pub limits: BlockLimits,
+    /// Độ khó tối thiểu (cùng cách hiểu với `pow_difficulty_bits`), so theo work; 0 = không giới hạn.
+    pub min_difficulty_bits: u32,
+    /// Timestamp header không được vượt quá giờ local + drift; `None` = không kiểm.
+    pub max_future_drift_secs: Option<i64>,
+    /// Timestamp header phải lớn hơn median của N tổ tiên gần nhất (thường `MEDIAN_TIME_SPAN`);
+    /// `None` = không kiểm (mặc định, như `RetargetPolicy` / `RewardPolicy` phải bật riêng).
+    pub median_time_span: Option<usize>,
+    /// Số block canonical tối đa được thay trong 1 reorg; `None` = không giới hạn.
+    pub max_reorg_depth: Option<u64>,
+    /// Tổng work tối thiểu của 1 tip mới được chọn (chặn flood header rẻ lúc IBD); block của nhánh
+    /// dưới ngưỡng vẫn được lưu, chỉ không thành tip. `None` = không giới hạn.
+    pub min_chain_work: Option<u128>,
+}
+
+impl Default for ConsensusRules {
+    fn default() -> Self {
+        Self {
+            limits: BlockLimits::default(),
+            min_difficulty_bits: 0,
+            max_future_drift_secs: Some(MAX_FUTURE_DRIFT_SECS),
+            median_time_span: None,
+            max_reorg_depth: None,
+            min_chain_work: None,
+        }
+    }
+}
+
 pub fn header_id(header: &BlockHeader) -> Hash256 {
     hash_header(header)
 }
 
+/// `pow_difficulty_bits` < 2^24 = số bit 0 đầu (kiểu cũ), còn lại = compact target (xem `Target::from_pow_bits`).
 pub fn pow_valid(header: &BlockHeader) -> bool {
     let id = header_id(header);
-    leading_zero_bits(&id) >= header.pow_difficulty_bits
+    meets_target(&id, &Target::from_pow_bits(header.pow_difficulty_bits))
+}
+
+/// Lượng work kỳ vọng để tìm được hash <= target của header: ~2^256 / (target + 1),
+/// bão hoà ở `u128::MAX`. Độ khó kiểu cũ `n` bit 0 đầu => đúng 2^n.
+pub fn work_of_header(header: &BlockHeader) -> u128 {
+    work_of_pow_bits(header.pow_difficulty_bits)
+}
+
+pub fn work_of_pow_bits(bits: u32) -> u128 {
+    work_of_target(&Target::from_pow_bits(bits))
+}
+
+fn work_of_target(target: &Target) -> u128 {
+    let Some(first) = target.0.iter().position(|&b| b != 0) else {
+        return u128::MAX;
+    };
+    let lz = first as u32 * 8 + target.0[first].leading_zeros();
+
+    // 64 bit cao nhất của target, chuẩn hoá để bit đầu = bit 63: target ~ m * 2^(192 - lz)
+    let mut window: u128 = 0;
+    for i in 0..9 {
+        window = (window << 8) | u128::from(target.0.get(first + i).copied().unwrap_or(0));
+    }
+    let m = ((window << (lz % 8)) >> 8) as u64;
+
+    // work = 2^(64 + lz) / m = q * 2^(lz - 63), với q = 2^127 / m trong (2^63, 2^64]
+    let q = (1u128 << 127) / u128::from(m);
+    let work = if lz >= 63 {
+        let shift = lz - 63;
+        if shift > q.leading_zeros() {
+            return u128::MAX;
+        }
+        q << shift
+    } else {
+        q >> (63 - lz)
+    };
+    work.max(1)
 }
 
 #[cfg(test)]
@@ -73,9 +273,134 @@ mod tests {
         assert!(pow_valid(&h));
     }
 
+    #[test]
+    fn pow_valid_with_compact_bits() {
+        // 0x2000ffff: target = 0x00ffff.. => byte đầu của hash phải = 0 (~1/256)
+        let mut h = BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: 1_700_000_000,
+            nonce: 0,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: 0x2000_ffff,
+        };
+        while !pow_valid(&h) {
+            h.nonce += 1;
+            assert!(h.nonce < 1_000_000, "mine test exceeded tries");
+        }
+        let id = header_id(&h);
+        assert_eq!(id.0[0], 0);
+        assert!(meets_target(&id, &Target::from_compact(0x2000_ffff)));
+
+        // target = 1: thực tế không đạt được
+        h.pow_difficulty_bits = 0x0300_0001;
+        assert!(!pow_valid(&h));
+    }
+
+    #[test]
+    fn work_grows_with_difficulty() {
+        let hdr = |bits: u32| BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: 0,
+            nonce: 0,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: bits,
+        };
+        // kiểu cũ: n bit 0 đầu => 2^n
+        assert_eq!(work_of_header(&hdr(0)), 1);
+        assert_eq!(work_of_header(&hdr(8)), 256);
+        assert_eq!(work_of_header(&hdr(100)), 1u128 << 100);
+        assert_eq!(work_of_header(&hdr(127)), 1u128 << 127);
+        assert_eq!(work_of_header(&hdr(128)), u128::MAX);
+        assert_eq!(work_of_header(&hdr(300)), u128::MAX);
+
+        // compact 0x2000ffff ~ 1 byte 0 đầu => ~256
+        assert_eq!(work_of_header(&hdr(0x2000_ffff)), 256);
+        // target nhỏ hơn => work lớn hơn
+        assert!(work_of_header(&hdr(0x1f00_ffff)) > work_of_header(&hdr(0x2000_ffff)));
+        assert!(work_of_header(&hdr(0x2000_7fff)) > work_of_header(&hdr(0x2000_ffff)));
+    }
+
+    #[test]
+    fn retarget_policy_moves_one_bit() {
+        let p = RetargetPolicy {
+            target_block_secs: 60,
+            window: 3,
+            min_bits: 0,
+            max_bits: 32,
+            mode: RetargetMode::StepBits,
+        };
+        let hdr = |ts: i64, bits: u32| BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: ts,
+            nonce: 0,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: bits,
+        };
+
+        assert_eq!(p.next_bits(&[hdr(0, 4), hdr(10, 4)]), 4);
+        assert_eq!(p.next_bits(&[hdr(0, 4), hdr(10, 4), hdr(20, 4)]), 5);
+        assert_eq!(p.next_bits(&[hdr(0, 4), hdr(60, 4), hdr(120, 4)]), 4);
+        assert_eq!(p.next_bits(&[hdr(0, 4), hdr(300, 4), hdr(600, 4)]), 3);
+        assert_eq!(p.next_bits(&[hdr(0, 0), hdr(300, 0), hdr(600, 0)]), 0);
+    }
+
     #[test]
     fn pow_policy_struct_exists() {
         let p = PowPolicy::new(16);
         assert_eq!(p.difficulty_bits, 16);
     }
+
+    #[test]
+    fn retarget_difficulty_scales_target_and_clamps_4x() {
+        let hdr = |ts: i64, bits: u32| BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: ts,
+            nonce: 0,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: bits,
+        };
+        let bits = 0x1d00_ffff;
+        let spaced = |step: i64| -> Vec<BlockHeader> { (0..5).map(|i| hdr(i * step, bits)).collect() };
+        let target = |b: u32| Target::from_pow_bits(b);
+
+        // đúng nhịp => giữ nguyên; chưa đủ cửa sổ => giữ nguyên
+        assert_eq!(retarget_difficulty(&spaced(60), 60, 5), bits);
+        assert_eq!(retarget_difficulty(&spaced(1)[..4], 60, 5), bits);
+
+        // nhanh gấp đôi => target / 2 (khó hơn); chậm gấp đôi => target * 2 (dễ hơn)
+        let fast = retarget_difficulty(&spaced(30), 60, 5);
+        assert_eq!(fast, 0x1c7f_ff80);
+        assert!(work_of_pow_bits(fast) > work_of_pow_bits(bits));
+        let slow = retarget_difficulty(&spaced(120), 60, 5);
+        assert_eq!(slow, 0x1d01_fffe);
+        assert!(work_of_pow_bits(slow) < work_of_pow_bits(bits));
+
+        // quá nhanh / quá chậm bị kẹp ở 4x
+        assert_eq!(retarget_difficulty(&spaced(1), 60, 5), retarget_difficulty(&spaced(15), 60, 5));
+        assert_eq!(target(retarget_difficulty(&spaced(0), 60, 5)), target(0x1c3f_ffc0));
+        assert_eq!(retarget_difficulty(&spaced(10_000), 60, 5), retarget_difficulty(&spaced(240), 60, 5));
+        assert_eq!(retarget_difficulty(&spaced(240), 60, 5), 0x1d03_fffc);
+
+        // target lớn nhất không tràn
+        let easy: Vec<BlockHeader> = (0..3).map(|i| hdr(i * 1_000, 0)).collect();
+        assert_eq!(target(retarget_difficulty(&easy, 60, 3)).0[0], 0xff);
+
+        // qua RetargetPolicy: kẹp theo work của min/max bits
+        let p = RetargetPolicy {
+            target_block_secs: 60,
+            window: 5,
+            min_bits: 0,
+            max_bits: 64,
+            mode: RetargetMode::Proportional,
+        };
+        assert_eq!(p.next_bits(&spaced(30)), fast);
+        let hard: Vec<BlockHeader> = (0..5).map(|i| hdr(i, 64)).collect();
+        assert_eq!(p.next_bits(&hard), 64);
+        let trivial: Vec<BlockHeader> = (0..5).map(|i| hdr(i * 1_000, 0)).collect();
+        assert_eq!(p.next_bits(&trivial), 0);
+    }
 }
@@ -1,11 +1,15 @@
 #![forbid(unsafe_code)]
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use egg_crypto::{tx_id_from_payload, validate_tx_id};
+use egg_crypto::{hash_tx, validate_tx_id};
 use egg_types::{Hash256, Transaction};
 use thiserror::Error;
 
+use crate::state::Clock;
+
 const DEFAULT_MAX_TXS: usize = 100_000;
 const DEFAULT_MAX_TOTAL_BYTES: usize = 64 * 1024 * 1024; // 64 MiB
 
@@ -13,6 +17,8 @@ const DEFAULT_MAX_TOTAL_BYTES: usize = 64 * 1024 * 1024; // 64 MiB
 pub enum AddOutcome {
     Added,
     AlreadyKnown,
+    /// tx mới trả fee-rate cao hơn và thay thế `old`
+    Replaced { old: Hash256 },
 }
 
 #[derive(Debug, Error)]
@@ -25,23 +31,157 @@ pub enum MempoolError {
 
     #[error("mempool full")]
     Full,
+
+    #[error("fee too low: fee {fee} for {size} bytes, min fee-rate {min_fee_rate}/byte")]
+    FeeTooLow { fee: u64, size: usize, min_fee_rate: u64 },
+
+    #[error("replacement of {replaces:?} must pay a higher fee-rate")]
+    ReplacementFeeTooLow { replaces: Hash256 },
 }
 
 pub type Result<T> = std::result::Result<T, MempoolError>;
 
+/// Lý do tx rời mempool (ngoài eviction).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalReason {
+    /// `remove` gọi trực tiếp (vd. tx đã vào block)
+    Explicit,
+    /// lấy ra qua `drain_fifo`/`drain_topological` để dựng block
+    Drained,
+}
+
+/// Thay đổi của mempool, phát qua `Mempool::set_event_sink` sau khi state đã cập nhật.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MempoolEvent {
+    Added { txid: Hash256, size: usize },
+    Removed { txid: Hash256, reason: RemovalReason },
+    /// bị tx trả fee-rate cao hơn thay thế (replace-by-fee)
+    Evicted { txid: Hash256 },
+}
+
+pub type MempoolEventSink = Box<dyn Fn(MempoolEvent) + Send>;
+
+/// Thông tin fee đọc từ payload (payload opaque với mempool nên phải qua hook).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxFeeInfo {
+    pub fee: u64,
+    /// tx cũ mà tx này muốn thay thế (replace-by-fee)
+    pub replaces: Option<Hash256>,
+}
+
+pub type FeeExtractor = Arc<dyn Fn(&Transaction) -> TxFeeInfo + Send + Sync>;
+
+/// Trả txid các tx cha mà tx này phụ thuộc (vd tiêu output của chúng).
+pub type DepsExtractor = Arc<dyn Fn(&Transaction) -> Vec<Hash256> + Send + Sync>;
+
+/// Tx cha (không còn trong mempool) đã nằm trong chain chưa.
+pub type ConfirmedChecker = Arc<dyn Fn(&Hash256) -> bool + Send + Sync>;
+
 #[derive(Clone)]
+pub struct MempoolConfig {
+    pub max_txs: usize,
+    pub max_total_bytes: usize,
+    /// fee tối thiểu trên mỗi byte payload; 0 = tắt
+    pub min_fee_rate: u64,
+    /// None => mọi tx có fee 0, không replace
+    pub fee_extractor: Option<FeeExtractor>,
+    /// None => tx không phụ thuộc nhau, `drain_topological` = FIFO
+    pub deps_extractor: Option<DepsExtractor>,
+    /// None => dependency không có trong mempool coi như đã confirm
+    pub is_confirmed: Option<ConfirmedChecker>,
+    /// nguồn thời điểm tx vào mempool (`inserted_at`); None = đồng hồ hệ thống
+    pub clock: Option<Clock>,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self {
+            max_txs: DEFAULT_MAX_TXS,
+            max_total_bytes: DEFAULT_MAX_TOTAL_BYTES,
+            min_fee_rate: 0,
+            fee_extractor: None,
+            deps_extractor: None,
+            is_confirmed: None,
+            clock: None,
+        }
+    }
+}
+
 pub struct Mempool {
+    cfg: MempoolConfig,
     by_id: HashMap<Hash256, Transaction>,
     order: VecDeque<Hash256>,
     total_payload_bytes: usize,
+    // txid -> unix giây lúc vào mempool
+    inserted_at: HashMap<Hash256, i64>,
+    event_sink: Option<MempoolEventSink>,
+}
+
+/// Bản clone không mang theo event sink (subscriber chỉ theo dõi mempool gốc).
+impl Clone for Mempool {
+    fn clone(&self) -> Self {
+        Self {
+            cfg: self.cfg.clone(),
+            by_id: self.by_id.clone(),
+            order: self.order.clone(),
+            total_payload_bytes: self.total_payload_bytes,
+            inserted_at: self.inserted_at.clone(),
+            event_sink: None,
+        }
+    }
+}
+
+/// a có fee-rate cao hơn b (so chéo, tránh chia).
+fn fee_rate_gt(a_fee: u64, a_len: usize, b_fee: u64, b_len: usize) -> bool {
+    (a_fee as u128) * (b_len.max(1) as u128) > (b_fee as u128) * (a_len.max(1) as u128)
 }
 
 impl Mempool {
     pub fn new() -> Self {
+        Self::with_config(MempoolConfig::default())
+    }
+
+    pub fn with_config(cfg: MempoolConfig) -> Self {
         Self {
+            cfg,
             by_id: HashMap::new(),
             order: VecDeque::new(),
             total_payload_bytes: 0,
+            inserted_at: HashMap::new(),
+            event_sink: None,
+        }
+    }
+
+    /// Nhận `MempoolEvent` cho mọi tx thêm/bớt; gọi sau khi state đã cập nhật nên sink đọc
+    /// lại mempool (qua handle khác) luôn thấy trạng thái khớp event.
+    pub fn set_event_sink(&mut self, sink: MempoolEventSink) {
+        self.event_sink = Some(sink);
+    }
+
+    pub fn clear_event_sink(&mut self) {
+        self.event_sink = None;
+    }
+
+    fn emit(&self, ev: MempoolEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink(ev);
+        }
+    }
+
+    fn now_utc(&self) -> i64 {
+        if let Some(c) = &self.cfg.clock {
+            return c();
+        }
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
+            .unwrap_or(0)
+    }
+
+    fn fee_info(&self, tx: &Transaction) -> TxFeeInfo {
+        match &self.cfg.fee_extractor {
+            Some(f) => f(tx),
+            None => TxFeeInfo::default(),
         }
     }
 
@@ -65,8 +205,18 @@ impl Mempool {
         self.by_id.get(&txid)
     }
 
+    /// Unix giây lúc tx vào mempool (theo `MempoolConfig::clock`).
+    pub fn inserted_at(&self, txid: Hash256) -> Option<i64> {
+        self.inserted_at.get(&txid).copied()
+    }
+
+    /// Txid đang có trong mempool (thứ tự không xác định).
+    pub fn txids(&self) -> Vec<Hash256> {
+        self.by_id.keys().copied().collect()
+    }
+
     pub fn add_tx(&mut self, tx: Transaction) -> Result<AddOutcome> {
-        let expected = tx_id_from_payload(&tx.payload);
+        let expected = hash_tx(&tx);
         if tx.id != expected || !validate_tx_id(&tx) {
             return Err(MempoolError::InvalidTxId {
                 expected,
@@ -78,32 +228,76 @@ impl Mempool {
             return Ok(AddOutcome::AlreadyKnown);
         }
 
-        if tx.payload.len() > DEFAULT_MAX_TOTAL_BYTES {
+        if tx.payload.len() > self.cfg.max_total_bytes {
             return Err(MempoolError::TxTooLarge {
                 size: tx.payload.len(),
             });
         }
 
-        if self.by_id.len() >= DEFAULT_MAX_TXS {
+        let info = self.fee_info(&tx);
+        let size = tx.payload.len();
+        if (info.fee as u128) < (self.cfg.min_fee_rate as u128) * (size as u128) {
+            return Err(MempoolError::FeeTooLow {
+                fee: info.fee,
+                size,
+                min_fee_rate: self.cfg.min_fee_rate,
+            });
+        }
+
+        // replace-by-fee: chỉ thay khi fee-rate mới cao hơn hẳn
+        let replaced = match info.replaces.and_then(|old| self.by_id.get(&old).map(|t| (old, t))) {
+            Some((old, old_tx)) => {
+                let old_info = self.fee_info(old_tx);
+                if !fee_rate_gt(info.fee, size, old_info.fee, old_tx.payload.len()) {
+                    return Err(MempoolError::ReplacementFeeTooLow { replaces: old });
+                }
+                Some((old, old_tx.payload.len()))
+            }
+            None => None,
+        };
+        let (freed_txs, freed_bytes) = replaced.map_or((0, 0), |(_, b)| (1, b));
+
+        if self.by_id.len() - freed_txs >= self.cfg.max_txs {
             return Err(MempoolError::Full);
         }
 
-        if self
-            .total_payload_bytes
-            .saturating_add(tx.payload.len())
-            > DEFAULT_MAX_TOTAL_BYTES
-        {
+        if (self.total_payload_bytes - freed_bytes).saturating_add(size) > self.cfg.max_total_bytes {
             return Err(MempoolError::Full);
         }
 
-        self.total_payload_bytes = self.total_payload_bytes.saturating_add(tx.payload.len());
-        self.order.push_back(tx.id);
-        self.by_id.insert(tx.id, tx);
-        Ok(AddOutcome::Added)
+        if let Some((old, _)) = replaced {
+            self.take(old);
+        }
+
+        let txid = tx.id;
+        self.total_payload_bytes = self.total_payload_bytes.saturating_add(size);
+        self.order.push_back(txid);
+        self.inserted_at.insert(txid, self.now_utc());
+        self.by_id.insert(txid, tx);
+
+        if let Some((old, _)) = replaced {
+            self.emit(MempoolEvent::Evicted { txid: old });
+        }
+        self.emit(MempoolEvent::Added { txid, size });
+        Ok(match replaced {
+            Some((old, _)) => AddOutcome::Replaced { old },
+            None => AddOutcome::Added,
+        })
     }
 
     pub fn remove(&mut self, txid: Hash256) -> Option<Transaction> {
+        let tx = self.take(txid)?;
+        self.emit(MempoolEvent::Removed {
+            txid,
+            reason: RemovalReason::Explicit,
+        });
+        Some(tx)
+    }
+
+    /// Bỏ tx khỏi mempool, không phát event.
+    fn take(&mut self, txid: Hash256) -> Option<Transaction> {
         let tx = self.by_id.remove(&txid)?;
+        self.inserted_at.remove(&txid);
         self.total_payload_bytes = self.total_payload_bytes.saturating_sub(tx.payload.len());
         // giữ `order` đơn giản: không xoá giữa; sẽ được skip khi drain.
         Some(tx)
@@ -117,10 +311,75 @@ impl Mempool {
                 break;
             };
             if let Some(tx) = self.by_id.remove(&txid) {
+                self.inserted_at.remove(&txid);
                 self.total_payload_bytes = self.total_payload_bytes.saturating_sub(tx.payload.len());
                 out.push(tx);
             }
         }
+        self.emit_drained(&out);
+        out
+    }
+
+    fn emit_drained(&self, txs: &[Transaction]) {
+        for tx in txs {
+            self.emit(MempoolEvent::Removed {
+                txid: tx.id,
+                reason: RemovalReason::Drained,
+            });
+        }
+    }
+
+    /// Như `drain_fifo` nhưng tx cha luôn đứng trước tx con (theo `deps_extractor`).
+    /// Dependency không còn trong mempool (vd cha đã drain ở lần trước) coi như đã confirm,
+    /// trừ khi `is_confirmed` trả false; tx có cha chưa confirm (hoặc thuộc vòng phụ thuộc)
+    /// bị bỏ qua và vẫn ở lại mempool. Giữa các tx sẵn sàng vẫn ưu tiên thứ tự FIFO.
+    pub fn drain_topological(&mut self, max: usize) -> Vec<Transaction> {
+        let Some(deps_of) = self.cfg.deps_extractor.clone() else {
+            return self.drain_fifo(max);
+        };
+        let is_confirmed = self.cfg.is_confirmed.clone();
+
+        let mut seen = HashSet::new();
+        let mut queue: Vec<(Hash256, Vec<Hash256>)> = Vec::new();
+        for id in &self.order {
+            let Some(tx) = self.by_id.get(id) else { continue };
+            if !seen.insert(*id) {
+                continue;
+            }
+            // chỉ giữ cha còn trong mempool; cha ngoài mempool phải đã confirm
+            let (deps, outside): (Vec<_>, Vec<_>) =
+                deps_of(tx).into_iter().partition(|d| self.by_id.contains_key(d));
+            let ready = match &is_confirmed {
+                Some(f) => outside.iter().all(|d| f(d)),
+                None => true,
+            };
+            if ready {
+                queue.push((*id, deps));
+            }
+        }
+
+        // mỗi vòng quét theo FIFO, lấy mọi tx đã đủ cha; dừng khi không còn tiến triển
+        let mut included: HashSet<Hash256> = HashSet::new();
+        let mut out_ids = Vec::new();
+        let mut progressed = true;
+        while progressed && out_ids.len() < max {
+            progressed = false;
+            queue.retain(|(id, deps)| {
+                if out_ids.len() >= max || !deps.iter().all(|d| included.contains(d)) {
+                    return true;
+                }
+                included.insert(*id);
+                out_ids.push(*id);
+                progressed = true;
+                false
+            });
+        }
+
+        let out: Vec<Transaction> = out_ids.iter().filter_map(|id| self.take(*id)).collect();
+        let by_id = &self.by_id;
+        let mut kept = HashSet::new();
+        self.order.retain(|id| by_id.contains_key(id) && kept.insert(*id));
+        self.emit_drained(&out);
         out
     }
 }
@@ -135,12 +394,14 @@ impl Default for Mempool {
 mod tests {
     use super::*;
     use egg_crypto::tx_id_from_payload;
+    use egg_types::CONTENT_TAG_OPAQUE;
 
     fn mk_tx(payload: &[u8]) -> Transaction {
         let id = tx_id_from_payload(payload);
         Transaction {
             id,
             payload: payload.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
         }
     }
 
@@ -206,4 +467,174 @@ mod tests {
         assert_eq!(mp.len(), 1);
         assert!(mp.contains(c.id));
     }
+
+    // payload test: fee(u64 BE) + replaces(32 byte, 0 = không) + data
+    fn mk_fee_tx(fee: u64, replaces: Option<Hash256>, data: &[u8]) -> Transaction {
+        let mut p = fee.to_be_bytes().to_vec();
+        p.extend_from_slice(&replaces.unwrap_or(Hash256::zero()).0);
+        p.extend_from_slice(data);
+        mk_tx(&p)
+    }
+
+    fn fee_cfg(min_fee_rate: u64) -> MempoolConfig {
+        MempoolConfig {
+            min_fee_rate,
+            fee_extractor: Some(Arc::new(|tx: &Transaction| {
+                let fee = u64::from_be_bytes(tx.payload[0..8].try_into().unwrap());
+                let mut r = [0u8; 32];
+                r.copy_from_slice(&tx.payload[8..40]);
+                let replaces = (r != [0u8; 32]).then_some(Hash256(r));
+                TxFeeInfo { fee, replaces }
+            })),
+            ..MempoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn fee_rate_floor_rejects_cheap_tx() {
+        let mut mp = Mempool::with_config(fee_cfg(2));
+        // payload 40 + 10 = 50 byte => cần fee >= 100
+        let cheap = mk_fee_tx(99, None, &[0u8; 10]);
+        assert!(matches!(
+            mp.add_tx(cheap),
+            Err(MempoolError::FeeTooLow { fee: 99, size: 50, min_fee_rate: 2 })
+        ));
+        assert_eq!(mp.add_tx(mk_fee_tx(100, None, &[0u8; 10])).unwrap(), AddOutcome::Added);
+    }
+
+    #[test]
+    fn replace_by_fee_evicts_lower_fee_rate_tx() {
+        let mut mp = Mempool::with_config(fee_cfg(1));
+        let old = mk_fee_tx(100, None, &[1u8; 10]);
+        let old_id = old.id;
+        mp.add_tx(old).unwrap();
+
+        let same_rate = mk_fee_tx(100, Some(old_id), &[2u8; 10]);
+        assert!(matches!(
+            mp.add_tx(same_rate),
+            Err(MempoolError::ReplacementFeeTooLow { replaces }) if replaces == old_id
+        ));
+
+        let bump = mk_fee_tx(150, Some(old_id), &[2u8; 10]);
+        let bump_id = bump.id;
+        assert_eq!(mp.add_tx(bump).unwrap(), AddOutcome::Replaced { old: old_id });
+        assert!(!mp.contains(old_id));
+        assert!(mp.contains(bump_id));
+        assert_eq!(mp.len(), 1);
+        assert_eq!(mp.total_payload_bytes(), 50);
+        assert_eq!(mp.drain_fifo(10).len(), 1);
+    }
+
+    #[test]
+    fn drain_topological_emits_parent_before_child() {
+        // payload test: byte đầu = 1 => phụ thuộc tx có payload `parent`
+        let parent = mk_tx(b"\0parent");
+        let parent_id = parent.id;
+        let child = mk_tx(b"\x01child");
+        let orphan = mk_tx(b"\x02orphan");
+        let cfg = MempoolConfig {
+            deps_extractor: Some(Arc::new(move |tx: &Transaction| match tx.payload[0] {
+                1 => vec![parent_id],
+                2 => vec![Hash256([7u8; 32])],
+                _ => vec![],
+            })),
+            is_confirmed: Some(Arc::new(|id: &Hash256| *id != Hash256([7u8; 32]))),
+            ..MempoolConfig::default()
+        };
+        let mut mp = Mempool::with_config(cfg);
+        mp.add_tx(child.clone()).unwrap();
+        mp.add_tx(orphan.clone()).unwrap();
+        mp.add_tx(parent.clone()).unwrap();
+
+        let out = mp.drain_topological(10);
+        let ids: Vec<Hash256> = out.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![parent.id, child.id]);
+
+        // tx thiếu cha vẫn nằm trong mempool
+        assert!(mp.contains(orphan.id));
+        assert_eq!(mp.drain_fifo(10).len(), 1);
+    }
+
+    #[test]
+    fn event_sink_reports_add_remove_drain_and_eviction() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut mp = Mempool::with_config(fee_cfg(1));
+        let sink = events.clone();
+        mp.set_event_sink(Box::new(move |ev| sink.lock().unwrap().push(ev)));
+        let take = || std::mem::take(&mut *events.lock().unwrap());
+
+        let a = mk_fee_tx(100, None, &[1u8; 10]);
+        let b = mk_fee_tx(100, None, &[2u8; 10]);
+        let (a_id, b_id) = (a.id, b.id);
+        mp.add_tx(a).unwrap();
+        mp.add_tx(b.clone()).unwrap();
+        // trùng hoặc bị từ chối => không có event
+        mp.add_tx(b).unwrap();
+        assert!(mp.add_tx(mk_fee_tx(1, None, &[3u8; 10])).is_err());
+        assert_eq!(
+            take(),
+            vec![
+                MempoolEvent::Added { txid: a_id, size: 50 },
+                MempoolEvent::Added { txid: b_id, size: 50 },
+            ]
+        );
+
+        let bump = mk_fee_tx(200, Some(a_id), &[4u8; 10]);
+        let bump_id = bump.id;
+        mp.add_tx(bump).unwrap();
+        assert_eq!(
+            take(),
+            vec![
+                MempoolEvent::Evicted { txid: a_id },
+                MempoolEvent::Added { txid: bump_id, size: 50 },
+            ]
+        );
+
+        mp.remove(b_id).unwrap();
+        assert!(mp.remove(b_id).is_none());
+        assert_eq!(
+            take(),
+            vec![MempoolEvent::Removed {
+                txid: b_id,
+                reason: RemovalReason::Explicit
+            }]
+        );
+
+        assert_eq!(mp.drain_fifo(10).len(), 1);
+        assert_eq!(
+            take(),
+            vec![MempoolEvent::Removed {
+                txid: bump_id,
+                reason: RemovalReason::Drained
+            }]
+        );
+
+        // bản clone không phát event
+        let mut copy = mp.clone();
+        copy.add_tx(mk_fee_tx(100, None, &[5u8; 10])).unwrap();
+        assert!(take().is_empty());
+    }
+
+    #[test]
+    fn drain_topological_releases_child_after_parent_drained() {
+        let parent = mk_tx(b"\0parent");
+        let parent_id = parent.id;
+        let child = mk_tx(b"\x01child");
+        let cfg = MempoolConfig {
+            deps_extractor: Some(Arc::new(move |tx: &Transaction| match tx.payload[0] {
+                1 => vec![parent_id],
+                _ => vec![],
+            })),
+            ..MempoolConfig::default()
+        };
+        let mut mp = Mempool::with_config(cfg);
+        mp.add_tx(parent.clone()).unwrap();
+        mp.add_tx(child.clone()).unwrap();
+
+        // block đầy sau tx cha: con ở lại cho block sau
+        assert_eq!(mp.drain_topological(1), vec![parent]);
+        assert!(mp.contains(child.id));
+        assert_eq!(mp.drain_topological(1), vec![child]);
+        assert_eq!(mp.len(), 0);
+    }
 }
@@ -1,13 +1,17 @@
 #![forbid(unsafe_code)]
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use egg_types::{Block, Hash256, Height};
 use thiserror::Error;
 
-use crate::block_builder::{build_block_template_from_mempool, BlockBuildError};
+use crate::block_builder::{build_block_template_with_limits, BlockBuildError, BlockLimits};
 use crate::mempool::Mempool;
 use crate::pow_valid;
 
 const DEFAULT_MAX_NONCE_TRIES: u64 = 50_000_000;
+/// Cứ mỗi chừng này nonce thì xem cờ cancel và báo progress.
+pub const MINING_CHECK_EVERY: u64 = 4096;
 
 #[derive(Debug, Error)]
 pub enum MiningError {
@@ -16,13 +20,34 @@ pub enum MiningError {
 
     #[error("pow not found within {max_tries} nonce tries")]
     PowNotFound { max_tries: u64 },
+
+    #[error("mining cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, MiningError>;
 
-pub fn mine_block(mut block: Block) -> Result<Block> {
+pub fn mine_block(block: Block) -> Result<Block> {
+    mine_block_cancellable(block, &AtomicBool::new(false), |_| {})
+}
+
+/// Như `mine_block`, nhưng mỗi `MINING_CHECK_EVERY` nonce gọi `on_progress(số nonce đã thử)`
+/// và dừng với `Cancelled` khi `cancel` được bật (vd. node nhận block mới từ mạng).
+pub fn mine_block_cancellable(
+    mut block: Block,
+    cancel: &AtomicBool,
+    mut on_progress: impl FnMut(u64),
+) -> Result<Block> {
     let mut tries: u64 = 0;
     while tries < DEFAULT_MAX_NONCE_TRIES {
+        if tries % MINING_CHECK_EVERY == 0 {
+            if tries > 0 {
+                on_progress(tries);
+            }
+            if cancel.load(Ordering::Relaxed) {
+                return Err(MiningError::Cancelled);
+            }
+        }
         if pow_valid(&block.header) {
             return Ok(block);
         }
@@ -43,12 +68,32 @@ pub fn mine_block_from_mempool(
     timestamp_utc: i64,
     pow_difficulty_bits: u32,
 ) -> Result<Block> {
-    let block = build_block_template_from_mempool(
+    mine_block_from_mempool_with_limits(
+        mempool,
+        parent,
+        height,
+        timestamp_utc,
+        pow_difficulty_bits,
+        &BlockLimits::default(),
+    )
+}
+
+/// Như `mine_block_from_mempool`, template theo `limits` (vd. từ `ConsensusRules`).
+pub fn mine_block_from_mempool_with_limits(
+    mempool: &mut Mempool,
+    parent: Hash256,
+    height: Height,
+    timestamp_utc: i64,
+    pow_difficulty_bits: u32,
+    limits: &BlockLimits,
+) -> Result<Block> {
+    let block = build_block_template_with_limits(
         mempool,
         parent,
         height,
         timestamp_utc,
         pow_difficulty_bits,
+        limits,
     )?;
 
     // Nếu mining fail: restore txs (best-effort) để không mất.
@@ -73,13 +118,14 @@ pub fn mine_block_from_mempool(
 mod tests {
     use super::*;
     use egg_crypto::tx_id_from_payload;
-    use egg_types::{Hash256, Transaction};
+    use egg_types::{Hash256, Transaction, CONTENT_TAG_OPAQUE};
 
     fn mk_tx(payload: &[u8]) -> Transaction {
         let id = tx_id_from_payload(payload);
         Transaction {
             id,
             payload: payload.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
         }
     }
 
@@ -101,4 +147,54 @@ mod tests {
         assert!(pow_valid(&blk.header));
         assert_eq!(blk.header.pow_difficulty_bits, 8);
     }
+
+    fn unminable_block() -> Block {
+        let mut mp = Mempool::new();
+        let mut blk = build_block_template_with_limits(
+            &mut mp,
+            Hash256::zero(),
+            Height(1),
+            1_700_000_000,
+            0,
+            &BlockLimits::default(),
+        )
+        .unwrap();
+        // 200 bit 0 đầu: không thể tìm được trong giới hạn nonce
+        blk.header.pow_difficulty_bits = 200;
+        blk
+    }
+
+    #[test]
+    fn cancel_flag_aborts_mining_promptly() {
+        let cancel = AtomicBool::new(false);
+        let started = std::time::Instant::now();
+        let r = std::thread::scope(|s| {
+            let h = s.spawn(|| mine_block_cancellable(unminable_block(), &cancel, |_| {}));
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cancel.store(true, Ordering::Relaxed);
+            h.join().unwrap()
+        });
+        assert!(matches!(r, Err(MiningError::Cancelled)), "{r:?}");
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        // cờ đã bật từ trước => không thử nonce nào
+        let mut calls = 0;
+        let r = mine_block_cancellable(unminable_block(), &cancel, |_| calls += 1);
+        assert!(matches!(r, Err(MiningError::Cancelled)));
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn progress_callback_reports_tries_periodically() {
+        let cancel = AtomicBool::new(false);
+        let mut seen = Vec::new();
+        let r = mine_block_cancellable(unminable_block(), &cancel, |tries| {
+            seen.push(tries);
+            if seen.len() == 3 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        });
+        assert!(matches!(r, Err(MiningError::Cancelled)));
+        assert_eq!(seen, vec![MINING_CHECK_EVERY, 2 * MINING_CHECK_EVERY, 3 * MINING_CHECK_EVERY]);
+    }
 }
@@ -1,15 +1,33 @@
 #![forbid(unsafe_code)]
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use egg_crypto::hash_chainspec;
-use egg_db::store::{BlockMeta, ChainMeta, ChainStore, ChainTip, StoreError};
-use egg_types::{Block, BlockHeader, ChainSpec, Hash256, Height};
+use egg_crypto::merkle::merkle_root_txids;
+use egg_db::store::{ensure_schema, BlockMeta, ChainMeta, ChainStore, ChainTip, StoreError, TxLocation};
+use egg_types::{Block, BlockHeader, ChainSpec, Hash256, HeaderProvider, Height, Transaction};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::block_builder::BlockBuildError;
 use crate::chainspec::{genesis_id, genesis_header, validate_chainspec, ChainSpecError};
-use crate::{header_id, pow_valid};
+use crate::coinbase::{parse_coinbase, RewardPolicy};
+use crate::explorer::Explorer;
+use crate::mempool::Mempool;
+use crate::{header_id, pow_valid, work_of_header, work_of_pow_bits, ConsensusRules, RetargetPolicy};
+
+const SYNC_HEADERS_BATCH: usize = 2000;
+/// Số orphan (header/block chưa biết parent) giữ tối đa trước khi evict cái cũ nhất.
+pub const DEFAULT_MAX_ORPHANS: usize = 1024;
+
+/// Hook được gọi mỗi khi 1 block được kiểm đầy đủ (PoW + merkle); dùng cho telemetry/test.
+pub type FullVerifyHook = Arc<dyn Fn(Hash256) + Send + Sync>;
+
+/// Nguồn "now" (unix giây) cho kiểm timestamp tương lai; mặc định là đồng hồ hệ thống.
+pub type Clock = Arc<dyn Fn() -> i64 + Send + Sync>;
 
 #[derive(Debug, Error)]
 pub enum ChainStateError {
@@ -28,8 +46,9 @@ pub enum ChainStateError {
     #[error("chainstore missing meta (required)")]
     MetaMissing,
 
+    // Box: ChainMeta lớn, giữ `Result<_, ChainStateError>` nhỏ (clippy::result_large_err)
     #[error("chain meta mismatch: expected={expected:?} got={got:?}")]
-    MetaMismatch { expected: ChainMeta, got: ChainMeta },
+    MetaMismatch { expected: Box<ChainMeta>, got: Box<ChainMeta> },
 
     #[error("genesis header mismatch between spec and stored data")]
     GenesisHeaderMismatch,
@@ -52,12 +71,202 @@ pub enum ChainStateError {
     #[error("missing block meta for block {id:?}")]
     MissingBlockMeta { id: Hash256 },
 
+    #[error("missing canonical hash at height {height:?}")]
+    MissingCanonHash { height: Height },
+
     #[error("block header does not match stored header for id {id:?}")]
     HeaderMismatch { id: Hash256 },
+
+    #[error("header sequence is empty")]
+    EmptyHeaderSequence,
+
+    #[error("header sequence starts from unknown parent {parent:?}")]
+    UnknownParent { parent: Hash256 },
+
+    #[error("header sequence broken at index {index}: expected parent {expected:?}, got {got:?}")]
+    BrokenHeaderLink { index: usize, expected: Hash256, got: Hash256 },
+
+    #[error("header sequence tip mismatch: expected {expected:?}, got {got:?}")]
+    HeaderTipMismatch { expected: Hash256, got: Hash256 },
+
+    #[error("best chain mismatch: incremental tip {incremental:?}, recomputed {recomputed:?}")]
+    BestChainMismatch { incremental: ChainTip, recomputed: ChainTip },
+
+    #[error("difficulty mismatch: expected {expected} bits, got {got}")]
+    DifficultyMismatch { expected: u32, got: u32 },
+
+    #[error("header timestamp {timestamp} is not after median time past {median}")]
+    TimestampTooEarly { timestamp: i64, median: i64 },
+
+    #[error("difficulty too low: minimum {min} bits, got {got}")]
+    DifficultyTooLow { min: u32, got: u32 },
+
+    #[error("header timestamp {timestamp} is beyond allowed future time {max}")]
+    TimestampTooFarFuture { timestamp: i64, max: i64 },
+
+    #[error("reorg depth {depth} exceeds maximum {max}")]
+    ReorgTooDeep { depth: u64, max: u64 },
+
+    #[error("child height overflows u64 (parent height {parent_height:?})")]
+    HeightOverflow { parent_height: Height },
+
+    #[error("block {id:?} is (or descends from) a block marked invalid")]
+    KnownInvalid { id: Hash256 },
+
+    #[error("cannot disconnect genesis")]
+    DisconnectGenesis,
+
+    #[error("operation cancelled")]
+    Cancelled,
+
+    #[error("block {id:?} missing coinbase as first tx")]
+    MissingCoinbase { id: Hash256 },
+
+    #[error("coinbase mismatch: expected height {expected_height:?} reward {expected_reward}, got height {got_height:?} reward {got_reward}")]
+    CoinbaseMismatch {
+        expected_height: Height,
+        expected_reward: u64,
+        got_height: Height,
+        got_reward: u64,
+    },
+}
+
+impl ChainStateError {
+    /// `true` nếu lỗi do dữ liệu peer gửi vi phạm consensus (node nên phạt peer và tiếp tục),
+    /// `false` nếu là lỗi nội bộ (store/spec/...) và node nên dừng.
+    pub fn is_peer_fault(&self) -> bool {
+        matches!(
+            self,
+            ChainStateError::InvalidPow
+                | ChainStateError::HeightNotParentPlusOne { .. }
+                | ChainStateError::GenesisIdMismatch { .. }
+                | ChainStateError::HeaderMismatch { .. }
+                | ChainStateError::BlockBuild(_)
+                | ChainStateError::EmptyHeaderSequence
+                | ChainStateError::UnknownParent { .. }
+                | ChainStateError::BrokenHeaderLink { .. }
+                | ChainStateError::HeaderTipMismatch { .. }
+                | ChainStateError::HeightOverflow { .. }
+                | ChainStateError::DifficultyMismatch { .. }
+                | ChainStateError::DifficultyTooLow { .. }
+                | ChainStateError::TimestampTooEarly { .. }
+                | ChainStateError::TimestampTooFarFuture { .. }
+                | ChainStateError::ReorgTooDeep { .. }
+                | ChainStateError::KnownInvalid { .. }
+                | ChainStateError::MissingCoinbase { .. }
+                | ChainStateError::CoinbaseMismatch { .. }
+        )
+    }
+
+    /// `true` nếu lỗi nằm ở chính header (PoW, độ khó, height/link với parent): id = hash(header)
+    /// nên đánh dấu id invalid là an toàn. Lỗi body thì không — peer có thể ghép header thật
+    /// với body giả.
+    pub fn is_header_fault(&self) -> bool {
+        matches!(
+            self,
+            ChainStateError::InvalidPow
+                | ChainStateError::BlockBuild(BlockBuildError::InvalidPow)
+                | ChainStateError::HeightNotParentPlusOne { .. }
+                | ChainStateError::HeightOverflow { .. }
+                | ChainStateError::GenesisIdMismatch { .. }
+                | ChainStateError::DifficultyMismatch { .. }
+                | ChainStateError::DifficultyTooLow { .. }
+                | ChainStateError::TimestampTooEarly { .. }
+        )
+    }
 }
 
 pub type Result<T> = std::result::Result<T, ChainStateError>;
 
+/// Height của block con; lỗi thay vì saturate tại u64::MAX (tránh 2 block cùng height).
+fn child_height(parent_height: Height) -> Result<Height> {
+    parent_height
+        .0
+        .checked_add(1)
+        .map(Height)
+        .ok_or(ChainStateError::HeightOverflow { parent_height })
+}
+
+/// Orphan theo thứ tự nhận (cũ nhất trước), index theo parent còn thiếu.
+/// Chỉ ở bộ nhớ: orphan đã lưu trước khi mở lại store không được đếm.
+#[derive(Clone, Debug, Default)]
+struct OrphanPool {
+    order: VecDeque<Hash256>,
+    parent_of: HashMap<Hash256, Hash256>,
+    by_parent: HashMap<Hash256, Vec<Hash256>>,
+}
+
+impl OrphanPool {
+    fn len(&self) -> usize {
+        self.parent_of.len()
+    }
+
+    fn insert(&mut self, id: Hash256, parent: Hash256) {
+        if self.parent_of.insert(id, parent).is_some() {
+            return;
+        }
+        self.order.push_back(id);
+        self.by_parent.entry(parent).or_default().push(id);
+    }
+
+    /// Parent vừa tới: các orphan chờ nó không còn là orphan.
+    fn take_children_of(&mut self, parent: Hash256) -> Vec<Hash256> {
+        let ids = self.by_parent.remove(&parent).unwrap_or_default();
+        for id in &ids {
+            self.parent_of.remove(id);
+        }
+        self.compact_order();
+        ids
+    }
+
+    fn pop_oldest(&mut self) -> Option<(Hash256, Hash256)> {
+        while let Some(id) = self.order.pop_front() {
+            let Some(parent) = self.parent_of.remove(&id) else {
+                continue; // đã được nối trước đó
+            };
+            if let Some(v) = self.by_parent.get_mut(&parent) {
+                v.retain(|x| *x != id);
+                if v.is_empty() {
+                    self.by_parent.remove(&parent);
+                }
+            }
+            return Some((id, parent));
+        }
+        None
+    }
+
+    // `order` giữ lại id đã được nối; dọn khi rác chiếm quá nửa
+    fn compact_order(&mut self) {
+        if self.order.len() > 2 * self.parent_of.len() + 16 {
+            let live = &self.parent_of;
+            self.order.retain(|id| live.contains_key(id));
+        }
+    }
+}
+
+/// Tip incremental lệch best chain tính lại từ đầu (`recompute_best_chain`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BestChainMismatch {
+    pub incremental: ChainTip,
+    pub recomputed: ChainTip,
+}
+
+/// Kết luận về peer gửi block bị từ chối (`handle_invalid_block_from_peer`); node tự áp
+/// lên `PeerMachine`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvalidBlockVerdict {
+    /// header tự nó sai consensus (đã đánh dấu invalid): phạt peer với lý do này
+    Penalize(String),
+    /// body không khớp header (merkle/tx/coinbase): phạt peer, không đánh dấu; tải lại block
+    /// từ peer khác
+    BadBody(String),
+    /// con cháu của block đã invalid: chỉ đánh dấu, không phạt thêm
+    DescendantOfInvalid,
+    /// phụ thuộc đồng hồ / chính sách local (timestamp tương lai, reorg quá sâu):
+    /// không đánh dấu, không phạt
+    Deferred(String),
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum IngestOutcome {
     AlreadyKnown,
@@ -66,6 +275,99 @@ pub enum IngestOutcome {
     NewTip,
 }
 
+/// Thống kê tx của block vừa ingest so với mempool local (phát hiện peer đào tx riêng).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IngestBlockReport {
+    pub txs_total: usize,
+    pub txs_from_mempool: usize,
+    pub txs_unknown: usize,
+}
+
+/// 1 node trong cây block (mọi nhánh đã biết, kể cả chỉ có header).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkTreeNode {
+    pub id: Hash256,
+    /// None với genesis
+    pub parent: Option<Hash256>,
+    pub height: Height,
+    pub children: Vec<Hash256>,
+    pub is_canonical: bool,
+    pub has_block: bool,
+}
+
+/// Cây block từ genesis (parent -> children) để debug reorg / xuất DOT.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkTree {
+    pub root: Hash256,
+    /// thứ tự BFS từ genesis
+    pub nodes: Vec<ForkTreeNode>,
+}
+
+/// Khác biệt best chain giữa 2 `ChainState` (xem `ChainState::diff`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainDiff {
+    /// Height đầu tiên 2 bên khác nhau (kể cả 1 bên chưa có); `None` nếu trùng hoàn toàn.
+    pub first_divergent_height: Option<Height>,
+    /// Hash canonical chỉ local có, từ `first_divergent_height` tới tip local.
+    pub local_only: Vec<Hash256>,
+    /// Hash canonical chỉ remote có, từ `first_divergent_height` tới tip remote.
+    pub remote_only: Vec<Hash256>,
+}
+
+impl ChainDiff {
+    pub fn is_empty(&self) -> bool {
+        self.first_divergent_height.is_none()
+    }
+}
+
+/// Độ khó hiện tại của mạng cho miner (xem `ChainState::difficulty_info`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DifficultyInfo {
+    /// `pow_difficulty_bits` của tip.
+    pub current_bits: u32,
+    /// Bits bắt buộc cho block kế tiếp trên tip.
+    pub next_required_bits: u32,
+    /// Số block còn thiếu tới khi cửa sổ retarget đủ (0 = block kế tiếp đã theo retarget);
+    /// `None` nếu không có policy (độ khó không bao giờ đổi).
+    pub blocks_until_retarget: Option<u64>,
+}
+
+/// Số mẫu gần nhất giữ cho `ChainState::mempool_inclusion_stats`.
+pub const INCLUSION_LATENCY_WINDOW: usize = 1024;
+/// Cận trên (giây, tính cả cận) của từng bucket; bucket cuối gom phần còn lại.
+pub const INCLUSION_LATENCY_BUCKETS: [u64; 8] = [10, 30, 60, 300, 600, 1800, 3600, u64::MAX];
+
+/// Thời gian tx nằm trong mempool trước khi được đào, trên `INCLUSION_LATENCY_WINDOW` mẫu gần nhất.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InclusionStats {
+    pub count: usize,
+    pub min_secs: u64,
+    pub max_secs: u64,
+    pub mean_secs: u64,
+    /// (cận trên giây, số mẫu) theo `INCLUSION_LATENCY_BUCKETS`
+    pub buckets: Vec<(u64, usize)>,
+}
+
+/// Trạng thái xác nhận của 1 tx (xem `ChainState::tx_confirmation_status`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfStatus {
+    /// không có trong chain canonical lẫn mempool
+    Unknown,
+    InMempool,
+    /// đã đào nhưng số xác nhận < ngưỡng
+    Unconfirmed { depth: u64 },
+    /// đủ ngưỡng nhưng chưa sâu hơn reorg sâu nhất đã thấy (còn rủi ro)
+    Confirmed { depth: u64 },
+    DeeplyConfirmed,
+}
+
+/// Tham chiếu block theo hash hoặc theo height canonical (xem `ChainState::resolve_header`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockRef {
+    Hash(Hash256),
+    Height(Height),
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HeaderIngestOutcome {
     AlreadyKnown,
@@ -73,12 +375,38 @@ pub enum HeaderIngestOutcome {
     StoredConnected,
 }
 
+/// Tuỳ chọn khi mở store đã có dữ liệu.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChainOpenOptions {
+    /// So byte-for-byte header genesis trong store với header sinh từ spec
+    /// (bắt store hỏng dù genesis id vẫn khớp).
+    pub verify_genesis_on_open: bool,
+}
+
+/// Clone chỉ clone handle store; `tip`/`meta` giữ theo giá trị => các clone KHÔNG được
+/// ingest đồng thời (cùng đọc 1 tip rồi ghi đè nhau). Đa luồng dùng `SharedChainState`.
 #[derive(Clone)]
 pub struct ChainState<S: ChainStore + Clone> {
     pub spec: ChainSpec,
     pub tip: ChainTip,
     pub meta: ChainMeta,
     store: S,
+    assume_valid: Option<Hash256>,
+    // id theo height của chain assume-valid (genesis..=assume_valid), dựng 1 lần khi đủ header
+    assume_valid_chain: Option<Vec<Hash256>>,
+    full_verify_hook: Option<FullVerifyHook>,
+    clock: Option<Clock>,
+    retarget: Option<RetargetPolicy>,
+    rules: ConsensusRules,
+    reward: Option<RewardPolicy>,
+    strict_best_chain: bool,
+    orphans: OrphanPool,
+    max_orphans: usize,
+    invalid_blocks: HashSet<Hash256>,
+    max_reorg_depth: u64,
+    // latency (giây) vào mempool -> được đào, tối đa INCLUSION_LATENCY_WINDOW mẫu
+    inclusion_latency: VecDeque<u64>,
+    repaired_on_open: Option<BestChainMismatch>,
 }
 
 impl<S: ChainStore + Clone> ChainState<S> {
@@ -86,6 +414,18 @@ impl<S: ChainStore + Clone> ChainState<S> {
         &self.store
     }
 
+    /// Đẩy mọi write còn treo của store xuống đĩa; gọi trước khi tắt node.
+    pub fn flush(&self) -> Result<()> {
+        self.store.flush()?;
+        Ok(())
+    }
+
+    /// Compact store (`ChainStore::compact`) để thu hồi chỗ trên đĩa sau khi xoá nhiều entry.
+    pub fn compact_store(&self) -> Result<()> {
+        self.store.compact()?;
+        Ok(())
+    }
+
     fn expected_meta(spec: &ChainSpec) -> Result<ChainMeta> {
         let gid = genesis_id(spec)?;
         Ok(ChainMeta {
@@ -95,20 +435,367 @@ impl<S: ChainStore + Clone> ChainState<S> {
         })
     }
 
-    fn hash_lt(a: Hash256, b: Hash256) -> bool {
-        a.0 < b.0
+    /// Block là tổ tiên (hoặc chính) `hash` được coi là đã kiểm bởi mạng:
+    /// bỏ qua PoW + txid/merkle, vẫn kiểm height/parent và giới hạn block.
+    pub fn set_assume_valid(&mut self, hash: Hash256) {
+        self.assume_valid = Some(hash);
+        self.assume_valid_chain = None;
+    }
+
+    pub fn clear_assume_valid(&mut self) {
+        self.assume_valid = None;
+        self.assume_valid_chain = None;
+    }
+
+    pub fn assume_valid(&self) -> Option<Hash256> {
+        self.assume_valid
+    }
+
+    /// Đánh dấu block invalid (lưu marker `invalid:` vào store, còn sau restart): block này và
+    /// con cháu bị từ chối khi ingest.
+    pub fn mark_block_invalid(&mut self, id: Hash256) -> Result<()> {
+        self.store.put_invalid_marker(id)?;
+        self.invalid_blocks.insert(id);
+        Ok(())
+    }
+
+    pub fn is_block_invalid(&self, id: Hash256) -> bool {
+        self.invalid_blocks.contains(&id)
+    }
+
+    /// Xử lý lỗi ingest block `id` nhận từ peer: lỗi do peer (`is_peer_fault`) trả verdict để
+    /// node phạt peer, pipeline chạy tiếp; chỉ lỗi header (`is_header_fault`) mới đánh dấu invalid.
+    /// Lỗi nội bộ trả lại nguyên vẹn.
+    pub fn handle_invalid_block_from_peer(
+        &mut self,
+        id: Hash256,
+        err: ChainStateError,
+    ) -> Result<InvalidBlockVerdict> {
+        if !err.is_peer_fault() {
+            return Err(err);
+        }
+        Ok(match err {
+            // check_not_invalid đã đánh dấu
+            ChainStateError::KnownInvalid { .. } => InvalidBlockVerdict::DescendantOfInvalid,
+            ChainStateError::TimestampTooFarFuture { .. }
+            | ChainStateError::ReorgTooDeep { .. } => {
+                InvalidBlockVerdict::Deferred(format!("block not accepted: {err}"))
+            }
+            err if err.is_header_fault() => {
+                self.mark_block_invalid(id)?;
+                InvalidBlockVerdict::Penalize(format!("invalid block: {err}"))
+            }
+            err => InvalidBlockVerdict::BadBody(format!("bad block body: {err}")),
+        })
+    }
+
+    /// Xoá mọi marker invalid (vd. sau nâng cấp consensus) khỏi store và bộ nhớ, trong 1 batch,
+    /// để block từng bị từ chối được xét lại; trả số block đã bỏ đánh dấu.
+    pub fn purge_invalid_markers(&mut self) -> Result<usize> {
+        let mut ids: HashSet<Hash256> = self.store.invalid_markers()?.into_iter().collect();
+        ids.extend(self.invalid_blocks.iter().copied());
+        self.atomically(|st| {
+            for id in &ids {
+                st.store.del_invalid_marker(*id)?;
+            }
+            st.invalid_blocks.clear();
+            Ok(())
+        })?;
+        Ok(ids.len())
+    }
+
+    fn check_not_invalid(&mut self, id: Hash256, parent: Hash256) -> Result<()> {
+        if self.invalid_blocks.contains(&id) {
+            return Err(ChainStateError::KnownInvalid { id });
+        }
+        if self.invalid_blocks.contains(&parent) {
+            self.invalid_blocks.insert(id);
+            return Err(ChainStateError::KnownInvalid { id });
+        }
+        Ok(())
+    }
+
+    /// Thay bộ tham số consensus (giới hạn block, độ khó tối thiểu, drift, độ sâu reorg).
+    pub fn set_consensus_rules(&mut self, rules: ConsensusRules) {
+        self.rules = rules;
+    }
+
+    pub fn consensus_rules(&self) -> &ConsensusRules {
+        &self.rules
+    }
+
+    /// Kiểm header theo `rules` (không cần parent).
+    fn check_header_rules(&self, header: &BlockHeader) -> Result<()> {
+        let min = self.rules.min_difficulty_bits;
+        if work_of_header(header) < work_of_pow_bits(min) {
+            return Err(ChainStateError::DifficultyTooLow {
+                min,
+                got: header.pow_difficulty_bits,
+            });
+        }
+        if let Some(drift) = self.rules.max_future_drift_secs {
+            let max = self.now_utc().saturating_add(drift);
+            if header.timestamp_utc > max {
+                return Err(ChainStateError::TimestampTooFarFuture {
+                    timestamp: header.timestamp_utc,
+                    max,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Thay đồng hồ dùng cho `max_future_drift_secs` (test / mô phỏng).
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = Some(clock);
+    }
+
+    fn now_utc(&self) -> i64 {
+        if let Some(c) = &self.clock {
+            return c();
+        }
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
+            .unwrap_or(0)
+    }
+
+    /// Median timestamp của tối đa `span` header tính từ `parent_id` lùi về (gồm cả parent).
+    pub fn median_time_past(&self, parent_id: Hash256, span: usize) -> Result<i64> {
+        let mut cur = self.must_header(parent_id)?;
+        let mut times = vec![cur.timestamp_utc];
+        while times.len() < span && cur.height != Height(0) && self.store.has_header(cur.parent)? {
+            cur = self.store.get_header(cur.parent)?;
+            times.push(cur.timestamp_utc);
+        }
+        times.sort_unstable();
+        Ok(times[times.len() / 2])
+    }
+
+    /// Chỉ kiểm khi đã bật `median_time_span` và biết parent (giống `check_child_difficulty`).
+    fn check_child_timestamp(&self, header: &BlockHeader) -> Result<()> {
+        let Some(span) = self.rules.median_time_span.filter(|&n| n > 0) else {
+            return Ok(());
+        };
+        if !self.store.has_header(header.parent)? {
+            return Ok(());
+        }
+        let median = self.median_time_past(header.parent, span)?;
+        if header.timestamp_utc <= median {
+            return Err(ChainStateError::TimestampTooEarly {
+                timestamp: header.timestamp_utc,
+                median,
+            });
+        }
+        Ok(())
+    }
+
+    /// Bật kiểm độ khó khi ingest: header/block phải có đúng `expected_difficulty_for_child`.
+    pub fn set_retarget_policy(&mut self, policy: RetargetPolicy) {
+        self.retarget = Some(policy);
+    }
+
+    pub fn retarget_policy(&self) -> Option<RetargetPolicy> {
+        self.retarget
+    }
+
+    /// Bật kiểm coinbase khi ingest: tx đầu của block (trừ genesis) phải là coinbase
+    /// đúng height và đúng `reward_schedule(height)`.
+    pub fn set_reward_policy(&mut self, policy: RewardPolicy) {
+        self.reward = Some(policy);
+    }
+
+    pub fn reward_policy(&self) -> Option<RewardPolicy> {
+        self.reward
+    }
+
+    /// Phần thưởng cho block tại `height`; không có policy => 0.
+    pub fn reward_schedule(&self, height: Height) -> u64 {
+        self.reward.map_or(0, |p| p.reward_at(height))
+    }
+
+    fn check_coinbase(&self, id: Hash256, block: &Block) -> Result<()> {
+        if self.reward.is_none() {
+            return Ok(());
+        }
+        let Some((got_height, got_reward)) = block.txs.first().and_then(parse_coinbase) else {
+            return Err(ChainStateError::MissingCoinbase { id });
+        };
+        let expected_height = block.header.height;
+        let expected_reward = self.reward_schedule(expected_height);
+        if got_height != expected_height || got_reward != expected_reward {
+            return Err(ChainStateError::CoinbaseMismatch {
+                expected_height,
+                expected_reward,
+                got_height,
+                got_reward,
+            });
+        }
+        Ok(())
+    }
+
+    /// `pow_difficulty_bits` bắt buộc cho block nối vào `parent_id`, theo cửa sổ tổ tiên của parent.
+    /// Không có policy => bằng độ khó của parent.
+    pub fn expected_difficulty_for_child(&self, parent_id: Hash256) -> Result<u32> {
+        let parent = self.must_header(parent_id)?;
+        let Some(policy) = self.retarget else {
+            return Ok(parent.pow_difficulty_bits);
+        };
+
+        let mut window = vec![parent];
+        while window.len() < policy.window {
+            let oldest = window.last().expect("non-empty");
+            if oldest.height == Height(0) || !self.store.has_header(oldest.parent)? {
+                break;
+            }
+            window.push(self.store.get_header(oldest.parent)?);
+        }
+        window.reverse();
+        Ok(policy.next_bits(&window))
+    }
+
+    /// Độ khó của tip và của block kế tiếp theo retarget policy.
+    pub fn difficulty_info(&self) -> Result<DifficultyInfo> {
+        let tip = self.must_header(self.tip.hash)?;
+        let blocks_until_retarget = self
+            .retarget
+            .filter(|p| p.window >= 2)
+            .map(|p| (p.window as u64).saturating_sub(self.tip.height.0 + 1));
+        Ok(DifficultyInfo {
+            current_bits: tip.pow_difficulty_bits,
+            next_required_bits: self.expected_difficulty_for_child(self.tip.hash)?,
+            blocks_until_retarget,
+        })
+    }
+
+    /// Chỉ kiểm khi đã bật policy và biết parent (orphan kiểm lúc nối vào sau).
+    fn check_child_difficulty(&self, header: &BlockHeader) -> Result<()> {
+        if self.retarget.is_none() || !self.store.has_header(header.parent)? {
+            return Ok(());
+        }
+        let expected = self.expected_difficulty_for_child(header.parent)?;
+        if header.pow_difficulty_bits != expected {
+            return Err(ChainStateError::DifficultyMismatch {
+                expected,
+                got: header.pow_difficulty_bits,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_full_verify_hook(&mut self, hook: FullVerifyHook) {
+        self.full_verify_hook = Some(hook);
+    }
+
+    fn note_full_verify(&self, id: Hash256) {
+        if let Some(h) = &self.full_verify_hook {
+            h(id);
+        }
+    }
+
+    /// `id` (ở `height`) có nằm trên nhánh từ genesis tới header assume-valid không.
+    /// Header assume-valid chưa có trong store => false (kiểm đầy đủ).
+    /// Chain tổ tiên của assume-valid chỉ đi 1 lần (khi header đã đủ tới genesis) rồi tra theo
+    /// height, để IBD không đi lại từ assume-valid cho mỗi block.
+    fn is_assumed_valid(&mut self, id: Hash256, height: Height) -> Result<bool> {
+        if self.assume_valid_chain.is_none() {
+            let Some(av) = self.assume_valid else {
+                return Ok(false);
+            };
+            // thiếu header giữa chừng: chưa dựng được, block nào cũng kiểm đầy đủ
+            let Some(chain) = self.assume_valid_ancestry(av)? else {
+                return Ok(false);
+            };
+            self.assume_valid_chain = Some(chain);
+        }
+        let chain = self.assume_valid_chain.as_deref().unwrap_or_default();
+        let idx = usize::try_from(height.0).ok();
+        Ok(idx.and_then(|i| chain.get(i)) == Some(&id))
+    }
+
+    /// Id theo height từ genesis tới `av`; `None` nếu thiếu header nào đó trên đường đi.
+    fn assume_valid_ancestry(&self, av: Hash256) -> Result<Option<Vec<Hash256>>> {
+        let mut chain = Vec::new();
+        let mut cur = av;
+        loop {
+            if !self.store.has_header(cur)? {
+                return Ok(None);
+            }
+            let hdr = self.store.get_header(cur)?;
+            chain.push(cur);
+            if hdr.height.0 == 0 {
+                break;
+            }
+            cur = hdr.parent;
+        }
+        chain.reverse();
+        Ok(Some(chain))
     }
 
     fn ensure_block_meta_from_header(&self, id: Hash256, hdr: &BlockHeader) -> Result<BlockMeta> {
         if let Some(m) = self.store.get_block_meta(id)? {
             return Ok(m);
         }
-        let m = BlockMeta {
+        let m = self.new_block_meta(hdr)?;
+        self.store.put_block_meta(id, m)?;
+        Ok(m)
+    }
+
+    /// Meta cho header mới; chain_work = work parent + work header nếu parent đã có work, không thì 0
+    /// (tính sau qua `chain_work_of` khi nối được vào chain).
+    fn new_block_meta(&self, hdr: &BlockHeader) -> Result<BlockMeta> {
+        let chain_work = match self.store.get_block_meta(hdr.parent)? {
+            Some(pm) if pm.chain_work > 0 => pm.chain_work.saturating_add(work_of_header(hdr)),
+            _ => 0,
+        };
+        Ok(BlockMeta {
             parent: hdr.parent,
             height: hdr.height,
+            chain_work,
+        })
+    }
+
+    /// Tổng work genesis -> `id`. Meta chưa có work (orphan vừa nối / bmeta cũ) được tính
+    /// theo parent pointer rồi ghi lại; nhánh chưa nối tới genesis => 0.
+    fn chain_work_of(&self, id: Hash256) -> Result<u128> {
+        let mut missing = Vec::new();
+        let mut cur = id;
+        let mut work = loop {
+            let m = self.must_block_meta(cur)?;
+            if m.chain_work > 0 {
+                break m.chain_work;
+            }
+            missing.push((cur, m));
+            if m.height == Height(0) {
+                break 0;
+            }
+            if self.store.get_block_meta(m.parent)?.is_none() {
+                return Ok(0);
+            }
+            cur = m.parent;
         };
-        self.store.put_block_meta(id, m)?;
-        Ok(m)
+        while let Some((x, mut m)) = missing.pop() {
+            work = work.saturating_add(work_of_header(&self.must_header(x)?));
+            m.chain_work = work;
+            self.store.put_block_meta(x, m)?;
+        }
+        Ok(work)
+    }
+
+    /// Fork choice: nhiều work hơn, rồi height cao hơn, rồi hash nhỏ hơn.
+    fn is_better_tip(&self, candidate: ChainTip, current: ChainTip) -> Result<bool> {
+        let key = |t: ChainTip, work: u128| (work, t.height.0, std::cmp::Reverse(t.hash));
+        let c = key(candidate, self.chain_work_of(candidate.hash)?);
+        let t = key(current, self.chain_work_of(current.hash)?);
+        Ok(c > t)
+    }
+
+    /// `ConsensusRules::min_chain_work`: nhánh tới `id` đủ work để làm tip chưa.
+    fn meets_min_chain_work(&self, id: Hash256) -> Result<bool> {
+        match self.rules.min_chain_work {
+            Some(min) => Ok(self.chain_work_of(id)? >= min),
+            None => Ok(true),
+        }
     }
 
     fn must_block_meta(&self, id: Hash256) -> Result<BlockMeta> {
@@ -131,6 +818,18 @@ impl<S: ChainStore + Clone> ChainState<S> {
         Ok(self.store.get_block(id)?)
     }
 
+    /// Reorg sửa canon trước rồi mới commit tip, bắt đầu từ ngay tại/trên tip cũ, nên reorg dở dang
+    /// luôn để lại dấu vết: canon[tip] bị thay/xoá, hoặc đã có canon ở height tip + 1.
+    fn canon_consistent_with_tip(&self) -> Result<bool> {
+        if self.store.get_canon_hash(self.tip.height)? != Some(self.tip.hash) {
+            return Ok(false);
+        }
+        let Some(above) = self.tip.height.0.checked_add(1) else {
+            return Ok(true);
+        };
+        Ok(self.store.get_canon_hash(Height(above))?.is_none())
+    }
+
     fn bootstrap_indexes_from_tip(&self, tip: ChainTip) -> Result<()> {
         let need_bmeta = self.store.get_block_meta(tip.hash)?.is_none();
         let need_canon = self.store.get_canon_hash(tip.height)?.is_none();
@@ -157,23 +856,53 @@ impl<S: ChainStore + Clone> ChainState<S> {
     }
 
     pub fn open_or_init(store: S, spec: ChainSpec) -> Result<Self> {
+        Self::open_or_init_with(store, spec, ChainOpenOptions::default())
+    }
+
+    pub fn open_or_init_with(store: S, spec: ChainSpec, opts: ChainOpenOptions) -> Result<Self> {
         validate_chainspec(&spec)?;
+        ensure_schema(&store)?;
         let expected = Self::expected_meta(&spec)?;
 
         match store.get_tip()? {
             Some(tip) => {
                 let got = store.get_meta()?.ok_or(ChainStateError::MetaMissing)?;
                 if got != expected {
-                    return Err(ChainStateError::MetaMismatch { expected, got });
+                    return Err(ChainStateError::MetaMismatch {
+                        expected: Box::new(expected),
+                        got: Box::new(got),
+                    });
                 }
 
-                let st = Self {
+                let mut st = Self {
                     spec,
                     tip,
                     meta: got,
                     store,
+                    assume_valid: None,
+                    assume_valid_chain: None,
+                    full_verify_hook: None,
+                    clock: None,
+                    retarget: None,
+                    rules: ConsensusRules::default(),
+                    reward: None,
+                    strict_best_chain: false,
+                    orphans: OrphanPool::default(),
+                    max_orphans: DEFAULT_MAX_ORPHANS,
+                    invalid_blocks: HashSet::new(),
+                    max_reorg_depth: 0,
+                    inclusion_latency: VecDeque::new(),
+                    repaired_on_open: None,
                 };
+                if opts.verify_genesis_on_open {
+                    st.verify_genesis_matches_spec()?;
+                }
                 st.bootstrap_indexes_from_tip(tip)?;
+                st.invalid_blocks = st.store.invalid_markers()?.into_iter().collect();
+                if !st.canon_consistent_with_tip()? {
+                    // reorg bị ngắt giữa chừng (crash sau khi ghi canon, trước khi commit tip)
+                    st.repaired_on_open = st.recompute_best_chain()?;
+                }
                 Ok(st)
             }
             None => {
@@ -202,6 +931,7 @@ impl<S: ChainStore + Clone> ChainState<S> {
                     BlockMeta {
                         parent: hdr.parent,
                         height: hdr.height,
+                        chain_work: work_of_header(&hdr),
                     },
                 )?;
                 store.set_canon_hash(Height(0), gid)?;
@@ -217,6 +947,20 @@ impl<S: ChainStore + Clone> ChainState<S> {
                     tip,
                     meta: expected,
                     store,
+                    assume_valid: None,
+                    assume_valid_chain: None,
+                    full_verify_hook: None,
+                    clock: None,
+                    retarget: None,
+                    rules: ConsensusRules::default(),
+                    reward: None,
+                    strict_best_chain: false,
+                    orphans: OrphanPool::default(),
+                    max_orphans: DEFAULT_MAX_ORPHANS,
+                    invalid_blocks: HashSet::new(),
+                    max_reorg_depth: 0,
+                    inclusion_latency: VecDeque::new(),
+                    repaired_on_open: None,
                 })
             }
         }
@@ -236,47 +980,543 @@ impl<S: ChainStore + Clone> ChainState<S> {
         Ok(self.store.get_canon_hash(height)?)
     }
 
-    pub fn get_headers_after(&self, start_hash: Hash256, max: usize) -> Result<Vec<BlockHeader>> {
-        if max == 0 {
-            return Ok(vec![]);
+    /// Header canonical tại `height`; `None` nếu cao hơn tip.
+    pub fn header_at_height(&self, height: Height) -> Result<Option<BlockHeader>> {
+        if height.0 > self.tip.height.0 {
+            return Ok(None);
         }
+        let Some(id) = self.store.get_canon_hash(height)? else {
+            return Ok(None);
+        };
+        Ok(Some(self.store.get_header(id)?))
+    }
 
-        let mut start_h: Option<u64> = None;
-        for h in 0..=self.tip.height.0 {
-            let hh = self.store.get_canon_hash(Height(h))?;
-            if let Some(x) = hh {
-                if x == start_hash {
-                    start_h = Some(h);
-                    break;
+    /// Header theo hash (mọi nhánh đã biết) hoặc height (canon index); `None` nếu không có.
+    pub fn resolve_header(&self, id: BlockRef) -> Result<Option<BlockHeader>> {
+        match id {
+            BlockRef::Height(h) => self.header_at_height(h),
+            BlockRef::Hash(hash) => {
+                if !self.store.has_header(hash)? {
+                    return Ok(None);
                 }
+                Ok(Some(self.store.get_header(hash)?))
             }
         }
-        let Some(sh) = start_h else {
-            return Ok(vec![]);
-        };
+    }
 
-        let mut out = Vec::new();
-        let mut cur_h = sh.saturating_add(1);
-        while cur_h <= self.tip.height.0 && out.len() < max {
-            let Some(hh) = self.store.get_canon_hash(Height(cur_h))? else { break; };
-            let hdr = self.store.get_header(hh)?;
-            out.push(hdr);
-            cur_h = cur_h.saturating_add(1);
+    /// Block canonical tại `height`; `None` nếu cao hơn tip hoặc chưa có body (headers-first).
+    pub fn block_at_height(&self, height: Height) -> Result<Option<Block>> {
+        if height.0 > self.tip.height.0 {
+            return Ok(None);
+        }
+        let Some(id) = self.store.get_canon_hash(height)? else {
+            return Ok(None);
+        };
+        if !self.store.has_block(id)? {
+            return Ok(None);
         }
+        Ok(Some(self.store.get_block(id)?))
+    }
 
-        Ok(out)
+    /// Header canonical từ genesis tới tip (theo canon index). Lỗi store được trả ra 1 lần
+    /// rồi iterator dừng; thiếu canon hash giữa chừng => `MissingCanonHash`.
+    pub fn canonical_headers(&self) -> impl Iterator<Item = Result<BlockHeader>> + '_ {
+        let tip = self.tip.height.0;
+        let mut next = Some(0u64);
+        std::iter::from_fn(move || {
+            let h = next.filter(|&h| h <= tip)?;
+            next = h.checked_add(1);
+            let height = Height(h);
+            let item = self
+                .store
+                .get_canon_hash(height)
+                .map_err(ChainStateError::from)
+                .and_then(|id| id.ok_or(ChainStateError::MissingCanonHash { height }))
+                .and_then(|id| Ok(self.store.get_header(id)?));
+            if item.is_err() {
+                next = None;
+            }
+            Some(item)
+        })
     }
 
-    fn reorg_canonical(&self, old_tip: ChainTip, new_tip: ChainTip) -> Result<()> {
-        let mut a = new_tip.hash;
-        let mut ha = new_tip.height.0;
-        let mut b = old_tip.hash;
-        let mut hb = old_tip.height.0;
+    /// `true` nếu block `id` nằm trên canonical chain hiện tại.
+    pub fn is_on_best_chain(&self, id: Hash256) -> Result<bool> {
+        let Some(m) = self.store.get_block_meta(id)? else {
+            return Ok(false);
+        };
+        if m.height.0 > self.tip.height.0 {
+            return Ok(false);
+        }
+        Ok(self.store.get_canon_hash(m.height)? == Some(id))
+    }
 
-        while ha > hb {
-            let m = self.must_block_meta(a)?;
-            a = m.parent;
-            ha = ha.saturating_sub(1);
+    /// Số xác nhận của block trên best chain (tip = 1); `None` nếu không canonical / không biết.
+    pub fn confirmations(&self, id: Hash256) -> Result<Option<u64>> {
+        if !self.is_on_best_chain(id)? {
+            return Ok(None);
+        }
+        let m = self.must_block_meta(id)?;
+        Ok(Some(self.tip.height.0 - m.height.0 + 1))
+    }
+
+    /// Client side của locator-based sync: hash canonical tại tip, rồi lùi 1, 2, 4, 8, ... block
+    /// (bước nhân đôi); luôn kết thúc bằng genesis.
+    pub fn build_block_locator(&self) -> Result<Vec<Hash256>> {
+        let tip_h = self.tip.height.0;
+        let mut heights = vec![tip_h];
+        let mut step = 1u64;
+        while step <= tip_h {
+            heights.push(tip_h - step);
+            match step.checked_mul(2) {
+                Some(s) => step = s,
+                None => break,
+            }
+        }
+        if heights.last() != Some(&0) {
+            heights.push(0);
+        }
+
+        let mut out = Vec::with_capacity(heights.len());
+        for h in heights {
+            if let Some(id) = self.store.get_canon_hash(Height(h))? {
+                out.push(id);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Server side của locator-based sync: entry đầu tiên trong locator (cao -> thấp)
+    /// nằm trên canonical chain là fork point để bắt đầu trả header.
+    pub fn fork_point_from_locator(&self, locator: &[Hash256]) -> Result<Option<(Height, Hash256)>> {
+        for id in locator {
+            if self.is_on_best_chain(*id)? {
+                let m = self.must_block_meta(*id)?;
+                return Ok(Some((m.height, *id)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_headers_after(&self, start_hash: Hash256, max: usize) -> Result<Vec<BlockHeader>> {
+        let ids = self.canon_ids_after(start_hash, max)?;
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            out.push(self.store.get_header(id)?);
+        }
+        Ok(out)
+    }
+
+    /// Như `get_headers_after` nhưng trả bytes canonical lấy thẳng từ store (không decode/re-encode).
+    pub fn get_raw_headers_after(&self, start_hash: Hash256, max: usize) -> Result<Vec<Vec<u8>>> {
+        let ids = self.canon_ids_after(start_hash, max)?;
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            out.push(self.store.get_header_bytes(id)?);
+        }
+        Ok(out)
+    }
+
+    /// Height của `id` nếu nằm trên canonical chain: đọc thẳng từ block meta,
+    /// chỉ quét canon index khi thiếu meta.
+    fn canon_height_of(&self, id: Hash256) -> Result<Option<u64>> {
+        if let Some(m) = self.store.get_block_meta(id)? {
+            if m.height.0 <= self.tip.height.0 && self.store.get_canon_hash(m.height)? == Some(id) {
+                return Ok(Some(m.height.0));
+            }
+            return Ok(None);
+        }
+        for h in 0..=self.tip.height.0 {
+            if self.store.get_canon_hash(Height(h))? == Some(id) {
+                return Ok(Some(h));
+            }
+        }
+        Ok(None)
+    }
+
+    fn canon_ids_after(&self, start_hash: Hash256, max: usize) -> Result<Vec<Hash256>> {
+        if max == 0 {
+            return Ok(vec![]);
+        }
+
+        let Some(sh) = self.canon_height_of(start_hash)? else {
+            return Ok(vec![]);
+        };
+
+        let mut out = Vec::new();
+        let mut cur_h = sh.saturating_add(1);
+        while cur_h <= self.tip.height.0 && out.len() < max {
+            let Some(hh) = self.store.get_canon_hash(Height(cur_h))? else { break; };
+            out.push(hh);
+            cur_h = cur_h.saturating_add(1);
+        }
+
+        Ok(out)
+    }
+
+    /// Đọc block + meta (height, parent) trong 1 lần gọi; `None` nếu chưa có block.
+    pub fn get_block_with_meta(&self, id: Hash256) -> Result<Option<(Block, BlockMeta)>> {
+        if !self.store.has_block(id)? {
+            return Ok(None);
+        }
+        let block = self.store.get_block(id)?;
+        let meta = self.must_block_meta(id)?;
+        Ok(Some((block, meta)))
+    }
+
+    /// Đếm số block canonical theo từng `pow_difficulty_bits` (kể cả genesis).
+    pub fn difficulty_histogram(&self) -> Result<BTreeMap<u32, u64>> {
+        let mut out = BTreeMap::new();
+        for h in 0..=self.tip.height.0 {
+            let Some(id) = self.store.get_canon_hash(Height(h))? else {
+                break;
+            };
+            let hdr = self.must_header(id)?;
+            *out.entry(hdr.pow_difficulty_bits).or_insert(0u64) += 1;
+        }
+        Ok(out)
+    }
+
+    /// Tổng work genesis -> tip có đạt `minimum` không.
+    pub fn total_work_meets(&self, minimum: u128) -> Result<bool> {
+        Ok(self.chain_work_of(self.tip.hash)? >= minimum)
+    }
+
+    /// true nếu header tip cũ hơn `max_age_secs` so với `now_utc` (chain có thể đang bị kẹt).
+    pub fn is_tip_stale(&self, now_utc: i64, max_age_secs: i64) -> Result<bool> {
+        let hdr = self.must_header(self.tip.hash)?;
+        Ok(now_utc.saturating_sub(hdr.timestamp_utc) > max_age_secs)
+    }
+
+    /// Commitment (merkle root) cho toàn bộ dãy hash canonical genesis -> tip.
+    /// 2 node cùng chain => cùng giá trị; khác nhau => so từng đoạn bằng `canonical_hashes_merkle_range`.
+    pub fn canonical_hashes_merkle(&self) -> Result<Hash256> {
+        self.canonical_hashes_merkle_range(Height(0), self.tip.height)
+    }
+
+    /// Merkle root của hash canonical trong [from, to] (cắt tại tip).
+    pub fn canonical_hashes_merkle_range(&self, from: Height, to: Height) -> Result<Hash256> {
+        let end = to.0.min(self.tip.height.0);
+        let mut ids = Vec::new();
+        let mut h = from.0;
+        while h <= end {
+            let Some(id) = self.store.get_canon_hash(Height(h))? else { break };
+            ids.push(id);
+            h = h.saturating_add(1);
+        }
+        Ok(merkle_root_txids(&ids))
+    }
+
+    /// Checkpoint (height, hash) canonical mỗi `interval` block tính từ genesis, luôn kèm tip
+    /// (`interval` = 0 => chỉ có tip). Dùng để nhúng vào ChainSpec/config sau này.
+    pub fn make_checkpoints(&self, interval: u64) -> Result<Vec<(Height, Hash256)>> {
+        let tip = self.tip.height.0;
+        let mut heights = Vec::new();
+        if interval > 0 {
+            heights.extend((0..=tip).step_by(usize::try_from(interval).unwrap_or(usize::MAX)));
+        }
+        if heights.last() != Some(&tip) {
+            heights.push(tip);
+        }
+
+        let mut out = Vec::with_capacity(heights.len());
+        for h in heights {
+            let height = Height(h);
+            let id = self
+                .store
+                .get_canon_hash(height)?
+                .ok_or(ChainStateError::MissingCanonHash { height })?;
+            out.push((height, id));
+        }
+        Ok(out)
+    }
+
+    /// Height cao nhất mà hash canonical của peer (`peer_canonical[h]` = hash tại height h)
+    /// trùng với local; `None` nếu lệch ngay từ genesis. Hash nối chuỗi nên phần trùng là
+    /// một đoạn đầu => tìm nhị phân.
+    pub fn latest_common_height(&self, peer_canonical: &[Hash256]) -> Result<Option<Height>> {
+        let n = (peer_canonical.len() as u64).min(self.tip.height.0.saturating_add(1));
+        // bất biến: [0, lo) khớp, [hi, n) lệch
+        let (mut lo, mut hi) = (0u64, n);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.store.get_canon_hash(Height(mid))? == Some(peer_canonical[mid as usize]) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(lo.checked_sub(1).map(Height))
+    }
+
+    /// So best chain với `other` (vd. 2 node trong integration test).
+    pub fn diff<T: ChainStore + Clone>(&self, other: &ChainState<T>) -> Result<ChainDiff> {
+        let n = self.tip.height.0.min(other.tip.height.0).saturating_add(1);
+        // như `latest_common_height`: phần trùng là 1 đoạn đầu, [0, lo) khớp
+        let (mut lo, mut hi) = (0u64, n);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.canon_hash(Height(mid))? == other.canon_hash(Height(mid))? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        if lo == n && self.tip == other.tip {
+            return Ok(ChainDiff::default());
+        }
+
+        let local_only = self.canon_range_ids(lo, self.tip.height.0)?;
+        let remote_only = other.canon_range_ids(lo, other.tip.height.0)?;
+        Ok(ChainDiff {
+            first_divergent_height: Some(Height(lo)),
+            local_only,
+            remote_only,
+        })
+    }
+
+    fn canon_range_ids(&self, from: u64, to: u64) -> Result<Vec<Hash256>> {
+        let mut out = Vec::new();
+        for h in from..=to {
+            let height = Height(h);
+            out.push(
+                self.store
+                    .get_canon_hash(height)?
+                    .ok_or(ChainStateError::MissingCanonHash { height })?,
+            );
+        }
+        Ok(out)
+    }
+
+    /// Tối đa `max` block canonical có height > `height`, theo thứ tự tăng dần.
+    pub fn blocks_since(&self, height: Height, max: usize) -> Result<Vec<Block>> {
+        let mut out = Vec::new();
+        let mut cur_h = height.0.saturating_add(1);
+        while cur_h <= self.tip.height.0 && out.len() < max {
+            let Some(id) = self.store.get_canon_hash(Height(cur_h))? else { break };
+            out.push(self.must_block(id)?);
+            cur_h = cur_h.saturating_add(1);
+        }
+        Ok(out)
+    }
+
+    /// Toàn bộ cây block đã biết (theo children index), đánh dấu node thuộc canon.
+    pub fn fork_tree(&self) -> Result<ForkTree> {
+        let root = self.meta.genesis_id;
+        let mut nodes = Vec::new();
+        let mut q = VecDeque::new();
+        q.push_back((root, None));
+        while let Some((id, parent)) = q.pop_front() {
+            let height = self.must_block_meta(id)?.height;
+            let children = self.store.get_children(id)?;
+            let is_canonical =
+                height.0 <= self.tip.height.0 && self.store.get_canon_hash(height)? == Some(id);
+            for c in &children {
+                q.push_back((*c, Some(id)));
+            }
+            nodes.push(ForkTreeNode {
+                id,
+                parent,
+                height,
+                children,
+                is_canonical,
+                has_block: self.store.has_block(id)?,
+            });
+        }
+        Ok(ForkTree { root, nodes })
+    }
+
+    /// Reorg sâu nhất (số block canonical bị thay) quan sát được từ khi mở state.
+    pub fn max_reorg_depth(&self) -> u64 {
+        self.max_reorg_depth
+    }
+
+    /// Tx đã đủ an toàn để coi là thanh toán xong chưa: depth = số block tính từ block chứa tx
+    /// tới tip (block chứa tx = 1). Đủ `min_confirmations` nhưng depth <= `max_reorg_depth`
+    /// thì chỉ là `Confirmed` (một reorg sâu như đã thấy vẫn có thể gỡ tx).
+    pub fn tx_confirmation_status(
+        &self,
+        txid: Hash256,
+        min_confirmations: u64,
+        mempool: &Mempool,
+    ) -> Result<ConfStatus> {
+        let Some((_, height)) = self.canonical_tx_location(txid)? else {
+            return Ok(if mempool.contains(txid) {
+                ConfStatus::InMempool
+            } else {
+                ConfStatus::Unknown
+            });
+        };
+        let depth = self.tip.height.0 - height.0 + 1;
+
+        Ok(if depth < min_confirmations {
+            ConfStatus::Unconfirmed { depth }
+        } else if depth <= self.max_reorg_depth {
+            ConfStatus::Confirmed { depth }
+        } else {
+            ConfStatus::DeeplyConfirmed
+        })
+    }
+
+    /// Bỏ khỏi mempool các tx đã được đào vào chain canonical (tra index txid -> block),
+    /// vd. mempool nạp lại sau restart. Trả số tx đã bỏ. Tx chỉ nằm ở nhánh phụ được giữ lại.
+    pub fn prune_mempool(&self, mempool: &mut Mempool) -> Result<usize> {
+        let mut removed = 0;
+        for txid in mempool.txids() {
+            if self.get_tx(txid)?.is_some() {
+                mempool.remove(txid);
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Giới hạn số orphan; vượt => evict orphan cũ nhất cùng nhánh con (chưa nối) của nó.
+    /// Giảm giới hạn không evict ngay, chỉ ở lần nhận orphan kế tiếp.
+    pub fn set_max_orphans(&mut self, max: usize) {
+        self.max_orphans = max;
+    }
+
+    /// Số header/block đang chờ parent (chỉ tính orphan nhận từ khi mở store).
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    fn note_orphan(&mut self, id: Hash256, parent: Hash256) -> Result<()> {
+        self.orphans.insert(id, parent);
+        while self.orphans.len() > self.max_orphans {
+            let Some((old, old_parent)) = self.orphans.pop_oldest() else {
+                break;
+            };
+            // parent đã có thì không còn là orphan, không xoá
+            if self.store.has_header(old_parent)? {
+                continue;
+            }
+            self.purge_orphan_subtree(old, old_parent)?;
+        }
+        Ok(())
+    }
+
+    /// Xoá orphan `root` và mọi hậu duệ của nó khỏi store (chưa nối nên không thuộc canon).
+    fn purge_orphan_subtree(&mut self, root: Hash256, parent: Hash256) -> Result<()> {
+        self.store.remove_child(parent, root)?;
+        let mut q = VecDeque::from([root]);
+        while let Some(id) = q.pop_front() {
+            for c in self.store.get_children(id)? {
+                self.store.remove_child(id, c)?;
+                q.push_back(c);
+            }
+            self.store.del_block(id)?;
+            self.store.del_block_meta(id)?;
+            self.store.del_header(id)?;
+        }
+        Ok(())
+    }
+
+    /// Strict: `recompute_best_chain` trả lỗi thay vì tự sửa khi tip incremental lệch.
+    pub fn set_strict_best_chain(&mut self, strict: bool) {
+        self.strict_best_chain = strict;
+    }
+
+    /// Tính lại best chain từ đầu (duyệt children từ genesis, chỉ block đã có body),
+    /// độc lập với tip cập nhật incremental; rồi ghi lại canon + tip cho khớp (1 batch).
+    /// Lệch: sửa và trả `Some(mismatch)` để caller log, hoặc lỗi `BestChainMismatch` nếu strict
+    /// (không ghi gì).
+    pub fn recompute_best_chain(&mut self) -> Result<Option<BestChainMismatch>> {
+        let mut best = ChainTip {
+            height: Height(0),
+            hash: self.meta.genesis_id,
+        };
+        let mut q = VecDeque::new();
+        q.push_back(self.meta.genesis_id);
+        while let Some(p) = q.pop_front() {
+            for c in self.store.get_children(p)? {
+                if !self.store.has_block(c)? {
+                    continue;
+                }
+                let m = self.must_block_meta(c)?;
+                let cand = ChainTip {
+                    height: m.height,
+                    hash: c,
+                };
+                if self.is_better_tip(cand, best)? && self.meets_min_chain_work(c)? {
+                    best = cand;
+                }
+                q.push_back(c);
+            }
+        }
+
+        let mismatch = (best != self.tip).then_some(BestChainMismatch {
+            incremental: self.tip,
+            recomputed: best,
+        });
+        if let (Some(m), true) = (mismatch, self.strict_best_chain) {
+            return Err(ChainStateError::BestChainMismatch {
+                incremental: m.incremental,
+                recomputed: m.recomputed,
+            });
+        }
+
+        self.atomically(|st| {
+            let mut cur = best.hash;
+            loop {
+                let m = st.must_block_meta(cur)?;
+                st.store.set_canon_hash(m.height, cur)?;
+                if m.height == Height(0) {
+                    break;
+                }
+                cur = m.parent;
+            }
+            // canon cũ phía trên best (liền mạch, xem reorg_canonical)
+            let mut h = best.height.0.saturating_add(1);
+            while st.store.get_canon_hash(Height(h))?.is_some() {
+                st.store.del_canon_hash(Height(h))?;
+                h = h.saturating_add(1);
+            }
+            st.store.set_tip(best)?;
+            st.tip = best;
+            Ok(())
+        })?;
+        Ok(mismatch)
+    }
+
+    /// Best chain đã phải sửa lúc mở store (reorg bị ngắt giữa chừng); node nên log.
+    pub fn best_chain_repaired_on_open(&self) -> Option<BestChainMismatch> {
+        self.repaired_on_open
+    }
+
+    /// Lùi tip về parent (admin "undo block cuối" / test reorg): xoá canon ở height cũ, giữ nguyên
+    /// header/body/meta/children nên nhận lại block đó sẽ nối lại. Trả id block bị tách.
+    /// `recompute_best_chain` vẫn coi block bị tách là ứng viên tip.
+    pub fn disconnect_tip(&mut self) -> Result<Hash256> {
+        let old = self.tip;
+        if old.height == Height(0) {
+            return Err(ChainStateError::DisconnectGenesis);
+        }
+        let m = self.must_block_meta(old.hash)?;
+        let new_tip = ChainTip {
+            height: Height(old.height.0 - 1),
+            hash: m.parent,
+        };
+        self.atomically(|st| {
+            st.store.del_canon_hash(old.height)?;
+            st.store.set_tip(new_tip)?;
+            st.tip = new_tip;
+            Ok(old.hash)
+        })
+    }
+
+    /// Height tổ tiên chung gần nhất của 2 tip (theo block meta).
+    fn common_ancestor_height(&self, old_tip: ChainTip, new_tip: ChainTip) -> Result<Height> {
+        let mut a = new_tip.hash;
+        let mut ha = new_tip.height.0;
+        let mut b = old_tip.hash;
+        let mut hb = old_tip.height.0;
+
+        while ha > hb {
+            let m = self.must_block_meta(a)?;
+            a = m.parent;
+            ha = ha.saturating_sub(1);
         }
         while hb > ha {
             let m = self.must_block_meta(b)?;
@@ -291,7 +1531,12 @@ impl<S: ChainStore + Clone> ChainState<S> {
             ha = ha.saturating_sub(1);
             hb = hb.saturating_sub(1);
         }
-        let ancestor_height = Height(ha);
+        Ok(Height(ha))
+    }
+
+    /// Ghi canon cho nhánh mới; trả số block canonical cũ bị thay (độ sâu reorg, 0 = chỉ nối dài).
+    fn reorg_canonical(&self, old_tip: ChainTip, new_tip: ChainTip) -> Result<u64> {
+        let ancestor_height = self.common_ancestor_height(old_tip, new_tip)?;
 
         let mut path: Vec<(Height, Hash256)> = Vec::new();
         let mut cur = new_tip.hash;
@@ -303,41 +1548,142 @@ impl<S: ChainStore + Clone> ChainState<S> {
             path.push((m.height, cur));
             cur = m.parent;
         }
-        path.reverse();
 
-        for (h, x) in path {
+        // Thao tác đầu tiên luôn chạm canon ngay tại/trên tip cũ (để reorg dở dang bị phát hiện
+        // khi mở lại), và phần canon phía trên tip luôn liền mạch.
+        let (ho, hn) = (old_tip.height.0, new_tip.height.0);
+        let order: Vec<(Height, Hash256)> = if hn > ho {
+            // nhánh mới cao hơn: ho+1 đi lên tới tip mới, rồi phần <= ho đi xuống
+            let above = path.iter().rev().filter(|(h, _)| h.0 > ho);
+            let below = path.iter().filter(|(h, _)| h.0 <= ho);
+            above.chain(below).copied().collect()
+        } else {
+            // nhánh mới (nhiều work hơn) thấp hơn hoặc bằng: xoá canon phía trên tip mới từ trên xuống
+            for h in (hn.saturating_add(1)..=ho).rev() {
+                self.store.del_canon_hash(Height(h))?;
+            }
+            path
+        };
+        for (h, x) in order {
             self.store.set_canon_hash(h, x)?;
+            // block mới vào canon ghi đè vị trí tx (tx cũng có thể nằm ở block nhánh phụ)
+            if self.store.has_block(x)? {
+                self.index_block_txs(x, &self.store.get_block(x)?)?;
+            }
         }
 
+        Ok(ho.saturating_sub(ancestor_height.0))
+    }
+
+    fn index_block_txs(&self, id: Hash256, block: &Block) -> Result<()> {
+        for (i, tx) in block.txs.iter().enumerate() {
+            self.store.put_tx_location(
+                tx.id,
+                TxLocation {
+                    block_id: id,
+                    index: i as u32,
+                },
+            )?;
+        }
         Ok(())
     }
 
-    fn maybe_set_tip(&mut self, candidate_hash: Hash256, candidate_height: Height) -> Result<bool> {
-        let better = if candidate_height.0 > self.tip.height.0 {
-            true
-        } else if candidate_height.0 == self.tip.height.0 {
-            Self::hash_lt(candidate_hash, self.tip.hash)
-        } else {
-            false
+    /// Vị trí + height của tx nếu block chứa nó (theo index) thuộc canon và có body.
+    fn canonical_tx_location(&self, txid: Hash256) -> Result<Option<(TxLocation, Height)>> {
+        let Some(loc) = self.store.get_tx_location(txid)? else {
+            return Ok(None);
         };
-
-        if !better {
-            return Ok(false);
+        let Some(meta) = self.store.get_block_meta(loc.block_id)? else {
+            return Ok(None);
+        };
+        if meta.height.0 > self.tip.height.0
+            || self.store.get_canon_hash(meta.height)? != Some(loc.block_id)
+            || !self.store.has_block(loc.block_id)?
+        {
+            return Ok(None);
         }
+        Ok(Some((loc, meta.height)))
+    }
 
+    /// (block id, vị trí trong block) của tx trên best chain.
+    /// Index chỉ ghi khi block vào canon (kể cả lúc reorg), nên sau reorg nó vẫn trỏ block canon
+    /// cuối cùng từng chứa tx; block đó rời best chain => `None` cho tới khi tx được đào lại.
+    pub fn find_tx(&self, txid: Hash256) -> Result<Option<(Hash256, usize)>> {
+        Ok(self
+            .canonical_tx_location(txid)?
+            .map(|(loc, _)| (loc.block_id, loc.index as usize)))
+    }
+
+    /// Tx đã được đào vào 1 block canonical (tra qua index txid -> block).
+    /// Tx chỉ mới nằm trong mempool (chưa đào) hoặc chỉ có ở nhánh phụ => `None`;
+    /// muốn tìm tx chờ đào thì hỏi `Mempool::get`.
+    pub fn get_tx(&self, txid: Hash256) -> Result<Option<Transaction>> {
+        let Some((loc, _)) = self.canonical_tx_location(txid)? else {
+            return Ok(None);
+        };
+        let block = self.store.get_block(loc.block_id)?;
+        Ok(block
+            .txs
+            .into_iter()
+            .nth(loc.index as usize)
+            .filter(|tx| tx.id == txid))
+    }
+
+    /// Facade đọc cho explorer/UI (block theo height/hash, tx, trang header, search).
+    pub fn explorer(&self) -> Explorer<'_, S> {
+        Explorer::new(self)
+    }
+
+    fn maybe_set_tip(&mut self, candidate_hash: Hash256, candidate_height: Height) -> Result<bool> {
         let old = self.tip;
         let new_tip = ChainTip {
             height: candidate_height,
             hash: candidate_hash,
         };
+        if !self.is_better_tip(new_tip, old)? {
+            return Ok(false);
+        }
+        if !self.has_bodies_to_canon(candidate_hash)? {
+            return Ok(false);
+        }
+        if !self.meets_min_chain_work(candidate_hash)? {
+            return Ok(false);
+        }
+        if let Some(max) = self.rules.max_reorg_depth {
+            let ancestor = self.common_ancestor_height(old, new_tip)?;
+            let depth = old.height.0.saturating_sub(ancestor.0);
+            if depth > max {
+                return Err(ChainStateError::ReorgTooDeep { depth, max });
+            }
+        }
 
+        // canon trước, tip sau: crash ở giữa được phát hiện + sửa khi mở lại (canon_consistent_with_tip)
+        let depth = self.reorg_canonical(old, new_tip)?;
         self.store.set_tip(new_tip)?;
         self.tip = new_tip;
-
-        self.reorg_canonical(old, new_tip)?;
+        self.max_reorg_depth = self.max_reorg_depth.max(depth);
         Ok(true)
     }
 
+    /// `true` nếu mọi block từ `id` lùi về canon đều đã có body (như `recompute_best_chain`):
+    /// body tổ tiên còn thiếu (vd. body giả bị bỏ, chờ tải lại) thì chưa được làm tip.
+    fn has_bodies_to_canon(&self, id: Hash256) -> Result<bool> {
+        let mut cur = id;
+        loop {
+            if !self.store.has_block(cur)? {
+                return Ok(false);
+            }
+            let m = self.must_block_meta(cur)?;
+            if self.store.get_canon_hash(m.height)? == Some(cur) {
+                return Ok(true);
+            }
+            if m.height == Height(0) {
+                return Ok(false);
+            }
+            cur = m.parent;
+        }
+    }
+
     fn try_connect_child(&mut self, parent: Hash256, child: Hash256) -> Result<bool> {
         if !self.store.has_header(child)? || !self.store.has_block(child)? {
             return Ok(false);
@@ -352,7 +1698,7 @@ impl<S: ChainStore + Clone> ChainState<S> {
         let parent_meta = self.ensure_block_meta_from_header(parent, &parent_hdr)?;
         let child_meta = self.ensure_block_meta_from_header(child, &child_hdr)?;
 
-        let expect_h = Height(parent_meta.height.0.saturating_add(1));
+        let expect_h = child_height(parent_meta.height)?;
         if child_meta.height != expect_h || child_hdr.height != expect_h {
             return Err(ChainStateError::HeightNotParentPlusOne {
                 parent_height: parent_meta.height,
@@ -380,54 +1726,244 @@ impl<S: ChainStore + Clone> ChainState<S> {
         Ok(())
     }
 
-    pub fn ingest_block(&mut self, block: Block) -> Result<(Hash256, IngestOutcome)> {
-        crate::block_builder::verify_block_merkle(&block)?;
+    /// Như `ingest_block`, kèm report tx nào đã có trong `mempool` trước khi nhận block.
+    pub fn ingest_block_with_report(
+        &mut self,
+        block: Block,
+        mempool: &Mempool,
+    ) -> Result<(Hash256, IngestOutcome, IngestBlockReport)> {
+        let txs_total = block.txs.len();
+        let txs_from_mempool = block.txs.iter().filter(|tx| mempool.contains(tx.id)).count();
+        let report = IngestBlockReport {
+            txs_total,
+            txs_from_mempool,
+            txs_unknown: txs_total - txs_from_mempool,
+        };
+        let (id, outcome) = self.ingest_block(block)?;
+        Ok((id, outcome, report))
+    }
 
-        if !pow_valid(&block.header) {
-            return Err(ChainStateError::InvalidPow);
+    /// Như `ingest_block`; nếu block nằm trên best chain thì bỏ tx của nó khỏi `mempool` và ghi
+    /// latency `lúc thấy block - lúc tx vào mempool` (xem `mempool_inclusion_stats`).
+    pub fn ingest_block_and_update_mempool(
+        &mut self,
+        block: Block,
+        mempool: &mut Mempool,
+    ) -> Result<(Hash256, IngestOutcome)> {
+        let first_seen = self.now_utc();
+        let txids: Vec<Hash256> = block.txs.iter().map(|tx| tx.id).collect();
+        let (id, outcome) = self.ingest_block(block)?;
+        if !self.is_on_best_chain(id)? {
+            return Ok((id, outcome));
+        }
+        for txid in txids {
+            let Some(inserted) = mempool.inserted_at(txid) else {
+                continue;
+            };
+            mempool.remove(txid);
+            if self.inclusion_latency.len() >= INCLUSION_LATENCY_WINDOW {
+                self.inclusion_latency.pop_front();
+            }
+            self.inclusion_latency
+                .push_back(u64::try_from(first_seen.saturating_sub(inserted)).unwrap_or(0));
         }
+        Ok((id, outcome))
+    }
 
-        let id = header_id(&block.header);
+    pub fn mempool_inclusion_stats(&self) -> InclusionStats {
+        let samples = &self.inclusion_latency;
+        if samples.is_empty() {
+            return InclusionStats::default();
+        }
+        let mut buckets: Vec<(u64, usize)> = INCLUSION_LATENCY_BUCKETS.iter().map(|&b| (b, 0)).collect();
+        for &s in samples {
+            if let Some(b) = buckets.iter_mut().find(|(upper, _)| s <= *upper) {
+                b.1 += 1;
+            }
+        }
+        let sum: u128 = samples.iter().map(|&s| u128::from(s)).sum();
+        InclusionStats {
+            count: samples.len(),
+            min_secs: samples.iter().copied().min().unwrap_or(0),
+            max_secs: samples.iter().copied().max().unwrap_or(0),
+            mean_secs: (sum / samples.len() as u128) as u64,
+            buckets,
+        }
+    }
 
-        if block.header.height == Height(0) {
-            if id != self.meta.genesis_id {
-                return Err(ChainStateError::GenesisIdMismatch {
-                    expected: self.meta.genesis_id,
-                    got: id,
+    /// Nối dãy block liên tiếp lên tip hiện tại (setup test / đoạn đã kiểm từ checkpoint).
+    /// Kiểm liên kết toàn dãy trước khi ghi (`BrokenHeaderLink`, index 0 = so với tip); block lỗi
+    /// consensus giữa chừng => các block trước nó vẫn đã được nối. Trả height tip mới.
+    pub fn fast_forward(&mut self, blocks: Vec<Block>) -> Result<Height> {
+        let mut expected = self.tip.hash;
+        for (index, b) in blocks.iter().enumerate() {
+            if b.header.parent != expected {
+                return Err(ChainStateError::BrokenHeaderLink {
+                    index,
+                    expected,
+                    got: b.header.parent,
                 });
             }
-            // genesis đã có khi open_or_init; nếu ai đó gửi lại genesis block thì coi như known
-            return Ok((id, IngestOutcome::AlreadyKnown));
+            expected = header_id(&b.header);
         }
 
-        // CASE: header đã có từ headers-first, nhưng block chưa có -> phải cho phép put_block + connect.
-        if self.store.has_header(id)? {
-            if self.store.has_block(id)? {
-                return Ok((id, IngestOutcome::AlreadyKnown));
+        for (index, b) in blocks.into_iter().enumerate() {
+            let parent = b.header.parent;
+            let (id, _) = self.ingest_block(b)?;
+            if self.tip.hash != id {
+                return Err(ChainStateError::BrokenHeaderLink {
+                    index,
+                    expected: self.tip.hash,
+                    got: parent,
+                });
             }
+        }
+        Ok(self.tip.height)
+    }
 
-            let stored_hdr = self.store.get_header(id)?;
-            if stored_hdr != block.header {
-                return Err(ChainStateError::HeaderMismatch { id });
+    /// Nhận nguyên 1 nhánh đã kiểm từ checkpoint: `headers` nối từ 1 ancestor đã biết tới
+    /// `expected_tip`, `blocks[i]` là body của `headers[i]`. Tất cả hoặc không: nhánh lỗi ở bất kỳ
+    /// block nào, hoặc nạp xong mà tip không thành `expected_tip`, thì store/tip giữ nguyên.
+    pub fn adopt_chain(
+        &mut self,
+        headers: Vec<BlockHeader>,
+        blocks: Vec<Block>,
+        expected_tip: Hash256,
+    ) -> Result<()> {
+        self.verify_header_sequence(&headers, expected_tip)?;
+        if let Some(h) = headers.get(blocks.len()) {
+            return Err(ChainStateError::MissingBlock { id: header_id(h) });
+        }
+        for (h, b) in headers.iter().zip(&blocks) {
+            if b.header != *h {
+                return Err(ChainStateError::HeaderMismatch { id: header_id(h) });
             }
-
+        }
+        // block thừa sau `expected_tip`
+        if let Some(b) = blocks.last().filter(|_| blocks.len() > headers.len()) {
+            return Err(ChainStateError::HeaderTipMismatch {
+                expected: expected_tip,
+                got: header_id(&b.header),
+            });
+        }
+
+        self.atomically(|st| {
+            for b in blocks {
+                st.ingest_block_inner(b)?;
+            }
+            if st.tip.hash != expected_tip {
+                return Err(ChainStateError::HeaderTipMismatch {
+                    expected: expected_tip,
+                    got: st.tip.hash,
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// Chạy `f` trong 1 batch của store: thành công => commit 1 lần (nguyên tử);
+    /// lỗi => bỏ mọi write đã gom và khôi phục trạng thái RAM (tip, orphan pool, block invalid,
+    /// latency), không để lại block ghi dở.
+    fn atomically<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let (tip, max_reorg_depth) = (self.tip, self.max_reorg_depth);
+        let orphans = self.orphans.clone();
+        let invalid_blocks = self.invalid_blocks.clone();
+        let inclusion_latency = self.inclusion_latency.clone();
+        self.store.begin_batch()?;
+        let res = f(self).and_then(|v| {
+            self.store.commit_batch()?;
+            Ok(v)
+        });
+        if res.is_err() {
+            self.store.abort_batch();
+            self.tip = tip;
+            self.max_reorg_depth = max_reorg_depth;
+            self.orphans = orphans;
+            self.invalid_blocks = invalid_blocks;
+            self.inclusion_latency = inclusion_latency;
+        }
+        res
+    }
+
+    pub fn ingest_block(&mut self, block: Block) -> Result<(Hash256, IngestOutcome)> {
+        self.atomically(|st| st.ingest_block_inner(block))
+    }
+
+    fn ingest_block_inner(&mut self, block: Block) -> Result<(Hash256, IngestOutcome)> {
+        let id = header_id(&block.header);
+        self.check_not_invalid(id, block.header.parent)?;
+
+        let assumed_valid = self.is_assumed_valid(id, block.header.height)?;
+        if assumed_valid {
+            crate::block_builder::validate_block_limits(&block, &self.rules.limits)?;
+        } else {
+            if !pow_valid(&block.header) {
+                return Err(ChainStateError::InvalidPow);
+            }
+            crate::block_builder::validate_block_standalone(&block, &self.rules.limits)?;
+            self.note_full_verify(id);
+        }
+
+        if block.header.height == Height(0) {
+            if id != self.meta.genesis_id {
+                return Err(ChainStateError::GenesisIdMismatch {
+                    expected: self.meta.genesis_id,
+                    got: id,
+                });
+            }
+            // genesis đã có khi open_or_init; nếu ai đó gửi lại genesis block thì coi như known
+            return Ok((id, IngestOutcome::AlreadyKnown));
+        }
+
+        self.check_header_rules(&block.header)?;
+        self.check_child_timestamp(&block.header)?;
+        self.check_child_difficulty(&block.header)?;
+        self.check_coinbase(id, &block)?;
+
+        // CASE: header đã có từ headers-first, nhưng block chưa có -> phải cho phép put_block + connect.
+        if self.store.has_header(id)? {
+            if self.store.has_block(id)? {
+                // block đã tách khỏi tip (`disconnect_tip`) được nối lại khi nhận lại
+                if self.store.get_block_meta(block.header.parent)?.is_some()
+                    && self.maybe_set_tip(id, block.header.height)?
+                {
+                    return Ok((id, IngestOutcome::NewTip));
+                }
+                return Ok((id, IngestOutcome::AlreadyKnown));
+            }
+
+            let stored_hdr = self.store.get_header(id)?;
+            if stored_hdr != block.header {
+                return Err(ChainStateError::HeaderMismatch { id });
+            }
+            // header nhận trước body chỉ cam kết merkle root: body phải khớp root đó kể cả dưới
+            // assume-valid, nếu không peer gắn tx tuỳ ý vào header hợp lệ
+            if assumed_valid {
+                crate::block_builder::verify_block_merkle(&block)?;
+            }
+
             self.store.put_block(id, &block)?;
             self.ensure_block_meta_from_header(id, &block.header)?;
+            // header đã canonical từ trước (headers-first): reorg sẽ không đi qua block này nữa
+            if self.store.get_canon_hash(block.header.height)? == Some(id) {
+                self.index_block_txs(id, &block)?;
+            }
 
             // đảm bảo parent->children index
             let p = block.header.parent;
             let existing_children = self.store.get_children(p)?;
-            if !existing_children.iter().any(|x| *x == id) {
+            if !existing_children.contains(&id) {
                 self.store.add_child(p, id)?;
             }
 
             if !self.store.has_header(p)? {
+                self.note_orphan(id, p)?;
                 return Ok((id, IngestOutcome::StoredOrphan));
             }
 
             let parent_hdr = self.store.get_header(p)?;
             let parent_meta = self.ensure_block_meta_from_header(p, &parent_hdr)?;
-            let expect_h = Height(parent_meta.height.0.saturating_add(1));
+            let expect_h = child_height(parent_meta.height)?;
             if block.header.height != expect_h {
                 return Err(ChainStateError::HeightNotParentPlusOne {
                     parent_height: parent_meta.height,
@@ -449,22 +1985,18 @@ impl<S: ChainStore + Clone> ChainState<S> {
         // CASE: header chưa có
         self.store.put_header(id, &block.header)?;
         self.store.put_block(id, &block)?;
-        self.store.put_block_meta(
-            id,
-            BlockMeta {
-                parent: block.header.parent,
-                height: block.header.height,
-            },
-        )?;
+        self.store.put_block_meta(id, self.new_block_meta(&block.header)?)?;
         self.store.add_child(block.header.parent, id)?;
+        self.orphans.take_children_of(id);
 
         if !self.store.has_header(block.header.parent)? {
+            self.note_orphan(id, block.header.parent)?;
             return Ok((id, IngestOutcome::StoredOrphan));
         }
 
         let parent_hdr = self.store.get_header(block.header.parent)?;
         let parent_meta = self.ensure_block_meta_from_header(block.header.parent, &parent_hdr)?;
-        let expect_h = Height(parent_meta.height.0.saturating_add(1));
+        let expect_h = child_height(parent_meta.height)?;
         if block.header.height != expect_h {
             return Err(ChainStateError::HeightNotParentPlusOne {
                 parent_height: parent_meta.height,
@@ -484,11 +2016,16 @@ impl<S: ChainStore + Clone> ChainState<S> {
     }
 
     pub fn ingest_header(&mut self, header: BlockHeader) -> Result<(Hash256, HeaderIngestOutcome)> {
+        self.atomically(|st| st.ingest_header_inner(header))
+    }
+
+    fn ingest_header_inner(&mut self, header: BlockHeader) -> Result<(Hash256, HeaderIngestOutcome)> {
         if !pow_valid(&header) {
             return Err(ChainStateError::InvalidPow);
         }
 
         let id = header_id(&header);
+        self.check_not_invalid(id, header.parent)?;
 
         if header.height == Height(0) {
             if id != self.meta.genesis_id {
@@ -504,23 +2041,23 @@ impl<S: ChainStore + Clone> ChainState<S> {
             return Ok((id, HeaderIngestOutcome::AlreadyKnown));
         }
 
+        self.check_header_rules(&header)?;
+        self.check_child_timestamp(&header)?;
+        self.check_child_difficulty(&header)?;
+
         self.store.put_header(id, &header)?;
-        self.store.put_block_meta(
-            id,
-            BlockMeta {
-                parent: header.parent,
-                height: header.height,
-            },
-        )?;
+        self.store.put_block_meta(id, self.new_block_meta(&header)?)?;
         self.store.add_child(header.parent, id)?;
+        self.orphans.take_children_of(id);
 
         if !self.store.has_header(header.parent)? {
+            self.note_orphan(id, header.parent)?;
             return Ok((id, HeaderIngestOutcome::StoredOrphan));
         }
 
         let ph = self.store.get_header(header.parent)?;
         let pm = self.ensure_block_meta_from_header(header.parent, &ph)?;
-        let expect_h = Height(pm.height.0.saturating_add(1));
+        let expect_h = child_height(pm.height)?;
         if header.height != expect_h {
             return Err(ChainStateError::HeightNotParentPlusOne {
                 parent_height: pm.height,
@@ -531,12 +2068,79 @@ impl<S: ChainStore + Clone> ChainState<S> {
         Ok((id, HeaderIngestOutcome::StoredConnected))
     }
 
+    /// Kiểm tra 1 chuỗi header nhận từ nguồn không tin cậy (checkpoint/light-sync) trước khi ingest:
+    /// - mỗi header có PoW hợp lệ
+    /// - header đầu nối vào 1 header đã biết, height = parent+1
+    /// - parent của mỗi header = id header trước, height liên tục
+    /// - header cuối hash ra `expected_tip`
+    ///
+    /// Không ghi gì vào store.
+    pub fn verify_header_sequence(&self, headers: &[BlockHeader], expected_tip: Hash256) -> Result<()> {
+        let Some(first) = headers.first() else {
+            return Err(ChainStateError::EmptyHeaderSequence);
+        };
+
+        if !self.store.has_header(first.parent)? {
+            return Err(ChainStateError::UnknownParent {
+                parent: first.parent,
+            });
+        }
+        let ph = self.store.get_header(first.parent)?;
+        let mut prev_id = first.parent;
+        let mut prev_height = ph.height;
+
+        for (i, h) in headers.iter().enumerate() {
+            if !pow_valid(h) {
+                return Err(ChainStateError::InvalidPow);
+            }
+            if h.parent != prev_id {
+                return Err(ChainStateError::BrokenHeaderLink {
+                    index: i,
+                    expected: prev_id,
+                    got: h.parent,
+                });
+            }
+            let expect_h = child_height(prev_height)?;
+            if h.height != expect_h {
+                return Err(ChainStateError::HeightNotParentPlusOne {
+                    parent_height: prev_height,
+                    child_height: h.height,
+                });
+            }
+            prev_id = header_id(h);
+            prev_height = h.height;
+        }
+
+        if prev_id != expected_tip {
+            return Err(ChainStateError::HeaderTipMismatch {
+                expected: expected_tip,
+                got: prev_id,
+            });
+        }
+        Ok(())
+    }
+
     pub fn validate_best_chain(&self) -> Result<()> {
+        self.validate_best_chain_with(|_| {}, &AtomicBool::new(false))
+    }
+
+    /// Như `validate_best_chain`, gọi `progress(height)` sau mỗi block đã kiểm (tip -> genesis)
+    /// và dừng với `Cancelled` ngay khi `cancel` được bật (vd. node tắt giữa lúc validate khi khởi động).
+    pub fn validate_best_chain_with(
+        &self,
+        mut progress: impl FnMut(Height),
+        cancel: &AtomicBool,
+    ) -> Result<()> {
         let mut cur = self.tip.hash;
+        // đi từ tip xuống; gặp assume-valid thì mọi block còn lại là tổ tiên của nó
+        let mut below_assume_valid = false;
 
         loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(ChainStateError::Cancelled);
+            }
             let hdr = self.must_header(cur)?;
-            let _blk = self.must_block(cur)?;
+            let blk = self.must_block(cur)?;
             let meta = self
                 .store
                 .get_block_meta(cur)?
@@ -546,12 +2150,18 @@ impl<S: ChainStore + Clone> ChainState<S> {
                 return Err(ChainStateError::MissingBlockMeta { id: cur });
             }
 
-            if !pow_valid(&hdr) {
-                return Err(ChainStateError::InvalidPow);
+            if Some(cur) == self.assume_valid {
+                below_assume_valid = true;
             }
 
-            let blk = self.store.get_block(cur)?;
-            crate::block_builder::verify_block_merkle(&blk)?;
+            if !below_assume_valid {
+                if !pow_valid(&hdr) {
+                    return Err(ChainStateError::InvalidPow);
+                }
+
+                crate::block_builder::verify_block_merkle(&blk)?;
+                self.note_full_verify(cur);
+            }
 
             if hdr.height == Height(0) {
                 if cur != self.meta.genesis_id {
@@ -560,13 +2170,14 @@ impl<S: ChainStore + Clone> ChainState<S> {
                         got: cur,
                     });
                 }
+                progress(hdr.height);
                 break;
             }
 
             let p = hdr.parent;
             let ph = self.must_header(p)?;
             let pm = self.ensure_block_meta_from_header(p, &ph)?;
-            let expect_h = Height(pm.height.0.saturating_add(1));
+            let expect_h = child_height(pm.height)?;
             if hdr.height != expect_h {
                 return Err(ChainStateError::HeightNotParentPlusOne {
                     parent_height: pm.height,
@@ -574,12 +2185,55 @@ impl<S: ChainStore + Clone> ChainState<S> {
                 });
             }
 
+            progress(hdr.height);
             cur = p;
         }
 
         Ok(())
     }
 
+    /// Headers-first sync không cần socket:
+    /// 1) kéo header từ `provider` bắt đầu từ tip hiện tại, ingest từng header
+    /// 2) backfill block body còn thiếu qua `fetch_block`, ingest theo thứ tự header
+    /// 3) validate best chain
+    pub fn sync_from_provider(
+        &mut self,
+        provider: &impl HeaderProvider,
+        fetch_block: impl Fn(Hash256) -> Option<Block>,
+    ) -> Result<()> {
+        // ---- Phase 1: headers ----
+        let mut header_ids: Vec<Hash256> = Vec::new();
+        let mut start = self.tip.hash;
+        loop {
+            let headers = provider.get_headers_after(start, SYNC_HEADERS_BATCH);
+            let Some(last) = headers.last() else { break };
+            let last_id = header_id(last);
+            if last_id == start {
+                break;
+            }
+
+            for h in headers {
+                let (id, _out) = self.ingest_header(h)?;
+                header_ids.push(id);
+            }
+            start = last_id;
+        }
+
+        // ---- Phase 2: backfill blocks ----
+        for id in header_ids {
+            if self.store.has_block(id)? {
+                continue;
+            }
+            let block = fetch_block(id).ok_or(ChainStateError::MissingBlock { id })?;
+            if header_id(&block.header) != id {
+                return Err(ChainStateError::HeaderMismatch { id });
+            }
+            self.ingest_block(block)?;
+        }
+
+        self.validate_best_chain()
+    }
+
     pub fn mine_and_append_one(
         &mut self,
         mempool: &mut crate::mempool::Mempool,
@@ -587,14 +2241,15 @@ impl<S: ChainStore + Clone> ChainState<S> {
         pow_difficulty_bits: u32,
     ) -> Result<Hash256> {
         let parent = self.tip.hash;
-        let height = Height(self.tip.height.0.saturating_add(1));
+        let height = child_height(self.tip.height)?;
 
-        let mined = crate::miner::mine_block_from_mempool(
+        let mined = crate::miner::mine_block_from_mempool_with_limits(
             mempool,
             parent,
             height,
             timestamp_utc,
             pow_difficulty_bits,
+            &self.rules.limits,
         )?;
 
         let (id, _out) = self.ingest_block(mined)?;
@@ -602,6 +2257,44 @@ impl<S: ChainStore + Clone> ChainState<S> {
     }
 }
 
+/// `ChainState` dùng chung giữa nhiều luồng: mọi ingest đi qua 1 mutex nên tip luôn nhất quán.
+pub struct SharedChainState<S: ChainStore + Clone> {
+    inner: Arc<Mutex<ChainState<S>>>,
+}
+
+impl<S: ChainStore + Clone> Clone for SharedChainState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S: ChainStore + Clone> SharedChainState<S> {
+    pub fn new(state: ChainState<S>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Khoá state (poisoned lock vẫn dùng tiếp: store là nguồn sự thật).
+    pub fn lock(&self) -> MutexGuard<'_, ChainState<S>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn tip(&self) -> ChainTip {
+        self.lock().tip
+    }
+
+    pub fn ingest_block(&self, block: Block) -> Result<(Hash256, IngestOutcome)> {
+        self.lock().ingest_block(block)
+    }
+
+    pub fn ingest_header(&self, header: BlockHeader) -> Result<(Hash256, HeaderIngestOutcome)> {
+        self.lock().ingest_header(header)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -611,6 +2304,8 @@ mod tests {
     use egg_db::MemKv;
     use egg_types::{ChainParams, GenesisSpec};
 
+    use crate::{RetargetMode, MAX_FUTURE_DRIFT_SECS, MEDIAN_TIME_SPAN};
+
     fn mk_spec(ts: i64) -> ChainSpec {
         ChainSpec {
             spec_version: 1,
@@ -658,7 +2353,9 @@ mod tests {
         let _ = st.ingest_block(b1).unwrap();
         let _ = st.ingest_block(b2).unwrap();
 
-        let expected = if id1.0 < id2.0 { id1 } else { id2 };
+        // Ord của Hash256 = so byte thô (big-endian)
+        assert_eq!(id1 < id2, id1.0 < id2.0);
+        let expected = id1.min(id2);
         assert_eq!(st.tip.height, Height(1));
         assert_eq!(st.tip.hash, expected);
 
@@ -715,6 +2412,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::manual_contains)]
     fn ingest_header_stores_orphan_then_parent_connects() {
         let kv = MemKv::new();
         let store = DbChainStore::new(kv);
@@ -819,4 +2517,2084 @@ mod tests {
         assert_eq!(st.tip.height, Height(1));
         assert_eq!(st.tip.hash, h1id);
     }
+
+    fn mk_header_seq(parent: Hash256, from_height: u64, n: u64) -> Vec<BlockHeader> {
+        let mut out = Vec::new();
+        let mut p = parent;
+        for i in 0..n {
+            let h = BlockHeader {
+                parent: p,
+                height: Height(from_height + i),
+                timestamp_utc: 1_700_000_000,
+                nonce: 500 + i,
+                merkle_root: Hash256::zero(),
+                pow_difficulty_bits: 0,
+            };
+            p = header_id(&h);
+            out.push(h);
+        }
+        out
+    }
+
+    #[test]
+    fn verify_header_sequence_accepts_valid_chain() {
+        let store = DbChainStore::new(MemKv::new());
+        let st = ChainState::open_or_init(store.clone(), mk_spec(1_700_000_000)).unwrap();
+
+        let hs = mk_header_seq(st.tip.hash, 1, 5);
+        let tip = header_id(hs.last().unwrap());
+        st.verify_header_sequence(&hs, tip).unwrap();
+
+        // không ghi gì vào store
+        assert!(!store.has_header(tip).unwrap());
+    }
+
+    #[test]
+    fn verify_header_sequence_rejects_broken_link() {
+        let store = DbChainStore::new(MemKv::new());
+        let st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+
+        let mut hs = mk_header_seq(st.tip.hash, 1, 4);
+        hs[2].parent = Hash256([7u8; 32]);
+        let tip = header_id(hs.last().unwrap());
+
+        let err = st.verify_header_sequence(&hs, tip).unwrap_err();
+        assert!(matches!(err, ChainStateError::BrokenHeaderLink { index: 2, .. }));
+
+        let orphan = mk_header_seq(Hash256([7u8; 32]), 1, 2);
+        let otip = header_id(orphan.last().unwrap());
+        let err = st.verify_header_sequence(&orphan, otip).unwrap_err();
+        assert!(matches!(err, ChainStateError::UnknownParent { .. }));
+    }
+
+    #[test]
+    fn verify_header_sequence_rejects_wrong_expected_tip() {
+        let store = DbChainStore::new(MemKv::new());
+        let st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+
+        let hs = mk_header_seq(st.tip.hash, 1, 3);
+        let err = st.verify_header_sequence(&hs, Hash256([1u8; 32])).unwrap_err();
+        assert!(matches!(err, ChainStateError::HeaderTipMismatch { .. }));
+
+        let err = st.verify_header_sequence(&[], Hash256([1u8; 32])).unwrap_err();
+        assert!(matches!(err, ChainStateError::EmptyHeaderSequence));
+    }
+
+    struct StateProvider<'a> {
+        st: &'a ChainState<DbChainStore<MemKv>>,
+    }
+
+    impl HeaderProvider for StateProvider<'_> {
+        fn get_headers_after(&self, start: Hash256, max: usize) -> Vec<BlockHeader> {
+            self.st.get_headers_after(start, max).unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn sync_from_provider_reaches_source_tip() {
+        let spec = mk_spec(1_700_000_000);
+
+        let src_store = DbChainStore::new(MemKv::new());
+        let mut src = ChainState::open_or_init(src_store.clone(), spec.clone()).unwrap();
+        for i in 1..=12u64 {
+            let b = mk_empty_block(src.tip.hash, Height(i), 300 + i);
+            src.ingest_block(b).unwrap();
+        }
+
+        let dst_store = DbChainStore::new(MemKv::new());
+        let mut dst = ChainState::open_or_init(dst_store.clone(), spec).unwrap();
+
+        let provider = StateProvider { st: &src };
+        dst.sync_from_provider(&provider, |id| src_store.get_block(id).ok())
+            .unwrap();
+
+        assert_eq!(dst.tip, src.tip);
+        for h in 0..=12u64 {
+            assert_eq!(
+                dst.canon_hash(Height(h)).unwrap(),
+                src.canon_hash(Height(h)).unwrap()
+            );
+        }
+
+        let err = {
+            let fresh_store = DbChainStore::new(MemKv::new());
+            let mut fresh = ChainState::open_or_init(fresh_store, mk_spec(1_700_000_000)).unwrap();
+            fresh.sync_from_provider(&provider, |_| None).unwrap_err()
+        };
+        assert!(matches!(err, ChainStateError::MissingBlock { .. }));
+    }
+
+    #[test]
+    fn difficulty_histogram_counts_canonical_blocks() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+
+        let bits = [0u32, 1, 1, 2, 0, 2, 2];
+        for (i, b) in bits.iter().enumerate() {
+            let mut blk = mk_empty_block(st.tip.hash, Height(i as u64 + 1), 0);
+            blk.header.pow_difficulty_bits = *b;
+            let blk = crate::miner::mine_block(blk).unwrap();
+            st.ingest_block(blk).unwrap();
+        }
+
+        let hist = st.difficulty_histogram().unwrap();
+        // genesis có difficulty 0
+        assert_eq!(hist.get(&0), Some(&3));
+        assert_eq!(hist.get(&1), Some(&2));
+        assert_eq!(hist.get(&2), Some(&3));
+        assert_eq!(hist.values().sum::<u64>(), 8);
+    }
+
+    #[test]
+    fn get_block_with_meta_is_consistent() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        let b1 = mk_empty_block(g, Height(1), 41);
+        let (id1, _) = st.ingest_block(b1.clone()).unwrap();
+
+        let (blk, meta) = st.get_block_with_meta(id1).unwrap().expect("block exists");
+        assert_eq!(blk, b1);
+        assert_eq!(meta.height, blk.header.height);
+        assert_eq!(meta.parent, blk.header.parent);
+
+        let (gblk, gmeta) = st.get_block_with_meta(g).unwrap().expect("genesis exists");
+        assert_eq!(gmeta.height, gblk.header.height);
+
+        assert!(st.get_block_with_meta(Hash256([3u8; 32])).unwrap().is_none());
+    }
+
+    #[test]
+    fn peer_fault_classification() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        let b1 = mk_empty_block(g, Height(1), 1);
+        let b1id = header_id(&b1.header);
+        st.ingest_block(b1).unwrap();
+
+        // parent đã biết nhưng height sai
+        let bad = mk_empty_block(b1id, Height(5), 2).header;
+        let err = st.ingest_header(bad).unwrap_err();
+        assert!(matches!(err, ChainStateError::HeightNotParentPlusOne { .. }));
+        assert!(err.is_peer_fault());
+
+        assert!(ChainStateError::InvalidPow.is_peer_fault());
+
+        let store_err = ChainStateError::Store(StoreError::Decode("x".to_string()));
+        assert!(!store_err.is_peer_fault());
+        assert!(!ChainStateError::MetaMissing.is_peer_fault());
+    }
+
+    #[test]
+    fn fork_point_from_locator_skips_stale_fork_entries() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        // canonical: g - a1 - a2 - a3 - a4
+        let mut a = vec![g];
+        for i in 1..=4u64 {
+            let b = mk_empty_block(*a.last().unwrap(), Height(i), 600 + i);
+            let (id, _) = st.ingest_block(b).unwrap();
+            a.push(id);
+        }
+
+        // stale fork từ a1: a1 - s2 - s3
+        let s2 = mk_empty_block(a[1], Height(2), 700);
+        let (s2id, _) = st.ingest_block(s2).unwrap();
+        let s3 = mk_empty_block(s2id, Height(3), 701);
+        let (s3id, _) = st.ingest_block(s3).unwrap();
+        assert_eq!(st.tip.hash, a[4]);
+
+        assert!(st.is_on_best_chain(a[2]).unwrap());
+        assert!(!st.is_on_best_chain(s2id).unwrap());
+
+        let locator = vec![Hash256([5u8; 32]), s3id, s2id, a[1], g];
+        assert_eq!(
+            st.fork_point_from_locator(&locator).unwrap(),
+            Some((Height(1), a[1]))
+        );
+
+        assert_eq!(st.fork_point_from_locator(&[s3id]).unwrap(), None);
+    }
+
+    #[test]
+    fn open_or_init_rejects_unsupported_store_schema() {
+        use egg_db::store::{StoreSchemaVersion, STORE_SCHEMA_VERSION};
+
+        let store = DbChainStore::new(MemKv::new());
+        let _ = ChainState::open_or_init(store.clone(), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(store.schema_version().unwrap(), Some(STORE_SCHEMA_VERSION));
+
+        store
+            .set_schema_version(StoreSchemaVersion(STORE_SCHEMA_VERSION.0 + 1))
+            .unwrap();
+        let err = ChainState::open_or_init(store, mk_spec(1_700_000_000)).err().unwrap();
+        assert!(matches!(
+            err,
+            ChainStateError::Store(StoreError::UnsupportedSchema { .. })
+        ));
+    }
+
+    #[test]
+    fn blocks_since_returns_ascending_canonical_blocks() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        for i in 1..=8u64 {
+            let b = mk_empty_block(st.tip.hash, Height(i), 800 + i);
+            st.ingest_block(b).unwrap();
+        }
+
+        let bs = st.blocks_since(Height(3), 100).unwrap();
+        assert_eq!(bs.len(), 5);
+        for (i, b) in bs.iter().enumerate() {
+            assert_eq!(b.header.height, Height(4 + i as u64));
+        }
+
+        let capped = st.blocks_since(Height(3), 2).unwrap();
+        assert_eq!(capped.len(), 2);
+        assert_eq!(capped[1].header.height, Height(5));
+
+        assert!(st.blocks_since(Height(8), 10).unwrap().is_empty());
+        assert!(st.blocks_since(Height(20), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn is_tip_stale_compares_tip_timestamp_to_now() {
+        let kv = MemKv::new();
+        let store = DbChainStore::new(kv);
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+
+        let b1 = mk_empty_block(st.tip.hash, Height(1), 1);
+        let ts = b1.header.timestamp_utc;
+        st.ingest_block(b1).unwrap();
+
+        assert!(!st.is_tip_stale(ts + 30, 600).unwrap());
+        assert!(!st.is_tip_stale(ts + 600, 600).unwrap());
+        assert!(st.is_tip_stale(ts + 86_400, 600).unwrap());
+    }
+
+    #[test]
+    fn assume_valid_skips_full_checks_below_it() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let kv = MemKv::new();
+        let store = DbChainStore::new(kv);
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        st.set_full_verify_hook(Arc::new(move |_| {
+            c.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let mut ids = vec![st.tip.hash];
+        for h in 1..=4u64 {
+            let b = mk_empty_block(st.tip.hash, Height(h), h);
+            let (id, _) = st.ingest_block(b).unwrap();
+            ids.push(id);
+        }
+        assert_eq!(count.swap(0, Ordering::SeqCst), 4);
+
+        // assume-valid = tip: không block nào bị kiểm lại merkle/PoW
+        st.set_assume_valid(st.tip.hash);
+        st.validate_best_chain().unwrap();
+        assert_eq!(count.swap(0, Ordering::SeqCst), 0);
+
+        // assume-valid ở height 2: chỉ height 3, 4 được kiểm đầy đủ
+        st.set_assume_valid(ids[2]);
+        st.validate_best_chain().unwrap();
+        assert_eq!(count.swap(0, Ordering::SeqCst), 2);
+
+        // block mới trên assume-valid vẫn được kiểm đầy đủ khi ingest
+        let b5 = mk_empty_block(st.tip.hash, Height(5), 5);
+        st.ingest_block(b5).unwrap();
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+
+        st.clear_assume_valid();
+        st.validate_best_chain().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+
+        // IBD headers-first: block dưới assume-valid ingest không cần kiểm đầy đủ
+        let mut ibd =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let c2 = Arc::new(AtomicUsize::new(0));
+        let c2h = c2.clone();
+        ibd.set_full_verify_hook(Arc::new(move |_| {
+            c2h.fetch_add(1, Ordering::SeqCst);
+        }));
+        for id in &ids[1..] {
+            ibd.ingest_header(st.store().get_header(*id).unwrap()).unwrap();
+        }
+        ibd.set_assume_valid(ids[3]);
+        for id in &ids[1..] {
+            ibd.ingest_block(st.store().get_block(*id).unwrap()).unwrap();
+        }
+        assert_eq!(ibd.tip.hash, ids[4]);
+        assert_eq!(c2.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn canonical_hashes_merkle_detects_fork() {
+        let spec = mk_spec(1_700_000_000);
+        let mut a = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec.clone()).unwrap();
+        let mut b = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec.clone()).unwrap();
+        let mut c = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec).unwrap();
+
+        for h in 1..=3u64 {
+            let blk = mk_empty_block(a.tip.hash, Height(h), h);
+            a.ingest_block(blk.clone()).unwrap();
+            b.ingest_block(blk.clone()).unwrap();
+            // c rẽ nhánh ở block cuối
+            let blk_c = if h == 3 { mk_empty_block(c.tip.hash, Height(h), 99) } else { blk };
+            c.ingest_block(blk_c).unwrap();
+        }
+
+        assert_eq!(a.canonical_hashes_merkle().unwrap(), b.canonical_hashes_merkle().unwrap());
+        assert_ne!(a.canonical_hashes_merkle().unwrap(), c.canonical_hashes_merkle().unwrap());
+
+        // đoạn chung trước fork vẫn khớp
+        assert_eq!(
+            a.canonical_hashes_merkle_range(Height(0), Height(2)).unwrap(),
+            c.canonical_hashes_merkle_range(Height(0), Height(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn ingest_block_report_counts_mempool_txs() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::{Transaction, CONTENT_TAG_OPAQUE};
+
+        let mk_tx = |p: &[u8]| Transaction {
+            id: tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+
+        let mut mp = Mempool::new();
+        mp.add_tx(mk_tx(b"a")).unwrap();
+        mp.add_tx(mk_tx(b"b")).unwrap();
+
+        let txs = vec![mk_tx(b"a"), mk_tx(b"b"), mk_tx(b"private")];
+        let leaves: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.header.merkle_root = merkle_root_txids(&leaves);
+        blk.txs = txs;
+
+        let (_, outcome, report) = st.ingest_block_with_report(blk, &mp).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert_eq!(
+            report,
+            IngestBlockReport {
+                txs_total: 3,
+                txs_from_mempool: 2,
+                txs_unknown: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn child_of_max_height_errors_with_height_overflow() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+
+        // dựng thẳng header + meta ở height u64::MAX - 1
+        let near_max = mk_empty_block(st.tip.hash, Height(u64::MAX - 1), 1);
+        let near_id = header_id(&near_max.header);
+        st.store().put_header(near_id, &near_max.header).unwrap();
+        st.store()
+            .put_block_meta(
+                near_id,
+                BlockMeta {
+                    parent: near_max.header.parent,
+                    height: near_max.header.height,
+                    chain_work: 0,
+                },
+            )
+            .unwrap();
+
+        // con ở u64::MAX vẫn hợp lệ
+        let at_max = mk_empty_block(near_id, Height(u64::MAX), 2);
+        let (max_id, _) = st.ingest_header(at_max.header.clone()).unwrap();
+
+        // con của block ở u64::MAX thì không có height hợp lệ
+        let over = mk_empty_block(max_id, Height(u64::MAX), 3);
+        let err = st.ingest_block(over).unwrap_err();
+        assert!(matches!(
+            err,
+            ChainStateError::HeightOverflow { parent_height } if parent_height == Height(u64::MAX)
+        ));
+    }
+
+    #[test]
+    fn shared_state_serializes_concurrent_ingest() {
+        let st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        let shared = SharedChainState::new(st);
+
+        // 2 luồng, mỗi luồng ingest 1 nhánh riêng dài 5 block
+        let handles: Vec<_> = [1_000u64, 2_000u64]
+            .into_iter()
+            .map(|base| {
+                let sh = shared.clone();
+                std::thread::spawn(move || {
+                    let mut parent = g;
+                    let mut ids = Vec::new();
+                    for h in 1..=5u64 {
+                        let (id, _) = sh.ingest_block(mk_empty_block(parent, Height(h), base + h)).unwrap();
+                        ids.push(id);
+                        parent = id;
+                    }
+                    ids
+                })
+            })
+            .collect();
+        let branches: Vec<Vec<Hash256>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        let st = shared.lock();
+        for id in branches.iter().flatten() {
+            assert!(st.store().has_block(*id).unwrap());
+        }
+        assert_eq!(st.tip.height, Height(5));
+        let tips = [branches[0][4], branches[1][4]];
+        let best = if tips[0].0 < tips[1].0 { tips[0] } else { tips[1] };
+        assert_eq!(st.tip.hash, best);
+        assert_eq!(st.store().get_tip().unwrap().unwrap(), st.tip);
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn retarget_rejects_old_difficulty_after_fast_blocks() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        st.set_retarget_policy(RetargetPolicy {
+            target_block_secs: 60,
+            window: 4,
+            min_bits: 0,
+            max_bits: 32,
+            mode: RetargetMode::StepBits,
+        });
+
+        // 3 block cách nhau 1s: chưa đủ cửa sổ => giữ độ khó 0
+        for h in 1..=3u64 {
+            assert_eq!(st.expected_difficulty_for_child(st.tip.hash).unwrap(), 0);
+            let mut b = mk_empty_block(st.tip.hash, Height(h), h);
+            b.header.timestamp_utc = 1_700_000_000 + h as i64;
+            st.ingest_block(b).unwrap();
+        }
+
+        // cửa sổ [0..3] mất 3s << 180s => tăng 1 bit
+        assert_eq!(st.expected_difficulty_for_child(st.tip.hash).unwrap(), 1);
+
+        let mut old = mk_empty_block(st.tip.hash, Height(4), 4);
+        old.header.timestamp_utc = 1_700_000_004;
+        let err = st.ingest_block(old.clone()).unwrap_err();
+        assert!(matches!(err, ChainStateError::DifficultyMismatch { expected: 1, got: 0 }));
+        assert!(matches!(
+            st.ingest_header(old.header.clone()),
+            Err(ChainStateError::DifficultyMismatch { .. })
+        ));
+
+        let mut ok = old;
+        ok.header.pow_difficulty_bits = 1;
+        while !pow_valid(&ok.header) {
+            ok.header.nonce += 1;
+        }
+        let (_, outcome) = st.ingest_block(ok).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+    }
+
+    #[test]
+    fn build_block_locator_doubles_back_from_tip_and_ends_at_genesis() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        for h in 1..=40u64 {
+            st.ingest_block(mk_empty_block(st.tip.hash, Height(h), h)).unwrap();
+        }
+
+        let loc = st.build_block_locator().unwrap();
+        assert_eq!(loc[0], st.tip.hash);
+        assert_eq!(*loc.last().unwrap(), g);
+        let heights: Vec<u64> = loc
+            .iter()
+            .map(|id| st.store().get_block_meta(*id).unwrap().unwrap().height.0)
+            .collect();
+        assert_eq!(heights, vec![40, 39, 38, 36, 32, 24, 8, 0]);
+        assert_eq!(st.fork_point_from_locator(&loc).unwrap(), Some((Height(40), st.tip.hash)));
+    }
+
+    #[test]
+    fn recompute_best_chain_matches_incremental_after_out_of_order_import() {
+        let src =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = src.tip.hash;
+
+        // nhánh a dài 5, nhánh b dài 3 (cùng rẽ từ genesis)
+        let mut blocks = Vec::new();
+        for (nonce_base, n) in [(100u64, 5u64), (200u64, 3u64)] {
+            let mut parent = g;
+            for h in 1..=n {
+                let b = mk_empty_block(parent, Height(h), nonce_base + h);
+                parent = header_id(&b.header);
+                blocks.push(b);
+            }
+        }
+        let a_tip = header_id(&blocks[4].header);
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        // ingest đảo ngược => phần lớn là orphan, nối lại dần
+        for b in blocks.iter().rev() {
+            st.ingest_block(b.clone()).unwrap();
+        }
+        assert_eq!(st.tip.hash, a_tip);
+
+        let incremental = st.tip;
+        st.set_strict_best_chain(true);
+        assert_eq!(st.recompute_best_chain().unwrap(), None);
+        assert_eq!(st.tip, incremental);
+        st.validate_best_chain().unwrap();
+
+        // tip incremental bị hỏng => strict báo lỗi, non-strict sửa lại
+        // (qua open_or_init thì đã được sửa ngay, xem interrupted_reorg_is_repaired_on_reopen)
+        let b_tip = header_id(&blocks[7].header);
+        st.tip = ChainTip {
+            height: Height(3),
+            hash: b_tip,
+        };
+        st.set_strict_best_chain(true);
+        assert!(matches!(
+            st.recompute_best_chain(),
+            Err(ChainStateError::BestChainMismatch { .. })
+        ));
+        st.set_strict_best_chain(false);
+        assert_eq!(
+            st.recompute_best_chain().unwrap(),
+            Some(BestChainMismatch {
+                incremental: ChainTip {
+                    height: Height(3),
+                    hash: b_tip,
+                },
+                recomputed: incremental,
+            })
+        );
+        assert_eq!(st.tip, incremental);
+        assert_eq!(st.canon_hash(Height(1)).unwrap(), Some(header_id(&blocks[0].header)));
+    }
+
+    #[derive(Clone, Default)]
+    struct CountingKv {
+        inner: MemKv,
+        reads: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl egg_db::KvStore for CountingKv {
+        fn get(&self, key: &[u8]) -> egg_db::Result<Vec<u8>> {
+            self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.get(key)
+        }
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> egg_db::Result<()> {
+            self.inner.put(key, value)
+        }
+        fn del(&self, key: &[u8]) -> egg_db::Result<()> {
+            self.inner.del(key)
+        }
+        fn has(&self, key: &[u8]) -> egg_db::Result<bool> {
+            self.reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.has(key)
+        }
+        fn batch(&self, ops: Vec<egg_db::KvOp>) -> egg_db::Result<()> {
+            self.inner.batch(ops)
+        }
+        fn scan_prefix(&self, prefix: &[u8]) -> egg_db::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            self.inner.scan_prefix(prefix)
+        }
+    }
+
+    #[test]
+    fn get_headers_after_uses_block_meta_for_start_height() {
+        use std::sync::atomic::Ordering;
+
+        let kv = CountingKv::default();
+        let reads = kv.reads.clone();
+        let mut st = ChainState::open_or_init(DbChainStore::new(kv), mk_spec(1_700_000_000)).unwrap();
+        let mut ids = vec![st.tip.hash];
+        for h in 1..=1000u64 {
+            let (id, _) = st.ingest_block(mk_empty_block(st.tip.hash, Height(h), h)).unwrap();
+            ids.push(id);
+        }
+
+        reads.store(0, Ordering::SeqCst);
+        let hs = st.get_headers_after(ids[600], 10).unwrap();
+        let n_reads = reads.load(Ordering::SeqCst);
+
+        assert_eq!(hs.len(), 10);
+        assert_eq!(hs[0].height, Height(601));
+        assert_eq!(header_id(&hs[9]), ids[610]);
+        // quét canon cũ cần > 600 lần đọc chỉ để tìm start
+        assert!(n_reads < 100, "too many reads: {}", n_reads);
+
+        // hash không thuộc chain => rỗng
+        assert!(st.get_headers_after(Hash256([7u8; 32]), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn open_with_genesis_verify_rejects_tampered_genesis_header() {
+        let spec = mk_spec(1_700_000_000);
+        let store = DbChainStore::new(MemKv::new());
+        let st = ChainState::open_or_init(store.clone(), spec.clone()).unwrap();
+        let gid = st.meta.genesis_id;
+
+        let opts = ChainOpenOptions {
+            verify_genesis_on_open: true,
+        };
+        ChainState::open_or_init_with(store.clone(), spec.clone(), opts).unwrap();
+
+        // store hỏng: header ở key genesis id không còn khớp spec
+        let mut bad = store.get_header(gid).unwrap();
+        bad.nonce += 1;
+        store.put_header(gid, &bad).unwrap();
+
+        assert!(ChainState::open_or_init(store.clone(), spec.clone()).is_ok());
+        assert!(matches!(
+            ChainState::open_or_init_with(store, spec, opts),
+            Err(ChainStateError::GenesisHeaderMismatch)
+        ));
+    }
+
+    #[test]
+    fn invalid_block_from_peer_marks_and_penalizes_once() {
+        let spec = mk_spec(1_700_000_000);
+        let mut st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec).unwrap();
+
+        // header sai height so với parent: lỗi của chính header
+        let bad = mk_empty_block(st.tip.hash, Height(5), 1);
+        let bad_id = header_id(&bad.header);
+        let err = st.ingest_block(bad).unwrap_err();
+        let verdict = st.handle_invalid_block_from_peer(bad_id, err).unwrap();
+        assert!(matches!(verdict, InvalidBlockVerdict::Penalize(ref r) if r.starts_with("invalid block")));
+        assert!(st.is_block_invalid(bad_id));
+
+        // con của block invalid bị từ chối, không phạt thêm
+        let child = mk_empty_block(bad_id, Height(6), 2);
+        let child_id = header_id(&child.header);
+        let err = st.ingest_block(child).unwrap_err();
+        assert!(matches!(err, ChainStateError::KnownInvalid { id } if id == child_id));
+        assert_eq!(
+            st.handle_invalid_block_from_peer(child_id, err).unwrap(),
+            InvalidBlockVerdict::DescendantOfInvalid
+        );
+
+        // lỗi nội bộ không bị nuốt
+        let err = st
+            .handle_invalid_block_from_peer(bad_id, ChainStateError::MetaMissing)
+            .unwrap_err();
+        assert!(matches!(err, ChainStateError::MetaMissing));
+    }
+
+    #[test]
+    fn bad_body_and_clock_errors_do_not_poison_block_id() {
+        let spec = mk_spec(1_700_000_000);
+        let mut st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec).unwrap();
+
+        // header thật + body giả: phạt nhưng không đánh dấu; body đúng tới sau vẫn được nhận
+        let honest = mk_empty_block(st.tip.hash, Height(1), 1);
+        let id = header_id(&honest.header);
+        let mut forged = honest.clone();
+        forged.txs.push(Transaction {
+            id: egg_crypto::tx_id_from_payload(b"evil"),
+            payload: b"evil".to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        });
+        let err = st.ingest_block(forged).unwrap_err();
+        assert!(!err.is_header_fault());
+        let verdict = st.handle_invalid_block_from_peer(id, err).unwrap();
+        assert!(matches!(verdict, InvalidBlockVerdict::BadBody(_)));
+        assert!(!st.is_block_invalid(id));
+        st.ingest_block(honest).unwrap();
+        assert_eq!(st.tip.hash, id);
+
+        // timestamp quá xa so với đồng hồ local: không đánh dấu, không phạt; đồng hồ theo kịp thì nhận
+        let mut rules = *st.consensus_rules();
+        rules.max_future_drift_secs = Some(60);
+        st.set_consensus_rules(rules);
+        st.set_clock(Arc::new(|| 1_700_000_000));
+        let mut early = mk_empty_block(id, Height(2), 2);
+        early.header.timestamp_utc = 1_700_001_000;
+        let early_id = header_id(&early.header);
+        let err = st.ingest_block(early.clone()).unwrap_err();
+        assert!(matches!(
+            st.handle_invalid_block_from_peer(early_id, err).unwrap(),
+            InvalidBlockVerdict::Deferred(_)
+        ));
+        assert!(!st.is_block_invalid(early_id));
+        st.set_clock(Arc::new(|| 1_700_001_000));
+        st.ingest_block(early).unwrap();
+        assert_eq!(st.tip.hash, early_id);
+    }
+
+    #[test]
+    fn latest_common_height_finds_fork_point() {
+        let spec = mk_spec(1_700_000_000);
+        let mut a = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec.clone()).unwrap();
+        let mut b = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec).unwrap();
+
+        for h in 1..=8u64 {
+            let blk = mk_empty_block(a.tip.hash, Height(h), h);
+            a.ingest_block(blk.clone()).unwrap();
+            // b rẽ nhánh từ height 6
+            let blk_b = if h > 5 { mk_empty_block(b.tip.hash, Height(h), 100 + h) } else { blk };
+            b.ingest_block(blk_b).unwrap();
+        }
+
+        let canon = |st: &ChainState<DbChainStore<MemKv>>| -> Vec<Hash256> {
+            (0..=st.tip.height.0)
+                .map(|h| st.store().get_canon_hash(Height(h)).unwrap().unwrap())
+                .collect()
+        };
+        let b_canon = canon(&b);
+
+        assert_eq!(a.latest_common_height(&b_canon).unwrap(), Some(Height(5)));
+        assert_eq!(a.latest_common_height(&canon(&a)).unwrap(), Some(Height(8)));
+        // peer chỉ biết 3 block đầu
+        assert_eq!(a.latest_common_height(&b_canon[..4]).unwrap(), Some(Height(3)));
+        assert_eq!(a.latest_common_height(&[Hash256([1u8; 32])]).unwrap(), None);
+        assert_eq!(a.latest_common_height(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn coinbase_enforced_when_reward_policy_set() {
+        use crate::coinbase::coinbase_tx;
+        use egg_types::Transaction;
+
+        let mk_block = |parent: Hash256, h: u64, txs: Vec<Transaction>| {
+            let leaves: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+            let mut b = mk_empty_block(parent, Height(h), h);
+            b.header.merkle_root = merkle_root_txids(&leaves);
+            b.txs = txs;
+            b
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        st.set_reward_policy(RewardPolicy {
+            initial_reward: 50,
+            halving_interval: 1000,
+        });
+        assert_eq!(st.reward_schedule(Height(1)), 50);
+
+        // thiếu coinbase
+        let err = st.ingest_block(mk_empty_block(st.tip.hash, Height(1), 1)).unwrap_err();
+        assert!(matches!(err, ChainStateError::MissingCoinbase { .. }));
+        assert!(err.is_peer_fault());
+
+        // coinbase sai height
+        let err = st
+            .ingest_block(mk_block(st.tip.hash, 1, vec![coinbase_tx(Height(2), 50)]))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ChainStateError::CoinbaseMismatch { expected_height: Height(1), got_height: Height(2), .. }
+        ));
+
+        // coinbase sai reward
+        let err = st
+            .ingest_block(mk_block(st.tip.hash, 1, vec![coinbase_tx(Height(1), 51)]))
+            .unwrap_err();
+        assert!(matches!(err, ChainStateError::CoinbaseMismatch { got_reward: 51, .. }));
+
+        let (_, outcome) = st
+            .ingest_block(mk_block(st.tip.hash, 1, vec![coinbase_tx(Height(1), 50)]))
+            .unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert_eq!(st.tip.height, Height(1));
+    }
+
+    #[test]
+    fn fork_tree_has_edges_and_canonical_flags() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        // canon: g -> a1 -> a2; nhánh phụ: a1 -> b2
+        let (a1, _) = st.ingest_block(mk_empty_block(g, Height(1), 1)).unwrap();
+        let (a2, _) = st.ingest_block(mk_empty_block(a1, Height(2), 2)).unwrap();
+        let (b2, _) = st.ingest_block(mk_empty_block(a1, Height(2), 99)).unwrap();
+        let canon2 = st.tip.hash;
+        let side2 = if canon2 == a2 { b2 } else { a2 };
+
+        let tree = st.fork_tree().unwrap();
+        assert_eq!(tree.root, g);
+        assert_eq!(tree.nodes.len(), 4);
+        let node = |id: Hash256| tree.nodes.iter().find(|n| n.id == id).unwrap();
+
+        assert_eq!(node(g).parent, None);
+        assert_eq!(node(g).children, vec![a1]);
+        let mut kids = node(a1).children.clone();
+        kids.sort_by_key(|h| h.0);
+        let mut expect = vec![a2, b2];
+        expect.sort_by_key(|h| h.0);
+        assert_eq!(kids, expect);
+        assert_eq!(node(side2).parent, Some(a1));
+        assert_eq!(node(side2).height, Height(2));
+
+        assert!(node(g).is_canonical && node(a1).is_canonical && node(canon2).is_canonical);
+        assert!(!node(side2).is_canonical);
+        assert!(tree.nodes.iter().all(|n| n.has_block));
+    }
+
+    #[test]
+    fn get_tx_finds_mined_tx_only() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::CONTENT_TAG_OPAQUE;
+
+        let mk_tx = |p: &[u8]| Transaction {
+            id: tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mined = mk_tx(b"mined");
+        let pending = mk_tx(b"pending");
+
+        let mut mp = Mempool::new();
+        mp.add_tx(pending.clone()).unwrap();
+
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.txs = vec![mk_tx(b"other"), mined.clone()];
+        blk.header.merkle_root = merkle_root_txids(&blk.txs.iter().map(|t| t.id).collect::<Vec<_>>());
+        st.ingest_block(blk).unwrap();
+
+        assert_eq!(st.get_tx(mined.id).unwrap(), Some(mined));
+        // chỉ trong mempool => None
+        assert_eq!(st.get_tx(pending.id).unwrap(), None);
+        assert!(mp.contains(pending.id));
+    }
+
+    #[test]
+    fn validate_best_chain_reports_progress_and_cancels() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        for h in 1..=6u64 {
+            st.ingest_block(mk_empty_block(st.tip.hash, Height(h), h)).unwrap();
+        }
+
+        let mut seen = Vec::new();
+        st.validate_best_chain_with(|h| seen.push(h.0), &AtomicBool::new(false))
+            .unwrap();
+        assert_eq!(seen, vec![6, 5, 4, 3, 2, 1, 0]);
+
+        // huỷ sau khi đã kiểm 3 block
+        let cancel = AtomicBool::new(false);
+        let mut n = 0;
+        let err = st
+            .validate_best_chain_with(
+                |_| {
+                    n += 1;
+                    if n == 3 {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                },
+                &cancel,
+            )
+            .unwrap_err();
+        assert!(matches!(err, ChainStateError::Cancelled));
+        assert!(!err.is_peer_fault());
+        assert_eq!(n, 3);
+    }
+
+    #[test]
+    fn prune_mempool_removes_only_mined_txs() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::CONTENT_TAG_OPAQUE;
+
+        let mk_tx = |p: &[u8]| Transaction {
+            id: tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mined = mk_tx(b"mined");
+        let pending = mk_tx(b"pending");
+
+        // mempool "đã lưu" trước khi block chứa `mined` được đào
+        let mut saved = Mempool::new();
+        saved.add_tx(mined.clone()).unwrap();
+        saved.add_tx(pending.clone()).unwrap();
+
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.txs = vec![mined.clone()];
+        blk.header.merkle_root = merkle_root_txids(&[mined.id]);
+        st.ingest_block(blk).unwrap();
+
+        assert_eq!(st.prune_mempool(&mut saved).unwrap(), 1);
+        assert!(!saved.contains(mined.id));
+        assert!(saved.contains(pending.id));
+        assert_eq!(st.prune_mempool(&mut saved).unwrap(), 0);
+    }
+
+    #[test]
+    fn tx_confirmation_status_levels() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::CONTENT_TAG_OPAQUE;
+
+        let mk_tx = |p: &[u8]| Transaction {
+            id: tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+        let mk_block_with = |parent: Hash256, h: u64, nonce: u64, tx: &Transaction| {
+            let mut b = mk_empty_block(parent, Height(h), nonce);
+            b.txs = vec![tx.clone()];
+            b.header.merkle_root = merkle_root_txids(&[tx.id]);
+            b
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        let mut mp = Mempool::new();
+        let pending = mk_tx(b"pending");
+        mp.add_tx(pending.clone()).unwrap();
+
+        assert_eq!(st.tx_confirmation_status(pending.id, 3, &mp).unwrap(), ConfStatus::InMempool);
+        assert_eq!(
+            st.tx_confirmation_status(mk_tx(b"nope").id, 3, &mp).unwrap(),
+            ConfStatus::Unknown
+        );
+
+        // nhánh a: tx_a ở height 1, dài 2
+        let tx_a = mk_tx(b"a");
+        let (a1, _) = st.ingest_block(mk_block_with(g, 1, 1, &tx_a)).unwrap();
+        assert_eq!(
+            st.tx_confirmation_status(tx_a.id, 3, &mp).unwrap(),
+            ConfStatus::Unconfirmed { depth: 1 }
+        );
+        st.ingest_block(mk_empty_block(a1, Height(2), 2)).unwrap();
+
+        // nhánh b dài 3 thay nhánh a => reorg sâu 2
+        let tx_b1 = mk_tx(b"b1");
+        let tx_b2 = mk_tx(b"b2");
+        let (b1, _) = st.ingest_block(mk_block_with(g, 1, 101, &tx_b1)).unwrap();
+        let (b2, _) = st.ingest_block(mk_block_with(b1, 2, 102, &tx_b2)).unwrap();
+        st.ingest_block(mk_empty_block(b2, Height(3), 103)).unwrap();
+        assert_eq!(st.tip.height, Height(3));
+        assert_eq!(st.max_reorg_depth(), 2);
+
+        // tx_a giờ chỉ ở nhánh phụ
+        assert_eq!(st.tx_confirmation_status(tx_a.id, 1, &mp).unwrap(), ConfStatus::Unknown);
+        assert_eq!(
+            st.tx_confirmation_status(tx_b2.id, 1, &mp).unwrap(),
+            ConfStatus::Confirmed { depth: 2 }
+        );
+        assert_eq!(
+            st.tx_confirmation_status(tx_b1.id, 1, &mp).unwrap(),
+            ConfStatus::DeeplyConfirmed
+        );
+    }
+
+    /// Ghi `tip:` lỗi khi `crash` bật. `atomic_batch` = false mô phỏng KV không có batch nguyên tử:
+    /// các op trước `tip:` trong batch đã được ghi.
+    #[derive(Clone, Default)]
+    struct CrashOnTipKv {
+        inner: MemKv,
+        crash: Arc<AtomicBool>,
+        atomic_batch: bool,
+    }
+
+    impl egg_db::KvStore for CrashOnTipKv {
+        fn get(&self, key: &[u8]) -> egg_db::Result<Vec<u8>> {
+            self.inner.get(key)
+        }
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> egg_db::Result<()> {
+            if key == b"tip:" && self.crash.load(Ordering::SeqCst) {
+                return Err(egg_db::DbError::Corrupted("injected crash".to_string()));
+            }
+            self.inner.put(key, value)
+        }
+        fn del(&self, key: &[u8]) -> egg_db::Result<()> {
+            self.inner.del(key)
+        }
+        fn has(&self, key: &[u8]) -> egg_db::Result<bool> {
+            self.inner.has(key)
+        }
+        fn scan_prefix(&self, prefix: &[u8]) -> egg_db::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            self.inner.scan_prefix(prefix)
+        }
+        fn batch(&self, ops: Vec<egg_db::KvOp>) -> egg_db::Result<()> {
+            use egg_db::KvOp;
+            let writes_tip = ops.iter().any(|op| matches!(op, KvOp::Put(k, _) if k == b"tip:"));
+            if self.atomic_batch && writes_tip && self.crash.load(Ordering::SeqCst) {
+                return Err(egg_db::DbError::Corrupted("injected crash".to_string()));
+            }
+            for op in ops {
+                match op {
+                    KvOp::Put(k, v) => self.put(k, v)?,
+                    KvOp::Del(k) => self.del(&k)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn interrupted_reorg_is_repaired_on_reopen() {
+        let kv = CrashOnTipKv::default();
+        let spec = mk_spec(1_700_000_000);
+        let mut st = ChainState::open_or_init(DbChainStore::new(kv.clone()), spec.clone()).unwrap();
+        let g = st.tip.hash;
+
+        // nhánh a (tip hiện tại) dài 2; nhánh b dài 3 sẽ gây reorg
+        let (a1, _) = st.ingest_block(mk_empty_block(g, Height(1), 1)).unwrap();
+        let (a2, _) = st.ingest_block(mk_empty_block(a1, Height(2), 2)).unwrap();
+        let (b1, _) = st.ingest_block(mk_empty_block(g, Height(1), 101)).unwrap();
+        let (b2, _) = st.ingest_block(mk_empty_block(b1, Height(2), 102)).unwrap();
+        let a_tip = st.tip.hash;
+        assert!(a_tip == a2 || a_tip == b2);
+
+        // crash: canon đã ghi cho nhánh b nhưng tip chưa commit
+        kv.crash.store(true, Ordering::SeqCst);
+        let (b3_id, b3) = {
+            let b = mk_empty_block(b2, Height(3), 103);
+            (header_id(&b.header), b)
+        };
+        assert!(matches!(st.ingest_block(b3), Err(ChainStateError::Store(_))));
+        drop(st);
+        kv.crash.store(false, Ordering::SeqCst);
+
+        let reopened = ChainState::open_or_init(DbChainStore::new(kv), spec).unwrap();
+        assert_eq!(reopened.tip.hash, b3_id);
+        assert_eq!(reopened.tip.height, Height(3));
+        assert_eq!(
+            reopened.best_chain_repaired_on_open().map(|m| m.recomputed.hash),
+            Some(b3_id)
+        );
+        // canon khớp đúng đường đi từ tip về genesis
+        for (h, id) in [(0, g), (1, b1), (2, b2), (3, b3_id)] {
+            assert_eq!(reopened.store().get_canon_hash(Height(h)).unwrap(), Some(id));
+        }
+        reopened.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn make_checkpoints_samples_canonical_chain_and_tip() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mut ids = vec![st.tip.hash];
+        for h in 1..=100u64 {
+            let (id, _) = st.ingest_block(mk_empty_block(ids[h as usize - 1], Height(h), h)).unwrap();
+            ids.push(id);
+        }
+
+        let cps = st.make_checkpoints(25).unwrap();
+        let expect: Vec<(Height, Hash256)> =
+            [0u64, 25, 50, 75, 100].iter().map(|&h| (Height(h), ids[h as usize])).collect();
+        assert_eq!(cps, expect);
+
+        // tip không rơi đúng bội số => vẫn được thêm vào cuối
+        let cps = st.make_checkpoints(30).unwrap();
+        let heights: Vec<u64> = cps.iter().map(|(h, _)| h.0).collect();
+        assert_eq!(heights, vec![0, 30, 60, 90, 100]);
+        assert_eq!(st.make_checkpoints(0).unwrap(), vec![(Height(100), ids[100])]);
+    }
+
+    fn mine_block(parent: Hash256, height: Height, bits: u32, nonce_base: u64) -> Block {
+        let mut b = mk_empty_block(parent, height, nonce_base);
+        b.header.pow_difficulty_bits = bits;
+        while !pow_valid(&b.header) {
+            b.header.nonce += 1;
+        }
+        b
+    }
+
+    #[test]
+    fn fork_choice_prefers_more_work_over_height() {
+        let kv = MemKv::new();
+        let spec = mk_spec(1_700_000_000);
+        let mut st = ChainState::open_or_init(DbChainStore::new(kv.clone()), spec.clone()).unwrap();
+        let g = st.tip.hash;
+
+        // 3 block dễ (work 1 mỗi block)
+        let mut parent = g;
+        let mut low = Vec::new();
+        for h in 1..=3u64 {
+            parent = st.ingest_block(mk_empty_block(parent, Height(h), 100 + h)).unwrap().0;
+            low.push(parent);
+        }
+        assert_eq!(st.tip.hash, low[2]);
+
+        // 2 block khó (8 bit => work 256 mỗi block) thắng dù thấp hơn
+        let (h1, _) = st.ingest_block(mine_block(g, Height(1), 8, 0)).unwrap();
+        let (h2, outcome) = st.ingest_block(mine_block(h1, Height(2), 8, 0)).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert_eq!(st.tip, ChainTip { height: Height(2), hash: h2 });
+        assert_eq!(st.chain_work_of(h2).unwrap(), 1 + 256 + 256);
+        assert_eq!(st.chain_work_of(low[2]).unwrap(), 4);
+        assert_eq!(st.max_reorg_depth(), 3);
+
+        // canon phía trên tip mới bị xoá, không bị coi là reorg dở dang khi mở lại
+        assert_eq!(st.canon_hash(Height(2)).unwrap(), Some(h2));
+        assert_eq!(st.canon_hash(Height(3)).unwrap(), None);
+        st.validate_best_chain().unwrap();
+        st.set_strict_best_chain(true);
+        st.recompute_best_chain().unwrap();
+
+        // nhánh dễ dài thêm 1 block vẫn chưa đủ work
+        let (l4, outcome) = st.ingest_block(mk_empty_block(low[2], Height(4), 104)).unwrap();
+        assert_eq!(outcome, IngestOutcome::StoredConnected);
+        assert!(!st.is_on_best_chain(l4).unwrap());
+
+        drop(st);
+        let reopened = ChainState::open_or_init(DbChainStore::new(kv), spec).unwrap();
+        assert_eq!(reopened.tip.hash, h2);
+    }
+
+    #[test]
+    fn custom_consensus_rules_are_enforced_on_ingest() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(*st.consensus_rules(), ConsensusRules::default());
+        let g = st.tip.hash;
+
+        let empty_size = egg_types::canonical::encode_block(&mk_empty_block(g, Height(1), 0)).len();
+        let mut rules = ConsensusRules::default();
+        rules.limits.max_block_bytes = empty_size;
+        rules.max_reorg_depth = Some(1);
+        st.set_consensus_rules(rules);
+
+        // block có 1 tx vượt kích thước tối đa
+        let tx = Transaction {
+            id: egg_crypto::tx_id_from_payload(b"too-big"),
+            payload: b"too-big".to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        };
+        let mut big = mk_empty_block(g, Height(1), 1);
+        big.header.merkle_root = merkle_root_txids(&[tx.id]);
+        big.txs = vec![tx];
+        let err = st.ingest_block(big).unwrap_err();
+        assert!(matches!(
+            err,
+            ChainStateError::BlockBuild(BlockBuildError::BlockTooLarge { max, .. }) if max == empty_size
+        ));
+        assert!(err.is_peer_fault());
+        assert_eq!(st.tip.hash, g);
+
+        // nhánh A cao 2, nhánh B cao 3 từ genesis => reorg sâu 2 > 1 bị từ chối
+        let (a1, _) = st.ingest_block(mk_empty_block(g, Height(1), 11)).unwrap();
+        let (a2, _) = st.ingest_block(mk_empty_block(a1, Height(2), 12)).unwrap();
+        let (b1, _) = st.ingest_block(mk_empty_block(g, Height(1), 21)).unwrap();
+        let (b2, _) = st.ingest_block(mk_empty_block(b1, Height(2), 22)).unwrap();
+        let err = st.ingest_block(mk_empty_block(b2, Height(3), 23)).unwrap_err();
+        assert!(matches!(err, ChainStateError::ReorgTooDeep { depth: 2, max: 1 }));
+        assert_eq!(st.tip, ChainTip { height: Height(2), hash: a2 });
+        assert_eq!(st.canon_hash(Height(1)).unwrap(), Some(a1));
+
+        // reorg sâu 1 vẫn được
+        let (c3, _) = st.ingest_block(mk_empty_block(a1, Height(2), 32)).unwrap();
+        let (c4, _) = st.ingest_block(mk_empty_block(c3, Height(3), 33)).unwrap();
+        assert_eq!(st.tip, ChainTip { height: Height(3), hash: c4 });
+        assert_eq!(st.max_reorg_depth(), 1);
+
+        // độ khó tối thiểu áp cho cả header
+        let mut rules = *st.consensus_rules();
+        rules.min_difficulty_bits = 4;
+        st.set_consensus_rules(rules);
+        let err = st.ingest_header(mk_empty_block(c4, Height(4), 0).header).unwrap_err();
+        assert!(matches!(err, ChainStateError::DifficultyTooLow { min: 4, got: 0 }));
+        st.ingest_header(mine_block(c4, Height(4), 4, 0).header).unwrap();
+
+        // timestamp quá xa tương lai
+        rules.max_future_drift_secs = Some(60);
+        st.set_consensus_rules(rules);
+        let mut future = mine_block(c4, Height(4), 4, 1000);
+        future.header.timestamp_utc = i64::MAX / 2;
+        while !pow_valid(&future.header) {
+            future.header.nonce += 1;
+        }
+        let err = st.ingest_block(future).unwrap_err();
+        assert!(matches!(err, ChainStateError::TimestampTooFarFuture { .. }));
+    }
+
+    #[test]
+    fn body_for_preexisting_header_must_match_its_merkle_root() {
+        let mut src =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mk_tx = |p: &[u8]| Transaction {
+            id: egg_crypto::tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        };
+        let mut ids = Vec::new();
+        let mut blocks = Vec::new();
+        for h in 1..=2u64 {
+            let tx = mk_tx(format!("tx-{h}").as_bytes());
+            let mut b = mk_empty_block(src.tip.hash, Height(h), h);
+            b.header.merkle_root = merkle_root_txids(&[tx.id]);
+            b.txs = vec![tx];
+            ids.push(src.ingest_block(b.clone()).unwrap().0);
+            blocks.push(b);
+        }
+
+        for assume_valid in [false, true] {
+            let mut st =
+                ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+            for b in &blocks {
+                st.ingest_header(b.header.clone()).unwrap();
+            }
+            if assume_valid {
+                st.set_assume_valid(ids[1]);
+            }
+
+            // header giữ nguyên (id không đổi) nhưng tx bị thay => root không khớp
+            let mut forged = blocks[0].clone();
+            forged.txs = vec![mk_tx(b"forged")];
+            let err = st.ingest_block(forged).unwrap_err();
+            assert!(
+                matches!(err, ChainStateError::BlockBuild(BlockBuildError::MerkleMismatch { .. })),
+                "assume_valid={assume_valid}: {err:?}"
+            );
+            assert!(err.is_peer_fault());
+            assert!(!st.store().has_block(ids[0]).unwrap());
+
+            // body đúng vẫn được nhận sau đó
+            for b in &blocks {
+                st.ingest_block(b.clone()).unwrap();
+            }
+            assert_eq!(st.tip.hash, ids[1]);
+            assert_eq!(st.get_tx(blocks[0].txs[0].id).unwrap(), Some(blocks[0].txs[0].clone()));
+        }
+    }
+
+    #[test]
+    fn median_time_past_and_future_drift_bound_timestamps() {
+        let base = 1_700_000_000i64;
+        let mut st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(base)).unwrap();
+        let mk_at = |parent: Hash256, h: u64, ts: i64| {
+            let mut b = mk_empty_block(parent, Height(h), h);
+            b.header.timestamp_utc = ts;
+            b
+        };
+
+        // genesis + 10 block lệch thứ tự: 11 timestamp = base + {0..10} phút => median = base + 5 phút
+        for (i, m) in [5i64, 1, 9, 3, 7, 2, 8, 4, 10, 6].into_iter().enumerate() {
+            st.ingest_block(mk_at(st.tip.hash, i as u64 + 1, base + m * 60)).unwrap();
+        }
+        let tip = st.tip.hash;
+        assert_eq!(st.median_time_past(tip, MEDIAN_TIME_SPAN).unwrap(), base + 300);
+
+        let mut rules = *st.consensus_rules();
+        rules.median_time_span = Some(MEDIAN_TIME_SPAN);
+        st.set_consensus_rules(rules);
+        st.set_clock(Arc::new(move || base + 1_000));
+
+        // bằng median => từ chối, cả header lẫn block
+        let err = st.ingest_header(mk_at(tip, 11, base + 300).header).unwrap_err();
+        assert!(matches!(err, ChainStateError::TimestampTooEarly { timestamp, median } if timestamp == median));
+        assert!(err.is_peer_fault());
+        let err = st.ingest_block(mk_at(tip, 11, base + 300)).unwrap_err();
+        assert!(matches!(err, ChainStateError::TimestampTooEarly { .. }));
+
+        // lớn hơn median là đủ, dù nhỏ hơn timestamp của tip
+        let (id, _) = st.ingest_block(mk_at(tip, 11, base + 301)).unwrap();
+        assert_eq!(st.tip.hash, id);
+
+        // quá "now" (đồng hồ inject) + 2 giờ => từ chối; đúng biên thì nhận
+        let max = base + 1_000 + MAX_FUTURE_DRIFT_SECS;
+        let err = st.ingest_header(mk_at(id, 12, max + 1).header).unwrap_err();
+        assert!(matches!(err, ChainStateError::TimestampTooFarFuture { timestamp, max: m } if timestamp == max + 1 && m == max));
+        st.ingest_header(mk_at(id, 12, max).header).unwrap();
+    }
+
+    #[test]
+    fn proportional_retarget_reads_ancestors_and_is_enforced() {
+        let base = 1_700_000_000i64;
+        let mut st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(base)).unwrap();
+        st.set_retarget_policy(RetargetPolicy {
+            target_block_secs: 60,
+            window: 3,
+            min_bits: 0,
+            max_bits: 64,
+            mode: RetargetMode::Proportional,
+        });
+
+        // genesis + 2 block cách nhau 1s: nhanh hơn 4x => target / 4 (work 1 -> 4)
+        for h in 1..=2u64 {
+            let mut b = mk_empty_block(st.tip.hash, Height(h), h);
+            b.header.timestamp_utc = base + h as i64;
+            st.ingest_block(b).unwrap();
+        }
+        let bits = st.expected_difficulty_for_child(st.tip.hash).unwrap();
+        assert_eq!(work_of_pow_bits(bits), 4);
+
+        let mut b = mk_empty_block(st.tip.hash, Height(3), 3);
+        b.header.timestamp_utc = base + 3;
+        let err = st.ingest_block(b.clone()).unwrap_err();
+        assert!(matches!(err, ChainStateError::DifficultyMismatch { expected, got: 0 } if expected == bits));
+
+        let mined = mine_block(st.tip.hash, Height(3), bits, 0);
+        let (id, _) = st.ingest_block(mined).unwrap();
+        assert_eq!(st.tip.hash, id);
+    }
+
+    #[test]
+    fn diff_reports_divergence_height_and_branch_hashes() {
+        let spec = mk_spec(1_700_000_000);
+        let mut a = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec.clone()).unwrap();
+        let mut b = ChainState::open_or_init(DbChainStore::new(MemKv::new()), spec).unwrap();
+        for h in 1..=4u64 {
+            let blk = mk_empty_block(a.tip.hash, Height(h), h);
+            a.ingest_block(blk.clone()).unwrap();
+            b.ingest_block(blk).unwrap();
+        }
+        assert!(a.diff(&b).unwrap().is_empty());
+        assert_eq!(a.diff(&b).unwrap(), ChainDiff::default());
+
+        // b dài hơn 1 block: chỉ remote có
+        let (b5, _) = b.ingest_block(mk_empty_block(b.tip.hash, Height(5), 5)).unwrap();
+        let d = a.diff(&b).unwrap();
+        assert_eq!(d.first_divergent_height, Some(Height(5)));
+        assert!(d.local_only.is_empty());
+        assert_eq!(d.remote_only, vec![b5]);
+
+        // a rẽ nhánh từ height 3: 2 block riêng
+        let fork = a.canon_hash(Height(2)).unwrap().unwrap();
+        let (a3, _) = a.ingest_block(mk_empty_block(fork, Height(3), 103)).unwrap();
+        let (a4, _) = a.ingest_block(mk_empty_block(a3, Height(4), 104)).unwrap();
+        let (a5, _) = a.ingest_block(mk_empty_block(a4, Height(5), 105)).unwrap();
+        let (a6, _) = a.ingest_block(mk_empty_block(a5, Height(6), 106)).unwrap();
+        assert_eq!(a.tip.hash, a6);
+
+        let d = a.diff(&b).unwrap();
+        assert_eq!(d.first_divergent_height, Some(Height(3)));
+        assert_eq!(d.local_only, vec![a3, a4, a5, a6]);
+        let b_branch: Vec<Hash256> = (3..=5).map(|h| b.canon_hash(Height(h)).unwrap().unwrap()).collect();
+        assert_eq!(d.remote_only, b_branch);
+
+        // đối xứng
+        let rev = b.diff(&a).unwrap();
+        assert_eq!(rev.first_divergent_height, Some(Height(3)));
+        assert_eq!((rev.local_only, rev.remote_only), (d.remote_only, d.local_only));
+    }
+
+    #[test]
+    fn height_lookups_and_canonical_headers_follow_reorg() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mut blocks = Vec::new();
+        for h in 1..=5u64 {
+            let b = mk_empty_block(st.tip.hash, Height(h), h);
+            st.ingest_block(b.clone()).unwrap();
+            blocks.push(b);
+        }
+
+        let headers: Vec<BlockHeader> = st.canonical_headers().collect::<Result<_>>().unwrap();
+        assert_eq!(headers.len(), 6);
+        for (h, hdr) in headers.iter().enumerate() {
+            assert_eq!(hdr.height, Height(h as u64));
+        }
+        assert_eq!(header_id(&headers[0]), st.meta.genesis_id);
+        assert_eq!(headers[3], blocks[2].header);
+
+        assert_eq!(st.block_at_height(Height(3)).unwrap(), Some(blocks[2].clone()));
+        assert_eq!(st.header_at_height(Height(5)).unwrap(), Some(blocks[4].header.clone()));
+        assert_eq!(st.block_at_height(Height(6)).unwrap(), None);
+        assert_eq!(st.header_at_height(Height(6)).unwrap(), None);
+
+        // reorg từ height 4 sang nhánh dài hơn
+        let mut parent = header_id(&blocks[2].header);
+        let mut fork = Vec::new();
+        for h in 4..=6u64 {
+            let b = mk_empty_block(parent, Height(h), 100 + h);
+            parent = st.ingest_block(b.clone()).unwrap().0;
+            fork.push(b);
+        }
+        assert_eq!(st.tip.hash, parent);
+        assert_eq!(st.block_at_height(Height(3)).unwrap(), Some(blocks[2].clone()));
+        assert_eq!(st.block_at_height(Height(4)).unwrap(), Some(fork[0].clone()));
+        assert_eq!(st.block_at_height(Height(6)).unwrap(), Some(fork[2].clone()));
+        let headers: Vec<BlockHeader> = st.canonical_headers().collect::<Result<_>>().unwrap();
+        assert_eq!(headers.len(), 7);
+        assert_eq!(headers[4], fork[0].header);
+
+        // canon index hỏng giữa chừng => lỗi 1 lần rồi dừng, không panic
+        st.store().del_canon_hash(Height(2)).unwrap();
+        let items: Vec<Result<BlockHeader>> = st.canonical_headers().collect();
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok() && items[1].is_ok());
+        assert!(matches!(items[2], Err(ChainStateError::MissingCanonHash { height: Height(2) })));
+    }
+
+    #[test]
+    fn fast_forward_appends_contiguous_blocks() {
+        let src = {
+            let mut st =
+                ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+            for h in 1..=20u64 {
+                st.ingest_block(mk_empty_block(st.tip.hash, Height(h), h)).unwrap();
+            }
+            st
+        };
+        let blocks: Vec<Block> = (1..=20u64)
+            .map(|h| src.block_at_height(Height(h)).unwrap().unwrap())
+            .collect();
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(st.fast_forward(blocks[..10].to_vec()).unwrap(), Height(10));
+        assert_eq!(st.fast_forward(Vec::new()).unwrap(), Height(10));
+        assert_eq!(st.fast_forward(blocks[10..].to_vec()).unwrap(), Height(20));
+        assert_eq!(st.tip, src.tip);
+        assert!(st.diff(&src).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fast_forward_rejects_block_not_on_tip() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        st.fast_forward(vec![mk_empty_block(g, Height(1), 1)]).unwrap();
+        let tip = st.tip;
+
+        // nhánh từ genesis, không nối lên tip => không ghi gì
+        let side = mk_empty_block(g, Height(1), 2);
+        let err = st.fast_forward(vec![side.clone()]).unwrap_err();
+        assert!(matches!(err, ChainStateError::BrokenHeaderLink { index: 0, expected, got } if expected == tip.hash && got == g));
+        assert!(!st.store().has_header(header_id(&side.header)).unwrap());
+
+        // đứt liên kết giữa dãy => block đầu cũng không được ghi
+        let b2 = mk_empty_block(tip.hash, Height(2), 2);
+        let stray = mk_empty_block(tip.hash, Height(3), 3);
+        let err = st.fast_forward(vec![b2.clone(), stray]).unwrap_err();
+        assert!(matches!(err, ChainStateError::BrokenHeaderLink { index: 1, .. }));
+        assert!(!st.store().has_header(header_id(&b2.header)).unwrap());
+        assert_eq!(st.tip, tip);
+    }
+
+    #[test]
+    fn find_tx_tracks_best_chain_across_reorgs() {
+        let mk_tx = |p: &[u8]| Transaction {
+            id: egg_crypto::tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        };
+        let with_txs = |parent: Hash256, h: u64, nonce: u64, txs: Vec<Transaction>| {
+            let mut b = mk_empty_block(parent, Height(h), nonce);
+            b.header.merkle_root = merkle_root_txids(&txs.iter().map(|t| t.id).collect::<Vec<_>>());
+            b.txs = txs;
+            b
+        };
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        let tx = mk_tx(b"moving");
+
+        // nhánh A: tx ở vị trí 1 của block 1
+        let (a1, _) = st.ingest_block(with_txs(g, 1, 1, vec![mk_tx(b"a"), tx.clone()])).unwrap();
+        assert_eq!(st.find_tx(tx.id).unwrap(), Some((a1, 1)));
+        assert_eq!(st.find_tx(mk_tx(b"absent").id).unwrap(), None);
+
+        // nhánh B dài hơn, không có tx => rời best chain
+        let (b1, _) = st.ingest_block(with_txs(g, 1, 11, vec![])).unwrap();
+        let (b2, _) = st.ingest_block(with_txs(b1, 2, 12, vec![])).unwrap();
+        assert_eq!(st.tip.hash, b2);
+        assert_eq!(st.find_tx(tx.id).unwrap(), None);
+        assert_eq!(st.get_tx(tx.id).unwrap(), None);
+
+        // tx được đào lại ở block 3 của B, vị trí 0
+        let (b3, _) = st.ingest_block(with_txs(b2, 3, 13, vec![tx.clone()])).unwrap();
+        assert_eq!(st.find_tx(tx.id).unwrap(), Some((b3, 0)));
+
+        // A vượt lên lại: index trỏ về block A
+        let (a2, _) = st.ingest_block(with_txs(a1, 2, 2, vec![])).unwrap();
+        let (a3, _) = st.ingest_block(with_txs(a2, 3, 3, vec![])).unwrap();
+        st.ingest_block(with_txs(a3, 4, 4, vec![])).unwrap();
+        assert_eq!(st.find_tx(tx.id).unwrap(), Some((a1, 1)));
+    }
+
+    #[test]
+    fn orphan_pool_evicts_oldest_and_survivors_still_connect() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        st.set_max_orphans(3);
+        let g = st.tip.hash;
+
+        // 5 nhánh từ genesis, mỗi nhánh 3 block: x[i] = [h1, h2, h3]
+        let branches: Vec<Vec<Block>> = (0..5u64)
+            .map(|i| {
+                let mut parent = g;
+                (1..=3u64)
+                    .map(|h| {
+                        let b = mk_empty_block(parent, Height(h), 1000 * (i + 1) + h);
+                        parent = header_id(&b.header);
+                        b
+                    })
+                    .collect()
+            })
+            .collect();
+        let id = |b: &Block| header_id(&b.header);
+
+        // nhánh 0: h3 tới trước h2 => h3 hết là orphan khi h2 tới, h2 là orphan gốc
+        assert_eq!(st.ingest_block(branches[0][2].clone()).unwrap().1, IngestOutcome::StoredOrphan);
+        assert_eq!(st.ingest_header(branches[0][1].header.clone()).unwrap().1, HeaderIngestOutcome::StoredOrphan);
+        assert_eq!(st.orphan_count(), 1);
+
+        for b in &branches[1..4] {
+            assert_eq!(st.ingest_block(b[1].clone()).unwrap().1, IngestOutcome::StoredOrphan);
+        }
+        // vượt cap: orphan cũ nhất (nhánh 0 h2) bị evict cùng con của nó
+        assert_eq!(st.orphan_count(), 3);
+        for b in &branches[0][1..] {
+            assert!(!st.store().has_header(id(b)).unwrap());
+            assert!(st.store().get_block_meta(id(b)).unwrap().is_none());
+        }
+        assert!(st.store().get_children(id(&branches[0][0])).unwrap().is_empty());
+
+        st.ingest_block(branches[4][1].clone()).unwrap();
+        assert_eq!(st.orphan_count(), 3);
+        assert!(!st.store().has_header(id(&branches[1][1])).unwrap());
+
+        // parent tới => orphan còn sống được nối, không bị đếm nữa
+        let (_, outcome) = st.ingest_block(branches[3][0].clone()).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert_eq!(st.tip.hash, id(&branches[3][1]));
+        assert_eq!(st.orphan_count(), 2);
+
+        // orphan đã bị evict: parent tới cũng không có gì để nối
+        st.ingest_block(branches[1][0].clone()).unwrap();
+        assert_eq!(st.tip.hash, id(&branches[3][1]));
+        assert_eq!(st.orphan_count(), 2);
+
+        // đầy lại: orphan có parent đã biết (nhánh 3 h3) không bao giờ bị evict
+        st.set_max_orphans(1);
+        st.ingest_block(branches[3][2].clone()).unwrap();
+        assert_eq!(st.tip.hash, id(&branches[3][2]));
+        st.ingest_block(mk_empty_block(Hash256([7u8; 32]), Height(9), 9)).unwrap();
+        assert_eq!(st.orphan_count(), 1);
+        assert!(st.store().has_block(id(&branches[3][2])).unwrap());
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn difficulty_info_tracks_retarget_window() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let genesis_bits = st.store().get_header(st.tip.hash).unwrap().pow_difficulty_bits;
+        assert_eq!(
+            st.difficulty_info().unwrap(),
+            DifficultyInfo {
+                current_bits: genesis_bits,
+                next_required_bits: genesis_bits,
+                blocks_until_retarget: None,
+            }
+        );
+
+        st.set_retarget_policy(RetargetPolicy {
+            target_block_secs: 60,
+            window: 4,
+            min_bits: 0,
+            max_bits: 32,
+            mode: RetargetMode::StepBits,
+        });
+        let info = st.difficulty_info().unwrap();
+        assert_eq!(info.current_bits, genesis_bits);
+        assert_eq!(info.next_required_bits, genesis_bits);
+        assert_eq!(info.blocks_until_retarget, Some(3));
+
+        for h in 1..=3u64 {
+            let mut b = mk_empty_block(st.tip.hash, Height(h), h);
+            b.header.timestamp_utc = 1_700_000_000 + h as i64;
+            st.ingest_block(b).unwrap();
+        }
+        // cửa sổ đủ, block nhanh => block kế tiếp phải khó hơn 1 bit
+        let info = st.difficulty_info().unwrap();
+        assert_eq!(info.current_bits, 0);
+        assert_eq!(info.next_required_bits, 1);
+        assert_eq!(info.blocks_until_retarget, Some(0));
+    }
+
+    #[test]
+    fn disconnect_tip_then_reingest_restores_tip_and_canon() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        assert!(matches!(st.disconnect_tip(), Err(ChainStateError::DisconnectGenesis)));
+
+        let mut blocks = Vec::new();
+        for h in 1..=3u64 {
+            let b = mk_empty_block(st.tip.hash, Height(h), h);
+            st.ingest_block(b.clone()).unwrap();
+            blocks.push(b);
+        }
+        let tip = st.tip;
+        let canon: Vec<_> = (0..=3).map(|h| st.canon_hash(Height(h)).unwrap()).collect();
+        let metas: Vec<_> = blocks
+            .iter()
+            .map(|b| st.store().get_block_meta(header_id(&b.header)).unwrap())
+            .collect();
+
+        let out = st.disconnect_tip().unwrap();
+        assert_eq!(out, tip.hash);
+        assert_eq!(st.tip.height, Height(2));
+        assert_eq!(st.tip.hash, header_id(&blocks[1].header));
+        assert_eq!(st.store().get_tip().unwrap().unwrap(), st.tip);
+        assert_eq!(st.canon_hash(Height(3)).unwrap(), None);
+        // dữ liệu block vẫn giữ để nối lại
+        assert!(st.store().has_block(out).unwrap());
+        assert_eq!(st.store().get_block_meta(out).unwrap(), metas[2]);
+        assert_eq!(st.store().get_children(st.tip.hash).unwrap(), vec![out]);
+        st.validate_best_chain().unwrap();
+
+        st.disconnect_tip().unwrap();
+        st.disconnect_tip().unwrap();
+        assert_eq!(st.tip.hash, g);
+        assert!(matches!(st.disconnect_tip(), Err(ChainStateError::DisconnectGenesis)));
+
+        for b in &blocks {
+            assert_eq!(st.ingest_block(b.clone()).unwrap().1, IngestOutcome::NewTip);
+        }
+        assert_eq!(st.ingest_block(blocks[2].clone()).unwrap().1, IngestOutcome::AlreadyKnown);
+        assert_eq!(st.tip, tip);
+        assert_eq!(st.store().get_tip().unwrap().unwrap(), tip);
+        let canon_after: Vec<_> = (0..=3).map(|h| st.canon_hash(Height(h)).unwrap()).collect();
+        assert_eq!(canon_after, canon);
+        for (b, m) in blocks.iter().zip(&metas) {
+            assert_eq!(st.store().get_block_meta(header_id(&b.header)).unwrap(), *m);
+        }
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn flush_persists_deferred_sled_writes_across_reopen() {
+        use egg_db::{DbError, FlushMode, SledKv};
+
+        let dir = tempfile::tempdir().unwrap();
+        let spec = mk_spec(1_700_000_000);
+        let (tip, blocks) = {
+            let kv = SledKv::open_with_mode(dir.path(), FlushMode::Deferred).unwrap();
+            let mut st = ChainState::open_or_init(DbChainStore::new(kv), spec.clone()).unwrap();
+            let mut blocks = Vec::new();
+            for h in 1..=3u64 {
+                let b = mk_empty_block(st.tip.hash, Height(h), h);
+                st.ingest_block(b.clone()).unwrap();
+                blocks.push(b);
+            }
+            st.flush().unwrap();
+            (st.tip, blocks)
+        };
+
+        // thread IO của sled có thể giữ file lock thêm một chút sau khi drop
+        let kv = (0..100)
+            .find_map(|_| match SledKv::open(dir.path()) {
+                Err(DbError::AlreadyLocked(_)) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    None
+                }
+                other => Some(other.unwrap()),
+            })
+            .expect("db still locked after drop");
+        let st = ChainState::open_or_init(DbChainStore::new(kv), spec).unwrap();
+        assert_eq!(st.tip, tip);
+        for b in &blocks {
+            let id = header_id(&b.header);
+            assert_eq!(st.store().get_block(id).unwrap(), *b);
+            assert_eq!(st.canon_hash(b.header.height).unwrap(), Some(id));
+        }
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn failed_ingest_batch_leaves_no_partial_block() {
+        let kv = CrashOnTipKv {
+            atomic_batch: true,
+            ..CrashOnTipKv::default()
+        };
+        let spec = mk_spec(1_700_000_000);
+        let mut st = ChainState::open_or_init(DbChainStore::new(kv.clone()), spec.clone()).unwrap();
+        let (b1, _) = st.ingest_block(mk_empty_block(st.tip.hash, Height(1), 1)).unwrap();
+        let tip = st.tip;
+
+        // lỗi ngay ở write cuối (tip) của batch: header/body/meta/children/canon đều không được ghi
+        kv.crash.store(true, Ordering::SeqCst);
+        let b2 = mk_empty_block(b1, Height(2), 2);
+        let b2_id = header_id(&b2.header);
+        assert!(matches!(st.ingest_block(b2.clone()), Err(ChainStateError::Store(_))));
+        assert_eq!(st.tip, tip);
+        assert!(!st.store().has_header(b2_id).unwrap());
+        assert!(!st.store().has_block(b2_id).unwrap());
+        assert!(st.store().get_block_meta(b2_id).unwrap().is_none());
+        assert!(st.store().get_children(b1).unwrap().is_empty());
+        assert_eq!(st.canon_hash(Height(2)).unwrap(), None);
+
+        // mở lại thấy đúng trạng thái trước lỗi; nhận lại block thì nối bình thường
+        kv.crash.store(false, Ordering::SeqCst);
+        drop(st);
+        let mut st = ChainState::open_or_init(DbChainStore::new(kv), spec).unwrap();
+        assert_eq!(st.tip, tip);
+        st.validate_best_chain().unwrap();
+        assert_eq!(st.ingest_block(b2).unwrap(), (b2_id, IngestOutcome::NewTip));
+    }
+
+    #[test]
+    fn min_chain_work_keeps_trivial_chain_from_becoming_tip() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip;
+        let mut rules = *st.consensus_rules();
+        rules.min_chain_work = Some(64);
+        st.set_consensus_rules(rules);
+
+        // 3 header độ khó 0 (work 1 mỗi cái): hợp lệ nhưng quá rẻ
+        let mut parent = g.hash;
+        for h in 1..=3u64 {
+            let (id, outcome) = st.ingest_block(mk_empty_block(parent, Height(h), h)).unwrap();
+            assert_eq!(outcome, IngestOutcome::StoredConnected);
+            parent = id;
+        }
+        assert_eq!(st.tip, g);
+        assert!(!st.total_work_meets(64).unwrap());
+        st.recompute_best_chain().unwrap();
+        assert_eq!(st.tip, g);
+
+        // thêm 1 block 6 bit (work 64) => nhánh đủ ngưỡng, nối cả nhánh lên tip
+        let (id, outcome) = st.ingest_block(mine_block(parent, Height(4), 6, 0)).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert_eq!(st.tip.hash, id);
+        assert!(st.total_work_meets(64).unwrap());
+        assert!(!st.total_work_meets(u128::MAX).unwrap());
+        assert_eq!(st.canon_hash(Height(1)).unwrap(), st.store().get_children(g.hash).unwrap().first().copied());
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn caching_store_is_transparent_through_reorg() {
+        let store = egg_db::CachingChainStore::new(DbChainStore::new(MemKv::new()), 4);
+        let mut st = ChainState::open_or_init(store.clone(), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        // nhánh a dài 2, rồi nhánh b dài 3 => reorg; cache nhỏ hơn số header để có eviction
+        let mut parent = g;
+        for h in 1..=2 {
+            let b = mk_empty_block(parent, Height(h), 100 + h);
+            parent = header_id(&b.header);
+            st.ingest_block(b).unwrap();
+        }
+        let mut parent = g;
+        for h in 1..=3 {
+            let b = mk_empty_block(parent, Height(h), 200 + h);
+            parent = header_id(&b.header);
+            st.ingest_block(b).unwrap();
+        }
+        assert_eq!(st.tip.hash, parent);
+        assert_eq!(st.tip.height, Height(3));
+        st.validate_best_chain().unwrap();
+
+        // mở lại trên store không cache thấy cùng trạng thái
+        let plain = ChainState::open_or_init(store.inner().clone(), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(plain.tip, st.tip);
+        plain.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn resolve_header_by_hash_and_height_agree() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+
+        let b1 = mk_empty_block(g, Height(1), 1);
+        let id1 = header_id(&b1.header);
+        st.ingest_block(b1.clone()).unwrap();
+        // nhánh phụ: resolve theo hash vẫn thấy, theo height thì không
+        let side = mk_empty_block(g, Height(1), 2);
+        let side_id = header_id(&side.header);
+        st.ingest_block(side.clone()).unwrap();
+        let canon1 = st.canon_hash(Height(1)).unwrap().unwrap();
+
+        let by_hash = st.resolve_header(BlockRef::Hash(canon1)).unwrap();
+        let by_height = st.resolve_header(BlockRef::Height(Height(1))).unwrap();
+        assert!(by_hash.is_some());
+        assert_eq!(by_hash, by_height);
+        let stale = if canon1 == id1 { (side_id, side.header) } else { (id1, b1.header) };
+        assert_eq!(st.resolve_header(BlockRef::Hash(stale.0)).unwrap(), Some(stale.1));
+
+        assert_eq!(st.resolve_header(BlockRef::Height(Height(2))).unwrap(), None);
+        assert_eq!(st.resolve_header(BlockRef::Hash(Hash256([0xAB; 32]))).unwrap(), None);
+    }
+
+    #[test]
+    fn mine_and_append_five_blocks_with_real_pow_validates() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::{Transaction, CONTENT_TAG_OPAQUE};
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mut mp = Mempool::new();
+        for i in 0..10u8 {
+            let p = [b't', i];
+            mp.add_tx(Transaction {
+                id: tx_id_from_payload(&p),
+                payload: p.to_vec(),
+                content_tag: CONTENT_TAG_OPAQUE,
+            })
+            .unwrap();
+        }
+
+        let mut prev = st.tip;
+        for h in 1..=5u64 {
+            let id = st.mine_and_append_one(&mut mp, 1_700_000_000 + h as i64 * 60, 8).unwrap();
+            assert_eq!(st.tip, ChainTip { height: Height(h), hash: id });
+            let hdr = st.store().get_header(id).unwrap();
+            assert_eq!(hdr.parent, prev.hash);
+            assert_eq!(hdr.pow_difficulty_bits, 8);
+            // PoW thật: cùng luật miner dùng và validator kiểm
+            assert!(pow_valid(&hdr));
+            prev = st.tip;
+        }
+        assert!(mp.is_empty());
+        assert_eq!(st.chain_work_of(st.tip.hash).unwrap(), 1 + 5 * 256);
+        st.validate_best_chain().unwrap();
+
+        // mở lại từ store vẫn validate với PoW thật
+        let reopened = ChainState::open_or_init(st.store().clone(), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(reopened.tip, st.tip);
+        reopened.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn purge_invalid_markers_allows_reingest() {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        let a = mk_empty_block(g, Height(1), 1);
+        let b = mk_empty_block(g, Height(1), 2);
+        let (a_id, b_id) = (header_id(&a.header), header_id(&b.header));
+
+        // như luật cũ coi 2 block này là invalid
+        st.mark_block_invalid(a_id).unwrap();
+        st.mark_block_invalid(b_id).unwrap();
+        for blk in [a.clone(), b.clone()] {
+            assert!(matches!(st.ingest_block(blk), Err(ChainStateError::KnownInvalid { .. })));
+        }
+        assert_eq!(st.tip.hash, g);
+
+        // marker lưu trong store: mở lại vẫn còn
+        let mut st = ChainState::open_or_init(st.store().clone(), mk_spec(1_700_000_000)).unwrap();
+        assert!(st.is_block_invalid(a_id) && st.is_block_invalid(b_id));
+
+        assert_eq!(st.purge_invalid_markers().unwrap(), 2);
+        assert!(!st.is_block_invalid(a_id) && !st.is_block_invalid(b_id));
+        assert!(st.store().invalid_markers().unwrap().is_empty());
+        assert_eq!(st.purge_invalid_markers().unwrap(), 0);
+
+        // purge cũng không còn sau restart
+        let mut st = ChainState::open_or_init(st.store().clone(), mk_spec(1_700_000_000)).unwrap();
+        assert!(!st.is_block_invalid(a_id) && !st.is_block_invalid(b_id));
+
+        assert_eq!(st.ingest_block(a).unwrap(), (a_id, IngestOutcome::NewTip));
+        assert_eq!(st.ingest_block(b).unwrap().0, b_id);
+        assert_eq!(st.tip.height, Height(1));
+        assert!(st.is_on_best_chain(a_id.min(b_id)).unwrap());
+    }
+
+    #[test]
+    fn inclusion_latency_recorded_when_block_mines_mempool_tx() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::{Transaction, CONTENT_TAG_OPAQUE};
+        use std::sync::atomic::AtomicI64;
+
+        let mk_tx = |p: &[u8]| Transaction {
+            id: tx_id_from_payload(p),
+            payload: p.to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+        let now = Arc::new(AtomicI64::new(1_700_000_000));
+        let clock: Clock = {
+            let now = Arc::clone(&now);
+            Arc::new(move || now.load(Ordering::SeqCst))
+        };
+
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        st.set_clock(Arc::clone(&clock));
+        let mut mp = Mempool::with_config(crate::mempool::MempoolConfig {
+            clock: Some(clock),
+            ..Default::default()
+        });
+        let (a, b) = (mk_tx(b"a"), mk_tx(b"b"));
+        mp.add_tx(a.clone()).unwrap();
+        now.fetch_add(30, Ordering::SeqCst);
+        mp.add_tx(b.clone()).unwrap();
+        assert_eq!(mp.inserted_at(a.id), Some(1_700_000_000));
+
+        // ~10 phút sau block chứa a, b (và 1 tx ngoài mempool) tới
+        now.fetch_add(570, Ordering::SeqCst);
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.txs = vec![a.clone(), b.clone(), mk_tx(b"private")];
+        blk.header.merkle_root = merkle_root_txids(&blk.txs.iter().map(|t| t.id).collect::<Vec<_>>());
+        let (_, outcome) = st.ingest_block_and_update_mempool(blk, &mut mp).unwrap();
+        assert_eq!(outcome, IngestOutcome::NewTip);
+        assert!(mp.is_empty());
+
+        let stats = st.mempool_inclusion_stats();
+        assert_eq!(stats.count, 2);
+        assert_eq!((stats.min_secs, stats.max_secs, stats.mean_secs), (570, 600, 585));
+        let counted: Vec<_> = stats.buckets.iter().filter(|(_, n)| *n > 0).copied().collect();
+        assert_eq!(counted, vec![(600, 2)]);
+
+        // block nhánh phụ (thấp hơn tip) không tính
+        st.ingest_block(mk_empty_block(st.tip.hash, Height(2), 2)).unwrap();
+        let c = mk_tx(b"c");
+        mp.add_tx(c.clone()).unwrap();
+        let mut side = mk_empty_block(st.canon_hash(Height(0)).unwrap().unwrap(), Height(1), 99);
+        side.txs = vec![c.clone()];
+        side.header.merkle_root = merkle_root_txids(&[c.id]);
+        let (side_id, _) = st.ingest_block_and_update_mempool(side, &mut mp).unwrap();
+        assert!(!st.is_on_best_chain(side_id).unwrap());
+        assert!(mp.contains(c.id));
+        assert_eq!(st.mempool_inclusion_stats().count, 2);
+    }
+
+    /// Chain b1 b2 b3 + nhánh phụ dài hơn từ b1: b2' b3' b4' (`bad_tip` => b4' chứa tx lệch merkle root).
+    fn adopt_fixture(bad_tip: bool) -> (ChainState<DbChainStore<MemKv>>, Vec<Block>) {
+        let mut st =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let g = st.tip.hash;
+        let (b1, _) = st.ingest_block(mk_empty_block(g, Height(1), 1)).unwrap();
+        let (b2, _) = st.ingest_block(mk_empty_block(b1, Height(2), 2)).unwrap();
+        st.ingest_block(mk_empty_block(b2, Height(3), 3)).unwrap();
+
+        let mut side = Vec::new();
+        let mut parent = b1;
+        for h in 2..=4 {
+            let b = mk_empty_block(parent, Height(h), 100 + h);
+            parent = header_id(&b.header);
+            side.push(b);
+        }
+        if bad_tip {
+            side[2].txs.push(egg_types::Transaction {
+                id: Hash256([7u8; 32]),
+                payload: b"x".to_vec(),
+                content_tag: egg_types::CONTENT_TAG_OPAQUE,
+            });
+        }
+        (st, side)
+    }
+
+    #[test]
+    fn adopt_chain_reorgs_to_verified_side_chain() {
+        let (mut st, side) = adopt_fixture(false);
+        let old_tip = st.tip;
+        let headers: Vec<BlockHeader> = side.iter().map(|b| b.header.clone()).collect();
+        let side_tip = header_id(&headers[2]);
+
+        // thiếu body của block cuối => từ chối trước khi ghi
+        let short = side[..2].to_vec();
+        assert!(matches!(
+            st.adopt_chain(headers.clone(), short, side_tip),
+            Err(ChainStateError::MissingBlock { id }) if id == side_tip
+        ));
+        assert_eq!(st.tip, old_tip);
+
+        st.adopt_chain(headers.clone(), side, side_tip).unwrap();
+        assert_eq!(st.tip.hash, side_tip);
+        assert_eq!(st.tip.height, Height(4));
+        assert_eq!(st.canon_hash(Height(2)).unwrap(), Some(header_id(&headers[0])));
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn adopt_chain_with_bad_block_leaves_local_chain_untouched() {
+        let (mut st, side) = adopt_fixture(true);
+        let old_tip = st.tip;
+        let old_canon: Vec<_> = (0..=3).map(|h| st.canon_hash(Height(h)).unwrap()).collect();
+        let headers: Vec<BlockHeader> = side.iter().map(|b| b.header.clone()).collect();
+        let side_tip = header_id(&headers[2]);
+
+        let err = st.adopt_chain(headers.clone(), side, side_tip).unwrap_err();
+        assert!(err.is_peer_fault(), "got {err:?}");
+
+        // b2', b3' hợp lệ nhưng cũng không được ghi
+        assert_eq!(st.tip, old_tip);
+        for h in &headers {
+            assert!(!st.store().has_header(header_id(h)).unwrap());
+            assert!(!st.store().has_block(header_id(h)).unwrap());
+        }
+        let canon: Vec<_> = (0..=3).map(|h| st.canon_hash(Height(h)).unwrap()).collect();
+        assert_eq!(canon, old_canon);
+        assert_eq!(st.canon_hash(Height(4)).unwrap(), None);
+
+        let reopened = ChainState::open_or_init(st.store().clone(), mk_spec(1_700_000_000)).unwrap();
+        assert_eq!(reopened.tip, old_tip);
+    }
+
+    #[test]
+    fn failed_atomically_restores_in_memory_state() {
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store, mk_spec(1_700_000_000)).unwrap();
+        st.mark_block_invalid(Hash256([1u8; 32])).unwrap();
+        let (a, b, c) = (Hash256([2u8; 32]), Hash256([3u8; 32]), Hash256([4u8; 32]));
+
+        let err = st
+            .atomically(|st| {
+                st.orphans.insert(a, b);
+                st.invalid_blocks.insert(c);
+                st.inclusion_latency.push_back(7);
+                Err::<(), _>(ChainStateError::KnownInvalid { id: c })
+            })
+            .unwrap_err();
+        assert!(matches!(err, ChainStateError::KnownInvalid { .. }));
+        assert_eq!(st.orphan_count(), 0);
+        assert!(st.is_block_invalid(Hash256([1u8; 32])));
+        assert!(!st.is_block_invalid(c));
+        assert!(st.inclusion_latency.is_empty());
+        // batch đã đóng: ingest tiếp vẫn mở được batch mới
+        st.atomically(|_| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn assume_valid_set_before_headers_arrive() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut src =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let mut ids = Vec::new();
+        for h in 1..=4u64 {
+            let (id, _) = src.ingest_block(mk_empty_block(src.tip.hash, Height(h), h)).unwrap();
+            ids.push(id);
+        }
+
+        let mut ibd =
+            ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec(1_700_000_000)).unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        ibd.set_full_verify_hook(Arc::new(move |_| {
+            c.fetch_add(1, Ordering::SeqCst);
+        }));
+        // chưa có header của assume-valid: block 1 kiểm đầy đủ, không cache chain dở dang
+        ibd.set_assume_valid(ids[2]);
+        ibd.ingest_header(src.store().get_header(ids[0]).unwrap()).unwrap();
+        ibd.ingest_block(src.store().get_block(ids[0]).unwrap()).unwrap();
+        assert_eq!(count.swap(0, Ordering::SeqCst), 1);
+
+        for id in &ids[1..] {
+            ibd.ingest_header(src.store().get_header(*id).unwrap()).unwrap();
+        }
+        for id in &ids[1..] {
+            ibd.ingest_block(src.store().get_block(*id).unwrap()).unwrap();
+        }
+        assert_eq!(ibd.tip.hash, ids[3]);
+        // chỉ block 4 (trên assume-valid) kiểm đầy đủ
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
 }
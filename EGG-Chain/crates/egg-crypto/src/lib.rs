@@ -34,18 +34,24 @@ pub fn hash_header(header: &BlockHeader) -> Hash256 {
     hash_domain(DOMAIN_BLOCK_HEADER, &enc)
 }
 
-/// TxID chuẩn: băm canonical tx-body (payload) KHÔNG chứa tx.id.
+/// TxID chuẩn: băm canonical tx-body (content tag + payload) KHÔNG chứa tx.id.
 pub fn hash_tx(tx: &Transaction) -> Hash256 {
-    tx_id_from_payload(&tx.payload)
+    tx_id_from_tagged_payload(tx.content_tag, &tx.payload)
 }
 
+/// TxID của tx opaque (content tag mặc định).
 pub fn tx_id_from_payload(payload: &[u8]) -> Hash256 {
     let enc = canonical::encode_tx_body(payload);
     hash_domain(DOMAIN_TX, &enc)
 }
 
+pub fn tx_id_from_tagged_payload(content_tag: u8, payload: &[u8]) -> Hash256 {
+    let enc = canonical::encode_tx_body_tagged(content_tag, payload);
+    hash_domain(DOMAIN_TX, &enc)
+}
+
 pub fn validate_tx_id(tx: &Transaction) -> bool {
-    tx.id == tx_id_from_payload(&tx.payload)
+    tx.id == hash_tx(tx)
 }
 
 pub fn hash_block(block: &Block) -> Hash256 {
@@ -71,10 +77,95 @@ pub fn leading_zero_bits(h: &Hash256) -> u32 {
     count
 }
 
+/// PoW target 256-bit big-endian: hash hợp lệ khi `hash <= target`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Target(pub [u8; 32]);
+
+impl Target {
+    /// Target không thể đạt (trừ hash toàn 0), dùng cho compact âm/tràn.
+    pub const ZERO: Target = Target([0u8; 32]);
+
+    /// Compact kiểu nBits: byte cao = số byte của target (size), 23 bit thấp = mantissa;
+    /// target = mantissa * 256^(size - 3). Bit dấu (0x0080_0000) hoặc tràn 256 bit => `ZERO`.
+    pub fn from_compact(compact: u32) -> Target {
+        if compact & 0x0080_0000 != 0 {
+            return Target::ZERO;
+        }
+        let size = (compact >> 24) as i32;
+        let mantissa = compact & 0x007f_ffff;
+        let mut out = [0u8; 32];
+        for i in 0..3i32 {
+            let byte = ((mantissa >> (8 * (2 - i))) & 0xff) as u8;
+            // vị trí byte tính từ byte thấp nhất
+            let pos = size - 1 - i;
+            if pos < 0 {
+                continue;
+            }
+            if pos >= 32 {
+                if byte != 0 {
+                    return Target::ZERO;
+                }
+                continue;
+            }
+            out[31 - pos as usize] = byte;
+        }
+        Target(out)
+    }
+
+    /// Ngược của `from_compact` (chuẩn hoá: mantissa không bật bit dấu; mất độ chính xác ngoài 3 byte đầu).
+    pub fn to_compact(&self) -> u32 {
+        let Some(first) = self.0.iter().position(|&b| b != 0) else {
+            return 0;
+        };
+        let mut size = (32 - first) as u32;
+        let byte_at = |i: usize| self.0.get(i).copied().unwrap_or(0) as u32;
+        let mut mantissa = (byte_at(first) << 16) | (byte_at(first + 1) << 8) | byte_at(first + 2);
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        (size << 24) | mantissa
+    }
+
+    /// Target tương đương điều kiện cũ `leading_zero_bits(hash) >= bits`.
+    pub fn from_leading_zero_bits(bits: u32) -> Target {
+        if bits >= 256 {
+            return Target::ZERO;
+        }
+        let mut out = [0xffu8; 32];
+        let full = (bits / 8) as usize;
+        for b in out.iter_mut().take(full) {
+            *b = 0;
+        }
+        if full < 32 {
+            out[full] = 0xff >> (bits % 8);
+        }
+        Target(out)
+    }
+
+    /// Diễn giải `pow_difficulty_bits` của header: size byte = 0 (giá trị < 2^24) là số bit 0
+    /// đầu kiểu cũ (chain hiện có giữ nguyên hợp lệ), ngược lại là compact target.
+    pub fn from_pow_bits(bits: u32) -> Target {
+        if bits >> 24 == 0 {
+            Target::from_leading_zero_bits(bits)
+        } else {
+            Target::from_compact(bits)
+        }
+    }
+}
+
+/// So sánh big-endian 256-bit: `hash <= target`.
+pub fn meets_target(hash: &Hash256, target: &Target) -> bool {
+    hash.0 <= target.0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use egg_types::{ChainParams, GenesisSpec, Height, Hash256};
+    use egg_types::{
+        ChainParams, ContentType, GenesisSpec, Hash256, Height, CONTENT_TAG_DATA_BLOB,
+        CONTENT_TAG_OPAQUE, CONTENT_TAG_TRANSFER,
+    };
 
     #[test]
     fn hash_is_deterministic_for_header() {
@@ -102,6 +193,7 @@ mod tests {
         let tx = Transaction {
             id: Hash256::zero(),
             payload: b"hello".to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
         };
 
         let a = hash_header(&h);
@@ -134,6 +226,7 @@ mod tests {
             txs: vec![Transaction {
                 id: Hash256::zero(),
                 payload: vec![1, 2, 3],
+                content_tag: CONTENT_TAG_OPAQUE,
             }],
         };
         assert_eq!(hash_block(&b), hash_block(&b));
@@ -163,10 +256,12 @@ mod tests {
         let a = Transaction {
             id: Hash256([1u8; 32]),
             payload: payload.clone(),
+            content_tag: CONTENT_TAG_OPAQUE,
         };
         let b = Transaction {
             id: Hash256([2u8; 32]),
             payload: payload.clone(),
+            content_tag: CONTENT_TAG_OPAQUE,
         };
 
         assert_eq!(hash_tx(&a), hash_tx(&b));
@@ -177,10 +272,107 @@ mod tests {
     fn validate_tx_id_works() {
         let payload = b"p".to_vec();
         let id = tx_id_from_payload(&payload);
-        let ok = Transaction { id, payload: payload.clone() };
+        let ok = Transaction {
+            id,
+            payload: payload.clone(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
         assert!(validate_tx_id(&ok));
 
-        let bad = Transaction { id: Hash256([9u8; 32]), payload };
+        let bad = Transaction {
+            id: Hash256([9u8; 32]),
+            payload,
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
         assert!(!validate_tx_id(&bad));
     }
+
+    #[test]
+    fn content_tag_is_bound_into_txid() {
+        let payload = b"same-bytes".to_vec();
+
+        let transfer = Transaction {
+            id: tx_id_from_tagged_payload(CONTENT_TAG_TRANSFER, &payload),
+            payload: payload.clone(),
+            content_tag: CONTENT_TAG_TRANSFER,
+        };
+        let blob = Transaction {
+            id: tx_id_from_tagged_payload(CONTENT_TAG_DATA_BLOB, &payload),
+            payload: payload.clone(),
+            content_tag: CONTENT_TAG_DATA_BLOB,
+        };
+
+        assert_ne!(transfer.id, blob.id);
+        assert_ne!(transfer.id, tx_id_from_payload(&payload));
+        assert!(validate_tx_id(&transfer));
+        assert!(validate_tx_id(&blob));
+        assert_eq!(transfer.content_type(), ContentType::Transfer);
+        assert_eq!(blob.content_type(), ContentType::DataBlob);
+
+        // tx opaque giữ nguyên txid cũ
+        assert_eq!(
+            tx_id_from_tagged_payload(CONTENT_TAG_OPAQUE, &payload),
+            tx_id_from_payload(&payload)
+        );
+
+        for tx in [transfer, blob] {
+            let back = canonical::decode_tx(&canonical::encode_tx(&tx)).unwrap();
+            assert_eq!(back, tx);
+            assert!(validate_tx_id(&back));
+
+            let body = canonical::encode_tx_body_tagged(tx.content_tag, &tx.payload);
+            let (tag, p) = canonical::decode_tx_body_tagged(&body).unwrap();
+            assert_eq!(tag, tx.content_tag);
+            assert_eq!(p, tx.payload);
+        }
+    }
+
+    #[test]
+    fn compact_target_roundtrip() {
+        // 0x1d00ffff: target = 0xffff * 256^(0x1d - 3)
+        let t = Target::from_compact(0x1d00_ffff);
+        let mut expect = [0u8; 32];
+        expect[32 - 0x1d + 1] = 0xff;
+        expect[32 - 0x1d + 2] = 0xff;
+        assert_eq!(t, Target(expect));
+        assert_eq!(t.to_compact(), 0x1d00_ffff);
+
+        // size nhỏ: mantissa bị dịch phải
+        assert_eq!(Target::from_compact(0x0212_3456).to_compact(), 0x0212_3400);
+        // bit dấu / tràn => ZERO
+        assert_eq!(Target::from_compact(0x1d80_0000), Target::ZERO);
+        assert_eq!(Target::from_compact(0x2200_0101), Target::ZERO);
+        assert_eq!(Target::ZERO.to_compact(), 0);
+    }
+
+    #[test]
+    fn meets_target_just_below_and_above() {
+        let t = Target::from_compact(0x1f12_3456);
+        let mut at = t.0;
+        assert!(meets_target(&Hash256(at), &t));
+
+        // ngay dưới target
+        at[3] -= 1;
+        assert!(meets_target(&Hash256(at), &t));
+
+        // ngay trên target
+        let mut above = t.0;
+        above[31] = 1;
+        assert!(!meets_target(&Hash256(above), &t));
+    }
+
+    #[test]
+    fn legacy_zero_bits_match_leading_zero_bits() {
+        let mut h = [0xffu8; 32];
+        h[0] = 0b0001_0000; // 3 bit 0 đầu
+        let h = Hash256(h);
+        for bits in 0..=8 {
+            assert_eq!(
+                meets_target(&h, &Target::from_pow_bits(bits)),
+                leading_zero_bits(&h) >= bits,
+                "bits={bits}"
+            );
+        }
+        assert!(!meets_target(&h, &Target::from_pow_bits(255)));
+    }
 }
@@ -4,24 +4,48 @@ use egg_types::Hash256;
 
 use crate::{hash_domain, DOMAIN_MERKLE};
 
+/// Phiên bản cây merkle. V1: tách domain lá (0x00) / node trong (0x01), root khác V0.
+///
+/// Chỉ để tham chiếu (log, tài liệu): không có cổng consensus nào chọn scheme theo hằng này.
+/// Lên V1 là hard reset — block có merkle root V0 không còn qua `verify_block_merkle`,
+/// chain cũ phải dựng lại từ genesis.
+pub const MERKLE_VERSION: u8 = 1;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+fn merkle_leaf(data: &[u8]) -> Hash256 {
+    let mut buf = Vec::with_capacity(1 + data.len());
+    buf.push(LEAF_PREFIX);
+    buf.extend_from_slice(data);
+    hash_domain(DOMAIN_MERKLE, &buf)
+}
+
 fn merkle_parent(left: Hash256, right: Hash256) -> Hash256 {
-    let mut buf = [0u8; 64];
-    buf[0..32].copy_from_slice(&left.0);
-    buf[32..64].copy_from_slice(&right.0);
+    let mut buf = [0u8; 65];
+    buf[0] = NODE_PREFIX;
+    buf[1..33].copy_from_slice(&left.0);
+    buf[33..65].copy_from_slice(&right.0);
     hash_domain(DOMAIN_MERKLE, &buf)
 }
 
 /// Merkle root deterministic cho danh sách TxID.
 /// - Nếu danh sách rỗng => Hash256::zero()
+/// - Nếu chỉ có 1 TxID => chính TxID đó
 /// - Nếu số lá lẻ => duplicate lá cuối
+/// - Lá băm `0x00 || txid`, node trong băm `0x01 || trái || phải` (V1, xem `MERKLE_VERSION`)
 pub fn merkle_root_txids(txids: &[Hash256]) -> Hash256 {
     if txids.is_empty() {
         return Hash256::zero();
     }
 
-    let mut layer: Vec<Hash256> = txids.to_vec();
+    if txids.len() == 1 {
+        return txids[0];
+    }
+
+    let mut layer: Vec<Hash256> = txids.iter().map(|id| merkle_leaf(&id.0)).collect();
     while layer.len() > 1 {
-        let mut next = Vec::with_capacity((layer.len() + 1) / 2);
+        let mut next = Vec::with_capacity(layer.len().div_ceil(2));
         for pair in layer.chunks(2) {
             let l = pair[0];
             let r = if pair.len() == 2 { pair[1] } else { pair[0] };
@@ -70,4 +94,22 @@ mod tests {
         let r2 = merkle_root_txids(&[a, b]);
         assert_eq!(r1, r2);
     }
+
+    #[test]
+    fn crafted_64_byte_leaf_is_not_internal_node() {
+        let a = h(1);
+        let b = h(2);
+        let (la, lb) = (merkle_leaf(&a.0), merkle_leaf(&b.0));
+        let node = merkle_parent(la, lb);
+        assert_eq!(merkle_root_txids(&[a, b]), node);
+
+        // "tx" 64 byte = ghép 2 con của node trong: hash lá không được trùng node
+        let mut crafted = [0u8; 64];
+        crafted[..32].copy_from_slice(&la.0);
+        crafted[32..].copy_from_slice(&lb.0);
+        assert_ne!(merkle_leaf(&crafted), node);
+
+        // lá 32 byte (txid) cũng không trùng node trong có cùng nội dung
+        assert_ne!(merkle_leaf(&a.0), merkle_parent(a, a));
+    }
 }
@@ -0,0 +1,472 @@
+#![forbid(unsafe_code)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use egg_types::{Block, BlockHeader, Hash256, Height};
+
+use crate::store::{BlockMeta, BlockStore, ChainMeta, ChainStore, ChainTip, Result, StoreSchemaVersion, TxLocation};
+
+/// Số entry mặc định mỗi cache (header, bmeta) của `CachingChainStore`.
+pub const DEFAULT_CACHE_ENTRIES: usize = 4096;
+
+/// LRU nhỏ theo id: `order` xếp theo lần dùng gần nhất (tick tăng dần), đầu map = cũ nhất.
+struct Lru<V> {
+    cap: usize,
+    tick: u64,
+    map: HashMap<Hash256, (V, u64)>,
+    order: BTreeMap<u64, Hash256>,
+}
+
+impl<V: Clone> Lru<V> {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            tick: 0,
+            map: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, id: &Hash256) -> Option<V> {
+        let (v, t) = self.map.get_mut(id)?;
+        self.order.remove(t);
+        self.tick += 1;
+        *t = self.tick;
+        self.order.insert(self.tick, *id);
+        Some(v.clone())
+    }
+
+    fn insert(&mut self, id: Hash256, v: V) {
+        if self.cap == 0 {
+            return;
+        }
+        self.remove(&id);
+        while self.map.len() >= self.cap {
+            let Some((_, old)) = self.order.pop_first() else {
+                break;
+            };
+            self.map.remove(&old);
+        }
+        self.tick += 1;
+        self.map.insert(id, (v, self.tick));
+        self.order.insert(self.tick, id);
+    }
+
+    fn remove(&mut self, id: &Hash256) {
+        if let Some((_, t)) = self.map.remove(id) {
+            self.order.remove(&t);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+struct Caches {
+    headers: Lru<BlockHeader>,
+    metas: Lru<BlockMeta>,
+}
+
+/// Bọc 1 `ChainStore`, giữ LRU header + bmeta đã decode để reorg / `validate_best_chain`
+/// không đọc lại đĩa. Clone chia sẻ cache (như `DbChainStore` chia sẻ KV), batch thì không.
+///
+/// Cache chỉ nạp khi đọc dữ liệu đã commit; `put_*` / `del_*` xoá entry tương ứng. Trong batch
+/// handle này bỏ qua cache (không đọc, không nạp) và ghi nhớ id đã ghi để xoá khỏi cache sau
+/// commit, nên write đang gom không lọt sang clone khác.
+pub struct CachingChainStore<S: ChainStore> {
+    inner: S,
+    caches: Arc<Mutex<Caches>>,
+    // Some = đang trong batch: id header / bmeta đã ghi
+    batch: Mutex<Option<(Vec<Hash256>, Vec<Hash256>)>>,
+}
+
+impl<S: ChainStore + Clone> Clone for CachingChainStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            caches: Arc::clone(&self.caches),
+            batch: Mutex::default(),
+        }
+    }
+}
+
+impl<S: ChainStore> CachingChainStore<S> {
+    /// `capacity` = số entry tối đa mỗi cache; 0 = không cache.
+    pub fn new(inner: S, capacity: usize) -> Self {
+        Self {
+            inner,
+            caches: Arc::new(Mutex::new(Caches {
+                headers: Lru::new(capacity),
+                metas: Lru::new(capacity),
+            })),
+            batch: Mutex::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// (số header, số bmeta) đang cache.
+    pub fn cached_len(&self) -> (usize, usize) {
+        let c = self.caches.lock().expect("mutex poisoned");
+        (c.headers.len(), c.metas.len())
+    }
+
+    fn caches(&self) -> std::sync::MutexGuard<'_, Caches> {
+        self.caches.lock().expect("mutex poisoned")
+    }
+
+    fn in_batch(&self) -> bool {
+        self.batch.lock().expect("mutex poisoned").is_some()
+    }
+
+    fn touch_header(&self, id: Hash256) {
+        if let Some((headers, _)) = self.batch.lock().expect("mutex poisoned").as_mut() {
+            headers.push(id);
+        }
+        self.caches().headers.remove(&id);
+    }
+
+    fn touch_meta(&self, id: Hash256) {
+        if let Some((_, metas)) = self.batch.lock().expect("mutex poisoned").as_mut() {
+            metas.push(id);
+        }
+        self.caches().metas.remove(&id);
+    }
+}
+
+impl<S: ChainStore> BlockStore for CachingChainStore<S> {
+    fn put_header(&self, id: Hash256, header: &BlockHeader) -> Result<()> {
+        self.touch_header(id);
+        self.inner.put_header(id, header)
+    }
+
+    fn get_header(&self, id: Hash256) -> Result<BlockHeader> {
+        if self.in_batch() {
+            return self.inner.get_header(id);
+        }
+        if let Some(h) = self.caches().headers.get(&id) {
+            return Ok(h);
+        }
+        let h = self.inner.get_header(id)?;
+        self.caches().headers.insert(id, h.clone());
+        Ok(h)
+    }
+
+    fn get_header_bytes(&self, id: Hash256) -> Result<Vec<u8>> {
+        self.inner.get_header_bytes(id)
+    }
+
+    fn has_header(&self, id: Hash256) -> Result<bool> {
+        if !self.in_batch() && self.caches().headers.get(&id).is_some() {
+            return Ok(true);
+        }
+        self.inner.has_header(id)
+    }
+
+    fn put_block(&self, id: Hash256, block: &Block) -> Result<()> {
+        self.inner.put_block(id, block)
+    }
+
+    fn get_block(&self, id: Hash256) -> Result<Block> {
+        self.inner.get_block(id)
+    }
+
+    fn has_block(&self, id: Hash256) -> Result<bool> {
+        self.inner.has_block(id)
+    }
+
+    fn del_header(&self, id: Hash256) -> Result<()> {
+        self.touch_header(id);
+        self.inner.del_header(id)
+    }
+
+    fn del_block(&self, id: Hash256) -> Result<()> {
+        self.inner.del_block(id)
+    }
+}
+
+impl<S: ChainStore> ChainStore for CachingChainStore<S> {
+    fn set_tip(&self, tip: ChainTip) -> Result<()> {
+        self.inner.set_tip(tip)
+    }
+
+    fn get_tip(&self) -> Result<Option<ChainTip>> {
+        self.inner.get_tip()
+    }
+
+    fn set_meta(&self, meta: ChainMeta) -> Result<()> {
+        self.inner.set_meta(meta)
+    }
+
+    fn get_meta(&self) -> Result<Option<ChainMeta>> {
+        self.inner.get_meta()
+    }
+
+    fn put_block_meta(&self, id: Hash256, meta: BlockMeta) -> Result<()> {
+        self.touch_meta(id);
+        self.inner.put_block_meta(id, meta)
+    }
+
+    fn get_block_meta(&self, id: Hash256) -> Result<Option<BlockMeta>> {
+        if self.in_batch() {
+            return self.inner.get_block_meta(id);
+        }
+        if let Some(m) = self.caches().metas.get(&id) {
+            return Ok(Some(m));
+        }
+        // không cache None: bmeta thường được ghi ngay sau lần hỏi đầu
+        let m = self.inner.get_block_meta(id)?;
+        if let Some(m) = m {
+            self.caches().metas.insert(id, m);
+        }
+        Ok(m)
+    }
+
+    fn del_block_meta(&self, id: Hash256) -> Result<()> {
+        self.touch_meta(id);
+        self.inner.del_block_meta(id)
+    }
+
+    fn add_child(&self, parent: Hash256, child: Hash256) -> Result<()> {
+        self.inner.add_child(parent, child)
+    }
+
+    fn get_children(&self, parent: Hash256) -> Result<Vec<Hash256>> {
+        self.inner.get_children(parent)
+    }
+
+    fn remove_child(&self, parent: Hash256, child: Hash256) -> Result<()> {
+        self.inner.remove_child(parent, child)
+    }
+
+    fn set_canon_hash(&self, height: Height, hash: Hash256) -> Result<()> {
+        self.inner.set_canon_hash(height, hash)
+    }
+
+    fn get_canon_hash(&self, height: Height) -> Result<Option<Hash256>> {
+        self.inner.get_canon_hash(height)
+    }
+
+    fn del_canon_hash(&self, height: Height) -> Result<()> {
+        self.inner.del_canon_hash(height)
+    }
+
+    fn set_schema_version(&self, v: StoreSchemaVersion) -> Result<()> {
+        self.inner.set_schema_version(v)
+    }
+
+    fn get_schema_version(&self) -> Result<Option<StoreSchemaVersion>> {
+        self.inner.get_schema_version()
+    }
+
+    fn put_tx_location(&self, txid: Hash256, loc: TxLocation) -> Result<()> {
+        self.inner.put_tx_location(txid, loc)
+    }
+
+    fn get_tx_location(&self, txid: Hash256) -> Result<Option<TxLocation>> {
+        self.inner.get_tx_location(txid)
+    }
+
+    fn put_invalid_marker(&self, id: Hash256) -> Result<()> {
+        self.inner.put_invalid_marker(id)
+    }
+
+    fn del_invalid_marker(&self, id: Hash256) -> Result<()> {
+        self.inner.del_invalid_marker(id)
+    }
+
+    fn invalid_markers(&self) -> Result<Vec<Hash256>> {
+        self.inner.invalid_markers()
+    }
+
+    fn begin_batch(&self) -> Result<()> {
+        self.inner.begin_batch()?;
+        *self.batch.lock().expect("mutex poisoned") = Some(Default::default());
+        Ok(())
+    }
+
+    fn commit_batch(&self) -> Result<()> {
+        let res = self.inner.commit_batch();
+        // clone khác có thể đã nạp bản cũ giữa lúc gom và lúc commit
+        if let Some((headers, metas)) = self.batch.lock().expect("mutex poisoned").take() {
+            let mut c = self.caches();
+            for id in &headers {
+                c.headers.remove(id);
+            }
+            for id in &metas {
+                c.metas.remove(id);
+            }
+        }
+        res
+    }
+
+    fn abort_batch(&self) {
+        self.inner.abort_batch();
+        // cache chỉ chứa dữ liệu đã commit nên không cần xoá
+        self.batch.lock().expect("mutex poisoned").take();
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.inner.compact()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::DbChainStore;
+    use crate::{KvOp, KvStore, MemKv};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// MemKv đếm số lần `get`.
+    #[derive(Clone, Default)]
+    struct CountingKv {
+        inner: MemKv,
+        gets: Arc<AtomicUsize>,
+    }
+
+    impl CountingKv {
+        fn gets(&self) -> usize {
+            self.gets.load(Ordering::SeqCst)
+        }
+    }
+
+    impl KvStore for CountingKv {
+        fn get(&self, key: &[u8]) -> crate::Result<Vec<u8>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.inner.get(key)
+        }
+        fn put(&self, key: Vec<u8>, value: Vec<u8>) -> crate::Result<()> {
+            self.inner.put(key, value)
+        }
+        fn del(&self, key: &[u8]) -> crate::Result<()> {
+            self.inner.del(key)
+        }
+        fn has(&self, key: &[u8]) -> crate::Result<bool> {
+            self.inner.has(key)
+        }
+        fn batch(&self, ops: Vec<KvOp>) -> crate::Result<()> {
+            self.inner.batch(ops)
+        }
+        fn scan_prefix(&self, prefix: &[u8]) -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            self.inner.scan_prefix(prefix)
+        }
+    }
+
+    fn header(nonce: u64) -> BlockHeader {
+        BlockHeader {
+            parent: Hash256::zero(),
+            height: Height(1),
+            timestamp_utc: 1_700_000_000,
+            nonce,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: 0,
+        }
+    }
+
+    fn meta(chain_work: u128) -> BlockMeta {
+        BlockMeta {
+            parent: Hash256::zero(),
+            height: Height(1),
+            chain_work,
+        }
+    }
+
+    #[test]
+    fn repeated_reads_hit_cache_and_writes_invalidate() {
+        let kv = CountingKv::default();
+        let store = CachingChainStore::new(DbChainStore::new(kv.clone()), 8);
+        let id = Hash256([1u8; 32]);
+
+        store.put_header(id, &header(1)).unwrap();
+        store.put_block_meta(id, meta(5)).unwrap();
+
+        let before = kv.gets();
+        assert_eq!(store.get_header(id).unwrap(), header(1));
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(5)));
+        assert_eq!(kv.gets(), before + 2);
+
+        // lần 2: không chạm KV
+        assert_eq!(store.get_header(id).unwrap(), header(1));
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(5)));
+        assert_eq!(kv.gets(), before + 2);
+
+        // ghi đè => đọc lại từ KV, thấy giá trị mới
+        store.put_header(id, &header(2)).unwrap();
+        store.put_block_meta(id, meta(9)).unwrap();
+        assert_eq!(store.get_header(id).unwrap(), header(2));
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(9)));
+        assert_eq!(kv.gets(), before + 4);
+
+        store.del_block_meta(id).unwrap();
+        assert_eq!(store.get_block_meta(id).unwrap(), None);
+    }
+
+    #[test]
+    fn cache_is_bounded_and_evicts_least_recently_used() {
+        let kv = CountingKv::default();
+        let store = CachingChainStore::new(DbChainStore::new(kv.clone()), 2);
+        let ids: Vec<_> = (1u8..=3).map(|i| Hash256([i; 32])).collect();
+        for (i, id) in ids.iter().enumerate() {
+            store.put_header(*id, &header(i as u64)).unwrap();
+        }
+
+        store.get_header(ids[0]).unwrap();
+        store.get_header(ids[1]).unwrap();
+        // dùng lại ids[0] => ids[1] thành cũ nhất, bị đẩy khi nạp ids[2]
+        store.get_header(ids[0]).unwrap();
+        store.get_header(ids[2]).unwrap();
+        assert_eq!(store.cached_len().0, 2);
+
+        let before = kv.gets();
+        store.get_header(ids[0]).unwrap();
+        store.get_header(ids[2]).unwrap();
+        assert_eq!(kv.gets(), before);
+        store.get_header(ids[1]).unwrap();
+        assert_eq!(kv.gets(), before + 1);
+    }
+
+    #[test]
+    fn aborted_batch_does_not_leave_stale_entries() {
+        let store = CachingChainStore::new(DbChainStore::new(MemKv::new()), 8);
+        let id = Hash256([4u8; 32]);
+
+        store.begin_batch().unwrap();
+        store.put_header(id, &header(1)).unwrap();
+        store.put_block_meta(id, meta(3)).unwrap();
+        // đọc trong batch thấy write đang gom nhưng không nạp cache
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(3)));
+        assert!(store.get_header(id).is_ok());
+        store.abort_batch();
+
+        assert!(!store.has_header(id).unwrap());
+        assert!(store.get_header(id).is_err());
+        assert_eq!(store.get_block_meta(id).unwrap(), None);
+    }
+
+    #[test]
+    fn batch_writes_do_not_leak_into_shared_cache() {
+        let store = CachingChainStore::new(DbChainStore::new(MemKv::new()), 8);
+        let other = store.clone();
+        let id = Hash256([5u8; 32]);
+        store.put_block_meta(id, meta(1)).unwrap();
+
+        store.begin_batch().unwrap();
+        store.put_block_meta(id, meta(2)).unwrap();
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(2)));
+        // clone đọc bản đã commit và nạp cache
+        assert_eq!(other.get_block_meta(id).unwrap(), Some(meta(1)));
+        store.commit_batch().unwrap();
+
+        assert_eq!(other.get_block_meta(id).unwrap(), Some(meta(2)));
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(meta(2)));
+    }
+}
@@ -7,10 +7,19 @@ use std::{
 
 use thiserror::Error;
 
+pub mod cache;
+pub mod log_kv;
+pub mod reputation;
+#[cfg(feature = "rocksdb")]
+pub mod rocks_kv;
 pub mod sled_kv;
 pub mod store;
 
-pub use sled_kv::SledKv;
+pub use cache::CachingChainStore;
+pub use log_kv::LogKv;
+#[cfg(feature = "rocksdb")]
+pub use rocks_kv::RocksKv;
+pub use sled_kv::{FlushMode, SledKv};
 
 #[derive(Debug, Error)]
 pub enum DbError {
@@ -19,15 +28,49 @@ pub enum DbError {
 
     #[error("sled error: {0}")]
     Sled(#[from] sled::Error),
+
+    #[cfg(feature = "rocksdb")]
+    #[error("rocksdb error: {0}")]
+    Rocks(#[from] rocksdb::Error),
+
+    #[error("database already locked: {0}")]
+    AlreadyLocked(String),
+
+    #[error("database corrupted: {0}")]
+    Corrupted(String),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, DbError>;
 
+/// 1 thao tác ghi trong `KvStore::batch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KvOp {
+    Put(Vec<u8>, Vec<u8>),
+    Del(Vec<u8>),
+}
+
 pub trait KvStore: Send + Sync + 'static {
     fn get(&self, key: &[u8]) -> Result<Vec<u8>>;
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
     fn del(&self, key: &[u8]) -> Result<()>;
     fn has(&self, key: &[u8]) -> Result<bool>;
+    /// Áp dụng `ops` theo thứ tự, nguyên tử: lỗi => không op nào có hiệu lực.
+    fn batch(&self, ops: Vec<KvOp>) -> Result<()>;
+    /// Mọi cặp (key, value) có key bắt đầu bằng `prefix`. `SledKv` trả theo thứ tự key;
+    /// store khác (vd. `MemKv`) không đảm bảo thứ tự, cần thì tự sort.
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    /// Đẩy write còn treo xuống đĩa; store không buffer (vd. `MemKv`) thì no-op.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Thu hồi chỗ của key đã xoá/ghi đè (vd. sau khi prune). Mặc định chỉ flush: store không có
+    /// compaction chủ động (sled tự dọn dần, `MemKv` không có gì để dọn).
+    fn compact(&self) -> Result<()> {
+        self.flush()
+    }
 }
 
 #[derive(Clone, Default)]
@@ -63,6 +106,29 @@ impl KvStore for MemKv {
         let g = self.inner.read().expect("rwlock poisoned");
         Ok(g.contains_key(key))
     }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let g = self.inner.read().expect("rwlock poisoned");
+        Ok(g.iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
+
+    fn batch(&self, ops: Vec<KvOp>) -> Result<()> {
+        let mut g = self.inner.write().expect("rwlock poisoned");
+        for op in ops {
+            match op {
+                KvOp::Put(k, v) => {
+                    g.insert(k, v);
+                }
+                KvOp::Del(k) => {
+                    g.remove(&k);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -70,6 +136,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn memkv_put_get_del() {
         let db = MemKv::new();
 
@@ -84,4 +151,34 @@ mod tests {
         assert_eq!(db.has(b"a").unwrap(), false);
         assert!(matches!(db.get(b"a"), Err(DbError::NotFound)));
     }
+
+    #[test]
+    fn memkv_batch_applies_ops_in_order() {
+        let db = MemKv::new();
+        db.put(b"x".to_vec(), b"0".to_vec()).unwrap();
+
+        db.batch(vec![
+            KvOp::Put(b"a".to_vec(), b"1".to_vec()),
+            KvOp::Del(b"x".to_vec()),
+            KvOp::Put(b"a".to_vec(), b"2".to_vec()),
+        ])
+        .unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"2".to_vec());
+        assert!(!db.has(b"x").unwrap());
+    }
+
+    #[test]
+    fn memkv_scan_prefix_returns_only_matching_keys() {
+        let db = MemKv::new();
+        for k in [&b"p:2"[..], b"p:1", b"q:1", b"p", b"p:3"] {
+            db.put(k.to_vec(), k.to_vec()).unwrap();
+        }
+
+        let mut got = db.scan_prefix(b"p:").unwrap();
+        got.sort();
+        let keys: Vec<_> = got.iter().map(|(k, _)| k.as_slice()).collect();
+        assert_eq!(keys, vec![&b"p:1"[..], b"p:2", b"p:3"]);
+        assert!(got.iter().all(|(k, v)| k == v));
+        assert!(db.scan_prefix(b"z").unwrap().is_empty());
+    }
 }
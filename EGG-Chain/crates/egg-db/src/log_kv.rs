@@ -0,0 +1,478 @@
+#![forbid(unsafe_code)]
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{DbError, KvOp, KvStore, Result};
+
+// val_len = TOMBSTONE => record xoá key (không có val)
+const TOMBSTONE: u32 = u32::MAX;
+// frame_len(u64 BE) || crc32(u32 BE)
+const FRAME_HEADER: usize = 12;
+
+/// KvStore ghi nối đuôi vào 1 file. Mỗi lần ghi (`put`/`del`/`batch`) là 1 frame
+/// `frame_len(u64 BE) || crc32(u32 BE) || records`, mỗi record `key_len(u32 BE) || key || val_len(u32 BE) || val`.
+/// Index trong RAM giữ offset value mới nhất của mỗi key; mở lại file => replay để dựng index,
+/// frame ghi dở / sai checksum ở đuôi bị bỏ cả frame (batch all-or-nothing).
+/// File chỉ lớn dần (phù hợp node archival/export ghi nhiều, ít ghi đè) tới khi gọi `compact`.
+#[derive(Clone)]
+pub struct LogKv {
+    inner: Arc<Mutex<LogInner>>,
+}
+
+struct LogInner {
+    path: PathBuf,
+    file: File,
+    // key -> (offset của value, độ dài value)
+    index: HashMap<Vec<u8>, (u64, u32)>,
+    end: u64,
+}
+
+impl LogKv {
+    /// Mở (hoặc tạo) file log. Frame cuối ghi dở (crash giữa chừng) bị cắt bỏ.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (index, end) = replay(&bytes);
+        if end < bytes.len() as u64 {
+            file.set_len(end)?;
+        }
+
+        Ok(Self {
+            inner: Arc::new(Mutex::new(LogInner {
+                path,
+                file,
+                index,
+                end,
+            })),
+        })
+    }
+
+    /// Viết lại log chỉ với value hiện hành của mỗi key (bỏ tombstone và bản ghi đè) vào file tạm
+    /// cạnh log rồi rename đè lên: crash giữa chừng để lại log cũ nguyên vẹn.
+    pub fn compact(&self) -> Result<()> {
+        let mut g = self.inner.lock().expect("mutex poisoned");
+
+        let mut live: Vec<(Vec<u8>, u64, u32)> =
+            g.index.iter().map(|(k, &(off, len))| (k.clone(), off, len)).collect();
+        live.sort_by_key(|&(_, off, _)| off);
+
+        // toàn bộ value hiện hành thành 1 frame
+        let mut buf = vec![0u8; FRAME_HEADER];
+        let mut index = HashMap::with_capacity(live.len());
+        for (key, off, len) in live {
+            let mut v = vec![0u8; len as usize];
+            g.file.seek(SeekFrom::Start(off))?;
+            g.file.read_exact(&mut v)?;
+            // key/value đã qua append_all nên vừa u32
+            buf.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&key);
+            buf.extend_from_slice(&len.to_be_bytes());
+            index.insert(key, (buf.len() as u64, len));
+            buf.extend_from_slice(&v);
+        }
+        seal_frame(&mut buf);
+
+        let mut tmp_path = g.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&buf)?;
+            tmp.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, &g.path)?;
+
+        g.file = OpenOptions::new().read(true).write(true).open(&g.path)?;
+        g.index = index;
+        g.end = buf.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let g = self.inner.lock().expect("mutex poisoned");
+        g.file.sync_data()?;
+        Ok(())
+    }
+
+    fn append(&self, key: &[u8], value: Option<&[u8]>) -> Result<()> {
+        self.append_all(&[(key, value)])
+    }
+
+    /// Ghi các record thành 1 frame bằng 1 lần `write_all` dưới 1 lock; lỗi => cắt file về cuối
+    /// frame trước, index không đổi.
+    fn append_all(&self, recs: &[(&[u8], Option<&[u8]>)]) -> Result<()> {
+        let too_large = |what: &str| {
+            DbError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("log: {what} too large"),
+            ))
+        };
+        let mut buf = vec![0u8; FRAME_HEADER];
+        let mut entries = Vec::with_capacity(recs.len());
+        for &(key, value) in recs {
+            let key_len = u32::try_from(key.len()).map_err(|_| too_large("key"))?;
+            let val_len = match value {
+                Some(v) => u32::try_from(v.len())
+                    .ok()
+                    .filter(|&n| n != TOMBSTONE)
+                    .ok_or_else(|| too_large("value"))?,
+                None => TOMBSTONE,
+            };
+            entries.push((key, val_len, buf.len() as u64));
+            buf.extend_from_slice(&key_len.to_be_bytes());
+            buf.extend_from_slice(key);
+            buf.extend_from_slice(&val_len.to_be_bytes());
+            if let Some(v) = value {
+                buf.extend_from_slice(v);
+            }
+        }
+        seal_frame(&mut buf);
+
+        let mut g = self.inner.lock().expect("mutex poisoned");
+        let start = g.end;
+        let written = g
+            .file
+            .seek(SeekFrom::Start(start))
+            .and_then(|_| g.file.write_all(&buf));
+        if let Err(e) = written {
+            // phần frame đã ghi không được ở lại file; replay vẫn bỏ frame hỏng nếu cắt cũng lỗi
+            let _ = g.file.set_len(start);
+            return Err(e.into());
+        }
+        g.end = start + buf.len() as u64;
+
+        for (key, val_len, off) in entries {
+            if val_len == TOMBSTONE {
+                g.index.remove(key);
+            } else {
+                let val_off = start + off + 8 + key.len() as u64;
+                g.index.insert(key.to_vec(), (val_off, val_len));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Điền header (độ dài + crc32 phần record) vào `FRAME_HEADER` byte đầu của `buf`.
+fn seal_frame(buf: &mut [u8]) {
+    let (header, body) = buf.split_at_mut(FRAME_HEADER);
+    header[..8].copy_from_slice(&(body.len() as u64).to_be_bytes());
+    header[8..].copy_from_slice(&crc32fast::hash(body).to_be_bytes());
+}
+
+/// Dựng index từ nội dung file; trả thêm offset kết thúc frame hợp lệ cuối cùng.
+/// Dừng ở frame đầu tiên thiếu byte hoặc sai checksum: frame đó không được áp dụng phần nào.
+fn replay(bytes: &[u8]) -> (HashMap<Vec<u8>, (u64, u32)>, u64) {
+    let mut index = HashMap::new();
+    let mut pos = 0usize;
+    while let (Some(len), Some(crc)) = (read_u64(bytes, pos), read_u32(bytes, pos + 8)) {
+        let body_start = pos + FRAME_HEADER;
+        let Some(body) = usize::try_from(len)
+            .ok()
+            .and_then(|len| bytes.get(body_start..body_start.checked_add(len)?))
+        else {
+            break;
+        };
+        if crc32fast::hash(body) != crc {
+            break;
+        }
+        let Some(recs) = parse_records(body) else { break };
+        for (key, val) in recs {
+            match val {
+                Some((off, val_len)) => {
+                    index.insert(key, ((body_start + off) as u64, val_len));
+                }
+                None => {
+                    index.remove(&key);
+                }
+            }
+        }
+        pos = body_start + body.len();
+    }
+    (index, pos as u64)
+}
+
+/// Record trong 1 frame: key và (offset value trong frame, độ dài), `None` nếu tombstone.
+type FrameRecord = (Vec<u8>, Option<(usize, u32)>);
+
+fn parse_records(body: &[u8]) -> Option<Vec<FrameRecord>> {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos < body.len() {
+        let key_start = pos + 4;
+        let key_end = key_start.checked_add(read_u32(body, pos)? as usize)?;
+        let key = body.get(key_start..key_end)?.to_vec();
+        let val_len = read_u32(body, key_end)?;
+        let val_start = key_end + 4;
+        if val_len == TOMBSTONE {
+            out.push((key, None));
+            pos = val_start;
+            continue;
+        }
+        let val_end = val_start.checked_add(val_len as usize)?;
+        body.get(val_start..val_end)?;
+        out.push((key, Some((val_start, val_len))));
+        pos = val_end;
+    }
+    Some(out)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    let b = bytes.get(at..at.checked_add(8)?)?;
+    Some(u64::from_be_bytes(b.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at.checked_add(4)?)?;
+    Some(u32::from_be_bytes(b.try_into().ok()?))
+}
+
+impl KvStore for LogKv {
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut g = self.inner.lock().expect("mutex poisoned");
+        let Some(&(off, len)) = g.index.get(key) else {
+            return Err(DbError::NotFound);
+        };
+        let mut out = vec![0u8; len as usize];
+        g.file.seek(SeekFrom::Start(off))?;
+        g.file.read_exact(&mut out)?;
+        Ok(out)
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.append(&key, Some(&value))
+    }
+
+    fn del(&self, key: &[u8]) -> Result<()> {
+        if !self.has(key)? {
+            return Ok(());
+        }
+        self.append(key, None)
+    }
+
+    fn has(&self, key: &[u8]) -> Result<bool> {
+        let g = self.inner.lock().expect("mutex poisoned");
+        Ok(g.index.contains_key(key))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut g = self.inner.lock().expect("mutex poisoned");
+        let found: Vec<(Vec<u8>, u64, u32)> = g
+            .index
+            .iter()
+            .filter(|(k, _)| k.starts_with(prefix))
+            .map(|(k, &(off, len))| (k.clone(), off, len))
+            .collect();
+        let mut out = Vec::with_capacity(found.len());
+        for (k, off, len) in found {
+            let mut v = vec![0u8; len as usize];
+            g.file.seek(SeekFrom::Start(off))?;
+            g.file.read_exact(&mut v)?;
+            out.push((k, v));
+        }
+        Ok(out)
+    }
+
+    /// Cả batch là 1 frame: crash giữa lúc ghi => replay bỏ cả batch.
+    fn batch(&self, ops: Vec<KvOp>) -> Result<()> {
+        let recs: Vec<(&[u8], Option<&[u8]>)> = ops
+            .iter()
+            .map(|op| match op {
+                KvOp::Put(k, v) => (k.as_slice(), Some(v.as_slice())),
+                KvOp::Del(k) => (k.as_slice(), None),
+            })
+            .collect();
+        self.append_all(&recs)
+    }
+
+    fn flush(&self) -> Result<()> {
+        LogKv::flush(self)
+    }
+
+    fn compact(&self) -> Result<()> {
+        LogKv::compact(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logkv_put_get_del_and_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = LogKv::open(dir.path().join("kv.log")).unwrap();
+
+        assert!(!db.has(b"a").unwrap());
+        assert!(matches!(db.get(b"a"), Err(DbError::NotFound)));
+
+        db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        db.put(b"b".to_vec(), Vec::new()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"1".to_vec());
+        assert_eq!(db.get(b"b").unwrap(), Vec::<u8>::new());
+
+        db.put(b"a".to_vec(), b"22".to_vec()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"22".to_vec());
+
+        db.del(b"a").unwrap();
+        assert!(!db.has(b"a").unwrap());
+        assert!(matches!(db.get(b"a"), Err(DbError::NotFound)));
+    }
+
+    #[test]
+    fn logkv_reopen_replays_index_with_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.log");
+        {
+            let db = LogKv::open(&path).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+            db.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+            db.put(b"a".to_vec(), b"3".to_vec()).unwrap();
+            db.del(b"b").unwrap();
+            db.put(b"c".to_vec(), b"4".to_vec()).unwrap();
+            db.flush().unwrap();
+        }
+
+        // record cuối ghi dở (crash) bị bỏ qua khi mở lại
+        let full_len = std::fs::metadata(&path).unwrap().len();
+        {
+            let mut f = OpenOptions::new().append(true).open(&path).unwrap();
+            f.write_all(&[0, 0, 0, 1, b'x', 0, 0]).unwrap();
+        }
+
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"3".to_vec());
+        assert!(!db.has(b"b").unwrap());
+        assert_eq!(db.get(b"c").unwrap(), b"4".to_vec());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), full_len);
+
+        // ghi tiếp sau khi cắt đuôi vẫn replay đúng
+        db.put(b"d".to_vec(), b"5".to_vec()).unwrap();
+        drop(db);
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"d").unwrap(), b"5".to_vec());
+    }
+
+    #[test]
+    fn logkv_batch_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.log");
+        {
+            let db = LogKv::open(&path).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+            db.batch(vec![
+                KvOp::Put(b"b".to_vec(), b"2".to_vec()),
+                KvOp::Del(b"a".to_vec()),
+                KvOp::Put(b"c".to_vec(), b"3".to_vec()),
+            ])
+            .unwrap();
+            assert!(!db.has(b"a").unwrap());
+            assert_eq!(db.get(b"c").unwrap(), b"3".to_vec());
+        }
+
+        let db = LogKv::open(&path).unwrap();
+        assert!(!db.has(b"a").unwrap());
+        assert_eq!(db.get(b"b").unwrap(), b"2".to_vec());
+        assert_eq!(db.get(b"c").unwrap(), b"3".to_vec());
+    }
+
+    #[test]
+    fn logkv_partial_or_corrupt_batch_is_dropped_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.log");
+        let batch = || {
+            vec![
+                KvOp::Put(b"tip".to_vec(), b"new-tip".to_vec()),
+                KvOp::Put(b"canon".to_vec(), b"new-canon".to_vec()),
+            ]
+        };
+        let good_len = {
+            let db = LogKv::open(&path).unwrap();
+            db.put(b"tip".to_vec(), b"old-tip".to_vec()).unwrap();
+            let len = std::fs::metadata(&path).unwrap().len();
+            db.batch(batch()).unwrap();
+            len
+        };
+
+        // crash sau record đầu của batch: cả batch bị bỏ, không nửa vời
+        let full = std::fs::read(&path).unwrap();
+        let cut = full.len() - b"new-canon".len();
+        std::fs::write(&path, &full[..cut]).unwrap();
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"tip").unwrap(), b"old-tip".to_vec());
+        assert!(!db.has(b"canon").unwrap());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+
+        // đủ byte nhưng sai checksum cũng bị bỏ
+        db.batch(batch()).unwrap();
+        drop(db);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"tip").unwrap(), b"old-tip".to_vec());
+        assert!(!db.has(b"canon").unwrap());
+
+        // ghi tiếp sau khi cắt vẫn replay đúng
+        db.batch(batch()).unwrap();
+        drop(db);
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"tip").unwrap(), b"new-tip".to_vec());
+        assert_eq!(db.get(b"canon").unwrap(), b"new-canon".to_vec());
+    }
+
+    #[test]
+    fn logkv_compact_drops_deleted_and_overwritten_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("kv.log");
+        let db = LogKv::open(&path).unwrap();
+
+        let val = |i: usize| format!("value-{i:02}-payload").into_bytes();
+        for i in 0..10 {
+            db.put(format!("k{i}").into_bytes(), val(i)).unwrap();
+        }
+        db.put(b"k0".to_vec(), b"value-00-rewritten".to_vec()).unwrap();
+        for i in (1..10).step_by(2) {
+            db.del(format!("k{i}").as_bytes()).unwrap();
+        }
+        let before = std::fs::metadata(&path).unwrap().len();
+
+        db.compact().unwrap();
+
+        let on_disk = std::fs::read(&path).unwrap();
+        let contains = |needle: &[u8]| on_disk.windows(needle.len()).any(|w| w == needle);
+        assert!((on_disk.len() as u64) < before);
+        assert!(!contains(&val(0)));
+        for i in (1..10).step_by(2) {
+            assert!(!contains(&val(i)), "deleted k{i} still on disk");
+            assert!(!contains(format!("k{i}").as_bytes()));
+        }
+        for i in (2..10).step_by(2) {
+            assert!(contains(&val(i)));
+            assert_eq!(db.get(format!("k{i}").as_bytes()).unwrap(), val(i));
+        }
+        assert_eq!(db.get(b"k0").unwrap(), b"value-00-rewritten".to_vec());
+
+        // ghi tiếp sau compact rồi mở lại vẫn đúng
+        db.put(b"k1".to_vec(), b"again".to_vec()).unwrap();
+        drop(db);
+        let db = LogKv::open(&path).unwrap();
+        assert_eq!(db.get(b"k1").unwrap(), b"again".to_vec());
+        assert_eq!(db.get(b"k4").unwrap(), val(4));
+        assert!(!db.has(b"k3").unwrap());
+        assert_eq!(db.scan_prefix(b"k").unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,159 @@
+#![forbid(unsafe_code)]
+
+use crate::store::{Result, StoreError};
+use crate::KvStore;
+
+/// Trạng thái uy tín của 1 peer, lưu bền để ban còn hiệu lực qua các lần kết nối.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerReputation {
+    pub penalty_score: i32,
+    /// UTC seconds; `None` = không bị ban.
+    pub banned_until_utc: Option<i64>,
+    pub ban_reason: Option<String>,
+}
+
+impl PeerReputation {
+    pub fn is_banned_at(&self, now_utc: i64) -> bool {
+        matches!(self.banned_until_utc, Some(until) if now_utc < until)
+    }
+}
+
+#[derive(Clone)]
+pub struct ReputationStore<S: KvStore> {
+    kv: S,
+}
+
+impl<S: KvStore> ReputationStore<S> {
+    pub fn new(kv: S) -> Self {
+        Self { kv }
+    }
+
+    fn k_rep(peer: &str) -> Vec<u8> {
+        let mut k = Vec::with_capacity(4 + peer.len());
+        k.extend_from_slice(b"rep:");
+        k.extend_from_slice(peer.as_bytes());
+        k
+    }
+
+    fn encode_rep(rep: &PeerReputation) -> Vec<u8> {
+        const MAGIC: [u8; 8] = *b"EGG_REP0";
+        let reason = rep.ban_reason.as_deref().unwrap_or("");
+        let mut out = Vec::with_capacity(8 + 4 + 1 + 8 + 4 + reason.len());
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&rep.penalty_score.to_be_bytes());
+        match rep.banned_until_utc {
+            Some(until) => {
+                out.push(1);
+                out.extend_from_slice(&until.to_be_bytes());
+            }
+            None => {
+                out.push(0);
+                out.extend_from_slice(&0i64.to_be_bytes());
+            }
+        }
+        let n: u32 = reason.len().try_into().unwrap_or(u32::MAX);
+        out.extend_from_slice(&n.to_be_bytes());
+        out.extend_from_slice(reason.as_bytes());
+        out
+    }
+
+    fn decode_rep(bytes: &[u8]) -> Result<PeerReputation> {
+        const MAGIC: [u8; 8] = *b"EGG_REP0";
+        if bytes.len() < 8 + 4 + 1 + 8 + 4 {
+            return Err(StoreError::Decode("rep: unexpected eof".to_string()));
+        }
+        if bytes[0..8] != MAGIC {
+            return Err(StoreError::Decode("rep: invalid magic".to_string()));
+        }
+        let score_bytes: [u8; 4] = bytes[8..12]
+            .try_into()
+            .map_err(|_| StoreError::Decode("rep: bad score bytes".to_string()))?;
+        let penalty_score = i32::from_be_bytes(score_bytes);
+
+        let until_bytes: [u8; 8] = bytes[13..21]
+            .try_into()
+            .map_err(|_| StoreError::Decode("rep: bad until bytes".to_string()))?;
+        let banned_until_utc = match bytes[12] {
+            0 => None,
+            1 => Some(i64::from_be_bytes(until_bytes)),
+            _ => return Err(StoreError::Decode("rep: bad ban flag".to_string())),
+        };
+
+        let n_bytes: [u8; 4] = bytes[21..25]
+            .try_into()
+            .map_err(|_| StoreError::Decode("rep: bad reason len".to_string()))?;
+        let n = u32::from_be_bytes(n_bytes) as usize;
+        if bytes.len() != 25 + n {
+            return Err(StoreError::Decode("rep: length mismatch".to_string()));
+        }
+        let reason = String::from_utf8(bytes[25..].to_vec())
+            .map_err(|_| StoreError::Decode("rep: invalid utf8 reason".to_string()))?;
+
+        Ok(PeerReputation {
+            penalty_score,
+            banned_until_utc,
+            ban_reason: if reason.is_empty() { None } else { Some(reason) },
+        })
+    }
+
+    pub fn put(&self, peer: &str, rep: &PeerReputation) -> Result<()> {
+        self.kv.put(Self::k_rep(peer), Self::encode_rep(rep))?;
+        Ok(())
+    }
+
+    pub fn get(&self, peer: &str) -> Result<Option<PeerReputation>> {
+        let key = Self::k_rep(peer);
+        if !self.kv.has(&key)? {
+            return Ok(None);
+        }
+        let val = self.kv.get(&key)?;
+        Ok(Some(Self::decode_rep(&val)?))
+    }
+
+    pub fn remove(&self, peer: &str) -> Result<()> {
+        self.kv.del(&Self::k_rep(peer))?;
+        Ok(())
+    }
+
+    pub fn is_banned(&self, peer: &str, now_utc: i64) -> Result<bool> {
+        Ok(self
+            .get(peer)?
+            .map(|r| r.is_banned_at(now_utc))
+            .unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemKv;
+
+    #[test]
+    fn reputation_roundtrip_and_expiry() {
+        let rs = ReputationStore::new(MemKv::new());
+        assert_eq!(rs.get("1.2.3.4").unwrap(), None);
+
+        let rep = PeerReputation {
+            penalty_score: 120,
+            banned_until_utc: Some(1_000),
+            ban_reason: Some("penalty threshold exceeded".to_string()),
+        };
+        rs.put("1.2.3.4", &rep).unwrap();
+        assert_eq!(rs.get("1.2.3.4").unwrap(), Some(rep));
+
+        assert!(rs.is_banned("1.2.3.4", 999).unwrap());
+        assert!(!rs.is_banned("1.2.3.4", 1_000).unwrap());
+        assert!(!rs.is_banned("5.6.7.8", 0).unwrap());
+
+        let clean = PeerReputation {
+            penalty_score: 5,
+            banned_until_utc: None,
+            ban_reason: None,
+        };
+        rs.put("5.6.7.8", &clean).unwrap();
+        assert_eq!(rs.get("5.6.7.8").unwrap(), Some(clean));
+
+        rs.remove("1.2.3.4").unwrap();
+        assert_eq!(rs.get("1.2.3.4").unwrap(), None);
+    }
+}
@@ -0,0 +1,218 @@
+#![forbid(unsafe_code)]
+
+use std::path::Path;
+use std::sync::Arc;
+
+use rocksdb::{Direction, ErrorKind, IteratorMode, Options, WriteBatch, DB};
+
+use crate::{DbError, KvOp, KvStore, Result};
+
+/// KvStore trên RocksDB (feature `rocksdb`), dùng thay `SledKv` cho chain lớn.
+/// Write đi qua WAL của RocksDB nên không cần flush sau mỗi put; `flush()` đẩy memtable xuống SST.
+#[derive(Clone)]
+pub struct RocksKv {
+    db: Arc<DB>,
+}
+
+impl RocksKv {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, path).map_err(classify_open_error)?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Compact toàn bộ key range: SST được viết lại, bỏ entry đã xoá/ghi đè.
+    pub fn compact(&self) -> Result<()> {
+        self.flush()?;
+        self.db.compact_range::<&[u8], &[u8]>(None, None);
+        Ok(())
+    }
+}
+
+/// Như sled, RocksDB báo lock/corruption khi open qua message:
+/// - lock bị giữ bởi handle khác => `IO error: While lock file: .../LOCK: ...`
+/// - dữ liệu hỏng => kind `Corruption`
+fn classify_open_error(e: rocksdb::Error) -> DbError {
+    if e.kind() == ErrorKind::Corruption {
+        return DbError::Corrupted(e.into_string());
+    }
+    if e.to_string().contains("lock file") {
+        return DbError::AlreadyLocked(e.into_string());
+    }
+    DbError::Rocks(e)
+}
+
+impl KvStore for RocksKv {
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.db.get(key)?.ok_or(DbError::NotFound)
+    }
+
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.db.put(key, value)?;
+        Ok(())
+    }
+
+    fn del(&self, key: &[u8]) -> Result<()> {
+        self.db.delete(key)?;
+        Ok(())
+    }
+
+    fn has(&self, key: &[u8]) -> Result<bool> {
+        Ok(self.db.get_pinned(key)?.is_some())
+    }
+
+    fn batch(&self, ops: Vec<KvOp>) -> Result<()> {
+        let mut b = WriteBatch::default();
+        for op in ops {
+            match op {
+                KvOp::Put(k, v) => b.put(k, v),
+                KvOp::Del(k) => b.delete(k),
+            }
+        }
+        self.db.write(b)?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        // không cấu hình prefix extractor: seek tới prefix rồi dừng ở key đầu tiên lệch prefix
+        let mut out = Vec::new();
+        for kv in self.db.iterator(IteratorMode::From(prefix, Direction::Forward)) {
+            let (k, v) = kv?;
+            if !k.starts_with(prefix) {
+                break;
+            }
+            out.push((k.into_vec(), v.into_vec()));
+        }
+        Ok(out)
+    }
+
+    fn flush(&self) -> Result<()> {
+        RocksKv::flush(self)
+    }
+
+    fn compact(&self) -> Result<()> {
+        RocksKv::compact(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{BlockMeta, BlockStore, ChainMeta, ChainStore, ChainTip, DbChainStore};
+    use egg_types::{canonical, Block, BlockHeader, Hash256, Height, Transaction, CONTENT_TAG_OPAQUE};
+
+    #[test]
+    fn kv_semantics_match_other_backends() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = RocksKv::open(dir.path()).unwrap();
+
+        assert!(!db.has(b"a").unwrap());
+        assert!(matches!(db.get(b"a"), Err(DbError::NotFound)));
+
+        db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        db.put(b"b".to_vec(), Vec::new()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"1".to_vec());
+        assert!(db.has(b"b").unwrap());
+
+        db.del(b"a").unwrap();
+        db.del(b"missing").unwrap();
+        assert!(!db.has(b"a").unwrap());
+
+        db.batch(vec![
+            KvOp::Put(b"p:2".to_vec(), b"2".to_vec()),
+            KvOp::Put(b"p:1".to_vec(), b"1".to_vec()),
+            KvOp::Del(b"b".to_vec()),
+            KvOp::Put(b"q:1".to_vec(), b"x".to_vec()),
+        ])
+        .unwrap();
+        assert!(!db.has(b"b").unwrap());
+        let keys: Vec<_> = db.scan_prefix(b"p:").unwrap().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"p:1".to_vec(), b"p:2".to_vec()]);
+    }
+
+    #[test]
+    fn open_same_path_twice_is_already_locked() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let _first = RocksKv::open(dir.path()).unwrap();
+        let err = RocksKv::open(dir.path()).err().expect("second open must fail");
+        assert!(matches!(err, DbError::AlreadyLocked(_)), "got {:?}", err);
+    }
+
+    fn sample_block() -> Block {
+        let tx = Transaction {
+            id: Hash256([3u8; 32]),
+            payload: b"tx".to_vec(),
+            content_tag: CONTENT_TAG_OPAQUE,
+        };
+        Block {
+            header: BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(1),
+                timestamp_utc: 1_700_000_000,
+                nonce: 7,
+                merkle_root: Hash256([2u8; 32]),
+                pow_difficulty_bits: 0,
+            },
+            txs: vec![tx],
+        }
+    }
+
+    #[test]
+    fn chain_store_roundtrip_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let block = sample_block();
+        let id = Hash256([9u8; 32]);
+        let parent = block.header.parent;
+        let tip = ChainTip {
+            height: Height(1),
+            hash: id,
+        };
+        let meta = ChainMeta {
+            chain_id: 7,
+            genesis_id: parent,
+            chainspec_hash: Hash256([4u8; 32]),
+        };
+        let bmeta = BlockMeta {
+            parent,
+            height: Height(1),
+            chain_work: 2,
+        };
+
+        {
+            let store = DbChainStore::open(RocksKv::open(dir.path()).unwrap()).unwrap();
+            store.put_header(id, &block.header).unwrap();
+            store.put_block(id, &block).unwrap();
+            store.set_tip(tip).unwrap();
+            store.set_meta(meta).unwrap();
+            store.put_block_meta(id, bmeta).unwrap();
+            store.add_child(parent, id).unwrap();
+            store.add_child(parent, id).unwrap();
+            store.set_canon_hash(Height(1), id).unwrap();
+
+            store.begin_batch().unwrap();
+            store.set_canon_hash(Height(2), id).unwrap();
+            store.del_canon_hash(Height(2)).unwrap();
+            store.commit_batch().unwrap();
+            store.flush().unwrap();
+        }
+
+        let store = DbChainStore::open(RocksKv::open(dir.path()).unwrap()).unwrap();
+        assert_eq!(store.get_header(id).unwrap(), block.header);
+        assert_eq!(store.get_header_bytes(id).unwrap(), canonical::encode_block_header(&block.header));
+        assert_eq!(store.get_block(id).unwrap(), block);
+        assert_eq!(store.get_tip().unwrap(), Some(tip));
+        assert_eq!(store.get_meta().unwrap(), Some(meta));
+        assert_eq!(store.get_block_meta(id).unwrap(), Some(bmeta));
+        assert_eq!(store.get_children(parent).unwrap(), vec![id]);
+        assert_eq!(store.get_canon_hash(Height(1)).unwrap(), Some(id));
+        assert_eq!(store.get_canon_hash(Height(2)).unwrap(), None);
+        assert_eq!(store.all_header_ids().unwrap(), vec![id]);
+    }
+}
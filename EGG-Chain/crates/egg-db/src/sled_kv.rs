@@ -1,18 +1,125 @@
 #![forbid(unsafe_code)]
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::{DbError, KvStore, Result};
+use crate::{DbError, KvOp, KvStore, Result};
 
+/// Chế độ flush của SledKv.
+/// - `EveryWrite`: flush sau mỗi put/del (mặc định, bền vững nhất).
+/// - `Deferred`: không flush sau mỗi write; flush khi gọi `flush()`/`close()` hoặc khi drop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushMode {
+    #[default]
+    EveryWrite,
+    Deferred,
+}
+
+/// Lưu ý: `Drop` chỉ flush best-effort và nuốt lỗi flush.
+/// Muốn biết flush có thành công hay không thì gọi `close()`.
 #[derive(Clone)]
 pub struct SledKv {
     db: sled::Db,
+    flush_mode: FlushMode,
 }
 
 impl SledKv {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let db = sled::open(path)?;
-        Ok(Self { db })
+        Self::open_with_mode(path, FlushMode::EveryWrite)
+    }
+
+    pub fn open_with_mode<P: AsRef<Path>>(path: P, flush_mode: FlushMode) -> Result<Self> {
+        let mut cfg = sled::Config::new().path(path.as_ref());
+        if flush_mode == FlushMode::Deferred {
+            // tắt flusher định kỳ của sled, chỉ flush khi được yêu cầu
+            cfg = cfg.flush_every_ms(None);
+        }
+        let db = cfg.open().map_err(|e| classify_open_error(e, path.as_ref()))?;
+        Ok(Self { db, flush_mode })
+    }
+
+    /// Mở DB; nếu phát hiện corruption thì chuyển thư mục hỏng sang `<path>.corrupt[.N]`
+    /// và mở DB mới RỖNG — không sửa gì, dữ liệu chain phải sync lại từ peer.
+    /// Trả về đường dẫn thư mục đã cách ly nếu có.
+    pub fn open_or_quarantine<P: AsRef<Path>>(path: P) -> Result<(Self, Option<PathBuf>)> {
+        let path = path.as_ref();
+        match Self::open(path) {
+            Ok(db) => Ok((db, None)),
+            Err(DbError::Corrupted(_)) => {
+                let quarantined = quarantine_path(path);
+                std::fs::rename(path, &quarantined).map_err(sled::Error::Io)?;
+                let db = Self::open(path)?;
+                Ok((db, Some(quarantined)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn flush_mode(&self) -> FlushMode {
+        self.flush_mode
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Flush toàn bộ write còn treo và trả lỗi nếu có (Drop không làm được việc này).
+    pub fn close(self) -> Result<()> {
+        self.flush()
+    }
+
+    fn flush_if_every_write(&self) -> Result<()> {
+        if self.flush_mode == FlushMode::EveryWrite {
+            self.db.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// sled không có error riêng cho lock/corruption khi open:
+/// - lock bị giữ bởi handle khác => `Io(Other, ..)` (kind gốc bị mất) => tự thử lock lại file
+/// - dữ liệu hỏng => `Corruption { .. }`
+fn classify_open_error(e: sled::Error, path: &Path) -> DbError {
+    match e {
+        sled::Error::Corruption { .. } => DbError::Corrupted(e.to_string()),
+        sled::Error::Io(ref io) if lock_is_held(path) => DbError::AlreadyLocked(io.to_string()),
+        other => DbError::Sled(other),
+    }
+}
+
+/// File `db` mà sled giữ lock exclusive (fs2) đang bị handle khác giữ hay không.
+/// Lock lấy được thì nhả ngay khi drop file.
+fn lock_is_held(path: &Path) -> bool {
+    use fs2::FileExt;
+
+    let Ok(file) = std::fs::OpenOptions::new().read(true).write(true).open(path.join("db")) else {
+        return false;
+    };
+    matches!(file.try_lock_exclusive(), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+}
+
+fn quarantine_path(path: &Path) -> PathBuf {
+    let base = path.as_os_str().to_owned();
+    let mut n: u32 = 0;
+    loop {
+        let mut candidate = base.clone();
+        if n == 0 {
+            candidate.push(".corrupt");
+        } else {
+            candidate.push(format!(".corrupt.{}", n));
+        }
+        let candidate = PathBuf::from(candidate);
+        if !candidate.exists() {
+            return candidate;
+        }
+        n = n.saturating_add(1);
+    }
+}
+
+impl Drop for SledKv {
+    fn drop(&mut self) {
+        // best-effort: Drop không thể trả lỗi
+        let _ = self.db.flush();
     }
 }
 
@@ -26,17 +133,156 @@ impl KvStore for SledKv {
 
     fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
         self.db.insert(key, value)?;
-        self.db.flush()?;
+        self.flush_if_every_write()?;
         Ok(())
     }
 
     fn del(&self, key: &[u8]) -> Result<()> {
         let _ = self.db.remove(key)?;
-        self.db.flush()?;
+        self.flush_if_every_write()?;
         Ok(())
     }
 
     fn has(&self, key: &[u8]) -> Result<bool> {
         Ok(self.db.contains_key(key)?)
     }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|kv| {
+                let (k, v) = kv?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect()
+    }
+
+    fn batch(&self, ops: Vec<KvOp>) -> Result<()> {
+        let mut b = sled::Batch::default();
+        for op in ops {
+            match op {
+                KvOp::Put(k, v) => b.insert(k, v),
+                KvOp::Del(k) => b.remove(k),
+            }
+        }
+        self.db.apply_batch(b)?;
+        self.flush_if_every_write()?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        SledKv::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // thread pool IO của sled có thể giữ file lock thêm một chút sau khi drop
+    fn reopen(path: &Path) -> Result<SledKv> {
+        for _ in 0..100 {
+            match SledKv::open(path) {
+                Err(DbError::AlreadyLocked(_)) => {
+                    std::thread::sleep(std::time::Duration::from_millis(10))
+                }
+                other => return other,
+            }
+        }
+        panic!("db still locked after drop");
+    }
+
+    #[test]
+    fn deferred_writes_persist_after_drop() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = SledKv::open_with_mode(dir.path(), FlushMode::Deferred).unwrap();
+            assert_eq!(db.flush_mode(), FlushMode::Deferred);
+            db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+            db.put(b"b".to_vec(), b"2".to_vec()).unwrap();
+        }
+
+        let db = reopen(dir.path()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"1".to_vec());
+        assert_eq!(db.get(b"b").unwrap(), b"2".to_vec());
+    }
+
+    #[test]
+    fn close_flushes_and_reports_ok() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let db = SledKv::open_with_mode(dir.path(), FlushMode::Deferred).unwrap();
+        db.put(b"k".to_vec(), b"v".to_vec()).unwrap();
+        db.close().unwrap();
+
+        let db = reopen(dir.path()).unwrap();
+        assert_eq!(db.get(b"k").unwrap(), b"v".to_vec());
+    }
+
+    #[test]
+    fn batch_is_applied_and_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = SledKv::open(dir.path()).unwrap();
+            db.put(b"old".to_vec(), b"0".to_vec()).unwrap();
+            db.batch(vec![
+                KvOp::Put(b"a".to_vec(), b"1".to_vec()),
+                KvOp::Del(b"old".to_vec()),
+                KvOp::Put(b"b".to_vec(), b"2".to_vec()),
+            ])
+            .unwrap();
+        }
+
+        let db = reopen(dir.path()).unwrap();
+        assert_eq!(db.get(b"a").unwrap(), b"1".to_vec());
+        assert_eq!(db.get(b"b").unwrap(), b"2".to_vec());
+        assert!(!db.has(b"old").unwrap());
+    }
+
+    #[test]
+    fn scan_prefix_is_key_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SledKv::open(dir.path()).unwrap();
+        for k in [&b"p:2"[..], b"q:1", b"p:1", b"p:3"] {
+            db.put(k.to_vec(), k.to_vec()).unwrap();
+        }
+
+        let got = db.scan_prefix(b"p:").unwrap();
+        let keys: Vec<_> = got.iter().map(|(k, _)| k.as_slice()).collect();
+        assert_eq!(keys, vec![&b"p:1"[..], b"p:2", b"p:3"]);
+    }
+
+    #[test]
+    fn open_same_path_twice_is_already_locked() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let _first = SledKv::open(dir.path()).unwrap();
+        let err = SledKv::open(dir.path()).err().expect("second open must fail");
+        assert!(matches!(err, DbError::AlreadyLocked(_)), "got {:?}", err);
+    }
+
+    #[test]
+    fn open_or_quarantine_moves_corrupted_db_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+
+        {
+            let db = SledKv::open(&path).unwrap();
+            db.put(b"a".to_vec(), b"1".to_vec()).unwrap();
+        }
+        // conf hợp lệ về format nhưng giá trị hỏng => sled trả Corruption
+        let mut conf = b"segment_size: garbage\nuse_compression: false\nversion: 0.34".to_vec();
+        conf.extend_from_slice(&[0u8; 4]);
+        std::fs::write(path.join("conf"), conf).unwrap();
+
+        let err = reopen(&path).err().expect("corrupted open must fail");
+        assert!(matches!(err, DbError::Corrupted(_)), "got {:?}", err);
+
+        let (db, quarantined) = SledKv::open_or_quarantine(&path).unwrap();
+        let quarantined = quarantined.expect("recovery happened");
+        assert!(quarantined.exists());
+        assert!(!db.has(b"a").unwrap());
+    }
 }
@@ -1,9 +1,12 @@
 #![forbid(unsafe_code)]
 
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
 use egg_types::{canonical, Block, BlockHeader, Hash256, Height};
 use thiserror::Error;
 
-use crate::{DbError, KvStore};
+use crate::{DbError, KvOp, KvStore};
 
 #[derive(Debug, Error)]
 pub enum StoreError {
@@ -12,10 +15,25 @@ pub enum StoreError {
 
     #[error("decode error: {0}")]
     Decode(String),
+
+    #[error("unsupported store schema: found {found:?}, supported {supported:?}")]
+    UnsupportedSchema {
+        found: StoreSchemaVersion,
+        supported: StoreSchemaVersion,
+    },
+
+    #[error("batch already open on this store handle")]
+    BatchAlreadyOpen,
 }
 
 pub type Result<T> = std::result::Result<T, StoreError>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StoreSchemaVersion(pub u32);
+
+/// Phiên bản layout key/value mà code hiện tại hỗ trợ.
+pub const STORE_SCHEMA_VERSION: StoreSchemaVersion = StoreSchemaVersion(1);
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ChainTip {
     pub height: Height,
@@ -33,16 +51,31 @@ pub struct ChainMeta {
 pub struct BlockMeta {
     pub parent: Hash256,
     pub height: Height,
+    /// Tổng work từ genesis tới block này (kể cả nó); 0 = chưa tính (orphan / bmeta cũ).
+    pub chain_work: u128,
+}
+
+/// Vị trí tx trong block: block chứa + index trong `block.txs`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxLocation {
+    pub block_id: Hash256,
+    pub index: u32,
 }
 
 pub trait BlockStore {
     fn put_header(&self, id: Hash256, header: &BlockHeader) -> Result<()>;
     fn get_header(&self, id: Hash256) -> Result<BlockHeader>;
+    /// Bytes canonical của header như đang lưu (không decode), dùng để phục vụ header.
+    fn get_header_bytes(&self, id: Hash256) -> Result<Vec<u8>>;
     fn has_header(&self, id: Hash256) -> Result<bool>;
 
     fn put_block(&self, id: Hash256, block: &Block) -> Result<()>;
     fn get_block(&self, id: Hash256) -> Result<Block>;
     fn has_block(&self, id: Hash256) -> Result<bool>;
+
+    /// Xoá header/body (vd. orphan bị evict); không có thì bỏ qua.
+    fn del_header(&self, id: Hash256) -> Result<()>;
+    fn del_block(&self, id: Hash256) -> Result<()>;
 }
 
 pub trait ChainStore: BlockStore {
@@ -54,22 +87,180 @@ pub trait ChainStore: BlockStore {
 
     fn put_block_meta(&self, id: Hash256, meta: BlockMeta) -> Result<()>;
     fn get_block_meta(&self, id: Hash256) -> Result<Option<BlockMeta>>;
+    fn del_block_meta(&self, id: Hash256) -> Result<()>;
 
     fn add_child(&self, parent: Hash256, child: Hash256) -> Result<()>;
     fn get_children(&self, parent: Hash256) -> Result<Vec<Hash256>>;
+    /// Bỏ `child` khỏi danh sách con của `parent`; danh sách rỗng thì xoá luôn key.
+    fn remove_child(&self, parent: Hash256, child: Hash256) -> Result<()>;
 
     fn set_canon_hash(&self, height: Height, hash: Hash256) -> Result<()>;
     fn get_canon_hash(&self, height: Height) -> Result<Option<Hash256>>;
+    fn del_canon_hash(&self, height: Height) -> Result<()>;
+
+    fn set_schema_version(&self, v: StoreSchemaVersion) -> Result<()>;
+    fn get_schema_version(&self) -> Result<Option<StoreSchemaVersion>>;
+
+    fn put_tx_location(&self, txid: Hash256, loc: TxLocation) -> Result<()>;
+    fn get_tx_location(&self, txid: Hash256) -> Result<Option<TxLocation>>;
+
+    /// Marker block invalid (tồn tại qua restart); xoá marker không có thì bỏ qua.
+    fn put_invalid_marker(&self, id: Hash256) -> Result<()>;
+    fn del_invalid_marker(&self, id: Hash256) -> Result<()>;
+    fn invalid_markers(&self) -> Result<Vec<Hash256>>;
+
+    /// Gom mọi write tới `commit_batch` rồi ghi 1 lần nguyên tử (`KvStore::batch`);
+    /// đọc trong lúc gom thấy write đang treo. Batch thuộc riêng handle này (clone khác không
+    /// thấy, không ghi vào); batch đang mở => `BatchAlreadyOpen`.
+    fn begin_batch(&self) -> Result<()>;
+    fn commit_batch(&self) -> Result<()>;
+    /// Bỏ mọi write đang gom (không có batch thì no-op).
+    fn abort_batch(&self);
+
+    /// Đẩy mọi write còn treo của KV bên dưới xuống đĩa (shutdown an toàn).
+    fn flush(&self) -> Result<()>;
+
+    /// Compact KV bên dưới (`KvStore::compact`) để thu hồi chỗ sau khi xoá nhiều entry.
+    fn compact(&self) -> Result<()>;
+}
+
+/// Ghi schema version nếu store chưa có (store mới hoặc store cũ trước khi có `schema:`),
+/// từ chối nếu schema khác phiên bản code hỗ trợ.
+pub fn ensure_schema<S: ChainStore + ?Sized>(store: &S) -> Result<StoreSchemaVersion> {
+    match store.get_schema_version()? {
+        Some(found) if found != STORE_SCHEMA_VERSION => Err(StoreError::UnsupportedSchema {
+            found,
+            supported: STORE_SCHEMA_VERSION,
+        }),
+        Some(found) => Ok(found),
+        None => {
+            store.set_schema_version(STORE_SCHEMA_VERSION)?;
+            Ok(STORE_SCHEMA_VERSION)
+        }
+    }
 }
 
-#[derive(Clone)]
+/// Key schema (STORE_SCHEMA_VERSION = 1):
+/// - `schema:`              -> StoreSchemaVersion
+/// - `hdr:`   + id(32)      -> canonical BlockHeader
+/// - `blk:`   + id(32)      -> canonical Block
+/// - `tip:`                 -> ChainTip
+/// - `meta:`                -> ChainMeta
+/// - `bmeta:` + id(32)      -> BlockMeta (bmeta cũ không có chain_work: đọc ra 0, không đổi schema)
+/// - `child:` + parent(32)  -> danh sách child id
+/// - `canon:` + height(u64 BE) -> id canonical tại height
+/// - `txloc:` + txid(32)    -> TxLocation (store cũ chưa có key này: index rỗng, không đổi schema)
+/// - `invalid:` + id(32)    -> rỗng: block bị đánh dấu invalid (store cũ: không có marker)
+///
+/// Clone chia sẻ KV nhưng không chia sẻ batch đang gom: mỗi handle có buffer riêng.
 pub struct DbChainStore<S: KvStore> {
     kv: S,
+    pending: Mutex<Option<PendingWrites>>,
+}
+
+impl<S: KvStore + Clone> Clone for DbChainStore<S> {
+    fn clone(&self) -> Self {
+        Self::new(self.kv.clone())
+    }
+}
+
+/// Write đang gom giữa `begin_batch`/`commit_batch`: giữ đúng thứ tự ghi (KV không nguyên tử khi
+/// crash vẫn thấy canon trước tip) + giá trị mới nhất mỗi key cho đọc.
+#[derive(Default)]
+struct PendingWrites {
+    ops: Vec<KvOp>,
+    // key -> Some(value) | None (đã xoá)
+    latest: HashMap<Vec<u8>, Option<Vec<u8>>>,
 }
 
 impl<S: KvStore> DbChainStore<S> {
     pub fn new(kv: S) -> Self {
-        Self { kv }
+        Self {
+            kv,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Như `new` nhưng kiểm tra/ghi schema version ngay.
+    pub fn open(kv: S) -> Result<Self> {
+        let st = Self::new(kv);
+        ensure_schema(&st)?;
+        Ok(st)
+    }
+
+    fn kv_get(&self, key: &[u8]) -> crate::Result<Vec<u8>> {
+        if let Some(p) = self.pending.lock().expect("mutex poisoned").as_ref() {
+            if let Some(v) = p.latest.get(key) {
+                return v.clone().ok_or(DbError::NotFound);
+            }
+        }
+        self.kv.get(key)
+    }
+
+    fn kv_has(&self, key: &[u8]) -> crate::Result<bool> {
+        if let Some(p) = self.pending.lock().expect("mutex poisoned").as_ref() {
+            if let Some(v) = p.latest.get(key) {
+                return Ok(v.is_some());
+            }
+        }
+        self.kv.has(key)
+    }
+
+    fn kv_put(&self, key: Vec<u8>, value: Vec<u8>) -> crate::Result<()> {
+        match self.pending.lock().expect("mutex poisoned").as_mut() {
+            Some(p) => {
+                p.latest.insert(key.clone(), Some(value.clone()));
+                p.ops.push(KvOp::Put(key, value));
+                Ok(())
+            }
+            None => self.kv.put(key, value),
+        }
+    }
+
+    fn kv_del(&self, key: &[u8]) -> crate::Result<()> {
+        match self.pending.lock().expect("mutex poisoned").as_mut() {
+            Some(p) => {
+                p.latest.insert(key.to_vec(), None);
+                p.ops.push(KvOp::Del(key.to_vec()));
+                Ok(())
+            }
+            None => self.kv.del(key),
+        }
+    }
+
+    pub fn schema_version(&self) -> Result<Option<StoreSchemaVersion>> {
+        self.get_schema_version()
+    }
+
+    /// Id của mọi header đang lưu (cả orphan / nhánh phụ), theo thứ tự byte của id.
+    pub fn all_header_ids(&self) -> Result<Vec<Hash256>> {
+        self.kv_scan_prefix(b"hdr:")?
+            .into_keys()
+            .map(|k| {
+                let id: [u8; 32] = k[4..]
+                    .try_into()
+                    .map_err(|_| StoreError::Decode("hdr: bad key length".to_string()))?;
+                Ok(Hash256(id))
+            })
+            .collect()
+    }
+
+    /// `KvStore::scan_prefix` + write đang gom, sort theo key (không phụ thuộc thứ tự của KV).
+    fn kv_scan_prefix(&self, prefix: &[u8]) -> crate::Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+        let mut out: BTreeMap<_, _> = self.kv.scan_prefix(prefix)?.into_iter().collect();
+        if let Some(p) = self.pending.lock().expect("mutex poisoned").as_ref() {
+            for (k, v) in p.latest.iter().filter(|(k, _)| k.starts_with(prefix)) {
+                match v {
+                    Some(v) => out.insert(k.clone(), v.clone()),
+                    None => out.remove(k),
+                };
+            }
+        }
+        Ok(out)
+    }
+
+    fn k_schema() -> &'static [u8] {
+        b"schema:"
     }
 
     fn k_header(id: Hash256) -> Vec<u8> {
@@ -115,6 +306,20 @@ impl<S: KvStore> DbChainStore<S> {
         k
     }
 
+    fn k_tx_location(txid: Hash256) -> Vec<u8> {
+        let mut k = Vec::with_capacity(6 + 32);
+        k.extend_from_slice(b"txloc:");
+        k.extend_from_slice(&txid.0);
+        k
+    }
+
+    fn k_invalid(id: Hash256) -> Vec<u8> {
+        let mut k = Vec::with_capacity(8 + 32);
+        k.extend_from_slice(b"invalid:");
+        k.extend_from_slice(&id.0);
+        k
+    }
+
     fn encode_tip(tip: ChainTip) -> Vec<u8> {
         const MAGIC: [u8; 8] = *b"EGG_TIP0";
         let mut out = Vec::with_capacity(48);
@@ -129,7 +334,7 @@ impl<S: KvStore> DbChainStore<S> {
         if bytes.len() < 8 + 8 + 32 {
             return Err(StoreError::Decode("tip: unexpected eof".to_string()));
         }
-        if &bytes[0..8] != MAGIC {
+        if bytes[0..8] != MAGIC {
             return Err(StoreError::Decode("tip: invalid magic".to_string()));
         }
         let h_bytes: [u8; 8] = bytes[8..16]
@@ -161,7 +366,7 @@ impl<S: KvStore> DbChainStore<S> {
         if bytes.len() < 8 + 4 + 32 + 32 {
             return Err(StoreError::Decode("meta: unexpected eof".to_string()));
         }
-        if &bytes[0..8] != MAGIC {
+        if bytes[0..8] != MAGIC {
             return Err(StoreError::Decode("meta: invalid magic".to_string()));
         }
         let cid_bytes: [u8; 4] = bytes[8..12]
@@ -183,22 +388,32 @@ impl<S: KvStore> DbChainStore<S> {
     }
 
     fn encode_block_meta(meta: BlockMeta) -> Vec<u8> {
-        const MAGIC: [u8; 8] = *b"EGG_BM00";
-        let mut out = Vec::with_capacity(8 + 32 + 8);
+        const MAGIC: [u8; 8] = *b"EGG_BM01";
+        let mut out = Vec::with_capacity(8 + 32 + 8 + 16);
         out.extend_from_slice(&MAGIC);
         out.extend_from_slice(&meta.parent.0);
         out.extend_from_slice(&meta.height.0.to_be_bytes());
+        out.extend_from_slice(&meta.chain_work.to_be_bytes());
         out
     }
 
     fn decode_block_meta(bytes: &[u8]) -> Result<BlockMeta> {
-        const MAGIC: [u8; 8] = *b"EGG_BM00";
+        const MAGIC_V0: [u8; 8] = *b"EGG_BM00";
+        const MAGIC_V1: [u8; 8] = *b"EGG_BM01";
         if bytes.len() < 8 + 32 + 8 {
             return Err(StoreError::Decode("bmeta: unexpected eof".to_string()));
         }
-        if &bytes[0..8] != MAGIC {
+        let chain_work = if bytes[0..8] == MAGIC_V0 {
+            0
+        } else if bytes[0..8] == MAGIC_V1 {
+            let w: [u8; 16] = bytes
+                .get(48..64)
+                .and_then(|b| b.try_into().ok())
+                .ok_or_else(|| StoreError::Decode("bmeta: unexpected eof".to_string()))?;
+            u128::from_be_bytes(w)
+        } else {
             return Err(StoreError::Decode("bmeta: invalid magic".to_string()));
-        }
+        };
         let mut parent = [0u8; 32];
         parent.copy_from_slice(&bytes[8..40]);
 
@@ -210,6 +425,7 @@ impl<S: KvStore> DbChainStore<S> {
         Ok(BlockMeta {
             parent: Hash256(parent),
             height,
+            chain_work,
         })
     }
 
@@ -230,7 +446,7 @@ impl<S: KvStore> DbChainStore<S> {
         if bytes.len() < 8 + 4 {
             return Err(StoreError::Decode("child: unexpected eof".to_string()));
         }
-        if &bytes[0..8] != MAGIC {
+        if bytes[0..8] != MAGIC {
             return Err(StoreError::Decode("child: invalid magic".to_string()));
         }
         let n_bytes: [u8; 4] = bytes[8..12]
@@ -254,6 +470,28 @@ impl<S: KvStore> DbChainStore<S> {
         Ok(out)
     }
 
+    fn encode_schema(v: StoreSchemaVersion) -> Vec<u8> {
+        const MAGIC: [u8; 8] = *b"EGG_SCH0";
+        let mut out = Vec::with_capacity(8 + 4);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&v.0.to_be_bytes());
+        out
+    }
+
+    fn decode_schema(bytes: &[u8]) -> Result<StoreSchemaVersion> {
+        const MAGIC: [u8; 8] = *b"EGG_SCH0";
+        if bytes.len() < 8 + 4 {
+            return Err(StoreError::Decode("schema: unexpected eof".to_string()));
+        }
+        if bytes[0..8] != MAGIC {
+            return Err(StoreError::Decode("schema: invalid magic".to_string()));
+        }
+        let v_bytes: [u8; 4] = bytes[8..12]
+            .try_into()
+            .map_err(|_| StoreError::Decode("schema: bad version bytes".to_string()))?;
+        Ok(StoreSchemaVersion(u32::from_be_bytes(v_bytes)))
+    }
+
     fn encode_canon(hash: Hash256) -> Vec<u8> {
         const MAGIC: [u8; 8] = *b"EGG_CA00";
         let mut out = Vec::with_capacity(8 + 32);
@@ -267,49 +505,93 @@ impl<S: KvStore> DbChainStore<S> {
         if bytes.len() < 8 + 32 {
             return Err(StoreError::Decode("canon: unexpected eof".to_string()));
         }
-        if &bytes[0..8] != MAGIC {
+        if bytes[0..8] != MAGIC {
             return Err(StoreError::Decode("canon: invalid magic".to_string()));
         }
         let mut h = [0u8; 32];
         h.copy_from_slice(&bytes[8..40]);
         Ok(Hash256(h))
     }
+
+    fn encode_tx_location(loc: TxLocation) -> Vec<u8> {
+        const MAGIC: [u8; 8] = *b"EGG_TL00";
+        let mut out = Vec::with_capacity(8 + 32 + 4);
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&loc.block_id.0);
+        out.extend_from_slice(&loc.index.to_be_bytes());
+        out
+    }
+
+    fn decode_tx_location(bytes: &[u8]) -> Result<TxLocation> {
+        const MAGIC: [u8; 8] = *b"EGG_TL00";
+        if bytes.len() < 8 + 32 + 4 {
+            return Err(StoreError::Decode("txloc: unexpected eof".to_string()));
+        }
+        if bytes[0..8] != MAGIC {
+            return Err(StoreError::Decode("txloc: invalid magic".to_string()));
+        }
+        let mut block_id = [0u8; 32];
+        block_id.copy_from_slice(&bytes[8..40]);
+
+        let i_bytes: [u8; 4] = bytes[40..44]
+            .try_into()
+            .map_err(|_| StoreError::Decode("txloc: bad index bytes".to_string()))?;
+
+        Ok(TxLocation {
+            block_id: Hash256(block_id),
+            index: u32::from_be_bytes(i_bytes),
+        })
+    }
 }
 
 impl<S: KvStore> BlockStore for DbChainStore<S> {
     fn put_header(&self, id: Hash256, header: &BlockHeader) -> Result<()> {
         let key = Self::k_header(id);
         let val = canonical::encode_block_header(header);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_header(&self, id: Hash256) -> Result<BlockHeader> {
         let key = Self::k_header(id);
-        let val = self.kv.get(&key)?;
-        canonical::decode_block_header(&val)
+        let val = self.kv_get(&key)?;
+        canonical::decode_block_header_strict(&val)
             .map_err(|e| StoreError::Decode(format!("header decode: {}", e)))
     }
 
+    fn get_header_bytes(&self, id: Hash256) -> Result<Vec<u8>> {
+        Ok(self.kv_get(&Self::k_header(id))?)
+    }
+
     fn has_header(&self, id: Hash256) -> Result<bool> {
-        Ok(self.kv.has(&Self::k_header(id))?)
+        Ok(self.kv_has(&Self::k_header(id))?)
     }
 
     fn put_block(&self, id: Hash256, block: &Block) -> Result<()> {
         let key = Self::k_block(id);
         let val = canonical::encode_block(block);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_block(&self, id: Hash256) -> Result<Block> {
         let key = Self::k_block(id);
-        let val = self.kv.get(&key)?;
-        canonical::decode_block(&val).map_err(|e| StoreError::Decode(format!("block decode: {}", e)))
+        let val = self.kv_get(&key)?;
+        canonical::decode_block_strict(&val).map_err(|e| StoreError::Decode(format!("block decode: {}", e)))
     }
 
     fn has_block(&self, id: Hash256) -> Result<bool> {
-        Ok(self.kv.has(&Self::k_block(id))?)
+        Ok(self.kv_has(&Self::k_block(id))?)
+    }
+
+    fn del_header(&self, id: Hash256) -> Result<()> {
+        self.kv_del(&Self::k_header(id))?;
+        Ok(())
+    }
+
+    fn del_block(&self, id: Hash256) -> Result<()> {
+        self.kv_del(&Self::k_block(id))?;
+        Ok(())
     }
 }
 
@@ -317,64 +599,69 @@ impl<S: KvStore> ChainStore for DbChainStore<S> {
     fn set_tip(&self, tip: ChainTip) -> Result<()> {
         let key = Self::k_tip().to_vec();
         let val = Self::encode_tip(tip);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_tip(&self) -> Result<Option<ChainTip>> {
         let key = Self::k_tip();
-        if !self.kv.has(key)? {
+        if !self.kv_has(key)? {
             return Ok(None);
         }
-        let val = self.kv.get(key)?;
+        let val = self.kv_get(key)?;
         Ok(Some(Self::decode_tip(&val)?))
     }
 
     fn set_meta(&self, meta: ChainMeta) -> Result<()> {
         let key = Self::k_meta().to_vec();
         let val = Self::encode_meta(meta);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_meta(&self) -> Result<Option<ChainMeta>> {
         let key = Self::k_meta();
-        if !self.kv.has(key)? {
+        if !self.kv_has(key)? {
             return Ok(None);
         }
-        let val = self.kv.get(key)?;
+        let val = self.kv_get(key)?;
         Ok(Some(Self::decode_meta(&val)?))
     }
 
     fn put_block_meta(&self, id: Hash256, meta: BlockMeta) -> Result<()> {
         let key = Self::k_block_meta(id);
         let val = Self::encode_block_meta(meta);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_block_meta(&self, id: Hash256) -> Result<Option<BlockMeta>> {
         let key = Self::k_block_meta(id);
-        if !self.kv.has(&key)? {
+        if !self.kv_has(&key)? {
             return Ok(None);
         }
-        let val = self.kv.get(&key)?;
+        let val = self.kv_get(&key)?;
         Ok(Some(Self::decode_block_meta(&val)?))
     }
 
+    fn del_block_meta(&self, id: Hash256) -> Result<()> {
+        self.kv_del(&Self::k_block_meta(id))?;
+        Ok(())
+    }
+
     fn add_child(&self, parent: Hash256, child: Hash256) -> Result<()> {
         let key = Self::k_children(parent);
-        let mut children = if self.kv.has(&key)? {
-            let val = self.kv.get(&key)?;
+        let mut children = if self.kv_has(&key)? {
+            let val = self.kv_get(&key)?;
             Self::decode_children(&val)?
         } else {
             Vec::new()
         };
 
-        if !children.iter().any(|h| *h == child) {
+        if !children.contains(&child) {
             children.push(child);
             let val = Self::encode_children(&children);
-            self.kv.put(key, val)?;
+            self.kv_put(key, val)?;
         }
 
         Ok(())
@@ -382,28 +669,132 @@ impl<S: KvStore> ChainStore for DbChainStore<S> {
 
     fn get_children(&self, parent: Hash256) -> Result<Vec<Hash256>> {
         let key = Self::k_children(parent);
-        if !self.kv.has(&key)? {
+        if !self.kv_has(&key)? {
             return Ok(Vec::new());
         }
-        let val = self.kv.get(&key)?;
+        let val = self.kv_get(&key)?;
         Self::decode_children(&val)
     }
 
+    fn remove_child(&self, parent: Hash256, child: Hash256) -> Result<()> {
+        let key = Self::k_children(parent);
+        if !self.kv_has(&key)? {
+            return Ok(());
+        }
+        let mut children = Self::decode_children(&self.kv_get(&key)?)?;
+        let before = children.len();
+        children.retain(|c| *c != child);
+        if children.is_empty() {
+            self.kv_del(&key)?;
+        } else if children.len() != before {
+            self.kv_put(key, Self::encode_children(&children))?;
+        }
+        Ok(())
+    }
+
     fn set_canon_hash(&self, height: Height, hash: Hash256) -> Result<()> {
         let key = Self::k_canon(height);
         let val = Self::encode_canon(hash);
-        self.kv.put(key, val)?;
+        self.kv_put(key, val)?;
         Ok(())
     }
 
     fn get_canon_hash(&self, height: Height) -> Result<Option<Hash256>> {
         let key = Self::k_canon(height);
-        if !self.kv.has(&key)? {
+        if !self.kv_has(&key)? {
             return Ok(None);
         }
-        let val = self.kv.get(&key)?;
+        let val = self.kv_get(&key)?;
         Ok(Some(Self::decode_canon(&val)?))
     }
+
+    fn del_canon_hash(&self, height: Height) -> Result<()> {
+        self.kv_del(&Self::k_canon(height))?;
+        Ok(())
+    }
+
+    fn set_schema_version(&self, v: StoreSchemaVersion) -> Result<()> {
+        self.kv_put(Self::k_schema().to_vec(), Self::encode_schema(v))?;
+        Ok(())
+    }
+
+    fn get_schema_version(&self) -> Result<Option<StoreSchemaVersion>> {
+        let key = Self::k_schema();
+        if !self.kv_has(key)? {
+            return Ok(None);
+        }
+        let val = self.kv_get(key)?;
+        Ok(Some(Self::decode_schema(&val)?))
+    }
+
+    fn put_tx_location(&self, txid: Hash256, loc: TxLocation) -> Result<()> {
+        let key = Self::k_tx_location(txid);
+        let val = Self::encode_tx_location(loc);
+        self.kv_put(key, val)?;
+        Ok(())
+    }
+
+    fn get_tx_location(&self, txid: Hash256) -> Result<Option<TxLocation>> {
+        let key = Self::k_tx_location(txid);
+        if !self.kv_has(&key)? {
+            return Ok(None);
+        }
+        let val = self.kv_get(&key)?;
+        Ok(Some(Self::decode_tx_location(&val)?))
+    }
+
+    fn put_invalid_marker(&self, id: Hash256) -> Result<()> {
+        self.kv_put(Self::k_invalid(id), Vec::new())?;
+        Ok(())
+    }
+
+    fn del_invalid_marker(&self, id: Hash256) -> Result<()> {
+        self.kv_del(&Self::k_invalid(id))?;
+        Ok(())
+    }
+
+    fn invalid_markers(&self) -> Result<Vec<Hash256>> {
+        self.kv_scan_prefix(b"invalid:")?
+            .into_keys()
+            .map(|k| {
+                let id: [u8; 32] = k[8..]
+                    .try_into()
+                    .map_err(|_| StoreError::Decode("invalid: bad key length".to_string()))?;
+                Ok(Hash256(id))
+            })
+            .collect()
+    }
+
+    fn begin_batch(&self) -> Result<()> {
+        let mut pending = self.pending.lock().expect("mutex poisoned");
+        if pending.is_some() {
+            return Err(StoreError::BatchAlreadyOpen);
+        }
+        *pending = Some(PendingWrites::default());
+        Ok(())
+    }
+
+    fn commit_batch(&self) -> Result<()> {
+        let Some(p) = self.pending.lock().expect("mutex poisoned").take() else {
+            return Ok(());
+        };
+        self.kv.batch(p.ops)?;
+        Ok(())
+    }
+
+    fn abort_batch(&self) {
+        self.pending.lock().expect("mutex poisoned").take();
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.kv.flush()?;
+        Ok(())
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.kv.compact()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -423,6 +814,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn store_header_roundtrip() {
         let kv = MemKv::new();
         let store = DbChainStore::new(kv);
@@ -435,9 +827,13 @@ mod tests {
 
         let back = store.get_header(id).unwrap();
         assert_eq!(hdr, back);
+
+        let raw = store.get_header_bytes(id).unwrap();
+        assert_eq!(canonical::decode_block_header(&raw).unwrap(), back);
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn store_block_roundtrip() {
         let kv = MemKv::new();
         let store = DbChainStore::new(kv);
@@ -455,6 +851,28 @@ mod tests {
         assert_eq!(blk, back);
     }
 
+    #[test]
+    fn store_rejects_padded_header_and_block() {
+        let kv = MemKv::new();
+        let store = DbChainStore::new(kv.clone());
+        let id = Hash256([3u8; 32]);
+        let blk = Block {
+            header: sample_header(),
+            txs: vec![],
+        };
+        store.put_header(id, &blk.header).unwrap();
+        store.put_block(id, &blk).unwrap();
+
+        // giá trị hợp lệ + 1 byte rác ở cuối
+        for key in [DbChainStore::<MemKv>::k_header(id), DbChainStore::<MemKv>::k_block(id)] {
+            let mut val = kv.get(&key).unwrap();
+            val.push(0);
+            kv.put(key, val).unwrap();
+        }
+        assert!(matches!(store.get_header(id), Err(StoreError::Decode(_))));
+        assert!(matches!(store.get_block(id), Err(StoreError::Decode(_))));
+    }
+
     #[test]
     fn store_tip_roundtrip() {
         let kv = MemKv::new();
@@ -499,6 +917,7 @@ mod tests {
         let m = BlockMeta {
             parent: Hash256([6u8; 32]),
             height: Height(7),
+            chain_work: (1u128 << 100) + 3,
         };
 
         assert_eq!(store.get_block_meta(id).unwrap(), None);
@@ -508,6 +927,22 @@ mod tests {
         assert_eq!(m, back);
     }
 
+    #[test]
+    fn legacy_block_meta_decodes_with_unknown_work() {
+        let mut raw = b"EGG_BM00".to_vec();
+        raw.extend_from_slice(&[6u8; 32]);
+        raw.extend_from_slice(&7u64.to_be_bytes());
+        let m = DbChainStore::<MemKv>::decode_block_meta(&raw).unwrap();
+        assert_eq!(
+            m,
+            BlockMeta {
+                parent: Hash256([6u8; 32]),
+                height: Height(7),
+                chain_work: 0,
+            }
+        );
+    }
+
     #[test]
     fn children_index_roundtrip_and_is_idempotent() {
         let kv = MemKv::new();
@@ -527,6 +962,13 @@ mod tests {
         assert_eq!(children.len(), 2);
         assert_eq!(children[0], c1);
         assert_eq!(children[1], c2);
+
+        store.remove_child(p, c1).unwrap();
+        store.remove_child(p, Hash256([9u8; 32])).unwrap();
+        assert_eq!(store.get_children(p).unwrap(), vec![c2]);
+        store.remove_child(p, c2).unwrap();
+        assert!(store.get_children(p).unwrap().is_empty());
+        assert!(!store.kv.has(&DbChainStore::<MemKv>::k_children(p)).unwrap());
     }
 
     #[test]
@@ -540,5 +982,154 @@ mod tests {
         assert_eq!(store.get_canon_hash(h).unwrap(), None);
         store.set_canon_hash(h, x).unwrap();
         assert_eq!(store.get_canon_hash(h).unwrap(), Some(x));
+        store.del_canon_hash(h).unwrap();
+        assert_eq!(store.get_canon_hash(h).unwrap(), None);
+    }
+
+    #[test]
+    fn tx_location_roundtrip() {
+        let store = DbChainStore::new(MemKv::new());
+
+        let txid = Hash256([4u8; 32]);
+        let loc = TxLocation {
+            block_id: Hash256([9u8; 32]),
+            index: 3,
+        };
+
+        assert_eq!(store.get_tx_location(txid).unwrap(), None);
+        store.put_tx_location(txid, loc).unwrap();
+        assert_eq!(store.get_tx_location(txid).unwrap(), Some(loc));
+    }
+
+    #[test]
+    fn invalid_markers_roundtrip() {
+        let store = DbChainStore::new(MemKv::new());
+        let (a, b) = (Hash256([1u8; 32]), Hash256([2u8; 32]));
+
+        assert!(store.invalid_markers().unwrap().is_empty());
+        store.put_invalid_marker(b).unwrap();
+        store.put_invalid_marker(a).unwrap();
+        store.put_invalid_marker(a).unwrap();
+        assert_eq!(store.invalid_markers().unwrap(), vec![a, b]);
+
+        store.del_invalid_marker(a).unwrap();
+        store.del_invalid_marker(Hash256([3u8; 32])).unwrap();
+        assert_eq!(store.invalid_markers().unwrap(), vec![b]);
+    }
+
+    #[test]
+    fn schema_written_on_open_and_future_schema_rejected() {
+        let kv = MemKv::new();
+
+        let store = DbChainStore::open(kv.clone()).unwrap();
+        assert_eq!(store.schema_version().unwrap(), Some(STORE_SCHEMA_VERSION));
+
+        // mở lại cùng schema => ok
+        DbChainStore::open(kv.clone()).unwrap();
+
+        let future = StoreSchemaVersion(STORE_SCHEMA_VERSION.0 + 1);
+        store.set_schema_version(future).unwrap();
+
+        let err = DbChainStore::open(kv).err().expect("future schema must be rejected");
+        assert!(matches!(
+            err,
+            StoreError::UnsupportedSchema { found, supported }
+                if found == future && supported == STORE_SCHEMA_VERSION
+        ));
+    }
+
+    #[test]
+    fn batch_buffers_writes_until_commit() {
+        let kv = MemKv::new();
+        let store = DbChainStore::new(kv.clone());
+        let h = sample_header();
+        let id = Hash256([9u8; 32]);
+        let tip = ChainTip {
+            height: Height(0),
+            hash: id,
+        };
+
+        store.begin_batch().unwrap();
+        store.put_header(id, &h).unwrap();
+        store.set_tip(tip).unwrap();
+        // đọc qua store thấy write đang gom, KV bên dưới chưa có gì
+        assert_eq!(store.get_header(id).unwrap(), h);
+        assert_eq!(store.get_tip().unwrap(), Some(tip));
+        assert!(!kv.has(&DbChainStore::<MemKv>::k_header(id)).unwrap());
+        store.abort_batch();
+        assert!(!store.has_header(id).unwrap());
+        assert_eq!(store.get_tip().unwrap(), None);
+
+        store.begin_batch().unwrap();
+        store.put_header(id, &h).unwrap();
+        store.set_tip(tip).unwrap();
+        store.del_header(id).unwrap();
+        store.commit_batch().unwrap();
+        assert!(!kv.has(&DbChainStore::<MemKv>::k_header(id)).unwrap());
+        assert_eq!(DbChainStore::new(kv).get_tip().unwrap(), Some(tip));
+    }
+
+    #[test]
+    fn all_header_ids_scans_exactly_stored_headers() {
+        let store = DbChainStore::new(MemKv::new());
+        assert!(store.all_header_ids().unwrap().is_empty());
+
+        let mut ids = Vec::new();
+        for i in [5u8, 1, 9, 3] {
+            let mut h = sample_header();
+            h.nonce = u64::from(i);
+            let id = Hash256([i; 32]);
+            store.put_header(id, &h).unwrap();
+            ids.push(id);
+        }
+        // key khác prefix không lẫn vào
+        store.put_block_meta(
+            Hash256([7u8; 32]),
+            BlockMeta {
+                parent: Hash256::zero(),
+                height: Height(1),
+                chain_work: 1,
+            },
+        )
+        .unwrap();
+        store.add_child(Hash256([5u8; 32]), Hash256([1u8; 32])).unwrap();
+
+        ids.sort_by_key(|id| id.0);
+        assert_eq!(store.all_header_ids().unwrap(), ids);
+
+        // thấy cả write đang gom
+        store.begin_batch().unwrap();
+        store.del_header(Hash256([9u8; 32])).unwrap();
+        store.put_header(Hash256([2u8; 32]), &sample_header()).unwrap();
+        let got = store.all_header_ids().unwrap();
+        let expect: Vec<_> = [1u8, 2, 3, 5].iter().map(|&i| Hash256([i; 32])).collect();
+        assert_eq!(got, expect);
+        store.commit_batch().unwrap();
+        assert_eq!(store.all_header_ids().unwrap(), got);
+    }
+
+    #[test]
+    fn batch_is_per_handle_and_not_nested() {
+        let kv = MemKv::new();
+        let store = DbChainStore::new(kv.clone());
+        let other = store.clone();
+        let tip = ChainTip {
+            height: Height(1),
+            hash: Hash256([1u8; 32]),
+        };
+
+        store.begin_batch().unwrap();
+        assert!(matches!(store.begin_batch(), Err(StoreError::BatchAlreadyOpen)));
+        store.set_canon_hash(Height(1), tip.hash).unwrap();
+        // clone không thấy write đang gom, ghi của nó đi thẳng xuống KV
+        assert_eq!(other.get_canon_hash(Height(1)).unwrap(), None);
+        other.set_tip(tip).unwrap();
+        store.abort_batch();
+
+        assert_eq!(store.get_tip().unwrap(), Some(tip));
+        assert_eq!(store.get_canon_hash(Height(1)).unwrap(), None);
+        // batch đã đóng => mở lại được
+        store.begin_batch().unwrap();
+        store.commit_batch().unwrap();
     }
 }
@@ -0,0 +1,126 @@
+#![forbid(unsafe_code)]
+
+use std::collections::{HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use rand::seq::IteratorRandom;
+
+/// Nguồn/đích địa chỉ peer cho `GetAddr`/`Addr`, inject vào `PeerMachine`.
+pub trait AddressBook: Send + std::fmt::Debug {
+    /// Tối đa `max` địa chỉ đã biết, chọn ngẫu nhiên (peer không dò được toàn bộ sổ).
+    fn sample(&self, max: usize) -> Vec<SocketAddr>;
+    /// Địa chỉ peer khác chia sẻ (đã qua `is_shareable_addr`).
+    fn add(&mut self, addrs: &[SocketAddr]);
+}
+
+/// Sổ địa chỉ dùng chung giữa các kết nối.
+pub type SharedAddressBook = Arc<Mutex<dyn AddressBook>>;
+
+/// Địa chỉ đáng chia sẻ/nhận: bỏ unspecified, multicast, port 0; loopback chỉ khi `allow_local`
+/// (mạng test/regtest chạy nhiều node trên 1 máy).
+pub fn is_shareable_addr(addr: &SocketAddr, allow_local: bool) -> bool {
+    let ip = addr.ip();
+    if addr.port() == 0 || ip.is_unspecified() || ip.is_multicast() {
+        return false;
+    }
+    if let IpAddr::V4(v4) = ip {
+        if v4.is_broadcast() {
+            return false;
+        }
+    }
+    allow_local || !ip.is_loopback()
+}
+
+/// `AddressBook` trong RAM, giữ tối đa `capacity` địa chỉ (đầy => bỏ địa chỉ cũ nhất).
+#[derive(Debug)]
+pub struct MemAddressBook {
+    capacity: usize,
+    order: VecDeque<SocketAddr>,
+    known: HashSet<SocketAddr>,
+}
+
+impl MemAddressBook {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::new(),
+            known: HashSet::new(),
+        }
+    }
+
+    pub fn shared(capacity: usize) -> SharedAddressBook {
+        Arc::new(Mutex::new(Self::new(capacity)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.known.contains(addr)
+    }
+}
+
+impl AddressBook for MemAddressBook {
+    fn sample(&self, max: usize) -> Vec<SocketAddr> {
+        self.order
+            .iter()
+            .copied()
+            .choose_multiple(&mut rand::thread_rng(), max)
+    }
+
+    fn add(&mut self, addrs: &[SocketAddr]) {
+        if self.capacity == 0 {
+            return;
+        }
+        for a in addrs {
+            if !self.known.insert(*a) {
+                continue;
+            }
+            self.order.push_back(*a);
+            if self.order.len() > self.capacity {
+                if let Some(old) = self.order.pop_front() {
+                    self.known.remove(&old);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shareable_addr_filters_unroutable() {
+        let a = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(is_shareable_addr(&a("203.0.113.5:9000"), false));
+        assert!(is_shareable_addr(&a("[2001:db8::1]:9000"), false));
+        assert!(!is_shareable_addr(&a("203.0.113.5:0"), false));
+        assert!(!is_shareable_addr(&a("0.0.0.0:9000"), true));
+        assert!(!is_shareable_addr(&a("[::]:9000"), true));
+        assert!(!is_shareable_addr(&a("255.255.255.255:9000"), true));
+        assert!(!is_shareable_addr(&a("224.0.0.1:9000"), true));
+        assert!(!is_shareable_addr(&a("127.0.0.1:9000"), false));
+        assert!(!is_shareable_addr(&a("[::1]:9000"), false));
+        assert!(is_shareable_addr(&a("127.0.0.1:9000"), true));
+    }
+
+    #[test]
+    fn mem_book_dedups_and_evicts_oldest() {
+        let mut book = MemAddressBook::new(2);
+        let a = |p: u16| SocketAddr::from(([10, 0, 0, 1], p));
+        book.add(&[a(1), a(1), a(2)]);
+        assert_eq!(book.len(), 2);
+        book.add(&[a(3)]);
+        assert!(!book.contains(&a(1)));
+        assert!(book.contains(&a(2)) && book.contains(&a(3)));
+        assert_eq!(book.sample(10).len(), 2);
+        assert_eq!(book.sample(1).len(), 1);
+    }
+}
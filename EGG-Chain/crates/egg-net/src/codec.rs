@@ -1,12 +1,14 @@
 #![forbid(unsafe_code)]
 
-use crate::protocol::{decode_message, encode_message, Message, ProtocolError};
+use crate::protocol::{decode_message, encode_headers_raw, encode_message, Message, ProtocolError};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FrameError {
     TooLarge { len: u32 },
     Protocol(ProtocolError),
     UnexpectedEof { needed: usize, remaining: usize },
+    /// Frame dở dang không hoàn tất trong thời gian chờ tối đa (peer nhỏ giọt byte).
+    PartialTimeout { buffered: usize, waited_ms: u64 },
 }
 
 impl core::fmt::Display for FrameError {
@@ -17,6 +19,9 @@ impl core::fmt::Display for FrameError {
             FrameError::UnexpectedEof { needed, remaining } => {
                 write!(f, "unexpected eof (needed {}, remaining {})", needed, remaining)
             }
+            FrameError::PartialTimeout { buffered, waited_ms } => {
+                write!(f, "partial frame timeout ({} bytes buffered after {} ms)", buffered, waited_ms)
+            }
         }
     }
 }
@@ -30,13 +35,23 @@ pub const MAX_FRAME_LEN: u32 = 8 * 1024 * 1024; // 8 MiB
 /// Encode 1 message thành frame: u32_be_len + payload.
 pub fn encode_frame(msg: &Message) -> Result<Vec<u8>> {
     let payload = encode_message(msg).map_err(FrameError::Protocol)?;
+    frame_payload(&payload)
+}
+
+/// Frame cho Headers từ canonical header bytes có sẵn (không decode/re-encode).
+pub fn encode_headers_frame_raw(raw_headers: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let payload = encode_headers_raw(raw_headers).map_err(FrameError::Protocol)?;
+    frame_payload(&payload)
+}
+
+fn frame_payload(payload: &[u8]) -> Result<Vec<u8>> {
     let len_u32: u32 = payload.len().try_into().unwrap_or(u32::MAX);
     if len_u32 > MAX_FRAME_LEN {
         return Err(FrameError::TooLarge { len: len_u32 });
     }
     let mut out = Vec::with_capacity(4 + payload.len());
     out.extend_from_slice(&len_u32.to_be_bytes());
-    out.extend_from_slice(&payload);
+    out.extend_from_slice(payload);
     Ok(out)
 }
 
@@ -89,6 +104,10 @@ mod tests {
             tip: Tip { height: 0, hash: Hash256([2u8; 32]) },
             node_nonce: 9,
             agent: "x".to_string(),
+            challenge: [3u8; 16],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: crate::protocol::DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: Some(10),
         };
 
         let fa = encode_frame(&a).unwrap();
@@ -1,5 +1,6 @@
 #![forbid(unsafe_code)]
 
+pub mod addr;
 pub mod codec;
 pub mod peer;
 pub mod protocol;
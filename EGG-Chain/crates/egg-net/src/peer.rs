@@ -1,15 +1,23 @@
 #![forbid(unsafe_code)]
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::{Duration, Instant};
 
 use egg_crypto::hash_header;
-use egg_types::{BlockHeader, Hash256};
+use egg_types::Hash256;
+/// Trait chuyển sang egg-types; giữ đường dẫn cũ `egg_net::peer::HeaderProvider`.
+pub use egg_types::HeaderProvider;
 
-use crate::protocol::{Message, Tip};
+use crate::addr::{is_shareable_addr, SharedAddressBook};
+use crate::codec::MAX_FRAME_LEN;
+use crate::protocol::{
+    Message, Tip, CHALLENGE_LEN, DEFAULT_MAX_HEADERS_PER_MSG, MAX_ADDRS_PER_MSG, MAX_LOCATOR_LEN,
+};
 
 const MAX_NOTFOUND_PER_ID: u8 = 2;
 const MAX_DISTINCT_NOTFOUND_IDS: usize = 16;
+// số id notfound tối đa được nhớ (LRU); peer rải notfound chậm để né ban không làm phình bộ nhớ
+const NOTFOUND_TRACK_CAP: usize = MAX_DISTINCT_NOTFOUND_IDS * 4;
 
 // ---- Penalty / Decay / Ban threshold ----
 const PENALTY_BAN_THRESHOLD: i32 = 100;
@@ -26,6 +34,10 @@ const PENALTY_BLOCK_NOTFOUND: i32 = 5;
 const PENALTY_TOO_MANY_NOTFOUND_PER_ID: i32 = 25;
 const PENALTY_TOO_MANY_DISTINCT_NOTFOUND: i32 = 40;
 const PENALTY_TIMEOUT: i32 = 8;
+const PENALTY_OVERSIZED_ADDR: i32 = 20;
+const PENALTY_OVERSIZED_LOCATOR: i32 = 20;
+/// Block không qua kiểm tra consensus (PoW/merkle/height...); 2 lần => ban.
+pub const PENALTY_INVALID_BLOCK: i32 = 50;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Role {
@@ -48,6 +60,7 @@ pub struct LocalInfo {
     pub tip: Tip,
     pub node_nonce: u64,
     pub agent: String,
+    pub pruned_from_height: Option<u64>,
 }
 
 #[derive(Clone, Debug)]
@@ -57,6 +70,52 @@ pub struct RemoteInfo {
     pub tip: Tip,
     pub node_nonce: u64,
     pub agent: String,
+    pub limits: MessageLimits,
+    pub pruned_from_height: Option<u64>,
+}
+
+/// Kích thước message tối đa 1 bên chấp nhận, quảng bá trong Hello/HelloAck.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageLimits {
+    pub max_frame_len: u32,
+    pub max_headers_per_msg: u32,
+}
+
+impl MessageLimits {
+    /// Giới hạn chung 2 bên cùng nhận được.
+    pub fn min(self, other: Self) -> Self {
+        Self {
+            max_frame_len: self.max_frame_len.min(other.max_frame_len),
+            max_headers_per_msg: self.max_headers_per_msg.min(other.max_headers_per_msg),
+        }
+    }
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: DEFAULT_MAX_HEADERS_PER_MSG,
+        }
+    }
+}
+
+/// Lọc peer theo `agent` trong handshake (match theo prefix), cho mạng private.
+/// Deny thắng allow; allowlist rỗng = cho phép mọi agent không bị deny.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AgentPolicy {
+    pub allow_prefixes: Vec<String>,
+    pub deny_prefixes: Vec<String>,
+}
+
+impl AgentPolicy {
+    pub fn allows(&self, agent: &str) -> bool {
+        if self.deny_prefixes.iter().any(|p| agent.starts_with(p.as_str())) {
+            return false;
+        }
+        self.allow_prefixes.is_empty()
+            || self.allow_prefixes.iter().any(|p| agent.starts_with(p.as_str()))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -66,9 +125,25 @@ pub struct PeerMachine {
     local: LocalInfo,
     remote: Option<RemoteInfo>,
 
+    agent_policy: AgentPolicy,
+
+    // giới hạn local quảng bá; sau handshake `limits` = min(local, remote)
+    local_limits: MessageLimits,
+    limits: MessageLimits,
+
+    // challenge gửi trong Hello, peer phải echo lại trong HelloAck
+    challenge: [u8; CHALLENGE_LEN],
+
+    // GetAddr/Addr: None => không tham gia trao đổi địa chỉ
+    addr_book: Option<SharedAddressBook>,
+    // nhận/chia sẻ cả địa chỉ loopback (mạng test chạy nhiều node trên 1 máy)
+    allow_local_addrs: bool,
+
     // headers-first sync cursor
     sync_enabled: bool,
     sync_cursor_start: Hash256,
+    // locator cho GetHeaders đầu tiên (rỗng => GetHeaders từ tip như cũ)
+    sync_locator: Vec<Hash256>,
     sync_batch_max: u32,
 
     // ban state
@@ -81,6 +156,8 @@ pub struct PeerMachine {
     // notfound tracking (để tính pattern)
     notfound_by_id: HashMap<Hash256, u8>,
     notfound_distinct_ids: HashSet<Hash256>,
+    // thứ tự dùng gần nhất (cũ nhất ở đầu) để evict
+    notfound_lru: VecDeque<Hash256>,
 
     // penalty
     penalty_score: i32,
@@ -97,10 +174,21 @@ impl PeerMachine {
             hs: HandshakeState::Init,
             sync_enabled: false,
             sync_cursor_start: local.tip.hash,
+            sync_locator: Vec::new(),
             sync_batch_max: 2000,
             local,
             remote: None,
 
+            agent_policy: AgentPolicy::default(),
+
+            local_limits: MessageLimits::default(),
+            limits: MessageLimits::default(),
+
+            challenge: rand::random(),
+
+            addr_book: None,
+            allow_local_addrs: false,
+
             banned: None,
 
             known_header_ids: known,
@@ -108,6 +196,7 @@ impl PeerMachine {
 
             notfound_by_id: HashMap::new(),
             notfound_distinct_ids: HashSet::new(),
+            notfound_lru: VecDeque::new(),
 
             penalty_score: 0,
             penalty_last_decay: Instant::now(),
@@ -121,6 +210,56 @@ impl PeerMachine {
         self
     }
 
+    pub fn with_agent_policy(mut self, policy: AgentPolicy) -> Self {
+        self.agent_policy = policy;
+        self
+    }
+
+    /// Giới hạn message node này nhận được (quảng bá trong Hello/HelloAck).
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> Self {
+        self.local_limits = limits;
+        self.limits = limits;
+        self
+    }
+
+    /// Giới hạn dùng với peer: của local trước handshake, min(local, remote) sau đó.
+    pub fn negotiated_limits(&self) -> MessageLimits {
+        self.limits
+    }
+
+    /// Số header tối đa xin / gửi trong 1 batch với peer này.
+    pub fn header_batch_max(&self) -> u32 {
+        self.sync_batch_max.min(self.limits.max_headers_per_msg).max(1)
+    }
+
+    /// Mở đầu header sync bằng locator (tip -> genesis) để peer tìm được điểm rẽ nhánh
+    /// khi chain local không phải prefix của chain peer.
+    pub fn with_sync_locator(mut self, locator: Vec<Hash256>) -> Self {
+        self.sync_locator = locator;
+        self
+    }
+
+    /// Trả lời `GetAddr` từ `book` và nạp địa chỉ hợp lệ từ `Addr` vào đó.
+    pub fn with_address_book(mut self, book: SharedAddressBook) -> Self {
+        self.addr_book = Some(book);
+        self
+    }
+
+    pub fn with_allow_local_addrs(mut self, allow: bool) -> Self {
+        self.allow_local_addrs = allow;
+        self
+    }
+
+    /// Ghi đè challenge (test / replay có kiểm soát).
+    pub fn with_challenge(mut self, challenge: [u8; CHALLENGE_LEN]) -> Self {
+        self.challenge = challenge;
+        self
+    }
+
+    pub fn challenge(&self) -> [u8; CHALLENGE_LEN] {
+        self.challenge
+    }
+
     pub fn is_ready(&self) -> bool {
         self.hs == HandshakeState::Ready
     }
@@ -129,6 +268,19 @@ impl PeerMachine {
         self.remote.as_ref()
     }
 
+    /// Height thấp nhất peer còn giữ block (None = full node / chưa handshake).
+    pub fn remote_pruned_from_height(&self) -> Option<u64> {
+        self.remote.as_ref().and_then(|r| r.pruned_from_height)
+    }
+
+    /// Peer có thể phục vụ block ở `height` không (peer đã prune thì bỏ qua block cũ).
+    pub fn can_serve_block_at(&self, height: u64) -> bool {
+        match self.remote_pruned_from_height() {
+            Some(from) => height >= from,
+            None => true,
+        }
+    }
+
     pub fn is_banned(&self) -> bool {
         self.banned.is_some()
     }
@@ -150,6 +302,15 @@ impl PeerMachine {
         self.inflight_blocks.len()
     }
 
+    /// Khôi phục uy tín đã lưu từ phiên trước (điểm phạt + ban còn hiệu lực).
+    pub fn restore_reputation(&mut self, penalty_score: i32, ban_reason: Option<String>) {
+        self.penalty_score = penalty_score.max(0);
+        self.penalty_last_decay = Instant::now();
+        if let Some(reason) = ban_reason {
+            self.ban(reason);
+        }
+    }
+
     fn ban(&mut self, reason: impl Into<String>) {
         if self.banned.is_none() {
             self.banned = Some(reason.into());
@@ -197,6 +358,11 @@ impl PeerMachine {
         self.add_penalty(Instant::now(), PENALTY_TIMEOUT, "timeout");
     }
 
+    /// Phạt peer vì lỗi phát hiện ngoài state machine (vd block sai consensus khi ingest).
+    pub fn penalize(&mut self, points: i32, why: &str) {
+        self.add_penalty(Instant::now(), points, why);
+    }
+
     pub fn start(&mut self) -> Vec<Message> {
         if self.is_banned() {
             return vec![];
@@ -210,6 +376,10 @@ impl PeerMachine {
                 tip: self.local.tip,
                 node_nonce: self.local.node_nonce,
                 agent: self.local.agent.clone(),
+                challenge: self.challenge,
+                max_frame_len: self.local_limits.max_frame_len,
+                max_headers_per_msg: self.local_limits.max_headers_per_msg,
+                pruned_from_height: self.local.pruned_from_height,
             }];
         }
         vec![]
@@ -218,7 +388,7 @@ impl PeerMachine {
     fn make_get_headers(&self, start: Hash256) -> Message {
         Message::GetHeaders {
             start,
-            max: self.sync_batch_max,
+            max: self.header_batch_max(),
         }
     }
 
@@ -227,6 +397,18 @@ impl PeerMachine {
         Message::GetBlock { id }
     }
 
+    /// Xin block tại các height `from_height..` trong 1 message; `ids` = id (theo header đã biết)
+    /// của từng height, đều được đánh dấu inflight. Id không có trong `BlockRange` trả về
+    /// vẫn inflight (peer có thể trả thiếu), caller tự xin lại qua `GetBlock`.
+    pub fn request_block_range(&mut self, from_height: u64, ids: &[Hash256]) -> Message {
+        self.inflight_blocks.extend(ids.iter().copied());
+        Message::GetBlockRange {
+            from_height,
+            count: ids.len().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn mark_remote(
         &mut self,
         chain_id: u32,
@@ -234,24 +416,47 @@ impl PeerMachine {
         tip: Tip,
         node_nonce: u64,
         agent: String,
+        limits: MessageLimits,
+        pruned_from_height: Option<u64>,
     ) {
+        self.limits = self.local_limits.min(limits);
         self.remote = Some(RemoteInfo {
             chain_id,
             genesis_id,
             tip,
             node_nonce,
             agent,
+            limits,
+            pruned_from_height,
         });
     }
 
     fn maybe_sync_kickoff(&mut self) -> Vec<Message> {
         if self.sync_enabled && self.hs == HandshakeState::Ready {
+            if !self.sync_locator.is_empty() {
+                return vec![Message::GetHeadersLocator {
+                    locator: std::mem::take(&mut self.sync_locator),
+                    max: self.header_batch_max(),
+                }];
+            }
             vec![self.make_get_headers(self.sync_cursor_start)]
         } else {
             vec![]
         }
     }
 
+    /// Vòng sync mới trên kết nối đang mở: GetHeaders theo locator của tip hiện tại
+    /// (rỗng nếu chưa handshake xong hoặc không bật header sync).
+    pub fn resync_headers(&mut self, locator: Vec<Hash256>) -> Vec<Message> {
+        if !self.sync_enabled || self.hs != HandshakeState::Ready {
+            return vec![];
+        }
+        vec![Message::GetHeadersLocator {
+            locator,
+            max: self.header_batch_max(),
+        }]
+    }
+
     fn hardening_on_block_reply(&mut self, now: Instant, id: Hash256) -> bool {
         // 1) unsolicited reply
         if !self.inflight_blocks.remove(&id) {
@@ -307,11 +512,22 @@ impl PeerMachine {
                 );
             }
         }
+
+        self.notfound_lru.retain(|x| *x != id);
+        self.notfound_lru.push_back(id);
+        while self.notfound_lru.len() > NOTFOUND_TRACK_CAP {
+            let Some(old) = self.notfound_lru.pop_front() else {
+                break;
+            };
+            self.notfound_by_id.remove(&old);
+            self.notfound_distinct_ids.remove(&old);
+        }
     }
 
     fn hardening_on_found(&mut self, id: Hash256) {
         self.notfound_by_id.remove(&id);
         self.notfound_distinct_ids.remove(&id);
+        self.notfound_lru.retain(|x| *x != id);
     }
 
     pub fn on_message(&mut self, msg: Message) -> Vec<Message> {
@@ -333,8 +549,20 @@ impl PeerMachine {
                 tip,
                 node_nonce,
                 agent,
+                challenge,
+                max_frame_len,
+                max_headers_per_msg,
+                pruned_from_height,
             } => {
-                self.mark_remote(chain_id, genesis_id, tip, node_nonce, agent);
+                if !self.agent_policy.allows(&agent) {
+                    self.ban("agent not allowed");
+                    return vec![];
+                }
+                let limits = MessageLimits {
+                    max_frame_len,
+                    max_headers_per_msg,
+                };
+                self.mark_remote(chain_id, genesis_id, tip, node_nonce, agent, limits, pruned_from_height);
 
                 match self.hs {
                     HandshakeState::Init => self.hs = HandshakeState::ReceivedHello,
@@ -348,9 +576,16 @@ impl PeerMachine {
                     tip: self.local.tip,
                     node_nonce: self.local.node_nonce,
                     agent: self.local.agent.clone(),
+                    challenge_echo: challenge,
+                    max_frame_len: self.local_limits.max_frame_len,
+                    max_headers_per_msg: self.local_limits.max_headers_per_msg,
+                    pruned_from_height: self.local.pruned_from_height,
                 }];
 
-                self.hs = HandshakeState::Ready;
+                // đã gửi Hello: chờ HelloAck echo đúng challenge mới Ready
+                if self.hs != HandshakeState::SentHello {
+                    self.hs = HandshakeState::Ready;
+                }
                 out.extend(self.maybe_sync_kickoff());
                 out
             }
@@ -361,13 +596,42 @@ impl PeerMachine {
                 tip,
                 node_nonce,
                 agent,
+                challenge_echo,
+                max_frame_len,
+                max_headers_per_msg,
+                pruned_from_height,
             } => {
-                self.mark_remote(chain_id, genesis_id, tip, node_nonce, agent);
+                // chỉ nhận HelloAck trả lời Hello của mình: ack tự phát / replay bị ban
+                if self.hs != HandshakeState::SentHello {
+                    self.ban("unexpected HelloAck");
+                    return vec![];
+                }
+                if challenge_echo != self.challenge {
+                    self.ban("handshake challenge mismatch");
+                    return vec![];
+                }
+
+                if !self.agent_policy.allows(&agent) {
+                    self.ban("agent not allowed");
+                    return vec![];
+                }
+                let limits = MessageLimits {
+                    max_frame_len,
+                    max_headers_per_msg,
+                };
+                self.mark_remote(chain_id, genesis_id, tip, node_nonce, agent, limits, pruned_from_height);
                 self.hs = HandshakeState::Ready;
                 self.maybe_sync_kickoff()
             }
 
             Message::GetHeaders { start: _, max: _ } => vec![],
+            Message::GetHeadersLocator { locator, max: _ } => {
+                // decode đã chặn; message dựng tay (transport khác) vẫn bị phạt
+                if locator.len() > MAX_LOCATOR_LEN {
+                    self.add_penalty(now, PENALTY_OVERSIZED_LOCATOR, "oversized locator");
+                }
+                vec![]
+            }
 
             Message::Headers { headers } => {
                 // hardening: ghi nhận known header ids
@@ -416,16 +680,62 @@ impl PeerMachine {
                 vec![]
             }
 
+            Message::GetBlockRange { from_height: _, count: _ } => vec![],
+
+            Message::BlockRange { blocks } => {
+                // mỗi block phải là 1 id đã xin (inflight) với header đã biết
+                for b in blocks.iter() {
+                    let id = hash_header(&b.header);
+                    if !self.hardening_on_block_reply(now, id) {
+                        return vec![];
+                    }
+                    self.hardening_on_found(id);
+                }
+                vec![]
+            }
+
+            Message::GetBlockTxids { id: _ } => vec![],
+            Message::BlockTxids { id: _, txids: _ } => vec![],
+
+            Message::CompactBlock { header, txids: _ } => {
+                self.known_header_ids.insert(hash_header(&header));
+                vec![]
+            }
+            Message::GetBlockTxn { id: _, indexes: _ } => vec![],
+            Message::BlockTxn { id: _, txs: _ } => vec![],
+
             Message::Ping { nonce } => vec![Message::Pong { nonce }],
             Message::Pong { nonce: _ } => vec![],
+
+            Message::GetAddr => {
+                let Some(book) = self.addr_book.as_ref().filter(|_| self.is_ready()) else {
+                    return vec![];
+                };
+                let mut addrs = book.lock().expect("mutex poisoned").sample(MAX_ADDRS_PER_MSG);
+                addrs.retain(|a| is_shareable_addr(a, self.allow_local_addrs));
+                vec![Message::Addr { addrs }]
+            }
+            Message::Addr { addrs } => {
+                if !self.is_ready() {
+                    return vec![];
+                }
+                if addrs.len() > MAX_ADDRS_PER_MSG {
+                    self.add_penalty(now, PENALTY_OVERSIZED_ADDR, "oversized Addr");
+                    return vec![];
+                }
+                let good: Vec<_> = addrs
+                    .into_iter()
+                    .filter(|a| is_shareable_addr(a, self.allow_local_addrs))
+                    .collect();
+                if let Some(book) = &self.addr_book {
+                    book.lock().expect("mutex poisoned").add(&good);
+                }
+                vec![]
+            }
         }
     }
 }
 
-pub trait HeaderProvider {
-    fn get_headers_after(&self, start: Hash256, max: usize) -> Vec<BlockHeader>;
-}
-
 pub fn handle_get_headers<P: HeaderProvider>(p: &P, start: Hash256, max: u32) -> Message {
     let list = p.get_headers_after(start, max as usize);
     Message::Headers { headers: list }
@@ -434,7 +744,7 @@ pub fn handle_get_headers<P: HeaderProvider>(p: &P, start: Hash256, max: u32) ->
 #[cfg(test)]
 mod tests {
     use super::*;
-    use egg_types::{Block, Hash256, Height};
+    use egg_types::{Block, BlockHeader, Hash256, Height};
 
     fn hdr(parent: Hash256, height: u64, nonce: u64) -> BlockHeader {
         BlockHeader {
@@ -458,6 +768,7 @@ mod tests {
             tip,
             node_nonce: 111,
             agent: "local".to_string(),
+            pruned_from_height: None,
         }
     }
 
@@ -471,12 +782,226 @@ mod tests {
             },
             node_nonce: 222,
             agent: "remote".to_string(),
+            challenge_echo: [0u8; CHALLENGE_LEN],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        }
+    }
+
+    /// Outbound đã gửi Hello (challenge 0) => `mk_ack()` hoàn tất handshake.
+    fn mk_started() -> PeerMachine {
+        let mut p = PeerMachine::new(Role::Outbound, mk_local()).with_challenge([0u8; CHALLENGE_LEN]);
+        let _ = p.start();
+        p
+    }
+
+    fn mk_ack_echo(challenge_echo: [u8; CHALLENGE_LEN]) -> Message {
+        let mut m = mk_ack();
+        if let Message::HelloAck { challenge_echo: e, .. } = &mut m {
+            *e = challenge_echo;
         }
+        m
+    }
+
+    #[test]
+    fn handshake_correct_challenge_echo_reaches_ready() {
+        let mut p =
+            PeerMachine::new(Role::Outbound, mk_local()).with_challenge([7u8; CHALLENGE_LEN]);
+        let out = p.start();
+        assert!(matches!(
+            out.as_slice(),
+            [Message::Hello { challenge, .. }] if *challenge == [7u8; CHALLENGE_LEN]
+        ));
+
+        let _ = p.on_message(mk_ack_echo([7u8; CHALLENGE_LEN]));
+        assert!(p.is_ready());
+        assert!(!p.is_banned());
+    }
+
+    #[test]
+    fn handshake_wrong_challenge_echo_is_rejected() {
+        let mut p =
+            PeerMachine::new(Role::Outbound, mk_local()).with_challenge([7u8; CHALLENGE_LEN]);
+        let _ = p.start();
+
+        let out = p.on_message(mk_ack_echo([8u8; CHALLENGE_LEN]));
+        assert!(out.is_empty());
+        assert!(!p.is_ready());
+        assert!(p.is_banned());
+        assert!(p.ban_reason().unwrap().contains("challenge"));
+    }
+
+    #[test]
+    fn unsolicited_or_replayed_hello_ack_is_rejected() {
+        // chưa start(): chưa gửi Hello nên không có gì để ack, dù echo khớp challenge
+        let mut p = PeerMachine::new(Role::Outbound, mk_local()).with_challenge([0u8; CHALLENGE_LEN]);
+        assert!(p.on_message(mk_ack()).is_empty());
+        assert!(!p.is_ready());
+        assert!(p.ban_reason().unwrap().contains("unexpected HelloAck"));
+
+        // inbound không bao giờ gửi Hello
+        let mut q = PeerMachine::new(Role::Inbound, mk_local()).with_challenge([0u8; CHALLENGE_LEN]);
+        let _ = q.on_message(mk_ack());
+        assert!(!q.is_ready());
+        assert!(q.is_banned());
+
+        // ack phát lại sau khi đã Ready
+        let mut r = mk_started();
+        let _ = r.on_message(mk_ack());
+        assert!(r.is_ready());
+        let _ = r.on_message(mk_ack());
+        assert!(r.is_banned());
+    }
+
+    #[test]
+    fn inbound_echoes_received_challenge() {
+        let mut p = PeerMachine::new(Role::Inbound, mk_local());
+        let out = p.on_message(Message::Hello {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 0,
+                hash: Hash256::zero(),
+            },
+            node_nonce: 222,
+            agent: "remote".to_string(),
+            challenge: [4u8; CHALLENGE_LEN],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        });
+        assert!(matches!(
+            out.first(),
+            Some(Message::HelloAck { challenge_echo, .. }) if *challenge_echo == [4u8; CHALLENGE_LEN]
+        ));
+        assert!(p.is_ready());
+    }
+
+    #[test]
+    fn agent_policy_allowlist_and_denylist() {
+        let policy = AgentPolicy {
+            allow_prefixes: vec!["remote".to_string()],
+            deny_prefixes: vec![],
+        };
+
+        // mk_ack() có agent "remote"
+        let mut ok = PeerMachine::new(Role::Outbound, mk_local())
+            .with_agent_policy(policy.clone())
+            .with_challenge([0u8; CHALLENGE_LEN]);
+        let _ = ok.start();
+        let _ = ok.on_message(mk_ack());
+        assert!(ok.is_ready());
+        assert!(!ok.is_banned());
+
+        let strict = AgentPolicy {
+            allow_prefixes: vec!["egg-node/".to_string()],
+            deny_prefixes: vec![],
+        };
+        let mut bad = PeerMachine::new(Role::Inbound, mk_local()).with_agent_policy(strict);
+        let out = bad.on_message(Message::Hello {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 0,
+                hash: Hash256::zero(),
+            },
+            node_nonce: 222,
+            agent: "other-client/1.0".to_string(),
+            challenge: [4u8; CHALLENGE_LEN],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        });
+        assert!(out.is_empty());
+        assert!(!bad.is_ready());
+        assert_eq!(bad.ban_reason(), Some("agent not allowed"));
+
+        let deny = AgentPolicy {
+            allow_prefixes: vec!["egg-node/".to_string()],
+            deny_prefixes: vec!["egg-node/0.0".to_string()],
+        };
+        assert!(deny.allows("egg-node/0.1"));
+        assert!(!deny.allows("egg-node/0.0.9"));
+        assert!(AgentPolicy::default().allows("anything"));
+    }
+
+    #[test]
+    fn header_sync_starts_with_locator_then_continues_by_cursor() {
+        let loc = vec![Hash256([3u8; 32]), Hash256::zero()];
+        let mut p = PeerMachine::new(Role::Outbound, mk_local())
+            .enable_header_sync(10)
+            .with_sync_locator(loc.clone())
+            .with_challenge([0u8; CHALLENGE_LEN]);
+        let _ = p.start();
+
+        let out = p.on_message(mk_ack());
+        assert_eq!(out, vec![Message::GetHeadersLocator { locator: loc, max: 10 }]);
+
+        let h1 = hdr(Hash256::zero(), 1, 1);
+        let out = p.on_message(Message::Headers { headers: vec![h1.clone()] });
+        assert_eq!(
+            out,
+            vec![Message::GetHeaders {
+                start: hash_header(&h1),
+                max: 10
+            }]
+        );
+
+        // peer hết header => vòng sau hỏi lại theo locator mới
+        assert!(p.on_message(Message::Headers { headers: vec![] }).is_empty());
+        let loc2 = vec![hash_header(&h1), Hash256::zero()];
+        assert_eq!(
+            p.resync_headers(loc2.clone()),
+            vec![Message::GetHeadersLocator { locator: loc2, max: 10 }]
+        );
+    }
+
+    #[test]
+    fn pruned_peer_handshake_limits_block_eligibility() {
+        let mut p =
+            PeerMachine::new(Role::Outbound, mk_local()).with_challenge([7u8; CHALLENGE_LEN]);
+        let _ = p.start();
+        assert!(p.can_serve_block_at(0));
+
+        let mut ack = mk_ack_echo([7u8; CHALLENGE_LEN]);
+        if let Message::HelloAck { pruned_from_height, .. } = &mut ack {
+            *pruned_from_height = Some(100);
+        }
+        let _ = p.on_message(ack);
+        assert!(p.is_ready());
+        assert_eq!(p.remote_pruned_from_height(), Some(100));
+        assert!(!p.can_serve_block_at(99));
+        assert!(p.can_serve_block_at(100));
+
+        // inbound: Hello của node đã prune cũng được ghi nhận, HelloAck quảng bá trạng thái local
+        let mut local = mk_local();
+        local.pruned_from_height = Some(5);
+        let mut q = PeerMachine::new(Role::Inbound, local);
+        let out = q.on_message(Message::Hello {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 0,
+                hash: Hash256::zero(),
+            },
+            node_nonce: 222,
+            agent: "remote".to_string(),
+            challenge: [4u8; CHALLENGE_LEN],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: Some(50),
+        });
+        assert!(matches!(
+            out.first(),
+            Some(Message::HelloAck { pruned_from_height: Some(5), .. })
+        ));
+        assert!(!q.can_serve_block_at(49));
     }
 
     #[test]
     fn penalty_ban_requires_threshold_not_immediate() {
-        let mut p = PeerMachine::new(Role::Outbound, mk_local());
+        let mut p = mk_started();
         let t0 = Instant::now();
         let _ = p.on_message_at(mk_ack(), t0);
         assert!(p.is_ready());
@@ -502,7 +1027,7 @@ mod tests {
 
     #[test]
     fn penalty_decays_over_time() {
-        let mut p = PeerMachine::new(Role::Outbound, mk_local());
+        let mut p = mk_started();
         let t0 = Instant::now();
         let _ = p.on_message_at(mk_ack(), t0);
         assert!(p.is_ready());
@@ -524,7 +1049,7 @@ mod tests {
 
     #[test]
     fn ban_after_too_many_distinct_notfound_ids_via_penalty_threshold() {
-        let mut p = PeerMachine::new(Role::Outbound, mk_local());
+        let mut p = mk_started();
         let t0 = Instant::now();
         let _ = p.on_message_at(mk_ack(), t0);
         assert!(p.is_ready());
@@ -558,7 +1083,7 @@ mod tests {
 
     #[test]
     fn reply_without_known_header_is_penalized_not_immediate_ban() {
-        let mut p = PeerMachine::new(Role::Outbound, mk_local());
+        let mut p = mk_started();
         let t0 = Instant::now();
         let _ = p.on_message_at(mk_ack(), t0);
         assert!(p.is_ready());
@@ -573,4 +1098,221 @@ mod tests {
         let _ = p.on_message_at(Message::BlockNotFound { id }, t0 + Duration::from_secs(2));
         assert!(p.is_banned());
     }
+
+    #[test]
+    fn notfound_tracking_is_bounded_under_slow_flood() {
+        let n = NOTFOUND_TRACK_CAP as u64 * 3;
+        let mut headers = Vec::new();
+        for i in 1..=n {
+            headers.push(hdr(Hash256::zero(), i, 20_000 + i));
+        }
+
+        // flood nhanh: ban ngay khi vượt ngưỡng distinct, trước khi LRU phải evict
+        let mut fast = mk_started();
+        let t0 = Instant::now();
+        let _ = fast.on_message_at(mk_ack(), t0);
+        let _ = fast.on_message_at(Message::Headers { headers: headers.clone() }, t0);
+        for (idx, h) in headers.iter().enumerate() {
+            let id = hash_header(h);
+            let _ = fast.request_block(id);
+            let _ = fast.on_message_at(Message::BlockNotFound { id }, t0);
+            assert_eq!(fast.is_banned(), idx >= MAX_DISTINCT_NOTFOUND_IDS, "idx={idx}");
+            if fast.is_banned() {
+                break;
+            }
+        }
+        assert_eq!(fast.distinct_notfound_count(), MAX_DISTINCT_NOTFOUND_IDS + 1);
+
+        // rải chậm để penalty kịp decay: không bị ban nhưng tracking vẫn bị chặn trên
+        let mut slow = mk_started();
+        let _ = slow.on_message_at(mk_ack(), t0);
+        let _ = slow.on_message_at(Message::Headers { headers: headers.clone() }, t0);
+        for (idx, h) in headers.iter().enumerate() {
+            let id = hash_header(h);
+            let _ = slow.request_block(id);
+            let _ = slow.on_message_at(
+                Message::BlockNotFound { id },
+                t0 + PENALTY_DECAY_EVERY * (idx as u32 + 1) * 2,
+            );
+            assert!(slow.distinct_notfound_count() <= NOTFOUND_TRACK_CAP);
+            assert!(slow.notfound_by_id.len() <= NOTFOUND_TRACK_CAP);
+            assert!(slow.notfound_lru.len() <= NOTFOUND_TRACK_CAP);
+        }
+        assert!(!slow.is_banned());
+        assert_eq!(slow.distinct_notfound_count(), NOTFOUND_TRACK_CAP);
+
+        // id cũ nhất đã bị evict, id mới nhất vẫn được nhớ
+        assert!(!slow.notfound_by_id.contains_key(&hash_header(&headers[0])));
+        assert!(slow.notfound_by_id.contains_key(&hash_header(&headers[n as usize - 1])));
+    }
+
+    #[test]
+    fn block_range_marks_all_ids_inflight_and_checks_reply() {
+        let mut p = mk_started();
+        let t0 = Instant::now();
+        let _ = p.on_message_at(mk_ack(), t0);
+
+        let mut headers = Vec::new();
+        let mut parent = Hash256::zero();
+        for h in 10..15u64 {
+            let hd = hdr(parent, h, h);
+            parent = hash_header(&hd);
+            headers.push(hd);
+        }
+        let _ = p.on_message_at(Message::Headers { headers: headers.clone() }, t0);
+        let ids: Vec<Hash256> = headers.iter().map(hash_header).collect();
+
+        let req = p.request_block_range(10, &ids);
+        assert_eq!(req, Message::GetBlockRange { from_height: 10, count: 5 });
+        assert_eq!(p.inflight_blocks_count(), 5);
+
+        // trả thiếu (3 block): phần còn lại vẫn inflight, không bị phạt
+        let blocks: Vec<Block> = headers[..3]
+            .iter()
+            .map(|h| Block {
+                header: h.clone(),
+                txs: vec![],
+            })
+            .collect();
+        let _ = p.on_message_at(Message::BlockRange { blocks: blocks.clone() }, t0);
+        assert_eq!(p.inflight_blocks_count(), 2);
+        assert_eq!(p.penalty_score(), 0);
+
+        // gửi lại block đã nhận => unsolicited
+        let _ = p.on_message_at(Message::BlockRange { blocks }, t0);
+        assert!(p.penalty_score() > 0);
+    }
+
+    #[test]
+    fn handshake_negotiates_smaller_message_limits() {
+        let local = MessageLimits {
+            max_frame_len: 1 << 20,
+            max_headers_per_msg: 500,
+        };
+        let mut p = PeerMachine::new(Role::Outbound, mk_local())
+            .with_challenge([7u8; CHALLENGE_LEN])
+            .with_message_limits(local)
+            .enable_header_sync(2000);
+        assert!(matches!(
+            p.start().as_slice(),
+            [Message::Hello { max_frame_len: 1_048_576, max_headers_per_msg: 500, .. }]
+        ));
+        assert_eq!(p.negotiated_limits(), local);
+
+        // remote nhận frame nhỏ hơn nhưng nhiều header hơn
+        let mut ack = mk_ack_echo([7u8; CHALLENGE_LEN]);
+        if let Message::HelloAck {
+            max_frame_len,
+            max_headers_per_msg,
+            ..
+        } = &mut ack
+        {
+            *max_frame_len = 1 << 16;
+            *max_headers_per_msg = 800;
+        }
+        let out = p.on_message(ack);
+        let expected = MessageLimits {
+            max_frame_len: 1 << 16,
+            max_headers_per_msg: 500,
+        };
+        assert_eq!(p.negotiated_limits(), expected);
+        assert_eq!(p.header_batch_max(), 500);
+        // batch header xin ra không vượt giới hạn đã thương lượng dù sync cho phép 2000
+        assert_eq!(out, vec![Message::GetHeaders { start: Hash256::zero(), max: 500 }]);
+
+        // inbound: HelloAck quảng bá giới hạn local, giữ min với Hello của remote
+        let mut q = PeerMachine::new(Role::Inbound, mk_local()).with_message_limits(local);
+        let out = q.on_message(Message::Hello {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 0,
+                hash: Hash256::zero(),
+            },
+            node_nonce: 222,
+            agent: "remote".to_string(),
+            challenge: [4u8; CHALLENGE_LEN],
+            max_frame_len: MAX_FRAME_LEN,
+            max_headers_per_msg: 100,
+            pruned_from_height: None,
+        });
+        assert!(matches!(
+            out.as_slice(),
+            [Message::HelloAck { max_frame_len: 1_048_576, max_headers_per_msg: 500, .. }]
+        ));
+        assert_eq!(q.header_batch_max(), 100);
+        assert_eq!(q.remote_info().unwrap().limits.max_headers_per_msg, 100);
+    }
+
+    #[test]
+    fn get_addr_returns_bounded_sanitized_sample_and_addr_feeds_book() {
+        use crate::addr::{AddressBook, MemAddressBook};
+        use std::net::SocketAddr;
+        use std::sync::{Arc, Mutex};
+
+        let book = Arc::new(Mutex::new(MemAddressBook::new(4096)));
+        {
+            let mut b = book.lock().unwrap();
+            let public: Vec<SocketAddr> =
+                (0..1200u16).map(|i| SocketAddr::from(([198, 51, (i >> 8) as u8, i as u8], 9000))).collect();
+            b.add(&public);
+            b.add(&["127.0.0.1:9000".parse().unwrap(), "0.0.0.0:9000".parse().unwrap()]);
+        }
+
+        let mut p = PeerMachine::new(Role::Outbound, mk_local())
+            .with_challenge([0u8; CHALLENGE_LEN])
+            .with_address_book(book.clone());
+        let _ = p.start();
+        // chưa handshake => không lộ sổ địa chỉ
+        assert!(p.on_message(Message::GetAddr).is_empty());
+        let _ = p.on_message(mk_ack());
+        assert!(p.is_ready());
+
+        let out = p.on_message(Message::GetAddr);
+        let [Message::Addr { addrs }] = out.as_slice() else {
+            panic!("expected 1 Addr, got {out:?}");
+        };
+        assert!(!addrs.is_empty() && addrs.len() <= MAX_ADDRS_PER_MSG);
+        assert!(addrs.iter().all(|a| is_shareable_addr(a, false)), "{addrs:?}");
+
+        let fresh: SocketAddr = "203.0.113.9:9000".parse().unwrap();
+        let _ = p.on_message(Message::Addr {
+            addrs: vec![fresh, "[::1]:9000".parse().unwrap(), "203.0.113.10:0".parse().unwrap()],
+        });
+        let b = book.lock().unwrap();
+        assert!(b.contains(&fresh));
+        assert!(!b.contains(&"[::1]:9000".parse().unwrap()));
+        assert!(!b.contains(&"203.0.113.10:0".parse().unwrap()));
+        drop(b);
+
+        // Addr quá cỡ bị phạt và bỏ qua
+        let before = book.lock().unwrap().len();
+        let _ = p.on_message(Message::Addr {
+            addrs: vec![fresh; MAX_ADDRS_PER_MSG + 1],
+        });
+        assert_eq!(p.penalty_score(), PENALTY_OVERSIZED_ADDR);
+        assert_eq!(book.lock().unwrap().len(), before);
+    }
+
+    #[test]
+    fn oversized_locator_is_penalized() {
+        let mut p = PeerMachine::new(Role::Outbound, mk_local()).with_challenge([0u8; CHALLENGE_LEN]);
+        let _ = p.start();
+        let _ = p.on_message(mk_ack());
+        assert!(p.is_ready());
+
+        let ok = p.on_message(Message::GetHeadersLocator {
+            locator: vec![Hash256([1u8; 32]); MAX_LOCATOR_LEN],
+            max: 10,
+        });
+        assert!(ok.is_empty());
+        assert_eq!(p.penalty_score(), 0);
+
+        let out = p.on_message(Message::GetHeadersLocator {
+            locator: vec![Hash256([1u8; 32]); MAX_LOCATOR_LEN + 1],
+            max: 10,
+        });
+        assert!(out.is_empty());
+        assert_eq!(p.penalty_score(), PENALTY_OVERSIZED_LOCATOR);
+    }
 }
@@ -1,9 +1,22 @@
 #![forbid(unsafe_code)]
 
-use egg_types::{canonical, Block, BlockHeader, Hash256};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use egg_types::{canonical, Block, BlockHeader, Hash256, Transaction};
 
 const MAGIC: [u8; 8] = *b"EGGNET00";
-const VERSION: u16 = 1;
+/// Version wire: tăng mỗi khi payload của message có sẵn đổi layout.
+/// 2 = Hello/HelloAck có challenge, giới hạn frame/header, prune height.
+const VERSION: u16 = 2;
+
+pub const CHALLENGE_LEN: usize = 16;
+/// Số header tối đa 1 node nhận trong 1 `Headers` nếu không cấu hình khác.
+pub const DEFAULT_MAX_HEADERS_PER_MSG: u32 = 2000;
+/// Số địa chỉ tối đa trong 1 `Addr`.
+pub const MAX_ADDRS_PER_MSG: usize = 1000;
+/// Số hash tối đa trong 1 locator: `ChainState::build_block_locator` sinh tip + tối đa 64 bước
+/// lùi lũy thừa 2 + genesis.
+pub const MAX_LOCATOR_LEN: usize = 66;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Tip {
@@ -14,12 +27,19 @@ pub struct Tip {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Message {
     // handshake
+    // `challenge`: random theo từng kết nối, bên nhận phải echo lại trong HelloAck (chống replay).
+    // `max_frame_len` / `max_headers_per_msg`: giới hạn bên gửi chấp nhận khi nhận; 2 bên dùng min.
+    // `pruned_from_height`: Some(h) = node đã prune, không phục vụ block có height < h.
     Hello {
         chain_id: u32,
         genesis_id: Hash256,
         tip: Tip,
         node_nonce: u64,
         agent: String,
+        challenge: [u8; CHALLENGE_LEN],
+        max_frame_len: u32,
+        max_headers_per_msg: u32,
+        pruned_from_height: Option<u64>,
     },
     HelloAck {
         chain_id: u32,
@@ -27,20 +47,45 @@ pub enum Message {
         tip: Tip,
         node_nonce: u64,
         agent: String,
+        challenge_echo: [u8; CHALLENGE_LEN],
+        max_frame_len: u32,
+        max_headers_per_msg: u32,
+        pruned_from_height: Option<u64>,
     },
 
     // headers-first
     GetHeaders { start: Hash256, max: u32 },
+    // locator: hash canonical local từ tip lùi về genesis (thưa dần); bên nhận trả header
+    // sau entry đầu tiên nằm trên best chain của nó => phát hiện được nhánh rẽ
+    GetHeadersLocator { locator: Vec<Hash256>, max: u32 },
     Headers { headers: Vec<BlockHeader> },
 
     // block download
     GetBlock { id: Hash256 },
     BlockFound { id: Hash256, block: Block },
     BlockNotFound { id: Hash256 },
+    // IBD: `count` block canonical liên tiếp từ `from_height` trong 1 round trip;
+    // bên trả có thể trả ít hơn (tới tip / giới hạn phía server), theo thứ tự height tăng dần
+    GetBlockRange { from_height: u64, count: u32 },
+    BlockRange { blocks: Vec<Block> },
+
+    // chỉ lấy txid của block (dựng lại block từ mempool, compact-block style)
+    // `txids`: None = không có block
+    GetBlockTxids { id: Hash256 },
+    BlockTxids { id: Hash256, txids: Option<Vec<Hash256>> },
+
+    // compact block relay: header + txid; bên nhận dựng lại từ mempool, chỉ xin tx còn thiếu
+    CompactBlock { header: BlockHeader, txids: Vec<Hash256> },
+    GetBlockTxn { id: Hash256, indexes: Vec<u32> },
+    BlockTxn { id: Hash256, txs: Vec<Transaction> },
 
     // keepalive
     Ping { nonce: u64 },
     Pong { nonce: u64 },
+
+    // peer discovery: mỗi địa chỉ = family(4|6) + ip bytes + port(u16 BE)
+    GetAddr,
+    Addr { addrs: Vec<SocketAddr> },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -51,6 +96,9 @@ pub enum ProtocolError {
     InvalidTag { tag: u8 },
     LengthOverflow { at: usize },
     InvalidUtf8 { at: usize },
+    InvalidOptionFlag { at: usize, flag: u8 },
+    InvalidAddrFamily { at: usize, family: u8 },
+    LocatorTooLong { len: usize },
     Canonical(String),
 }
 
@@ -67,6 +115,15 @@ impl core::fmt::Display for ProtocolError {
             ProtocolError::InvalidTag { tag } => write!(f, "invalid message tag {}", tag),
             ProtocolError::LengthOverflow { at } => write!(f, "length overflow at {}", at),
             ProtocolError::InvalidUtf8 { at } => write!(f, "invalid utf8 at {}", at),
+            ProtocolError::InvalidOptionFlag { at, flag } => {
+                write!(f, "invalid option flag {} at {}", flag, at)
+            }
+            ProtocolError::InvalidAddrFamily { at, family } => {
+                write!(f, "invalid address family {} at {}", family, at)
+            }
+            ProtocolError::LocatorTooLong { len } => {
+                write!(f, "locator too long: {} entries (max {})", len, MAX_LOCATOR_LEN)
+            }
             ProtocolError::Canonical(e) => write!(f, "canonical decode error: {}", e),
         }
     }
@@ -132,6 +189,40 @@ impl<'a> Cursor<'a> {
         Ok(Hash256(out))
     }
 
+    fn take_challenge(&mut self) -> Result<[u8; CHALLENGE_LEN]> {
+        let b = self.take(CHALLENGE_LEN)?;
+        let mut out = [0u8; CHALLENGE_LEN];
+        out.copy_from_slice(b);
+        Ok(out)
+    }
+
+    fn take_opt_u64_be(&mut self) -> Result<Option<u64>> {
+        let at = self.pos;
+        match self.take_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.take_u64_be()?)),
+            flag => Err(ProtocolError::InvalidOptionFlag { at, flag }),
+        }
+    }
+
+    fn take_socket_addr(&mut self) -> Result<SocketAddr> {
+        let at = self.pos;
+        let ip = match self.take_u8()? {
+            4 => {
+                let b = self.take(4)?;
+                IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            6 => {
+                let mut o = [0u8; 16];
+                o.copy_from_slice(self.take(16)?);
+                IpAddr::V6(Ipv6Addr::from(o))
+            }
+            family => return Err(ProtocolError::InvalidAddrFamily { at, family }),
+        };
+        let port = self.take_u16_be()?;
+        Ok(SocketAddr::new(ip, port))
+    }
+
     fn expect_magic(&mut self) -> Result<()> {
         let at = self.pos;
         let b = self.take(8)?;
@@ -166,12 +257,20 @@ fn push_u32_be(out: &mut Vec<u8>, v: u32) {
 fn push_u64_be(out: &mut Vec<u8>, v: u64) {
     out.extend_from_slice(&v.to_be_bytes());
 }
+fn push_opt_u64_be(out: &mut Vec<u8>, v: Option<u64>) {
+    match v {
+        None => push_u8(out, 0),
+        Some(x) => {
+            push_u8(out, 1);
+            push_u64_be(out, x);
+        }
+    }
+}
 fn push_hash256(out: &mut Vec<u8>, h: Hash256) {
     out.extend_from_slice(&h.0);
 }
 fn push_string_len_u32(out: &mut Vec<u8>, s: &str) -> Result<()> {
     let len: u32 = s
-        .as_bytes()
         .len()
         .try_into()
         .map_err(|_| ProtocolError::LengthOverflow { at: out.len() })?;
@@ -179,6 +278,13 @@ fn push_string_len_u32(out: &mut Vec<u8>, s: &str) -> Result<()> {
     out.extend_from_slice(s.as_bytes());
     Ok(())
 }
+fn push_len_u32(out: &mut Vec<u8>, len: usize) -> Result<()> {
+    let len: u32 = len
+        .try_into()
+        .map_err(|_| ProtocolError::LengthOverflow { at: out.len() })?;
+    push_u32_be(out, len);
+    Ok(())
+}
 fn push_bytes_len_u32(out: &mut Vec<u8>, b: &[u8]) -> Result<()> {
     let len: u32 = b
         .len()
@@ -189,6 +295,20 @@ fn push_bytes_len_u32(out: &mut Vec<u8>, b: &[u8]) -> Result<()> {
     Ok(())
 }
 
+fn push_socket_addr(out: &mut Vec<u8>, a: &SocketAddr) {
+    match a.ip() {
+        IpAddr::V4(v4) => {
+            push_u8(out, 4);
+            out.extend_from_slice(&v4.octets());
+        }
+        IpAddr::V6(v6) => {
+            push_u8(out, 6);
+            out.extend_from_slice(&v6.octets());
+        }
+    }
+    push_u16_be(out, a.port());
+}
+
 fn encode_tip(out: &mut Vec<u8>, tip: Tip) {
     push_u64_be(out, tip.height);
     push_hash256(out, tip.hash);
@@ -211,9 +331,23 @@ const TAG_GET_BLOCK: u8 = 12;
 const TAG_BLOCK_FOUND: u8 = 13;
 const TAG_BLOCK_NOT_FOUND: u8 = 14;
 
+const TAG_GET_BLOCK_TXIDS: u8 = 15;
+const TAG_BLOCK_TXIDS: u8 = 16;
+const TAG_COMPACT_BLOCK: u8 = 17;
+const TAG_GET_BLOCK_TXN: u8 = 18;
+const TAG_BLOCK_TXN: u8 = 19;
+
 const TAG_PING: u8 = 20;
 const TAG_PONG: u8 = 21;
 
+const TAG_GET_HEADERS_LOCATOR: u8 = 22;
+
+const TAG_GET_BLOCK_RANGE: u8 = 23;
+const TAG_BLOCK_RANGE: u8 = 24;
+
+const TAG_GET_ADDR: u8 = 25;
+const TAG_ADDR: u8 = 26;
+
 /// Binary encoding:
 /// MAGIC(8) + VERSION(u16) + TAG(u8) + payload...
 pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
@@ -228,6 +362,10 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             tip,
             node_nonce,
             agent,
+            challenge,
+            max_frame_len,
+            max_headers_per_msg,
+            pruned_from_height,
         } => {
             push_u8(&mut out, TAG_HELLO);
             push_u32_be(&mut out, *chain_id);
@@ -235,6 +373,10 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             encode_tip(&mut out, *tip);
             push_u64_be(&mut out, *node_nonce);
             push_string_len_u32(&mut out, agent)?;
+            out.extend_from_slice(challenge);
+            push_u32_be(&mut out, *max_frame_len);
+            push_u32_be(&mut out, *max_headers_per_msg);
+            push_opt_u64_be(&mut out, *pruned_from_height);
         }
         Message::HelloAck {
             chain_id,
@@ -242,6 +384,10 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             tip,
             node_nonce,
             agent,
+            challenge_echo,
+            max_frame_len,
+            max_headers_per_msg,
+            pruned_from_height,
         } => {
             push_u8(&mut out, TAG_HELLO_ACK);
             push_u32_be(&mut out, *chain_id);
@@ -249,12 +395,24 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             encode_tip(&mut out, *tip);
             push_u64_be(&mut out, *node_nonce);
             push_string_len_u32(&mut out, agent)?;
+            out.extend_from_slice(challenge_echo);
+            push_u32_be(&mut out, *max_frame_len);
+            push_u32_be(&mut out, *max_headers_per_msg);
+            push_opt_u64_be(&mut out, *pruned_from_height);
         }
         Message::GetHeaders { start, max } => {
             push_u8(&mut out, TAG_GET_HEADERS);
             push_hash256(&mut out, *start);
             push_u32_be(&mut out, *max);
         }
+        Message::GetHeadersLocator { locator, max } => {
+            push_u8(&mut out, TAG_GET_HEADERS_LOCATOR);
+            push_len_u32(&mut out, locator.len())?;
+            for h in locator {
+                push_hash256(&mut out, *h);
+            }
+            push_u32_be(&mut out, *max);
+        }
         Message::Headers { headers } => {
             push_u8(&mut out, TAG_HEADERS);
             let n: u32 = headers.len().try_into().unwrap_or(u32::MAX);
@@ -279,6 +437,65 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             push_u8(&mut out, TAG_BLOCK_NOT_FOUND);
             push_hash256(&mut out, *id);
         }
+        Message::GetBlockRange { from_height, count } => {
+            push_u8(&mut out, TAG_GET_BLOCK_RANGE);
+            push_u64_be(&mut out, *from_height);
+            push_u32_be(&mut out, *count);
+        }
+        Message::BlockRange { blocks } => {
+            push_u8(&mut out, TAG_BLOCK_RANGE);
+            push_len_u32(&mut out, blocks.len())?;
+            for b in blocks {
+                push_bytes_len_u32(&mut out, &canonical::encode_block(b))?;
+            }
+        }
+        Message::GetBlockTxids { id } => {
+            push_u8(&mut out, TAG_GET_BLOCK_TXIDS);
+            push_hash256(&mut out, *id);
+        }
+        Message::BlockTxids { id, txids } => {
+            push_u8(&mut out, TAG_BLOCK_TXIDS);
+            push_hash256(&mut out, *id);
+            match txids {
+                None => push_u8(&mut out, 0),
+                Some(list) => {
+                    push_u8(&mut out, 1);
+                    let n: u32 = list
+                        .len()
+                        .try_into()
+                        .map_err(|_| ProtocolError::LengthOverflow { at: out.len() })?;
+                    push_u32_be(&mut out, n);
+                    for t in list {
+                        push_hash256(&mut out, *t);
+                    }
+                }
+            }
+        }
+        Message::CompactBlock { header, txids } => {
+            push_u8(&mut out, TAG_COMPACT_BLOCK);
+            let hb = canonical::encode_block_header(header);
+            push_bytes_len_u32(&mut out, &hb)?;
+            push_len_u32(&mut out, txids.len())?;
+            for t in txids {
+                push_hash256(&mut out, *t);
+            }
+        }
+        Message::GetBlockTxn { id, indexes } => {
+            push_u8(&mut out, TAG_GET_BLOCK_TXN);
+            push_hash256(&mut out, *id);
+            push_len_u32(&mut out, indexes.len())?;
+            for i in indexes {
+                push_u32_be(&mut out, *i);
+            }
+        }
+        Message::BlockTxn { id, txs } => {
+            push_u8(&mut out, TAG_BLOCK_TXN);
+            push_hash256(&mut out, *id);
+            push_len_u32(&mut out, txs.len())?;
+            for tx in txs {
+                push_bytes_len_u32(&mut out, &canonical::encode_tx(tx))?;
+            }
+        }
         Message::Ping { nonce } => {
             push_u8(&mut out, TAG_PING);
             push_u64_be(&mut out, *nonce);
@@ -287,8 +504,76 @@ pub fn encode_message(msg: &Message) -> Result<Vec<u8>> {
             push_u8(&mut out, TAG_PONG);
             push_u64_be(&mut out, *nonce);
         }
+        Message::GetAddr => push_u8(&mut out, TAG_GET_ADDR),
+        Message::Addr { addrs } => {
+            push_u8(&mut out, TAG_ADDR);
+            push_len_u32(&mut out, addrs.len())?;
+            for a in addrs {
+                push_socket_addr(&mut out, a);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// `encode_message(msg).len()` tính trực tiếp, không cấp phát buffer
+/// (vd. gom header/tx vào 1 message sát `MAX_FRAME_LEN`).
+pub fn encoded_len(msg: &Message) -> usize {
+    const HDR_LEN: usize = 100;
+    // MAGIC + VERSION + TAG
+    let base = 8 + 2 + 1;
+    let opt_u64 = |v: &Option<u64>| 1 + v.map_or(0, |_| 8);
+    let tip = 8 + 32;
+
+    base + match msg {
+        Message::Hello {
+            agent,
+            pruned_from_height,
+            ..
+        } => 4 + 32 + tip + 8 + 4 + agent.len() + CHALLENGE_LEN + 8 + opt_u64(pruned_from_height),
+        Message::HelloAck {
+            agent,
+            pruned_from_height,
+            ..
+        } => 4 + 32 + tip + 8 + 4 + agent.len() + CHALLENGE_LEN + 8 + opt_u64(pruned_from_height),
+        Message::GetHeaders { .. } => 32 + 4,
+        Message::GetHeadersLocator { locator, .. } => 4 + 32 * locator.len() + 4,
+        Message::Headers { headers } => 4 + headers.len() * (4 + HDR_LEN),
+        Message::GetBlock { .. } => 32,
+        Message::BlockFound { block, .. } => 32 + 4 + canonical::encoded_block_len(block),
+        Message::BlockNotFound { .. } => 32,
+        Message::GetBlockRange { .. } => 8 + 4,
+        Message::BlockRange { blocks } => {
+            4 + blocks.iter().map(|b| 4 + canonical::encoded_block_len(b)).sum::<usize>()
+        }
+        Message::GetBlockTxids { .. } => 32,
+        Message::BlockTxids { txids, .. } => 32 + 1 + txids.as_ref().map_or(0, |l| 4 + 32 * l.len()),
+        Message::CompactBlock { txids, .. } => 4 + HDR_LEN + 4 + 32 * txids.len(),
+        Message::GetBlockTxn { indexes, .. } => 32 + 4 + 4 * indexes.len(),
+        Message::BlockTxn { txs, .. } => {
+            32 + 4 + txs.iter().map(|tx| 4 + canonical::encoded_tx_len(tx)).sum::<usize>()
+        }
+        Message::Ping { .. } | Message::Pong { .. } => 8,
+        Message::GetAddr => 0,
+        Message::Addr { addrs } => {
+            4 + addrs.iter().map(|a| 1 + if a.is_ipv4() { 4 } else { 16 } + 2).sum::<usize>()
+        }
     }
+}
 
+/// Encode message Headers từ các header đã ở dạng canonical bytes (zero re-encode).
+/// Output giống hệt `encode_message(&Message::Headers { .. })` cho cùng danh sách header.
+pub fn encode_headers_raw(raw_headers: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    push_u16_be(&mut out, VERSION);
+    push_u8(&mut out, TAG_HEADERS);
+    let n: u32 = raw_headers.len().try_into().unwrap_or(u32::MAX);
+    push_u32_be(&mut out, n);
+    for hb in raw_headers {
+        push_bytes_len_u32(&mut out, hb)?;
+    }
     Ok(out)
 }
 
@@ -308,12 +593,20 @@ pub fn decode_message(bytes: &[u8]) -> Result<Message> {
             let tip = decode_tip(&mut c)?;
             let node_nonce = c.take_u64_be()?;
             let agent = c.take_string_len_u32()?;
+            let challenge = c.take_challenge()?;
+            let max_frame_len = c.take_u32_be()?;
+            let max_headers_per_msg = c.take_u32_be()?;
+            let pruned_from_height = c.take_opt_u64_be()?;
             Ok(Message::Hello {
                 chain_id,
                 genesis_id,
                 tip,
                 node_nonce,
                 agent,
+                challenge,
+                max_frame_len,
+                max_headers_per_msg,
+                pruned_from_height,
             })
         }
         TAG_HELLO_ACK => {
@@ -322,12 +615,20 @@ pub fn decode_message(bytes: &[u8]) -> Result<Message> {
             let tip = decode_tip(&mut c)?;
             let node_nonce = c.take_u64_be()?;
             let agent = c.take_string_len_u32()?;
+            let challenge_echo = c.take_challenge()?;
+            let max_frame_len = c.take_u32_be()?;
+            let max_headers_per_msg = c.take_u32_be()?;
+            let pruned_from_height = c.take_opt_u64_be()?;
             Ok(Message::HelloAck {
                 chain_id,
                 genesis_id,
                 tip,
                 node_nonce,
                 agent,
+                challenge_echo,
+                max_frame_len,
+                max_headers_per_msg,
+                pruned_from_height,
             })
         }
         TAG_GET_HEADERS => {
@@ -335,6 +636,18 @@ pub fn decode_message(bytes: &[u8]) -> Result<Message> {
             let max = c.take_u32_be()?;
             Ok(Message::GetHeaders { start, max })
         }
+        TAG_GET_HEADERS_LOCATOR => {
+            let n = c.take_u32_be()? as usize;
+            if n > MAX_LOCATOR_LEN {
+                return Err(ProtocolError::LocatorTooLong { len: n });
+            }
+            let mut locator = Vec::with_capacity(n.min(c.remaining() / 32));
+            for _ in 0..n {
+                locator.push(c.take_hash256()?);
+            }
+            let max = c.take_u32_be()?;
+            Ok(Message::GetHeadersLocator { locator, max })
+        }
         TAG_HEADERS => {
             let n = c.take_u32_be()? as usize;
             let mut headers = Vec::with_capacity(n);
@@ -360,6 +673,76 @@ pub fn decode_message(bytes: &[u8]) -> Result<Message> {
             let id = c.take_hash256()?;
             Ok(Message::BlockNotFound { id })
         }
+        TAG_GET_BLOCK_RANGE => {
+            let from_height = c.take_u64_be()?;
+            let count = c.take_u32_be()?;
+            Ok(Message::GetBlockRange { from_height, count })
+        }
+        TAG_BLOCK_RANGE => {
+            let n = c.take_u32_be()? as usize;
+            let mut blocks = Vec::with_capacity(n.min(c.remaining() / 4));
+            for _ in 0..n {
+                let bb = c.take_bytes_len_u32()?;
+                let b = canonical::decode_block(&bb)
+                    .map_err(|e| ProtocolError::Canonical(e.to_string()))?;
+                blocks.push(b);
+            }
+            Ok(Message::BlockRange { blocks })
+        }
+        TAG_GET_BLOCK_TXIDS => {
+            let id = c.take_hash256()?;
+            Ok(Message::GetBlockTxids { id })
+        }
+        TAG_BLOCK_TXIDS => {
+            let id = c.take_hash256()?;
+            let flag_at = c.pos;
+            let txids = match c.take_u8()? {
+                0 => None,
+                1 => {
+                    let n = c.take_u32_be()? as usize;
+                    // không tin n từ peer khi cấp phát
+                    let mut list = Vec::with_capacity(n.min(c.remaining() / 32));
+                    for _ in 0..n {
+                        list.push(c.take_hash256()?);
+                    }
+                    Some(list)
+                }
+                flag => return Err(ProtocolError::InvalidOptionFlag { at: flag_at, flag }),
+            };
+            Ok(Message::BlockTxids { id, txids })
+        }
+        TAG_COMPACT_BLOCK => {
+            let hb = c.take_bytes_len_u32()?;
+            let header = canonical::decode_block_header(&hb)
+                .map_err(|e| ProtocolError::Canonical(e.to_string()))?;
+            let n = c.take_u32_be()? as usize;
+            let mut txids = Vec::with_capacity(n.min(c.remaining() / 32));
+            for _ in 0..n {
+                txids.push(c.take_hash256()?);
+            }
+            Ok(Message::CompactBlock { header, txids })
+        }
+        TAG_GET_BLOCK_TXN => {
+            let id = c.take_hash256()?;
+            let n = c.take_u32_be()? as usize;
+            let mut indexes = Vec::with_capacity(n.min(c.remaining() / 4));
+            for _ in 0..n {
+                indexes.push(c.take_u32_be()?);
+            }
+            Ok(Message::GetBlockTxn { id, indexes })
+        }
+        TAG_BLOCK_TXN => {
+            let id = c.take_hash256()?;
+            let n = c.take_u32_be()? as usize;
+            let mut txs = Vec::with_capacity(n.min(c.remaining() / 4));
+            for _ in 0..n {
+                let tb = c.take_bytes_len_u32()?;
+                let tx = canonical::decode_tx(&tb)
+                    .map_err(|e| ProtocolError::Canonical(e.to_string()))?;
+                txs.push(tx);
+            }
+            Ok(Message::BlockTxn { id, txs })
+        }
         TAG_PING => {
             let nonce = c.take_u64_be()?;
             Ok(Message::Ping { nonce })
@@ -368,6 +751,16 @@ pub fn decode_message(bytes: &[u8]) -> Result<Message> {
             let nonce = c.take_u64_be()?;
             Ok(Message::Pong { nonce })
         }
+        TAG_GET_ADDR => Ok(Message::GetAddr),
+        TAG_ADDR => {
+            let n = c.take_u32_be()? as usize;
+            // địa chỉ nhỏ nhất (IPv4) = 7 byte
+            let mut addrs = Vec::with_capacity(n.min(c.remaining() / 7));
+            for _ in 0..n {
+                addrs.push(c.take_socket_addr()?);
+            }
+            Ok(Message::Addr { addrs })
+        }
         other => Err(ProtocolError::InvalidTag { tag: other }),
     }
 }
@@ -399,6 +792,32 @@ mod tests {
             },
             node_nonce: 123,
             agent: "egg-node/0.1".to_string(),
+            challenge: [5u8; CHALLENGE_LEN],
+            max_frame_len: 1 << 20,
+            max_headers_per_msg: 500,
+            pruned_from_height: None,
+        };
+
+        let enc = encode_message(&m).unwrap();
+        let dec = decode_message(&enc).unwrap();
+        assert_eq!(m, dec);
+    }
+
+    #[test]
+    fn roundtrip_hello_ack_pruned() {
+        let m = Message::HelloAck {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 500,
+                hash: Hash256([8u8; 32]),
+            },
+            node_nonce: 321,
+            agent: "egg-node/0.1".to_string(),
+            challenge_echo: [6u8; CHALLENGE_LEN],
+            max_frame_len: 1 << 20,
+            max_headers_per_msg: 500,
+            pruned_from_height: Some(400),
         };
 
         let enc = encode_message(&m).unwrap();
@@ -406,6 +825,41 @@ mod tests {
         assert_eq!(m, dec);
     }
 
+    #[test]
+    fn hello_rejects_invalid_option_flag() {
+        let m = Message::Hello {
+            chain_id: 1,
+            genesis_id: Hash256([9u8; 32]),
+            tip: Tip {
+                height: 7,
+                hash: Hash256([8u8; 32]),
+            },
+            node_nonce: 123,
+            agent: "a".to_string(),
+            challenge: [5u8; CHALLENGE_LEN],
+            max_frame_len: 1 << 20,
+            max_headers_per_msg: 500,
+            pruned_from_height: None,
+        };
+        let mut enc = encode_message(&m).unwrap();
+        let last = enc.len() - 1;
+        enc[last] = 2;
+        assert!(matches!(
+            decode_message(&enc),
+            Err(ProtocolError::InvalidOptionFlag { flag: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn roundtrip_get_headers_locator() {
+        let m = Message::GetHeadersLocator {
+            locator: vec![Hash256([3u8; 32]), Hash256([2u8; 32]), Hash256([1u8; 32])],
+            max: 500,
+        };
+        let enc = encode_message(&m).unwrap();
+        assert_eq!(decode_message(&enc).unwrap(), m);
+    }
+
     #[test]
     fn roundtrip_headers() {
         let m = Message::Headers {
@@ -416,6 +870,16 @@ mod tests {
         assert_eq!(m, dec);
     }
 
+    #[test]
+    fn raw_headers_encode_matches_message_encoding() {
+        let headers = vec![sample_header(1, 1), sample_header(2, 2)];
+        let raw: Vec<Vec<u8>> = headers.iter().map(canonical::encode_block_header).collect();
+
+        let enc = encode_headers_raw(&raw).unwrap();
+        assert_eq!(enc, encode_message(&Message::Headers { headers: headers.clone() }).unwrap());
+        assert_eq!(decode_message(&enc).unwrap(), Message::Headers { headers });
+    }
+
     #[test]
     fn roundtrip_block_found() {
         let blk = Block {
@@ -432,6 +896,94 @@ mod tests {
         assert_eq!(m, dec);
     }
 
+    #[test]
+    fn roundtrip_block_txids() {
+        let msgs = [
+            Message::GetBlockTxids { id: Hash256([4u8; 32]) },
+            Message::BlockTxids {
+                id: Hash256([4u8; 32]),
+                txids: Some(vec![Hash256([5u8; 32]), Hash256([6u8; 32])]),
+            },
+            Message::BlockTxids {
+                id: Hash256([4u8; 32]),
+                txids: Some(vec![]),
+            },
+            Message::BlockTxids {
+                id: Hash256([4u8; 32]),
+                txids: None,
+            },
+        ];
+        for m in msgs {
+            let enc = encode_message(&m).unwrap();
+            assert_eq!(decode_message(&enc).unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn roundtrip_compact_block_messages() {
+        let tx = Transaction {
+            id: Hash256([7u8; 32]),
+            payload: vec![1, 2, 3],
+            content_tag: 0,
+        };
+        let msgs = [
+            Message::CompactBlock {
+                header: sample_header(3, 9),
+                txids: vec![Hash256([5u8; 32]), Hash256([6u8; 32])],
+            },
+            Message::GetBlockTxn {
+                id: Hash256([4u8; 32]),
+                indexes: vec![0, 3, 17],
+            },
+            Message::BlockTxn {
+                id: Hash256([4u8; 32]),
+                txs: vec![tx.clone(), tx],
+            },
+        ];
+        for m in msgs {
+            let enc = encode_message(&m).unwrap();
+            assert_eq!(decode_message(&enc).unwrap(), m);
+        }
+    }
+
+    #[test]
+    fn roundtrip_block_range() {
+        let tx = Transaction {
+            id: Hash256([7u8; 32]),
+            payload: vec![1, 2, 3],
+            content_tag: 0,
+        };
+        let msgs = [
+            Message::GetBlockRange {
+                from_height: 10,
+                count: 20,
+            },
+            Message::BlockRange {
+                blocks: vec![
+                    Block {
+                        header: sample_header(1, 10),
+                        txs: vec![tx],
+                    },
+                    Block {
+                        header: sample_header(2, 11),
+                        txs: vec![],
+                    },
+                ],
+            },
+            Message::BlockRange { blocks: vec![] },
+        ];
+        for m in msgs {
+            let enc = encode_message(&m).unwrap();
+            assert_eq!(decode_message(&enc).unwrap(), m);
+        }
+
+        // count khai báo lớn nhưng thiếu dữ liệu => lỗi, không cấp phát theo count
+        let mut enc = encode_message(&Message::BlockRange { blocks: vec![] }).unwrap();
+        let n = enc.len();
+        enc[n - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(decode_message(&enc), Err(ProtocolError::UnexpectedEof { .. })));
+    }
+
     #[test]
     fn roundtrip_block_not_found() {
         let m = Message::BlockNotFound { id: Hash256([4u8; 32]) };
@@ -439,4 +991,160 @@ mod tests {
         let dec = decode_message(&enc).unwrap();
         assert_eq!(m, dec);
     }
+
+    #[test]
+    fn encoded_len_matches_encoding_for_every_variant() {
+        let tip = Tip {
+            height: 7,
+            hash: Hash256([8u8; 32]),
+        };
+        let tx = |tag: u8, n: usize| Transaction {
+            id: Hash256([7u8; 32]),
+            payload: vec![1u8; n],
+            content_tag: tag,
+        };
+        let msgs = [
+            Message::Hello {
+                chain_id: 1,
+                genesis_id: Hash256([9u8; 32]),
+                tip,
+                node_nonce: 1,
+                agent: "egg-node/0.1".to_string(),
+                challenge: [5u8; CHALLENGE_LEN],
+                max_frame_len: 1 << 20,
+                max_headers_per_msg: 500,
+                pruned_from_height: None,
+            },
+            Message::HelloAck {
+                chain_id: 1,
+                genesis_id: Hash256([9u8; 32]),
+                tip,
+                node_nonce: 2,
+                agent: "ack".to_string(),
+                challenge_echo: [6u8; CHALLENGE_LEN],
+                max_frame_len: 1 << 20,
+                max_headers_per_msg: 500,
+                pruned_from_height: Some(40),
+            },
+            Message::GetHeaders {
+                start: Hash256([1u8; 32]),
+                max: 10,
+            },
+            Message::GetHeadersLocator {
+                locator: vec![Hash256([3u8; 32]), Hash256([2u8; 32])],
+                max: 500,
+            },
+            Message::Headers {
+                headers: vec![sample_header(1, 1), sample_header(2, 2)],
+            },
+            Message::GetBlock { id: Hash256([4u8; 32]) },
+            Message::BlockFound {
+                id: Hash256([3u8; 32]),
+                block: Block {
+                    header: sample_header(7, 3),
+                    txs: vec![tx(0, 5), tx(3, 0)],
+                },
+            },
+            Message::BlockNotFound { id: Hash256([4u8; 32]) },
+            Message::GetBlockRange {
+                from_height: 10,
+                count: 20,
+            },
+            Message::BlockRange {
+                blocks: vec![
+                    Block {
+                        header: sample_header(7, 3),
+                        txs: vec![tx(0, 5)],
+                    },
+                    Block {
+                        header: sample_header(8, 4),
+                        txs: vec![],
+                    },
+                ],
+            },
+            Message::BlockRange { blocks: vec![] },
+            Message::GetBlockTxids { id: Hash256([4u8; 32]) },
+            Message::BlockTxids {
+                id: Hash256([4u8; 32]),
+                txids: Some(vec![Hash256([5u8; 32])]),
+            },
+            Message::BlockTxids {
+                id: Hash256([4u8; 32]),
+                txids: None,
+            },
+            Message::CompactBlock {
+                header: sample_header(3, 9),
+                txids: vec![Hash256([5u8; 32]), Hash256([6u8; 32])],
+            },
+            Message::GetBlockTxn {
+                id: Hash256([4u8; 32]),
+                indexes: vec![0, 3, 17],
+            },
+            Message::BlockTxn {
+                id: Hash256([4u8; 32]),
+                txs: vec![tx(0, 3), tx(1, 9)],
+            },
+            Message::Ping { nonce: 1 },
+            Message::Pong { nonce: 2 },
+            Message::GetAddr,
+            Message::Addr {
+                addrs: vec!["10.0.0.1:9000".parse().unwrap(), "[2001:db8::1]:9001".parse().unwrap()],
+            },
+        ];
+        for m in msgs {
+            assert_eq!(encoded_len(&m), encode_message(&m).unwrap().len(), "{m:?}");
+        }
+    }
+
+    #[test]
+    fn roundtrip_addr_ipv4_and_ipv6() {
+        let addrs: Vec<SocketAddr> = vec![
+            "203.0.113.7:8333".parse().unwrap(),
+            "[2001:db8::42]:18444".parse().unwrap(),
+            "[::ffff:192.0.2.1]:1".parse().unwrap(),
+        ];
+        for m in [Message::GetAddr, Message::Addr { addrs: addrs.clone() }, Message::Addr { addrs: vec![] }] {
+            let enc = encode_message(&m).unwrap();
+            assert_eq!(decode_message(&enc).unwrap(), m);
+        }
+
+        // IPv4-mapped giữ nguyên dạng IPv6, không bị thu về IPv4
+        let enc = encode_message(&Message::Addr { addrs: addrs.clone() }).unwrap();
+        let Message::Addr { addrs: got } = decode_message(&enc).unwrap() else {
+            panic!("expected Addr");
+        };
+        assert!(got[2].is_ipv6());
+
+        // family lạ => lỗi, không panic
+        let mut bad = encode_message(&Message::Addr { addrs: vec![addrs[0]] }).unwrap();
+        let family_at = 8 + 2 + 1 + 4;
+        bad[family_at] = 5;
+        assert_eq!(
+            decode_message(&bad),
+            Err(ProtocolError::InvalidAddrFamily { at: family_at, family: 5 })
+        );
+    }
+
+    #[test]
+    fn decode_rejects_frame_from_older_wire_version() {
+        let mut enc = encode_message(&Message::Ping { nonce: 1 }).unwrap();
+        enc[8..10].copy_from_slice(&1u16.to_be_bytes());
+        assert_eq!(decode_message(&enc), Err(ProtocolError::UnsupportedVersion { got: 1 }));
+    }
+
+    #[test]
+    fn decode_rejects_oversized_locator() {
+        let locator = vec![Hash256([1u8; 32]); MAX_LOCATOR_LEN];
+        let ok = Message::GetHeadersLocator { locator, max: 10 };
+        assert_eq!(decode_message(&encode_message(&ok).unwrap()).unwrap(), ok);
+
+        let long = Message::GetHeadersLocator {
+            locator: vec![Hash256([1u8; 32]); MAX_LOCATOR_LEN + 1],
+            max: 10,
+        };
+        assert_eq!(
+            decode_message(&encode_message(&long).unwrap()),
+            Err(ProtocolError::LocatorTooLong { len: MAX_LOCATOR_LEN + 1 })
+        );
+    }
 }
@@ -1,22 +1,45 @@
 #![forbid(unsafe_code)]
 
+use std::cell::Cell;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
-use std::time::{Duration, Instant};
-
-use egg_chain::state::ChainState;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use egg_chain::compact::{txs_at, PartialBlock};
+use egg_chain::mempool::{AddOutcome, Mempool, MempoolError};
+use egg_chain::block_builder::BlockBuildError;
+use egg_chain::state::{BlockRef, ChainState, ChainStateError, IngestOutcome, InvalidBlockVerdict};
 use egg_crypto::hash_header;
+use egg_db::reputation::{PeerReputation, ReputationStore};
 use egg_db::store::ChainStore;
-use egg_net::codec::{decode_frame, encode_frame, FrameError};
-use egg_net::peer::{handle_get_headers, HeaderProvider, PeerMachine, Role};
-use egg_net::protocol::{Message, Tip};
+use egg_db::{KvStore, MemKv};
+use egg_net::codec::{decode_frame, encode_frame, encode_headers_frame_raw, FrameError, MAX_FRAME_LEN};
+use egg_net::peer::{PeerMachine, Role, PENALTY_INVALID_BLOCK};
+use egg_net::protocol::{encoded_len, Message, Tip};
+use egg_rpc::{ChainInfo, ChainStatus, RpcError, RpcMethod, RpcRequest, RpcResponse, RpcResult, SubmitBlockOutcome};
+
+pub mod rpc;
 
 const MAX_BLOCK_RETRIES: u8 = 2; // tổng attempt = 1 + MAX_BLOCK_RETRIES
 const BLOCK_WINDOW: usize = 16;
+/// Số block tối đa trả cho 1 `GetBlockRange` (peer xin nhiều hơn thì bị cắt).
+const MAX_BLOCK_RANGE: u32 = 128;
+/// Số batch Headers tối đa được tải trước phần block; đủ thì giữ GetHeaders kế tiếp
+/// cho tới khi block của các header đã nhận được tải xong (header/block xen kẽ, không chạy quá xa).
+const HEADER_WINDOW: usize = 8;
 const PER_REQ_RESEND_AFTER: Duration = Duration::from_secs(2);
 const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(20);
 const IO_TICK_TIMEOUT: Duration = Duration::from_secs(1);
+/// Thời gian tối đa cho 1 frame từ byte đầu tiên tới khi đủ (kể cả qua nhiều lần recv).
+const MAX_FRAME_WAIT: Duration = Duration::from_secs(30);
+/// Số frame tối đa chờ gửi cho 1 peer; đầy => bỏ Ping/Pong, message khác thì ngắt peer.
+const MAX_SEND_QUEUE: usize = 256;
+const DEFAULT_LISTEN_BACKLOG: i32 = 128;
+const PEER_BAN_DURATION_SECS: i64 = 24 * 60 * 60;
+/// Tip không đổi quá lâu (so với block interval) => báo stale cho monitoring.
+pub const STALE_TIP_MAX_AGE_SECS: i64 = 60 * 60;
 
 #[derive(Debug)]
 pub enum NodeError {
@@ -63,6 +86,13 @@ fn is_io_timeout(e: &std::io::Error) -> bool {
 struct FramedTcp {
     stream: TcpStream,
     buf: Vec<u8>,
+    max_frame_wait: Duration,
+    // lúc bắt đầu nhận frame đang dở (buf khác rỗng)
+    partial_since: Option<Instant>,
+    // frame chờ gửi; `out_off` = số byte của frame đầu đã ghi
+    outq: VecDeque<Vec<u8>>,
+    out_off: usize,
+    max_send_queue: usize,
 }
 
 impl FramedTcp {
@@ -73,13 +103,85 @@ impl FramedTcp {
         Ok(Self {
             stream,
             buf: Vec::with_capacity(64 * 1024),
+            max_frame_wait: MAX_FRAME_WAIT,
+            partial_since: None,
+            outq: VecDeque::new(),
+            out_off: 0,
+            max_send_queue: MAX_SEND_QUEUE,
         })
     }
 
+    #[cfg(test)]
+    fn with_max_send_queue(mut self, n: usize) -> Self {
+        self.max_send_queue = n;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_max_frame_wait(mut self, d: Duration) -> Self {
+        self.max_frame_wait = d;
+        self
+    }
+
     fn send(&mut self, msg: &Message) -> Result<()> {
         let frame = encode_frame(msg)?;
-        self.stream.write_all(&frame)?;
-        self.stream.flush()?;
+        let low_priority = matches!(msg, Message::Ping { .. } | Message::Pong { .. });
+        self.enqueue(frame, low_priority)
+    }
+
+    /// Gửi frame đã encode sẵn (vd. Headers từ raw bytes).
+    fn send_frame(&mut self, frame: &[u8]) -> Result<()> {
+        self.enqueue(frame.to_vec(), false)
+    }
+
+    /// Đưa frame vào hàng đợi rồi ghi không chặn; peer đọc chậm không làm treo vòng lặp node.
+    fn enqueue(&mut self, frame: Vec<u8>, low_priority: bool) -> Result<()> {
+        self.flush_pending()?;
+        if self.outq.len() >= self.max_send_queue {
+            if low_priority {
+                return Ok(());
+            }
+            return Err(NodeError::Protocol(format!(
+                "send queue full ({} frames): peer too slow",
+                self.outq.len()
+            )));
+        }
+        self.outq.push_back(frame);
+        self.flush_pending()
+    }
+
+    /// Ghi phần hàng đợi mà socket nhận được ngay (non-blocking), phần còn lại để lần sau.
+    fn flush_pending(&mut self) -> Result<()> {
+        if self.outq.is_empty() {
+            return Ok(());
+        }
+        self.stream.set_nonblocking(true)?;
+        let r = self.write_queued();
+        self.stream.set_nonblocking(false)?;
+        r
+    }
+
+    fn write_queued(&mut self) -> Result<()> {
+        while let Some(front) = self.outq.front() {
+            match self.stream.write(&front[self.out_off..]) {
+                Ok(0) => {
+                    return Err(NodeError::Io(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "peer closed",
+                    )))
+                }
+                Ok(n) => {
+                    self.out_off += n;
+                    if self.out_off == front.len() {
+                        self.outq.pop_front();
+                        self.out_off = 0;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(NodeError::Io(e)),
+            }
+        }
         Ok(())
     }
 
@@ -88,12 +190,28 @@ impl FramedTcp {
             match decode_frame(&self.buf) {
                 Ok((msg, used)) => {
                     self.buf.drain(0..used);
+                    // phần còn lại (nếu có) là frame kế tiếp, tính giờ lại từ đầu
+                    self.partial_since = (!self.buf.is_empty()).then(Instant::now);
                     return Ok(msg);
                 }
                 Err(FrameError::UnexpectedEof { .. }) => {}
                 Err(e) => return Err(NodeError::Frame(e)),
             }
 
+            // frame gửi còn dở (vd. Headers lớn) phải tiếp tục ghi trong lúc chờ đọc
+            self.flush_pending()?;
+
+            // deadline giữ qua các lần recv (caller `continue` khi read timeout)
+            if let Some(since) = self.partial_since {
+                let waited = since.elapsed();
+                if waited > self.max_frame_wait {
+                    return Err(NodeError::Frame(FrameError::PartialTimeout {
+                        buffered: self.buf.len(),
+                        waited_ms: waited.as_millis() as u64,
+                    }));
+                }
+            }
+
             let mut tmp = [0u8; 8192];
             let n = self.stream.read(&mut tmp)?;
             if n == 0 {
@@ -102,39 +220,610 @@ impl FramedTcp {
                     "peer closed",
                 )));
             }
+            if self.buf.is_empty() {
+                self.partial_since = Some(Instant::now());
+            }
             self.buf.extend_from_slice(&tmp[..n]);
         }
     }
 }
 
-struct ChainProvider<'a, S: ChainStore + Clone> {
-    st: &'a ChainState<S>,
+thread_local! {
+    static NODE_NONCE_OVERRIDE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Nonce cho `LocalInfo.node_nonce` (phát hiện tự kết nối tới chính mình): random từ OS entropy,
+/// hoặc giá trị override của thread hiện tại nếu có.
+pub fn generate_node_nonce() -> u64 {
+    NODE_NONCE_OVERRIDE
+        .with(Cell::get)
+        .unwrap_or_else(rand::random)
+}
+
+/// Cố định nonce cho các session chạy trên thread hiện tại (test cần deterministic); `None` = random.
+pub fn set_node_nonce_override(nonce: Option<u64>) {
+    NODE_NONCE_OVERRIDE.with(|c| c.set(nonce));
+}
+
+fn now_utc() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Snapshot trạng thái chain cho RPC `chain_status`.
+pub fn chain_status<S: ChainStore + Clone>(
+    state: &ChainState<S>,
+    now_utc: i64,
+    max_age_secs: i64,
+) -> Result<ChainStatus> {
+    let hdr = state
+        .store()
+        .get_header(state.tip.hash)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    let stale = state
+        .is_tip_stale(now_utc, max_age_secs)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    Ok(ChainStatus::new(state.tip.height.0, hdr.timestamp_utc, stale))
+}
+
+/// RPC `get_difficulty`: độ khó tip và độ khó bắt buộc cho block kế tiếp.
+pub fn get_difficulty<S: ChainStore + Clone>(state: &ChainState<S>) -> Result<egg_rpc::DifficultyInfo> {
+    let info = state
+        .difficulty_info()
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    Ok(egg_rpc::DifficultyInfo::new(
+        info.current_bits,
+        info.next_required_bits,
+        info.blocks_until_retarget,
+    ))
+}
+
+/// RPC `submit_block`: nhận block đã đào từ pool/miner ngoài.
+/// Block orphan vẫn được lưu nhưng báo lỗi (miner đang đào trên parent node không biết).
+pub fn submit_block<S: ChainStore + Clone>(
+    state: &mut ChainState<S>,
+    block: egg_types::Block,
+) -> core::result::Result<RpcResult, RpcError> {
+    let (id, outcome) = state.ingest_block(block).map_err(|e| {
+        let code = match &e {
+            ChainStateError::InvalidPow => egg_rpc::RPC_ERR_INVALID_POW,
+            ChainStateError::BlockBuild(BlockBuildError::MerkleMismatch { .. }) => {
+                egg_rpc::RPC_ERR_INVALID_MERKLE
+            }
+            e if e.is_peer_fault() => egg_rpc::RPC_ERR_INVALID_BLOCK,
+            _ => egg_rpc::RPC_ERR_INTERNAL,
+        };
+        RpcError {
+            code,
+            message: e.to_string(),
+        }
+    })?;
+    let outcome = match outcome {
+        IngestOutcome::AlreadyKnown => SubmitBlockOutcome::AlreadyKnown,
+        IngestOutcome::StoredConnected => SubmitBlockOutcome::StoredConnected,
+        IngestOutcome::NewTip => SubmitBlockOutcome::NewTip,
+        IngestOutcome::StoredOrphan => {
+            return Err(RpcError {
+                code: egg_rpc::RPC_ERR_ORPHAN_BLOCK,
+                message: format!("block {:?} has unknown parent (stored as orphan)", id),
+            })
+        }
+    };
+    Ok(RpcResult::SubmitBlock { id, outcome })
+}
+
+/// RPC `get_block`: block theo id kèm trạng thái best chain, để client không nhầm block ở nhánh
+/// phụ là đã xác nhận.
+pub fn get_block<S: ChainStore + Clone>(
+    state: &ChainState<S>,
+    id: egg_types::Hash256,
+) -> core::result::Result<RpcResult, RpcError> {
+    let Some((block, _)) = state.get_block_with_meta(id).map_err(rpc_internal)? else {
+        return Err(RpcError {
+            code: egg_rpc::RPC_ERR_BLOCK_NOT_FOUND,
+            message: format!("block {:?} not found", id),
+        });
+    };
+    let confirmations = state.confirmations(id).map_err(rpc_internal)?;
+    Ok(RpcResult::Block {
+        block,
+        on_best_chain: confirmations.is_some(),
+        confirmations,
+    })
+}
+
+fn rpc_internal(e: impl std::fmt::Display) -> RpcError {
+    RpcError {
+        code: egg_rpc::RPC_ERR_INTERNAL,
+        message: e.to_string(),
+    }
+}
+
+/// RPC `get_chain_info`: chain nào (tên, id, genesis) + tip hiện tại.
+pub fn chain_info<S: ChainStore + Clone>(state: &ChainState<S>) -> Result<ChainInfo> {
+    let hdr = state
+        .store()
+        .get_header(state.tip.hash)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    Ok(ChainInfo::new(
+        state.spec.chain.chain_name.clone(),
+        state.meta.chain_id,
+        state.meta.genesis_id,
+        state.tip.height.0,
+        state.tip.hash,
+        hdr.timestamp_utc,
+    ))
+}
+
+/// RPC `get_header_by_id`: header theo id (kể cả nhánh phụ / chưa có body).
+pub fn get_header<S: ChainStore + Clone>(
+    state: &ChainState<S>,
+    id: egg_types::Hash256,
+) -> core::result::Result<RpcResult, RpcError> {
+    let Some(header) = state.resolve_header(BlockRef::Hash(id)).map_err(rpc_internal)? else {
+        return Err(RpcError {
+            code: egg_rpc::RPC_ERR_BLOCK_NOT_FOUND,
+            message: format!("header {:?} not found", id),
+        });
+    };
+    let confirmations = state.confirmations(id).map_err(rpc_internal)?;
+    Ok(RpcResult::Header {
+        header,
+        on_best_chain: confirmations.is_some(),
+        confirmations,
+    })
+}
+
+/// RPC `get_block_by_height`: block canonical tại `height`.
+pub fn get_block_by_height<S: ChainStore + Clone>(
+    state: &ChainState<S>,
+    height: u64,
+) -> core::result::Result<RpcResult, RpcError> {
+    let id = if height <= state.tip.height.0 {
+        state.canon_hash(egg_types::Height(height)).map_err(rpc_internal)?
+    } else {
+        None
+    };
+    let Some(id) = id else {
+        return Err(RpcError {
+            code: egg_rpc::RPC_ERR_BLOCK_NOT_FOUND,
+            message: format!("no canonical block at height {}", height),
+        });
+    };
+    get_block(state, id)
+}
+
+/// RPC `submit_tx`: txid tính từ payload rồi đưa vào mempool; tx đã có => `accepted` = false.
+pub fn submit_tx(mempool: &mut Mempool, payload: Vec<u8>) -> core::result::Result<RpcResult, RpcError> {
+    let tx = egg_types::Transaction {
+        id: egg_crypto::tx_id_from_payload(&payload),
+        payload,
+        content_tag: egg_types::CONTENT_TAG_OPAQUE,
+    };
+    let txid = tx.id;
+    let outcome = mempool.add_tx(tx).map_err(|e| {
+        let code = match &e {
+            MempoolError::InvalidTxId { .. } => egg_rpc::RPC_ERR_INVALID_TX_ID,
+            MempoolError::Full => egg_rpc::RPC_ERR_MEMPOOL_FULL,
+            MempoolError::TxTooLarge { .. } => egg_rpc::RPC_ERR_TX_TOO_LARGE,
+            _ => egg_rpc::RPC_ERR_TX_REJECTED,
+        };
+        RpcError {
+            code,
+            message: e.to_string(),
+        }
+    })?;
+    Ok(RpcResult::SubmitTx {
+        txid,
+        accepted: !matches!(outcome, AddOutcome::AlreadyKnown),
+    })
+}
+
+/// Trả lời 1 request đọc chain / ghi mempool. `PeerHealth` (cần peer) và `SubmitBlock` (cần ghi
+/// chain) trả `RPC_ERR_METHOD_UNAVAILABLE`: caller tự xử lý trước khi gọi tới đây.
+pub fn handle_request<S: ChainStore + Clone>(
+    state: &ChainState<S>,
+    mempool: &mut Mempool,
+    req: &RpcRequest,
+) -> RpcResponse {
+    let result = match &req.method {
+        RpcMethod::PeerHealth | RpcMethod::SubmitBlock { .. } => Err(RpcError {
+            code: egg_rpc::RPC_ERR_METHOD_UNAVAILABLE,
+            message: "method not served by chain dispatcher".to_string(),
+        }),
+        RpcMethod::ChainStatus => chain_status(state, now_utc(), STALE_TIP_MAX_AGE_SECS)
+            .map(RpcResult::ChainStatus)
+            .map_err(rpc_internal),
+        RpcMethod::GetDifficulty => get_difficulty(state)
+            .map(RpcResult::GetDifficulty)
+            .map_err(rpc_internal),
+        RpcMethod::GetChainInfo => chain_info(state).map(RpcResult::ChainInfo).map_err(rpc_internal),
+        RpcMethod::GetTip => Ok(RpcResult::Tip {
+            height: state.tip.height.0,
+            hash: state.tip.hash,
+        }),
+        RpcMethod::GetBlockById { id } => get_block(state, *id),
+        RpcMethod::GetHeaderById { id } => get_header(state, *id),
+        RpcMethod::GetBlockByHeight { height } => get_block_by_height(state, *height),
+        RpcMethod::SubmitTx { payload } => submit_tx(mempool, payload.clone()),
+    };
+    match result {
+        Ok(result) => RpcResponse::Ok { id: req.id, result },
+        Err(error) => RpcResponse::Err { id: req.id, error },
+    }
+}
+
+/// Số lần thử nối lại tối đa của 1 orphan (mỗi block mới tới = 1 lần) trước khi bỏ.
+pub const DEFAULT_MAX_ORPHAN_RETRIES: u8 = 16;
+
+struct StagedOrphan {
+    block: egg_types::Block,
+    source: String,
+    retries: u8,
+}
+
+/// Kết quả 1 lần `OrphanManager::submit_block`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrphanReport {
+    /// block đã ingest được (kể cả orphan vừa nối lại), theo thứ tự ingest
+    pub connected: Vec<egg_types::Hash256>,
+    /// (block, peer gửi) bị bỏ vì quá số lần thử, header invalid hoặc body giả => caller phạt peer
+    pub discarded: Vec<(egg_types::Hash256, String)>,
+}
+
+/// Giữ tạm block tới trước parent (nhiều peer / gossip giao lệch thứ tự) ngoài store,
+/// thử nối lại mỗi khi có block mới; orphan quá `max_retries` lần bị bỏ.
+pub struct OrphanManager {
+    staged: HashMap<egg_types::Hash256, StagedOrphan>,
+    max_retries: u8,
+}
+
+impl OrphanManager {
+    pub fn new(max_retries: u8) -> Self {
+        Self {
+            staged: HashMap::new(),
+            max_retries,
+        }
+    }
+
+    pub fn staged_len(&self) -> usize {
+        self.staged.len()
+    }
+
+    pub fn submit_block<S: ChainStore + Clone>(
+        &mut self,
+        st: &mut ChainState<S>,
+        block: egg_types::Block,
+        source: &str,
+    ) -> Result<OrphanReport> {
+        let mut report = OrphanReport::default();
+
+        // lượt retry cho orphan đang chờ (block mới tới = 1 lần thử)
+        let mut expired = Vec::new();
+        for (id, o) in self.staged.iter_mut() {
+            o.retries = o.retries.saturating_add(1);
+            if o.retries > self.max_retries {
+                expired.push(*id);
+            }
+        }
+        for id in expired {
+            if let Some(o) = self.staged.remove(&id) {
+                report.discarded.push((id, o.source));
+            }
+        }
+
+        let id = hash_header(&block.header);
+        self.staged.insert(
+            id,
+            StagedOrphan {
+                block,
+                source: source.to_string(),
+                retries: 0,
+            },
+        );
+
+        // nối mọi orphan đã có parent, lặp tới khi không còn tiến triển
+        loop {
+            let mut ready = Vec::new();
+            for (id, o) in &self.staged {
+                let has_parent = egg_db::store::BlockStore::has_block(st.store(), o.block.header.parent)
+                    .map_err(|e| NodeError::Chain(e.to_string()))?;
+                if has_parent {
+                    ready.push(*id);
+                }
+            }
+            if ready.is_empty() {
+                break;
+            }
+            for id in ready {
+                let Some(o) = self.staged.remove(&id) else { continue };
+                match st.ingest_block(o.block) {
+                    Ok(_) => report.connected.push(id),
+                    // chỉ lỗi header mới đánh dấu id: body giả không được chặn block thật
+                    Err(e) if e.is_peer_fault() => {
+                        let verdict = st
+                            .handle_invalid_block_from_peer(id, e)
+                            .map_err(|e| NodeError::Chain(e.to_string()))?;
+                        if matches!(
+                            verdict,
+                            InvalidBlockVerdict::Penalize(_) | InvalidBlockVerdict::BadBody(_)
+                        ) {
+                            report.discarded.push((id, o.source));
+                        }
+                    }
+                    Err(e) => return Err(NodeError::Chain(e.to_string())),
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl Default for OrphanManager {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ORPHAN_RETRIES)
+    }
+}
+
+/// Tổng block inflight tối đa trên mọi peer (mặc định của `InflightConfig`).
+pub const DEFAULT_MAX_GLOBAL_INFLIGHT: usize = 4 * BLOCK_WINDOW;
+
+/// Giới hạn block đang xin khi tải từ nhiều peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InflightConfig {
+    /// cửa sổ của từng peer
+    pub per_peer_window: usize,
+    /// tổng mọi peer cộng lại: thêm peer không nhân bộ nhớ / băng thông lên
+    pub max_global_inflight: usize,
+}
+
+impl Default for InflightConfig {
+    fn default() -> Self {
+        Self {
+            per_peer_window: BLOCK_WINDOW,
+            max_global_inflight: DEFAULT_MAX_GLOBAL_INFLIGHT,
+        }
+    }
+}
+
+/// Theo dõi block inflight theo peer; chỉ cấp slot khi còn chỗ ở cả cửa sổ peer lẫn giới hạn chung.
+pub struct InflightManager {
+    cfg: InflightConfig,
+    by_peer: HashMap<String, HashSet<egg_types::Hash256>>,
+    total: usize,
+}
+
+impl InflightManager {
+    pub fn new(cfg: InflightConfig) -> Self {
+        Self {
+            cfg,
+            by_peer: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn config(&self) -> InflightConfig {
+        self.cfg
+    }
+
+    pub fn total_inflight(&self) -> usize {
+        self.total
+    }
+
+    pub fn peer_inflight(&self, peer: &str) -> usize {
+        self.by_peer.get(peer).map_or(0, HashSet::len)
+    }
+
+    /// Số request `peer` còn được gửi thêm lúc này.
+    pub fn free_slots(&self, peer: &str) -> usize {
+        let peer_free = self.cfg.per_peer_window.saturating_sub(self.peer_inflight(peer));
+        let global_free = self.cfg.max_global_inflight.saturating_sub(self.total);
+        peer_free.min(global_free)
+    }
+
+    /// Ghi nhận gửi `id` cho `peer`; `false` nếu hết slot hoặc peer đã đang xin `id`.
+    pub fn try_request(&mut self, peer: &str, id: egg_types::Hash256) -> bool {
+        if self.free_slots(peer) == 0 {
+            return false;
+        }
+        if !self.by_peer.entry(peer.to_string()).or_default().insert(id) {
+            return false;
+        }
+        self.total += 1;
+        true
+    }
+
+    /// Block đã về / NotFound / timeout: trả slot; `false` nếu `id` không inflight ở `peer`.
+    pub fn complete(&mut self, peer: &str, id: egg_types::Hash256) -> bool {
+        let Some(ids) = self.by_peer.get_mut(peer) else {
+            return false;
+        };
+        if !ids.remove(&id) {
+            return false;
+        }
+        if ids.is_empty() {
+            self.by_peer.remove(peer);
+        }
+        self.total -= 1;
+        true
+    }
+
+    /// Peer ngắt kết nối: trả lại mọi id nó đang giữ để gán cho peer khác.
+    pub fn remove_peer(&mut self, peer: &str) -> Vec<egg_types::Hash256> {
+        let ids: Vec<_> = self.by_peer.remove(peer).into_iter().flatten().collect();
+        self.total -= ids.len();
+        ids
+    }
+}
+
+impl Default for InflightManager {
+    fn default() -> Self {
+        Self::new(InflightConfig::default())
+    }
+}
+
+/// Phân loại peer theo height tip so với local; mỗi list chứa index vào `remotes`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerClassification {
+    /// cao hơn local: nguồn tải block
+    pub ahead: Vec<usize>,
+    pub equal: Vec<usize>,
+    /// thấp hơn local: đích relay
+    pub behind: Vec<usize>,
+}
+
+pub fn classify_peers(local_tip: Tip, remotes: &[Tip]) -> PeerClassification {
+    let mut out = PeerClassification::default();
+    for (i, r) in remotes.iter().enumerate() {
+        match r.height.cmp(&local_tip.height) {
+            std::cmp::Ordering::Greater => out.ahead.push(i),
+            std::cmp::Ordering::Equal => out.equal.push(i),
+            std::cmp::Ordering::Less => out.behind.push(i),
+        }
+    }
+    out
+}
+
+/// Nạp uy tín đã lưu của `peer_key` vào `peer` (ban chỉ còn hiệu lực nếu chưa hết hạn).
+pub fn restore_peer_reputation<K: KvStore>(
+    reps: &ReputationStore<K>,
+    peer_key: &str,
+    peer: &mut PeerMachine,
+    now_utc: i64,
+) -> Result<()> {
+    let Some(rep) = reps
+        .get(peer_key)
+        .map_err(|e| NodeError::Chain(e.to_string()))?
+    else {
+        return Ok(());
+    };
+
+    let ban_reason = if rep.is_banned_at(now_utc) {
+        Some(rep.ban_reason.unwrap_or_else(|| "banned".to_string()))
+    } else {
+        None
+    };
+    peer.restore_reputation(rep.penalty_score, ban_reason);
+    Ok(())
+}
+
+/// Lưu uy tín hiện tại của `peer`; ban mới được giữ `PEER_BAN_DURATION_SECS`.
+pub fn save_peer_reputation<K: KvStore>(
+    reps: &ReputationStore<K>,
+    peer_key: &str,
+    peer: &PeerMachine,
+    now_utc: i64,
+) -> Result<()> {
+    let prev = reps
+        .get(peer_key)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+
+    let banned_until_utc = if peer.is_banned() {
+        match prev.as_ref().and_then(|r| r.banned_until_utc) {
+            Some(until) if now_utc < until => Some(until),
+            _ => Some(now_utc.saturating_add(PEER_BAN_DURATION_SECS)),
+        }
+    } else {
+        None
+    };
+
+    let rep = PeerReputation {
+        penalty_score: peer.penalty_score(),
+        banned_until_utc,
+        ban_reason: peer.ban_reason().map(|s| s.to_string()),
+    };
+    reps.put(peer_key, &rep)
+        .map_err(|e| NodeError::Chain(e.to_string()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponderConfig {
+    /// SO_REUSEADDR: cho phép bind lại port ngay sau khi node restart (TIME_WAIT).
+    pub reuse_address: bool,
+    pub backlog: i32,
 }
 
-impl<'a, S: ChainStore + Clone> HeaderProvider for ChainProvider<'a, S> {
-    fn get_headers_after(
-        &self,
-        start: egg_types::Hash256,
-        max: usize,
-    ) -> Vec<egg_types::BlockHeader> {
-        ChainState::<S>::get_headers_after(self.st, start, max).unwrap_or_default()
+impl Default for ResponderConfig {
+    fn default() -> Self {
+        Self {
+            reuse_address: true,
+            backlog: DEFAULT_LISTEN_BACKLOG,
+        }
     }
 }
 
+/// Bind listener cho responder. `addr` dạng "ip:port"; port 0 => ephemeral port.
+pub fn bind_responder(addr: &str, cfg: ResponderConfig) -> Result<TcpListener> {
+    let addr: SocketAddr = addr.parse().map_err(|e| {
+        NodeError::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("invalid listen address {:?}: {}", addr, e),
+        ))
+    })?;
+
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    socket.set_reuse_address(cfg.reuse_address)?;
+    socket.bind(&addr.into())?;
+    socket.listen(cfg.backlog)?;
+    Ok(socket.into())
+}
+
 pub fn run_responder_once<S: ChainStore + Clone>(
     listener: TcpListener,
     spec: egg_types::ChainSpec,
     store: S,
 ) -> Result<()> {
-    let (stream, _) = listener.accept()?;
+    let reps = ReputationStore::new(MemKv::new());
+    run_responder_once_with_reputation(listener, spec, store, &reps)
+}
+
+/// Như `run_responder_once`, nhưng uy tín peer (theo IP) được nạp khi kết nối
+/// và lưu lại khi phiên kết thúc, nên peer đã bị ban không kết nối lại được cho tới khi hết hạn.
+pub fn run_responder_once_with_reputation<S: ChainStore + Clone, K: KvStore>(
+    listener: TcpListener,
+    spec: egg_types::ChainSpec,
+    store: S,
+    reps: &ReputationStore<K>,
+) -> Result<()> {
+    responder_session(listener, spec, store, reps, &Mempool::new())
+}
+
+/// Như `run_responder_once`, kèm mempool local để dựng lại CompactBlock peer gửi tới
+/// (chỉ xin peer các tx mempool chưa có qua GetBlockTxn).
+pub fn run_responder_once_with_mempool<S: ChainStore + Clone>(
+    listener: TcpListener,
+    spec: egg_types::ChainSpec,
+    store: S,
+    mempool: &Mempool,
+) -> Result<()> {
+    let reps = ReputationStore::new(MemKv::new());
+    responder_session(listener, spec, store, &reps, mempool)
+}
+
+fn responder_session<S: ChainStore + Clone, K: KvStore>(
+    listener: TcpListener,
+    spec: egg_types::ChainSpec,
+    store: S,
+    reps: &ReputationStore<K>,
+    mempool: &Mempool,
+) -> Result<()> {
+    let (stream, remote_addr) = listener.accept()?;
+    let peer_key = remote_addr.ip().to_string();
     let mut io = FramedTcp::new(stream)?;
 
-    let st =
-        ChainState::open_or_init(store.clone(), spec).map_err(|e| NodeError::Chain(e.to_string()))?;
+    let mut st = open_chain(store.clone(), spec)?;
     let local_tip = Tip {
         height: st.tip.height.0,
         hash: st.tip.hash,
     };
+    // compact block đang chờ BlockTxn
+    let mut pending_compact: Option<(PartialBlock, Vec<u32>)> = None;
 
     let mut peer = PeerMachine::new(
         Role::Inbound,
@@ -142,12 +831,20 @@ pub fn run_responder_once<S: ChainStore + Clone>(
             chain_id: st.meta.chain_id,
             genesis_id: st.meta.genesis_id,
             tip: local_tip,
-            node_nonce: 2002,
+            node_nonce: generate_node_nonce(),
             agent: "egg-node/responder".to_string(),
+            pruned_from_height: None,
         },
     );
 
-    let provider = ChainProvider { st: &st };
+    restore_peer_reputation(reps, &peer_key, &mut peer, now_utc())?;
+    if peer.is_banned() {
+        return Err(NodeError::Protocol(format!(
+            "peer banned: {}",
+            peer.ban_reason().unwrap_or("unknown")
+        )));
+    }
+
 
     loop {
         let msg = match io.recv() {
@@ -163,6 +860,7 @@ pub fn run_responder_once<S: ChainStore + Clone>(
         }
 
         if peer.is_banned() {
+            save_peer_reputation(reps, &peer_key, &peer, now_utc())?;
             return Err(NodeError::Protocol(format!(
                 "peer banned: {}",
                 peer.ban_reason().unwrap_or("unknown")
@@ -170,10 +868,28 @@ pub fn run_responder_once<S: ChainStore + Clone>(
         }
 
         if peer.is_ready() {
+            // không gửi Headers lớn hơn peer nhận được (đã thương lượng trong handshake)
+            let headers_cap = peer.negotiated_limits().max_headers_per_msg;
             match msg {
                 Message::GetHeaders { start, max } => {
-                    let resp = handle_get_headers(&provider, start, max);
-                    io.send(&resp)?;
+                    // header phục vụ thẳng từ bytes trong store, không decode/re-encode
+                    let raw = st
+                        .get_raw_headers_after(start, max.min(headers_cap) as usize)
+                        .unwrap_or_default();
+                    io.send_frame(&encode_headers_frame_raw(&raw)?)?;
+                }
+                Message::GetHeadersLocator { locator, max } => {
+                    // chain của peer có thể rẽ nhánh: trả header từ fork point chung
+                    let fork = st
+                        .fork_point_from_locator(&locator)
+                        .map_err(|e| NodeError::Chain(e.to_string()))?;
+                    let raw = match fork {
+                        Some((_, start)) => st
+                            .get_raw_headers_after(start, max.min(headers_cap) as usize)
+                            .unwrap_or_default(),
+                        None => Vec::new(),
+                    };
+                    io.send_frame(&encode_headers_frame_raw(&raw)?)?;
                 }
                 Message::GetBlock { id } => {
                     let have = egg_db::store::BlockStore::has_block(st.store(), id)
@@ -186,14 +902,138 @@ pub fn run_responder_once<S: ChainStore + Clone>(
                         io.send(&Message::BlockFound { id, block: blk })?;
                     }
                 }
+                Message::GetBlockRange { from_height, count } => {
+                    io.send(&block_range_response(&st, from_height, count)?)?;
+                }
+                Message::GetBlockTxids { id } => {
+                    io.send(&block_txids_response(&st, id)?)?;
+                }
+                Message::GetBlockTxn { id, indexes } => {
+                    let have = egg_db::store::BlockStore::has_block(st.store(), id)
+                        .map_err(|e| NodeError::Chain(e.to_string()))?;
+                    if !have {
+                        io.send(&Message::BlockNotFound { id })?;
+                    } else {
+                        let blk = egg_db::store::BlockStore::get_block(st.store(), id)
+                            .map_err(|e| NodeError::Chain(e.to_string()))?;
+                        let txs =
+                            txs_at(&blk, &indexes).map_err(|e| NodeError::Protocol(e.to_string()))?;
+                        io.send(&Message::BlockTxn { id, txs })?;
+                    }
+                }
+                Message::CompactBlock { header, txids } => {
+                    let pb = PartialBlock::from_mempool(header, txids, mempool);
+                    let missing = pb.missing_indexes();
+                    if missing.is_empty() {
+                        ingest_compact(&mut st, pb)?;
+                    } else {
+                        io.send(&Message::GetBlockTxn {
+                            id: pb.id(),
+                            indexes: missing.clone(),
+                        })?;
+                        pending_compact = Some((pb, missing));
+                    }
+                }
+                Message::BlockTxn { id, txs } => {
+                    if let Some((mut pb, indexes)) = pending_compact.take() {
+                        if pb.id() != id {
+                            return Err(NodeError::Protocol(format!(
+                                "BlockTxn for unexpected block {:?}",
+                                id
+                            )));
+                        }
+                        pb.fill(&indexes, txs)
+                            .map_err(|e| NodeError::Protocol(e.to_string()))?;
+                        ingest_compact(&mut st, pb)?;
+                    }
+                }
                 _ => {}
             }
         }
     }
 
+    save_peer_reputation(reps, &peer_key, &peer, now_utc())?;
+    Ok(())
+}
+
+/// `ChainState::open_or_init`; best chain phải sửa lúc mở (reorg bị ngắt) thì log ra stderr.
+fn open_chain<S: ChainStore + Clone>(store: S, spec: egg_types::ChainSpec) -> Result<ChainState<S>> {
+    let st = ChainState::open_or_init(store, spec).map_err(|e| NodeError::Chain(e.to_string()))?;
+    if let Some(m) = st.best_chain_repaired_on_open() {
+        eprintln!(
+            "warning: best chain mismatch on open: incremental tip {:?}, recomputed {:?}",
+            m.incremental, m.recomputed
+        );
+    }
+    Ok(st)
+}
+
+/// Phạt peer theo verdict của `ChainState::handle_invalid_block_from_peer`.
+fn apply_invalid_block_verdict(peer: &mut PeerMachine, verdict: &InvalidBlockVerdict) {
+    match verdict {
+        InvalidBlockVerdict::Penalize(reason) | InvalidBlockVerdict::BadBody(reason) => {
+            peer.penalize(PENALTY_INVALID_BLOCK, reason);
+        }
+        InvalidBlockVerdict::DescendantOfInvalid | InvalidBlockVerdict::Deferred(_) => {}
+    }
+}
+
+fn ingest_compact<S: ChainStore + Clone>(st: &mut ChainState<S>, pb: PartialBlock) -> Result<()> {
+    let block = pb.into_block().map_err(|e| NodeError::Protocol(e.to_string()))?;
+    st.ingest_block(block)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
     Ok(())
 }
 
+/// Block canonical từ `from_height`, tối đa `min(count, MAX_BLOCK_RANGE)` block và vừa 1 frame;
+/// dừng sớm ở tip hoặc block chưa có body.
+fn block_range_response<S: ChainStore + Clone>(
+    st: &ChainState<S>,
+    from_height: u64,
+    count: u32,
+) -> Result<Message> {
+    let max = count.min(MAX_BLOCK_RANGE) as usize;
+    let mut blocks = Vec::new();
+    let mut size = encoded_len(&Message::BlockRange { blocks: Vec::new() });
+    let mut h = from_height;
+    while blocks.len() < max {
+        let Some(b) = st
+            .block_at_height(egg_types::Height(h))
+            .map_err(|e| NodeError::Chain(e.to_string()))?
+        else {
+            break;
+        };
+        size += 4 + egg_types::canonical::encoded_block_len(&b);
+        if size > MAX_FRAME_LEN as usize {
+            break;
+        }
+        blocks.push(b);
+        let Some(next) = h.checked_add(1) else {
+            break;
+        };
+        h = next;
+    }
+    Ok(Message::BlockRange { blocks })
+}
+
+fn block_txids_response<S: ChainStore + Clone>(
+    st: &ChainState<S>,
+    id: egg_types::Hash256,
+) -> Result<Message> {
+    let have = egg_db::store::BlockStore::has_block(st.store(), id)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    if !have {
+        return Ok(Message::BlockTxids { id, txids: None });
+    }
+    let blk = egg_db::store::BlockStore::get_block(st.store(), id)
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    let txids = blk.txs.iter().map(|t| t.id).collect();
+    Ok(Message::BlockTxids {
+        id,
+        txids: Some(txids),
+    })
+}
+
 #[derive(Clone, Copy, Debug)]
 struct InflightEntry {
     retries: u8,
@@ -206,100 +1046,142 @@ pub fn run_syncer_once<S: ChainStore + Clone>(
     store: S,
     batch_max: u32,
 ) -> Result<()> {
-    let stream = TcpStream::connect(addr)?;
-    let mut io = FramedTcp::new(stream)?;
+    let reps = ReputationStore::new(MemKv::new());
+    run_syncer_once_with_reputation(addr, spec, store, batch_max, &reps)
+}
 
-    let mut st =
-        ChainState::open_or_init(store.clone(), spec).map_err(|e| NodeError::Chain(e.to_string()))?;
-    let local_tip = Tip {
-        height: st.tip.height.0,
-        hash: st.tip.hash,
-    };
+/// Như `run_syncer_once`, nhưng uy tín peer (theo IP) được nạp trước phiên và lưu lại khi kết thúc
+/// (kể cả khi lỗi), gồm cả điểm phạt do peer gửi block invalid.
+pub fn run_syncer_once_with_reputation<S: ChainStore + Clone, K: KvStore>(
+    addr: std::net::SocketAddr,
+    spec: egg_types::ChainSpec,
+    store: S,
+    batch_max: u32,
+    reps: &ReputationStore<K>,
+) -> Result<()> {
+    let peer_key = addr.ip().to_string();
+    let (mut io, mut st, mut peer) = connect_syncer(addr, spec, store, batch_max)?;
+
+    restore_peer_reputation(reps, &peer_key, &mut peer, now_utc())?;
+    if peer.is_banned() {
+        return Err(NodeError::Protocol(format!(
+            "peer banned: {}",
+            peer.ban_reason().unwrap_or("unknown")
+        )));
+    }
 
-    let mut peer = PeerMachine::new(
-        Role::Outbound,
-        egg_net::peer::LocalInfo {
-            chain_id: st.meta.chain_id,
-            genesis_id: st.meta.genesis_id,
-            tip: local_tip,
-            node_nonce: 1001,
-            agent: "egg-node/syncer".to_string(),
-        },
-    )
-    .enable_header_sync(batch_max);
+    let r = syncer_session(&mut io, &mut st, &mut peer);
+    save_peer_reputation(reps, &peer_key, &peer, now_utc())?;
+    r
+}
 
+/// Như `run_syncer_once` nhưng giữ kết nối: hết header mới thì chờ `poll_interval`
+/// rồi hỏi lại từ tip hiện tại. Chỉ dừng khi lỗi hoặc `shutdown` được bật.
+pub fn run_syncer_loop<S: ChainStore + Clone>(
+    addr: std::net::SocketAddr,
+    spec: egg_types::ChainSpec,
+    store: S,
+    batch_max: u32,
+    poll_interval: Duration,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    let (mut io, mut st, mut peer) = connect_syncer(addr, spec, store, batch_max)?;
     for m in peer.start() {
         io.send(&m)?;
     }
 
-    // ---- Phase 1: sync headers ----
-    let mut downloaded_ids: Vec<egg_types::Hash256> = Vec::new();
-    let mut last_progress = Instant::now();
-
     loop {
-        if Instant::now().duration_since(last_progress) > SESSION_IDLE_TIMEOUT {
-            return Err(NodeError::Protocol("header sync idle timeout".to_string()));
-        }
-
-        let msg = match io.recv() {
-            Ok(m) => m,
-            Err(NodeError::Io(e)) if is_io_timeout(&e) => continue,
-            Err(e) => return Err(e),
-        };
+        sync_round(&mut io, &mut st, &mut peer)?;
 
-        if let Message::Headers { headers } = &msg {
-            if headers.is_empty() {
-                let out = peer.on_message(msg.clone());
-                for m in out {
-                    io.send(&m)?;
-                }
-                if peer.is_banned() {
-                    return Err(NodeError::Protocol(format!(
-                        "peer banned: {}",
-                        peer.ban_reason().unwrap_or("unknown")
-                    )));
-                }
+        let deadline = Instant::now() + poll_interval;
+        while !shutdown.load(Ordering::Relaxed) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
                 break;
             }
-
-            last_progress = Instant::now();
-
-            for h in headers.iter().cloned() {
-                let id = hash_header(&h);
-                downloaded_ids.push(id);
-                let _ = st.ingest_header(h).map_err(|e| NodeError::Chain(e.to_string()))?;
-            }
+            std::thread::sleep(left.min(IO_TICK_TIMEOUT));
+        }
+        if shutdown.load(Ordering::Relaxed) {
+            break;
         }
 
-        let out = peer.on_message(msg.clone());
-        for m in out {
+        let locator = st
+            .build_block_locator()
+            .map_err(|e| NodeError::Chain(e.to_string()))?;
+        for m in peer.resync_headers(locator) {
             io.send(&m)?;
         }
+    }
 
-        if peer.is_banned() {
-            return Err(NodeError::Protocol(format!(
-                "peer banned: {}",
-                peer.ban_reason().unwrap_or("unknown")
-            )));
-        }
+    st.validate_best_chain()
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    Ok(())
+}
+
+fn connect_syncer<S: ChainStore + Clone>(
+    addr: std::net::SocketAddr,
+    spec: egg_types::ChainSpec,
+    store: S,
+    batch_max: u32,
+) -> Result<(FramedTcp, ChainState<S>, PeerMachine)> {
+    let stream = TcpStream::connect(addr)?;
+    let io = FramedTcp::new(stream)?;
+
+    let st = open_chain(store, spec)?;
+    let local_tip = Tip {
+        height: st.tip.height.0,
+        hash: st.tip.hash,
+    };
+    let locator = st
+        .build_block_locator()
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+
+    let peer = PeerMachine::new(
+        Role::Outbound,
+        egg_net::peer::LocalInfo {
+            chain_id: st.meta.chain_id,
+            genesis_id: st.meta.genesis_id,
+            tip: local_tip,
+            node_nonce: generate_node_nonce(),
+            agent: "egg-node/syncer".to_string(),
+            pruned_from_height: None,
+        },
+    )
+    .enable_header_sync(batch_max)
+    .with_sync_locator(locator);
+
+    Ok((io, st, peer))
+}
+
+fn syncer_session<S: ChainStore + Clone>(
+    io: &mut FramedTcp,
+    st: &mut ChainState<S>,
+    peer: &mut PeerMachine,
+) -> Result<()> {
+    for m in peer.start() {
+        io.send(&m)?;
     }
+    sync_round(io, st, peer)?;
+    st.validate_best_chain()
+        .map_err(|e| NodeError::Chain(e.to_string()))?;
+    Ok(())
+}
+
+/// 1 vòng header + block cho tới khi peer trả Headers rỗng và không còn block đang chờ.
+fn sync_round<S: ChainStore + Clone>(
+    io: &mut FramedTcp,
+    st: &mut ChainState<S>,
+    peer: &mut PeerMachine,
+) -> Result<()> {
+    // header và block tải xen kẽ: tối đa HEADER_WINDOW batch header đi trước phần block
+    let mut headers_done = false;
+    let mut header_batches_ahead: usize = 0;
+    let mut deferred_get_headers: Option<Message> = None;
 
-    // ---- Phase 2: pipeline download blocks ----
     let mut pending: VecDeque<egg_types::Hash256> = VecDeque::new();
     let mut seen: HashSet<egg_types::Hash256> = HashSet::new();
-    for id in downloaded_ids.into_iter() {
-        if !seen.insert(id) {
-            continue;
-        }
-        let have = egg_db::store::BlockStore::has_block(st.store(), id)
-            .map_err(|e| NodeError::Chain(e.to_string()))?;
-        if !have {
-            pending.push_back(id);
-        }
-    }
-
     let mut inflight: HashMap<egg_types::Hash256, InflightEntry> = HashMap::new();
-    last_progress = Instant::now();
+    let mut last_progress = Instant::now();
 
     loop {
         let now = Instant::now();
@@ -324,13 +1206,23 @@ pub fn run_syncer_once<S: ChainStore + Clone>(
             );
         }
 
-        if pending.is_empty() && inflight.is_empty() {
+        let blocks_idle = pending.is_empty() && inflight.is_empty();
+        if blocks_idle {
+            // block đã bắt kịp header => mở lại cửa sổ header
+            header_batches_ahead = 0;
+            if let Some(req) = deferred_get_headers.take() {
+                io.send(&req)?;
+            }
+        }
+
+        if headers_done && blocks_idle {
             break;
         }
 
         if Instant::now().duration_since(last_progress) > SESSION_IDLE_TIMEOUT {
             return Err(NodeError::Protocol(format!(
-                "block sync idle timeout: pending={} inflight={}",
+                "sync idle timeout: headers_done={} pending={} inflight={}",
+                headers_done,
                 pending.len(),
                 inflight.len()
             )));
@@ -343,8 +1235,39 @@ pub fn run_syncer_once<S: ChainStore + Clone>(
         };
 
         if let Some(msg) = maybe_msg {
+            if let Message::Headers { headers } = &msg {
+                if headers.is_empty() {
+                    headers_done = true;
+                } else {
+                    last_progress = Instant::now();
+                    header_batches_ahead = header_batches_ahead.saturating_add(1);
+
+                    for h in headers.iter().cloned() {
+                        let id = hash_header(&h);
+                        let height = h.height.0;
+                        let _ = st.ingest_header(h).map_err(|e| NodeError::Chain(e.to_string()))?;
+                        if !seen.insert(id) {
+                            continue;
+                        }
+                        // peer đã prune: không xin block cũ hơn pruned_from_height (tránh BlockNotFound)
+                        if !peer.can_serve_block_at(height) {
+                            continue;
+                        }
+                        let have = egg_db::store::BlockStore::has_block(st.store(), id)
+                            .map_err(|e| NodeError::Chain(e.to_string()))?;
+                        if !have {
+                            pending.push_back(id);
+                        }
+                    }
+                }
+            }
+
             let out = peer.on_message(msg.clone());
             for m in out {
+                if matches!(m, Message::GetHeaders { .. }) && header_batches_ahead >= HEADER_WINDOW {
+                    deferred_get_headers = Some(m);
+                    continue;
+                }
                 io.send(&m)?;
             }
 
@@ -381,9 +1304,20 @@ pub fn run_syncer_once<S: ChainStore + Clone>(
                         )));
                     }
 
-                    let _ = st
-                        .ingest_block(block)
-                        .map_err(|e| NodeError::Chain(e.to_string()))?;
+                    // block sai consensus: phạt peer, tải tiếp block khác. Body xấu không đánh dấu
+                    // id: block vẫn thiếu nên lần sync sau (peer khác) xin lại
+                    if let Err(e) = st.ingest_block(block) {
+                        let verdict = st
+                            .handle_invalid_block_from_peer(id, e)
+                            .map_err(|e| NodeError::Chain(e.to_string()))?;
+                        apply_invalid_block_verdict(peer, &verdict);
+                        if peer.is_banned() {
+                            return Err(NodeError::Protocol(format!(
+                                "peer banned: {}",
+                                peer.ban_reason().unwrap_or("unknown")
+                            )));
+                        }
+                    }
 
                     last_progress = Instant::now();
                 }
@@ -450,11 +1384,383 @@ pub fn run_syncer_once<S: ChainStore + Clone>(
         }
     }
 
-    st.validate_best_chain()
-        .map_err(|e| NodeError::Chain(e.to_string()))?;
     Ok(())
 }
 
+/// Chờ mỗi peer tối đa chừng này khi đọc vòng tròn qua nhiều kết nối.
+const MULTI_PEER_IO_TICK: Duration = Duration::from_millis(20);
+
+/// Tải header + block song song từ nhiều peer. Mỗi id được xin từ peer còn slot
+/// (`InflightManager`) và đã gửi header của id đó; BlockNotFound/timeout/peer rớt => gán lại
+/// cho peer khác, vẫn giữ giới hạn retry theo id.
+pub struct BlockDownloader {
+    addrs: Vec<SocketAddr>,
+    batch_max: u32,
+    cfg: InflightConfig,
+}
+
+struct DlPeer {
+    key: String,
+    io: FramedTcp,
+    machine: PeerMachine,
+    // id header peer này đã gửi: chỉ xin block peer đã biết header (tránh phạt reply-without-header)
+    known: HashSet<egg_types::Hash256>,
+    headers_done: bool,
+}
+
+enum PeerStatus {
+    Keep,
+    Drop(String),
+}
+
+struct DownloadState {
+    inflight: InflightManager,
+    pending: VecDeque<(egg_types::Hash256, u64)>,
+    seen: HashSet<egg_types::Hash256>,
+    // id -> (peer key, lúc gửi)
+    sent: HashMap<egg_types::Hash256, (String, Instant)>,
+    retries: HashMap<egg_types::Hash256, u8>,
+    last_progress: Instant,
+}
+
+impl BlockDownloader {
+    pub fn new(addrs: Vec<SocketAddr>, batch_max: u32) -> Self {
+        Self {
+            addrs,
+            batch_max,
+            cfg: InflightConfig::default(),
+        }
+    }
+
+    pub fn with_inflight_config(mut self, cfg: InflightConfig) -> Self {
+        self.cfg = cfg;
+        self
+    }
+
+    /// Sync tới khi mọi peer còn sống hết header mới và mọi block đã tải; lỗi nếu không còn peer nào.
+    pub fn run<S: ChainStore + Clone>(&self, spec: egg_types::ChainSpec, store: S) -> Result<()> {
+        let mut st = open_chain(store, spec)?;
+
+        let mut peers = Vec::new();
+        for addr in &self.addrs {
+            // peer không kết nối được thì bỏ qua, còn peer khác
+            if let Ok(p) = self.connect(*addr, &st) {
+                peers.push(p);
+            }
+        }
+
+        let mut dl = DownloadState {
+            inflight: InflightManager::new(self.cfg),
+            pending: VecDeque::new(),
+            seen: HashSet::new(),
+            sent: HashMap::new(),
+            retries: HashMap::new(),
+            last_progress: Instant::now(),
+        };
+
+        let mut last_drop: Option<String> = None;
+        loop {
+            if peers.is_empty() {
+                return Err(NodeError::Protocol(format!(
+                    "no peer left: pending={} last_error={}",
+                    dl.pending.len(),
+                    last_drop.as_deref().unwrap_or("connect failed")
+                )));
+            }
+
+            let mut dropped = dl.assign(&st, &mut peers)?;
+
+            let headers_done = peers.iter().all(|p| p.headers_done);
+            if headers_done && dl.inflight.total_inflight() == 0 {
+                if dl.pending.is_empty() {
+                    break;
+                }
+                if dropped.is_empty() {
+                    return Err(NodeError::Protocol(format!(
+                        "no peer can serve {} pending blocks",
+                        dl.pending.len()
+                    )));
+                }
+            }
+
+            if dl.last_progress.elapsed() > SESSION_IDLE_TIMEOUT {
+                return Err(NodeError::Protocol(format!(
+                    "sync idle timeout: pending={} inflight={}",
+                    dl.pending.len(),
+                    dl.inflight.total_inflight()
+                )));
+            }
+
+            for (i, p) in peers.iter_mut().enumerate() {
+                if dropped.iter().any(|(d, _)| *d == i) {
+                    continue;
+                }
+                let status = match p.io.recv() {
+                    Ok(msg) => dl.on_message(&mut st, p, msg)?,
+                    Err(NodeError::Io(e)) if is_io_timeout(&e) => PeerStatus::Keep,
+                    Err(e) => PeerStatus::Drop(e.to_string()),
+                };
+                if let PeerStatus::Drop(why) = status {
+                    dropped.push((i, why));
+                }
+            }
+
+            dropped.extend(dl.resend_timed_out(&st, &mut peers)?);
+
+            dropped.sort_by_key(|(i, _)| std::cmp::Reverse(*i));
+            dropped.dedup_by_key(|(i, _)| *i);
+            for (i, why) in dropped {
+                last_drop = Some(why);
+                let p = peers.remove(i);
+                for id in dl.inflight.remove_peer(&p.key) {
+                    dl.requeue(&st, id)?;
+                }
+            }
+        }
+
+        st.validate_best_chain()
+            .map_err(|e| NodeError::Chain(e.to_string()))?;
+        Ok(())
+    }
+
+    fn connect<S: ChainStore + Clone>(&self, addr: SocketAddr, st: &ChainState<S>) -> Result<DlPeer> {
+        let stream = TcpStream::connect(addr)?;
+        let mut io = FramedTcp::new(stream)?;
+        io.stream.set_read_timeout(Some(MULTI_PEER_IO_TICK))?;
+
+        let locator = st
+            .build_block_locator()
+            .map_err(|e| NodeError::Chain(e.to_string()))?;
+        let mut machine = PeerMachine::new(
+            Role::Outbound,
+            egg_net::peer::LocalInfo {
+                chain_id: st.meta.chain_id,
+                genesis_id: st.meta.genesis_id,
+                tip: Tip {
+                    height: st.tip.height.0,
+                    hash: st.tip.hash,
+                },
+                node_nonce: generate_node_nonce(),
+                agent: "egg-node/syncer".to_string(),
+                pruned_from_height: None,
+            },
+        )
+        .enable_header_sync(self.batch_max)
+        .with_sync_locator(locator);
+
+        for m in machine.start() {
+            io.send(&m)?;
+        }
+        Ok(DlPeer {
+            key: addr.to_string(),
+            io,
+            machine,
+            known: HashSet::new(),
+            headers_done: false,
+        })
+    }
+}
+
+impl DownloadState {
+    /// Gán id đang chờ cho peer biết header + còn nhiều slot nhất; id chưa gán được giữ lại.
+    /// Trả peer gửi lỗi (index, lý do).
+    fn assign<S: ChainStore + Clone>(
+        &mut self,
+        st: &ChainState<S>,
+        peers: &mut [DlPeer],
+    ) -> Result<Vec<(usize, String)>> {
+        let mut dropped: Vec<(usize, String)> = Vec::new();
+        let mut rest = VecDeque::new();
+        let now = Instant::now();
+
+        while let Some((id, height)) = self.pending.pop_front() {
+            let have = egg_db::store::BlockStore::has_block(st.store(), id)
+                .map_err(|e| NodeError::Chain(e.to_string()))?;
+            if have {
+                continue;
+            }
+
+            let best = peers
+                .iter()
+                .enumerate()
+                .filter(|(i, p)| {
+                    !dropped.iter().any(|(d, _)| d == i)
+                        && p.known.contains(&id)
+                        && p.machine.can_serve_block_at(height)
+                })
+                .map(|(i, p)| (i, self.inflight.free_slots(&p.key)))
+                .filter(|(_, free)| *free > 0)
+                .max_by_key(|(i, free)| (*free, std::cmp::Reverse(*i)));
+            let Some((i, _)) = best else {
+                rest.push_back((id, height));
+                continue;
+            };
+
+            let p = &mut peers[i];
+            let req = p.machine.request_block(id);
+            if let Err(e) = p.io.send(&req) {
+                dropped.push((i, e.to_string()));
+                rest.push_back((id, height));
+                continue;
+            }
+            self.inflight.try_request(&p.key, id);
+            self.sent.insert(id, (p.key.clone(), now));
+        }
+
+        self.pending = rest;
+        Ok(dropped)
+    }
+
+    fn on_message<S: ChainStore + Clone>(
+        &mut self,
+        st: &mut ChainState<S>,
+        p: &mut DlPeer,
+        msg: Message,
+    ) -> Result<PeerStatus> {
+        if let Message::Headers { headers } = &msg {
+            if headers.is_empty() {
+                p.headers_done = true;
+            } else {
+                self.last_progress = Instant::now();
+            }
+            for h in headers.iter().cloned() {
+                let id = hash_header(&h);
+                let height = h.height.0;
+                let _ = st.ingest_header(h).map_err(|e| NodeError::Chain(e.to_string()))?;
+                p.known.insert(id);
+                if !self.seen.insert(id) {
+                    continue;
+                }
+                let have = egg_db::store::BlockStore::has_block(st.store(), id)
+                    .map_err(|e| NodeError::Chain(e.to_string()))?;
+                if !have {
+                    self.pending.push_back((id, height));
+                }
+            }
+        }
+
+        for m in p.machine.on_message(msg.clone()) {
+            if let Err(e) = p.io.send(&m) {
+                return Ok(PeerStatus::Drop(e.to_string()));
+            }
+        }
+        if p.machine.is_banned() {
+            return Ok(PeerStatus::Drop(format!(
+                "peer banned: {}",
+                p.machine.ban_reason().unwrap_or("unknown")
+            )));
+        }
+
+        match msg {
+            Message::BlockFound { id, block } => {
+                // reply trễ cho id đã gán lại: PeerMachine tự phạt nếu là unsolicited
+                if !self.inflight.complete(&p.key, id) {
+                    return Ok(PeerStatus::Keep);
+                }
+                self.sent.remove(&id);
+
+                let has_h = egg_db::store::BlockStore::has_header(st.store(), id)
+                    .map_err(|e| NodeError::Chain(e.to_string()))?;
+                let hid = hash_header(&block.header);
+                if !has_h || hid != id {
+                    self.requeue(st, id)?;
+                    return Ok(PeerStatus::Drop(format!(
+                        "BlockFound {:?} without matching local header (hashes to {:?})",
+                        id, hid
+                    )));
+                }
+
+                if let Err(e) = st.ingest_block(block) {
+                    let verdict = st
+                        .handle_invalid_block_from_peer(id, e)
+                        .map_err(|e| NodeError::Chain(e.to_string()))?;
+                    apply_invalid_block_verdict(&mut p.machine, &verdict);
+                    // body xấu: header vẫn hợp lệ, xin lại block từ peer khác
+                    if matches!(verdict, InvalidBlockVerdict::BadBody(_)) {
+                        p.known.remove(&id);
+                        self.bump_retry(id, "bad body")?;
+                        self.requeue(st, id)?;
+                    }
+                    if p.machine.is_banned() {
+                        return Ok(PeerStatus::Drop(format!(
+                            "peer banned: {}",
+                            p.machine.ban_reason().unwrap_or("unknown")
+                        )));
+                    }
+                }
+                self.last_progress = Instant::now();
+            }
+
+            Message::BlockNotFound { id } => {
+                if !self.inflight.complete(&p.key, id) {
+                    return Ok(PeerStatus::Keep);
+                }
+                self.sent.remove(&id);
+                // peer này không có block: lần sau gán cho peer khác
+                p.known.remove(&id);
+                self.bump_retry(id, "not found")?;
+                self.requeue(st, id)?;
+            }
+
+            _ => {}
+        }
+        Ok(PeerStatus::Keep)
+    }
+
+    /// Request quá `PER_REQ_RESEND_AFTER`: trả slot, phạt peer và đưa id về hàng chờ.
+    fn resend_timed_out<S: ChainStore + Clone>(
+        &mut self,
+        st: &ChainState<S>,
+        peers: &mut [DlPeer],
+    ) -> Result<Vec<(usize, String)>> {
+        let now = Instant::now();
+        let expired: Vec<(egg_types::Hash256, String)> = self
+            .sent
+            .iter()
+            .filter(|(_, (_, at))| now.duration_since(*at) >= PER_REQ_RESEND_AFTER)
+            .map(|(id, (key, _))| (*id, key.clone()))
+            .collect();
+
+        let mut dropped = Vec::new();
+        for (id, key) in expired {
+            self.inflight.complete(&key, id);
+            self.bump_retry(id, "timeout")?;
+            self.requeue(st, id)?;
+
+            if let Some(i) = peers.iter().position(|p| p.key == key) {
+                let p = &mut peers[i];
+                p.machine.note_timeout();
+                if p.machine.is_banned() {
+                    let why = format!("peer banned: {}", p.machine.ban_reason().unwrap_or("unknown"));
+                    dropped.push((i, why));
+                }
+            }
+        }
+        Ok(dropped)
+    }
+
+    fn bump_retry(&mut self, id: egg_types::Hash256, why: &str) -> Result<()> {
+        let n = self.retries.entry(id).or_insert(0);
+        if *n >= MAX_BLOCK_RETRIES {
+            return Err(NodeError::Protocol(format!(
+                "block {:?} {} after {} retries",
+                id, why, MAX_BLOCK_RETRIES
+            )));
+        }
+        *n += 1;
+        Ok(())
+    }
+
+    fn requeue<S: ChainStore + Clone>(&mut self, st: &ChainState<S>, id: egg_types::Hash256) -> Result<()> {
+        self.sent.remove(&id);
+        let height = egg_db::store::BlockStore::get_header(st.store(), id)
+            .map(|h| h.height.0)
+            .map_err(|e| NodeError::Chain(e.to_string()))?;
+        self.pending.push_front((id, height));
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -495,6 +1801,7 @@ mod tests {
         Block { header, txs: vec![] }
     }
 
+    #[allow(clippy::unnecessary_cast)]
     fn build_chain_with_blocks(
         store: DbChainStore<MemKv>,
         spec: ChainSpec,
@@ -515,6 +1822,134 @@ mod tests {
         hashes
     }
 
+    /// Harness cho test mạng: 1 node = ChainState trên MemKv, responder trên cổng ephemeral.
+    struct TestNode {
+        spec: ChainSpec,
+        store: DbChainStore<MemKv>,
+    }
+
+    impl TestNode {
+        fn new() -> Self {
+            let spec = mk_spec(1_700_000_000);
+            let store = DbChainStore::new(MemKv::new());
+            let _ = ChainState::open_or_init(store.clone(), spec.clone()).unwrap();
+            Self { spec, store }
+        }
+
+        fn state(&self) -> ChainState<DbChainStore<MemKv>> {
+            ChainState::open_or_init(self.store.clone(), self.spec.clone()).unwrap()
+        }
+
+        fn tip(&self) -> egg_db::store::ChainTip {
+            self.state().tip
+        }
+
+        /// Nối `n` block rỗng lên tip; `nonce_base` khác nhau => nhánh khác nhau.
+        fn extend(&self, n: u64, nonce_base: u64) -> Vec<Hash256> {
+            let mut st = self.state();
+            let mut ids = Vec::new();
+            for _ in 0..n {
+                let h = st.tip.height.0 + 1;
+                let (id, _) = st
+                    .ingest_block(mk_empty_block(st.tip.hash, Height(h), nonce_base + h))
+                    .unwrap();
+                ids.push(id);
+            }
+            ids
+        }
+
+        fn copy_blocks_from(&self, other: &TestNode, ids: &[Hash256]) {
+            let mut st = self.state();
+            for id in ids {
+                let b = egg_db::store::BlockStore::get_block(&other.store, *id).unwrap();
+                st.ingest_block(b).unwrap();
+            }
+        }
+
+        /// Responder phục vụ đúng 1 phiên.
+        fn serve_once(&self) -> (SocketAddr, thread::JoinHandle<Result<()>>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let (spec, store) = (self.spec.clone(), self.store.clone());
+            let h = thread::spawn(move || run_responder_once(listener, spec, store));
+            (addr, h)
+        }
+
+        /// Sync header + block từ `other` qua TCP; lỗi của responder cũng được trả về.
+        fn sync_from(&self, other: &TestNode) -> Result<()> {
+            let (addr, responder) = other.serve_once();
+            let r = run_syncer_once(addr, self.spec.clone(), self.store.clone(), 2000);
+            let rr = responder.join().expect("responder thread panicked");
+            r.and(rr)
+        }
+    }
+
+    fn mk_peer() -> PeerMachine {
+        PeerMachine::new(
+            Role::Inbound,
+            egg_net::peer::LocalInfo {
+                chain_id: 1,
+                genesis_id: Hash256([9u8; 32]),
+                tip: Tip {
+                    height: 0,
+                    hash: Hash256::zero(),
+                },
+                node_nonce: 1,
+                agent: "test".to_string(),
+                pruned_from_height: None,
+            },
+        )
+    }
+
+    #[test]
+    fn banned_peer_stays_banned_across_reconnect_until_expiry() {
+        let reps = ReputationStore::new(MemKv::new());
+        let key = "10.0.0.7";
+        let t0 = 1_700_000_000i64;
+
+        // phiên 1: peer gửi BlockFound không được yêu cầu 2 lần => ban
+        let mut p = mk_peer();
+        let blk = mk_empty_block(Hash256::zero(), Height(1), 1);
+        let id = hash_header(&blk.header);
+        let _ = p.on_message(Message::BlockFound { id, block: blk.clone() });
+        let _ = p.on_message(Message::BlockFound { id, block: blk });
+        assert!(p.is_banned());
+        save_peer_reputation(&reps, key, &p, t0).unwrap();
+
+        // phiên 2: kết nối lại trước khi hết hạn => vẫn bị ban
+        let mut p2 = mk_peer();
+        restore_peer_reputation(&reps, key, &mut p2, t0 + 60).unwrap();
+        assert!(p2.is_banned());
+        assert!(p2.ban_reason().unwrap().contains("threshold"));
+        assert_eq!(p2.penalty_score(), p.penalty_score());
+
+        // lưu lại trong lúc còn ban không gia hạn thêm
+        save_peer_reputation(&reps, key, &p2, t0 + 120).unwrap();
+        let rep = reps.get(key).unwrap().unwrap();
+        assert_eq!(rep.banned_until_utc, Some(t0 + PEER_BAN_DURATION_SECS));
+
+        // phiên 3: sau khi hết hạn => không còn ban
+        let mut p3 = mk_peer();
+        restore_peer_reputation(&reps, key, &mut p3, t0 + PEER_BAN_DURATION_SECS).unwrap();
+        assert!(!p3.is_banned());
+    }
+
+    #[test]
+    fn bind_responder_ephemeral_port() {
+        let listener = bind_responder("127.0.0.1:0", ResponderConfig::default()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert!(addr.ip().is_loopback());
+
+        let any = bind_responder("0.0.0.0:0", ResponderConfig::default()).unwrap();
+        assert_ne!(any.local_addr().unwrap().port(), 0);
+
+        assert!(matches!(
+            bind_responder("not-an-addr", ResponderConfig::default()),
+            Err(NodeError::Io(_))
+        ));
+    }
+
     #[test]
     fn tcp_two_nodes_sync_headers_and_blocks_to_same_tip() {
         let spec = mk_spec(1_700_000_000);
@@ -557,4 +1992,998 @@ mod tests {
             assert!(has_b, "missing block at height {} id={:?}", h, id);
         }
     }
+
+    #[test]
+    fn chain_status_reports_stale_tip() {
+        let store = DbChainStore::new(MemKv::new());
+        let spec = mk_spec(1_700_000_000);
+        build_chain_with_blocks(store.clone(), spec.clone(), 2);
+        let st = ChainState::open_or_init(store, spec).unwrap();
+
+        let fresh = chain_status(&st, 1_700_000_060, STALE_TIP_MAX_AGE_SECS).unwrap();
+        assert_eq!(fresh, ChainStatus::new(2, 1_700_000_000, false));
+
+        let stale = chain_status(&st, 1_700_100_000, STALE_TIP_MAX_AGE_SECS).unwrap();
+        assert!(stale.tip_stale);
+    }
+
+    #[test]
+    fn get_difficulty_reports_tip_bits_without_retarget() {
+        let store = DbChainStore::new(MemKv::new());
+        let spec = mk_spec(1_700_000_000);
+        build_chain_with_blocks(store.clone(), spec.clone(), 2);
+        let st = ChainState::open_or_init(store, spec).unwrap();
+
+        let bits = egg_db::store::BlockStore::get_header(st.store(), st.tip.hash)
+            .unwrap()
+            .pow_difficulty_bits;
+        assert_eq!(get_difficulty(&st).unwrap(), egg_rpc::DifficultyInfo::new(bits, bits, None));
+    }
+
+    #[test]
+    fn classify_peers_by_tip_height() {
+        let tip = |height: u64| Tip {
+            height,
+            hash: Hash256([height as u8; 32]),
+        };
+        let remotes = [tip(12), tip(3), tip(10), tip(11), tip(10)];
+
+        let c = classify_peers(tip(10), &remotes);
+        assert_eq!(c.ahead, vec![0, 3]);
+        assert_eq!(c.equal, vec![2, 4]);
+        assert_eq!(c.behind, vec![1]);
+
+        assert_eq!(classify_peers(tip(0), &[]), PeerClassification::default());
+    }
+
+    #[test]
+    fn responder_serves_block_txids() {
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::{Transaction, CONTENT_TAG_OPAQUE};
+
+        let spec = mk_spec(1_700_000_000);
+        let store = DbChainStore::new(MemKv::new());
+        let mut st = ChainState::open_or_init(store.clone(), spec.clone()).unwrap();
+
+        let txs: Vec<Transaction> = [&b"a"[..], b"bb", b"ccc"]
+            .iter()
+            .map(|p| Transaction {
+                id: tx_id_from_payload(p),
+                payload: p.to_vec(),
+                content_tag: CONTENT_TAG_OPAQUE,
+            })
+            .collect();
+        let want: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.header.merkle_root = merkle_root_txids(&want);
+        blk.txs = txs;
+        let (bid, _) = st.ingest_block(blk).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let t_responder = thread::spawn(move || {
+            run_responder_once(listener, spec, store).unwrap();
+        });
+
+        let mut io = FramedTcp::new(TcpStream::connect(addr).unwrap()).unwrap();
+        io.send(&Message::Hello {
+            chain_id: 1,
+            genesis_id: st.meta.genesis_id,
+            tip: Tip {
+                height: 0,
+                hash: st.meta.genesis_id,
+            },
+            node_nonce: 5,
+            agent: "test".to_string(),
+            challenge: [1u8; 16],
+            max_frame_len: egg_net::codec::MAX_FRAME_LEN,
+            max_headers_per_msg: egg_net::protocol::DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        })
+        .unwrap();
+        assert!(matches!(io.recv().unwrap(), Message::HelloAck { .. }));
+
+        io.send(&Message::GetBlockTxids { id: bid }).unwrap();
+        assert_eq!(
+            io.recv().unwrap(),
+            Message::BlockTxids {
+                id: bid,
+                txids: Some(want),
+            }
+        );
+
+        let missing = Hash256([7u8; 32]);
+        io.send(&Message::GetBlockTxids { id: missing }).unwrap();
+        assert_eq!(
+            io.recv().unwrap(),
+            Message::BlockTxids {
+                id: missing,
+                txids: None,
+            }
+        );
+
+        drop(io);
+        t_responder.join().unwrap();
+    }
+
+    #[test]
+    fn compact_block_reconstructed_fetching_only_missing_txs() {
+        use egg_chain::compact::compact_of;
+        use egg_crypto::tx_id_from_payload;
+        use egg_types::{Transaction, CONTENT_TAG_OPAQUE};
+
+        let spec = mk_spec(1_700_000_000);
+        let store = DbChainStore::new(MemKv::new());
+        let st = ChainState::open_or_init(store.clone(), spec.clone()).unwrap();
+
+        let txs: Vec<Transaction> = (0u8..8)
+            .map(|i| Transaction {
+                id: tx_id_from_payload(&[i; 4]),
+                payload: vec![i; 4],
+                content_tag: CONTENT_TAG_OPAQUE,
+            })
+            .collect();
+        let leaves: Vec<Hash256> = txs.iter().map(|t| t.id).collect();
+        let mut blk = mk_empty_block(st.tip.hash, Height(1), 1);
+        blk.header.merkle_root = merkle_root_txids(&leaves);
+        blk.txs = txs.clone();
+        let bid = hash_header(&blk.header);
+
+        // receiver đã có mọi tx trừ index 2 và 5
+        let mut mp = Mempool::new();
+        for (i, tx) in txs.iter().enumerate() {
+            if i != 2 && i != 5 {
+                mp.add_tx(tx.clone()).unwrap();
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (spec_r, store_r) = (spec.clone(), store.clone());
+        let t_receiver = thread::spawn(move || {
+            run_responder_once_with_mempool(listener, spec_r, store_r, &mp).unwrap();
+        });
+
+        let mut io = FramedTcp::new(TcpStream::connect(addr).unwrap()).unwrap();
+        io.send(&Message::Hello {
+            chain_id: 1,
+            genesis_id: st.meta.genesis_id,
+            tip: Tip {
+                height: 1,
+                hash: bid,
+            },
+            node_nonce: 5,
+            agent: "test".to_string(),
+            challenge: [1u8; 16],
+            max_frame_len: egg_net::codec::MAX_FRAME_LEN,
+            max_headers_per_msg: egg_net::protocol::DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        })
+        .unwrap();
+        assert!(matches!(io.recv().unwrap(), Message::HelloAck { .. }));
+
+        let (header, txids) = compact_of(&blk);
+        io.send(&Message::CompactBlock { header, txids }).unwrap();
+        let indexes = match io.recv().unwrap() {
+            Message::GetBlockTxn { id, indexes } => {
+                assert_eq!(id, bid);
+                indexes
+            }
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(indexes, vec![2, 5]);
+        io.send(&Message::BlockTxn {
+            id: bid,
+            txs: txs_at(&blk, &indexes).unwrap(),
+        })
+        .unwrap();
+
+        // đóng phiên; receiver đã ingest block
+        drop(io);
+        t_receiver.join().unwrap();
+        let back = egg_db::store::BlockStore::get_block(&store, bid).unwrap();
+        assert_eq!(back, blk);
+        let st = ChainState::open_or_init(store, spec).unwrap();
+        assert_eq!(st.tip.hash, bid);
+    }
+
+    #[test]
+    fn responder_serves_block_range_in_one_exchange() {
+        let remote = TestNode::new();
+        let ids = remote.extend(40, 0);
+        let st = remote.state();
+        let (addr, responder) = remote.serve_once();
+
+        let mut io = FramedTcp::new(TcpStream::connect(addr).unwrap()).unwrap();
+        io.send(&Message::Hello {
+            chain_id: 1,
+            genesis_id: st.meta.genesis_id,
+            tip: Tip {
+                height: 0,
+                hash: st.meta.genesis_id,
+            },
+            node_nonce: 5,
+            agent: "test".to_string(),
+            challenge: [1u8; 16],
+            max_frame_len: egg_net::codec::MAX_FRAME_LEN,
+            max_headers_per_msg: egg_net::protocol::DEFAULT_MAX_HEADERS_PER_MSG,
+            pruned_from_height: None,
+        })
+        .unwrap();
+        assert!(matches!(io.recv().unwrap(), Message::HelloAck { .. }));
+
+        // block 10..30 trong 1 request/response
+        io.send(&Message::GetBlockRange {
+            from_height: 10,
+            count: 21,
+        })
+        .unwrap();
+        let blocks = match io.recv().unwrap() {
+            Message::BlockRange { blocks } => blocks,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(blocks.len(), 21);
+        for (i, b) in blocks.iter().enumerate() {
+            assert_eq!(b.header.height, Height(10 + i as u64));
+            assert_eq!(hash_header(&b.header), ids[9 + i]);
+        }
+
+        // count lớn vẫn dừng ở tip; quá tip => rỗng
+        io.send(&Message::GetBlockRange {
+            from_height: 35,
+            count: u32::MAX,
+        })
+        .unwrap();
+        assert!(matches!(io.recv().unwrap(), Message::BlockRange { blocks } if blocks.len() == 6));
+        io.send(&Message::GetBlockRange {
+            from_height: 41,
+            count: 5,
+        })
+        .unwrap();
+        assert_eq!(io.recv().unwrap(), Message::BlockRange { blocks: vec![] });
+
+        drop(io);
+        responder.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn harness_node_with_prefix_catches_up() {
+        let peer = TestNode::new();
+        let ids = peer.extend(12, 0);
+
+        let local = TestNode::new();
+        local.copy_blocks_from(&peer, &ids[..4]);
+        assert_eq!(local.tip().height, Height(4));
+
+        local.sync_from(&peer).unwrap();
+        assert_eq!(local.tip(), peer.tip());
+        local.state().validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn harness_blocks_relay_across_three_nodes() {
+        let a = TestNode::new();
+        let b = TestNode::new();
+        let c = TestNode::new();
+        a.extend(6, 0);
+
+        b.sync_from(&a).unwrap();
+        c.sync_from(&b).unwrap();
+        assert_eq!(c.tip(), a.tip());
+
+        // A đào thêm, C lấy trực tiếp phần mới
+        a.extend(3, 0);
+        c.sync_from(&a).unwrap();
+        assert_eq!(c.tip(), a.tip());
+        assert_ne!(b.tip(), a.tip());
+    }
+
+    #[test]
+    fn syncer_reorgs_to_longer_remote_fork() {
+        // A: chain ngắn của syncer, B: chain dài hơn của responder, rẽ nhánh từ genesis
+        let local = TestNode::new();
+        local.extend(3, 100);
+        let remote = TestNode::new();
+        let b = remote.extend(8, 200);
+        assert_ne!(local.state().canon_hash(Height(1)).unwrap(), Some(b[0]));
+
+        local.sync_from(&remote).unwrap();
+        assert_eq!(local.tip(), remote.tip());
+        assert_eq!(local.state().canon_hash(Height(1)).unwrap(), Some(b[0]));
+        local.state().validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn syncer_converges_from_fork_two_blocks_deep() {
+        // chung 8 block đầu; syncer có 2 block riêng trên đó, responder dài hơn 4 block
+        let remote = TestNode::new();
+        let shared = remote.extend(8, 0);
+        let local = TestNode::new();
+        local.copy_blocks_from(&remote, &shared);
+        let stale = local.extend(2, 500);
+        let theirs = remote.extend(4, 0);
+        assert_eq!(local.tip().hash, stale[1]);
+
+        // locator bắt đầu bằng nhánh riêng, responder phải trả header từ fork point (height 8)
+        let loc = local.state().build_block_locator().unwrap();
+        assert_eq!(&loc[..3], &[stale[1], stale[0], shared[7]]);
+        assert_eq!(
+            remote.state().fork_point_from_locator(&loc).unwrap(),
+            Some((Height(8), shared[7]))
+        );
+
+        local.sync_from(&remote).unwrap();
+        assert_eq!(local.tip(), remote.tip());
+        let st = local.state();
+        assert_eq!(st.canon_hash(Height(9)).unwrap(), Some(theirs[0]));
+        assert!(!st.is_on_best_chain(stale[0]).unwrap());
+        st.validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn syncer_skips_invalid_block_and_penalizes_peer() {
+        let remote = TestNode::new();
+        let ids = remote.extend(5, 0);
+
+        // block 3 bị thay body (merkle không khớp header), id vẫn = hash(header)
+        let mut bad = egg_db::store::BlockStore::get_block(&remote.store, ids[2]).unwrap();
+        bad.txs.push(egg_types::Transaction {
+            id: egg_crypto::tx_id_from_payload(b"evil"),
+            payload: b"evil".to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        });
+        egg_db::store::BlockStore::put_block(&remote.store, ids[2], &bad).unwrap();
+
+        let local = TestNode::new();
+        let reps = ReputationStore::new(MemKv::new());
+        let (addr, responder) = remote.serve_once();
+        run_syncer_once_with_reputation(addr, local.spec.clone(), local.store.clone(), 2000, &reps)
+            .unwrap();
+        responder.join().expect("responder thread panicked").unwrap();
+
+        // sync xong tới ngay trước block xấu; con cháu của nó không được nhận
+        assert_eq!(local.tip().hash, ids[1]);
+        let rep = reps.get("127.0.0.1").unwrap().unwrap();
+        assert!(rep.penalty_score > 0);
+        assert!(rep.ban_reason.is_none());
+
+        // body giả không đầu độc id: peer trung thực gửi body đúng sau đó vẫn được nhận
+        let honest = TestNode::new();
+        let honest_ids = honest.extend(5, 0);
+        assert_eq!(honest_ids, ids);
+        let (addr, responder) = honest.serve_once();
+        run_syncer_once(addr, local.spec.clone(), local.store.clone(), 2000).unwrap();
+        responder.join().expect("responder thread panicked").unwrap();
+        assert_eq!(local.tip().hash, ids[4]);
+    }
+
+    #[test]
+    fn recv_times_out_on_trickled_partial_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let writer = thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            // khai báo frame 1000 byte rồi nhỏ giọt từng byte, không bao giờ đủ
+            let _ = s.write_all(&1000u32.to_be_bytes());
+            for _ in 0..40 {
+                if s.write_all(&[0u8]).is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let mut io = FramedTcp::new(TcpStream::connect(addr).unwrap())
+            .unwrap()
+            .with_max_frame_wait(Duration::from_millis(300));
+        let err = loop {
+            match io.recv() {
+                Err(NodeError::Io(e)) if is_io_timeout(&e) => continue,
+                other => break other.unwrap_err(),
+            }
+        };
+        assert!(matches!(err, NodeError::Frame(FrameError::PartialTimeout { buffered, .. }) if buffered >= 4));
+        drop(io);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn stalled_reader_fills_send_queue_and_is_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // client kết nối nhưng không bao giờ đọc
+        let client = TcpStream::connect(addr).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let mut io = FramedTcp::new(server).unwrap().with_max_send_queue(4);
+
+        let big = vec![0u8; 1 << 20];
+        for _ in 0..64 {
+            if io.outq.len() == 4 {
+                break;
+            }
+            io.send_frame(&big).unwrap();
+        }
+        assert_eq!(io.outq.len(), 4);
+
+        // ping bị bỏ khi hàng đợi đầy, message thường => ngắt peer thay vì block
+        io.send(&Message::Ping { nonce: 1 }).unwrap();
+        assert_eq!(io.outq.len(), 4);
+        let err = io.send(&Message::GetBlock { id: Hash256::zero() }).unwrap_err();
+        assert!(matches!(err, NodeError::Protocol(ref m) if m.contains("send queue full")));
+        drop(client);
+    }
+
+    #[test]
+    fn node_nonce_random_unless_overridden() {
+        assert_ne!(generate_node_nonce(), generate_node_nonce());
+
+        set_node_nonce_override(Some(42));
+        assert_eq!(generate_node_nonce(), 42);
+        // override chỉ áp cho thread đặt nó
+        assert_ne!(thread::spawn(generate_node_nonce).join().unwrap(), 42);
+
+        set_node_nonce_override(None);
+        assert_ne!(generate_node_nonce(), generate_node_nonce());
+    }
+
+    #[test]
+    fn orphan_manager_connects_out_of_order_blocks() {
+        let src = TestNode::new();
+        let ids = src.extend(4, 0);
+        let blk = |i: usize| egg_db::store::BlockStore::get_block(&src.store, ids[i]).unwrap();
+
+        let node = TestNode::new();
+        let mut st = node.state();
+        let mut om = OrphanManager::default();
+
+        // cháu, con, rồi cha
+        assert!(om.submit_block(&mut st, blk(2), "p2").unwrap().connected.is_empty());
+        assert!(om.submit_block(&mut st, blk(1), "p1").unwrap().connected.is_empty());
+        assert_eq!(om.staged_len(), 2);
+        let r = om.submit_block(&mut st, blk(0), "p0").unwrap();
+        assert_eq!(r.connected, vec![ids[0], ids[1], ids[2]]);
+        assert!(r.discarded.is_empty());
+        assert_eq!(om.staged_len(), 0);
+        assert_eq!(st.tip.hash, ids[2]);
+
+        // orphan không bao giờ có parent bị bỏ sau max_retries block mới, báo peer gửi
+        let mut om = OrphanManager::new(1);
+        let lone = mk_empty_block(Hash256([7u8; 32]), Height(9), 9);
+        let lone_id = hash_header(&lone.header);
+        om.submit_block(&mut st, lone, "bad").unwrap();
+        om.submit_block(&mut st, blk(3), "p3").unwrap();
+        let r = om.submit_block(&mut st, blk(3), "p3").unwrap();
+        assert_eq!(r.discarded, vec![(lone_id, "bad".to_string())]);
+        assert_eq!(om.staged_len(), 0);
+    }
+
+    #[test]
+    fn orphan_manager_forged_body_does_not_poison_block_id() {
+        let src = TestNode::new();
+        let ids = src.extend(2, 0);
+        let blk = |i: usize| egg_db::store::BlockStore::get_block(&src.store, ids[i]).unwrap();
+
+        let node = TestNode::new();
+        let mut st = node.state();
+        let mut om = OrphanManager::default();
+
+        // con tới trước với body giả (header thật): bị bỏ + báo peer, nhưng id không bị đánh dấu
+        let mut forged = blk(1);
+        forged.txs.push(egg_types::Transaction {
+            id: egg_crypto::tx_id_from_payload(b"evil"),
+            payload: b"evil".to_vec(),
+            content_tag: egg_types::CONTENT_TAG_OPAQUE,
+        });
+        om.submit_block(&mut st, forged, "evil").unwrap();
+        let r = om.submit_block(&mut st, blk(0), "p0").unwrap();
+        assert_eq!(r.connected, vec![ids[0]]);
+        assert_eq!(r.discarded, vec![(ids[1], "evil".to_string())]);
+        assert!(!st.is_block_invalid(ids[1]));
+
+        // body thật tới sau vẫn được nhận
+        let r = om.submit_block(&mut st, blk(1), "p1").unwrap();
+        assert_eq!(r.connected, vec![ids[1]]);
+        assert_eq!(st.tip.hash, ids[1]);
+    }
+
+    #[test]
+    fn inflight_manager_caps_total_across_peers() {
+        let cfg = InflightConfig {
+            per_peer_window: 8,
+            max_global_inflight: 10,
+        };
+        let mut m = InflightManager::new(cfg);
+        let peers = ["p0", "p1", "p2"];
+        let id = |i: u32| {
+            let mut h = [0u8; 32];
+            h[..4].copy_from_slice(&i.to_be_bytes());
+            Hash256(h)
+        };
+
+        // gán vòng tròn cho 3 peer tới khi không peer nào nhận nữa
+        let mut next = 0u32;
+        loop {
+            let mut progressed = false;
+            for p in peers {
+                if m.try_request(p, id(next)) {
+                    next += 1;
+                    progressed = true;
+                }
+                assert!(m.total_inflight() <= cfg.max_global_inflight);
+                assert!(m.peer_inflight(p) <= cfg.per_peer_window);
+            }
+            if !progressed {
+                break;
+            }
+        }
+        // 3 cửa sổ cho phép 24 nhưng tổng dừng ở 10
+        assert_eq!(m.total_inflight(), 10);
+        assert_eq!(next, 10);
+        assert!(peers.iter().all(|p| m.free_slots(p) == 0));
+        assert!(!m.try_request("p3", id(99)));
+
+        // trả slot ở 1 peer => peer khác cũng được dùng
+        assert!(m.complete("p0", id(0)));
+        assert!(!m.complete("p0", id(0)));
+        assert_eq!(m.free_slots("p1"), 1);
+        assert!(m.try_request("p1", id(100)));
+        assert!(!m.try_request("p2", id(101)));
+
+        // peer rời đi: id của nó được trả lại để gán lại
+        let held = m.peer_inflight("p2");
+        let mut back = m.remove_peer("p2");
+        back.sort_by_key(|h| h.0);
+        assert_eq!(back.len(), held);
+        assert_eq!(m.total_inflight(), 10 - held);
+        assert!(back.iter().all(|h| m.try_request("p3", *h)));
+        assert_eq!(m.total_inflight(), 10);
+    }
+
+    /// Responder giả: trả Headers ngay nhưng giữ GetBlock lại tới khi syncer ngừng gửi (~50ms),
+    /// rồi trả hết 1 lượt. Trả số batch header (khác rỗng) lớn nhất đã phục vụ giữa 2 lượt trả block.
+    fn serve_blocks_lazily(node: &TestNode) -> (SocketAddr, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let st = node.state();
+        let h = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut io = FramedTcp::new(stream).unwrap();
+            io.stream.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            let mut peer = PeerMachine::new(
+                Role::Inbound,
+                egg_net::peer::LocalInfo {
+                    chain_id: st.meta.chain_id,
+                    genesis_id: st.meta.genesis_id,
+                    tip: Tip {
+                        height: st.tip.height.0,
+                        hash: st.tip.hash,
+                    },
+                    node_nonce: 7,
+                    agent: "lazy".to_string(),
+                    pruned_from_height: None,
+                },
+            );
+            let (mut held, mut batches, mut max_batches) = (Vec::new(), 0usize, 0usize);
+            loop {
+                let msg = match io.recv() {
+                    Ok(m) => m,
+                    Err(NodeError::Io(e)) if is_io_timeout(&e) => {
+                        for id in held.drain(..) {
+                            let block = egg_db::store::BlockStore::get_block(st.store(), id).unwrap();
+                            io.send(&Message::BlockFound { id, block }).unwrap();
+                        }
+                        batches = 0;
+                        continue;
+                    }
+                    Err(_) => return max_batches,
+                };
+                for m in peer.on_message(msg.clone()) {
+                    io.send(&m).unwrap();
+                }
+                let headers = match msg {
+                    Message::GetHeaders { start, max } => st.get_headers_after(start, max as usize).unwrap(),
+                    Message::GetHeadersLocator { locator, max } => {
+                        let (_, start) = st.fork_point_from_locator(&locator).unwrap().unwrap();
+                        st.get_headers_after(start, max as usize).unwrap()
+                    }
+                    Message::GetBlock { id } => {
+                        held.push(id);
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if !headers.is_empty() {
+                    batches += 1;
+                    max_batches = max_batches.max(batches);
+                }
+                io.send(&Message::Headers { headers }).unwrap();
+            }
+        });
+        (addr, h)
+    }
+
+    #[test]
+    fn header_sync_is_windowed_and_interleaved_with_blocks() {
+        let remote = TestNode::new();
+        remote.extend(40, 100);
+        let local = TestNode::new();
+        let batch_max = 2u32;
+
+        let (addr, responder) = serve_blocks_lazily(&remote);
+        run_syncer_once(addr, local.spec.clone(), local.store.clone(), batch_max).unwrap();
+        let max_batches = responder.join().unwrap();
+        assert_eq!(local.tip(), remote.tip());
+
+        // 20 batch header nhưng không quá HEADER_WINDOW batch trước khi block bắt kịp
+        assert!(max_batches > 0 && max_batches <= HEADER_WINDOW, "max_batches={max_batches}");
+    }
+
+    /// Responder giả nối thêm `grow` block ngay sau lần đầu trả Headers rỗng (syncer đã bắt kịp),
+    /// rồi gửi tip mới qua `tip_tx`.
+    fn serve_and_grow_after_first_sync(
+        node: &TestNode,
+        grow: u64,
+        tip_tx: mpsc::Sender<Hash256>,
+    ) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut st = node.state();
+        let h = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut io = FramedTcp::new(stream).unwrap();
+            let mut peer = PeerMachine::new(
+                Role::Inbound,
+                egg_net::peer::LocalInfo {
+                    chain_id: st.meta.chain_id,
+                    genesis_id: st.meta.genesis_id,
+                    tip: Tip {
+                        height: st.tip.height.0,
+                        hash: st.tip.hash,
+                    },
+                    node_nonce: 7,
+                    agent: "growing".to_string(),
+                    pruned_from_height: None,
+                },
+            );
+            let mut grown = false;
+            loop {
+                let msg = match io.recv() {
+                    Ok(m) => m,
+                    Err(NodeError::Io(e)) if is_io_timeout(&e) => continue,
+                    Err(_) => return,
+                };
+                for m in peer.on_message(msg.clone()) {
+                    io.send(&m).unwrap();
+                }
+                let headers = match msg {
+                    Message::GetHeaders { start, max } => st.get_headers_after(start, max as usize).unwrap(),
+                    Message::GetHeadersLocator { locator, max } => {
+                        let (_, start) = st.fork_point_from_locator(&locator).unwrap().unwrap();
+                        st.get_headers_after(start, max as usize).unwrap()
+                    }
+                    Message::GetBlock { id } => {
+                        let block = egg_db::store::BlockStore::get_block(st.store(), id).unwrap();
+                        io.send(&Message::BlockFound { id, block }).unwrap();
+                        continue;
+                    }
+                    _ => continue,
+                };
+                let caught_up = headers.is_empty();
+                io.send(&Message::Headers { headers }).unwrap();
+                if caught_up && !grown {
+                    grown = true;
+                    for _ in 0..grow {
+                        let h = st.tip.height.0 + 1;
+                        st.ingest_block(mk_empty_block(st.tip.hash, Height(h), 500 + h))
+                            .unwrap();
+                    }
+                    tip_tx.send(st.tip.hash).unwrap();
+                }
+            }
+        });
+        (addr, h)
+    }
+
+    #[test]
+    fn syncer_loop_picks_up_blocks_produced_after_initial_sync() {
+        let remote = TestNode::new();
+        remote.extend(5, 100);
+        let local = TestNode::new();
+
+        let (tip_tx, tip_rx) = mpsc::channel();
+        let (addr, responder) = serve_and_grow_after_first_sync(&remote, 3, tip_tx);
+
+        let shutdown = std::sync::Arc::new(AtomicBool::new(false));
+        let (spec, store, stop) = (local.spec.clone(), local.store.clone(), shutdown.clone());
+        let syncer = thread::spawn(move || {
+            run_syncer_loop(addr, spec, store, 2, Duration::from_millis(20), &stop)
+        });
+
+        // tip mới chỉ xuất hiện sau vòng đầu => syncer phải tải nó ở vòng sau
+        let new_tip = tip_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !egg_db::store::BlockStore::has_block(&local.store, new_tip).unwrap() {
+            assert!(Instant::now() < deadline, "new blocks never synced");
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        shutdown.store(true, Ordering::Relaxed);
+        syncer.join().unwrap().unwrap();
+        responder.join().unwrap();
+
+        assert_eq!(local.tip(), remote.tip());
+        assert_eq!(local.tip().height, Height(8));
+    }
+
+    /// Responder giả phục vụ header đầy đủ nhưng chỉ trả `serve` block rồi ngắt kết nối
+    /// ngay khi nhận GetBlock kế tiếp (peer chết giữa lúc sync).
+    fn serve_then_die(node: &TestNode, serve: usize) -> (SocketAddr, thread::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let st = node.state();
+        let h = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut io = FramedTcp::new(stream).unwrap();
+            let mut peer = PeerMachine::new(
+                Role::Inbound,
+                egg_net::peer::LocalInfo {
+                    chain_id: st.meta.chain_id,
+                    genesis_id: st.meta.genesis_id,
+                    tip: Tip {
+                        height: st.tip.height.0,
+                        hash: st.tip.hash,
+                    },
+                    node_nonce: 9,
+                    agent: "dying".to_string(),
+                    pruned_from_height: None,
+                },
+            );
+            let mut served = 0;
+            loop {
+                let msg = match io.recv() {
+                    Ok(m) => m,
+                    Err(NodeError::Io(e)) if is_io_timeout(&e) => continue,
+                    Err(_) => return served,
+                };
+                for m in peer.on_message(msg.clone()) {
+                    io.send(&m).unwrap();
+                }
+                let headers = match msg {
+                    Message::GetHeaders { start, max } => st.get_headers_after(start, max as usize).unwrap(),
+                    Message::GetHeadersLocator { locator, max } => {
+                        let (_, start) = st.fork_point_from_locator(&locator).unwrap().unwrap();
+                        st.get_headers_after(start, max as usize).unwrap()
+                    }
+                    Message::GetBlock { id } => {
+                        if served == serve {
+                            return served;
+                        }
+                        let block = egg_db::store::BlockStore::get_block(st.store(), id).unwrap();
+                        io.send(&Message::BlockFound { id, block }).unwrap();
+                        served += 1;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                io.send(&Message::Headers { headers }).unwrap();
+            }
+        });
+        (addr, h)
+    }
+
+    #[test]
+    fn block_downloader_completes_via_other_peer_when_one_dies() {
+        let remote = TestNode::new();
+        remote.extend(60, 100);
+        let local = TestNode::new();
+
+        let (dying_addr, dying) = serve_then_die(&remote, 5);
+        let (good_addr, good) = remote.serve_once();
+
+        BlockDownloader::new(vec![dying_addr, good_addr], 16)
+            .run(local.spec.clone(), local.store.clone())
+            .unwrap();
+
+        assert_eq!(dying.join().unwrap(), 5);
+        good.join().unwrap().unwrap();
+        assert_eq!(local.tip(), remote.tip());
+        local.state().validate_best_chain().unwrap();
+    }
+
+    #[test]
+    fn block_downloader_fails_when_no_peer_reachable() {
+        let local = TestNode::new();
+        // cổng vừa đóng => connect bị từ chối
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let r = BlockDownloader::new(vec![addr], 16).run(local.spec.clone(), local.store.clone());
+        assert!(matches!(r, Err(NodeError::Protocol(ref m)) if m.contains("no peer left")), "{r:?}");
+    }
+
+    #[test]
+    fn submit_block_reports_outcome_or_error_code() {
+        let node = TestNode::new();
+        let mut st = node.state();
+        let g = st.tip.hash;
+
+        let b1 = mk_empty_block(g, Height(1), 1);
+        let id1 = hash_header(&b1.header);
+        assert_eq!(
+            submit_block(&mut st, b1.clone()),
+            Ok(RpcResult::SubmitBlock {
+                id: id1,
+                outcome: SubmitBlockOutcome::NewTip
+            })
+        );
+        assert!(matches!(
+            submit_block(&mut st, b1),
+            Ok(RpcResult::SubmitBlock {
+                outcome: SubmitBlockOutcome::AlreadyKnown,
+                ..
+            })
+        ));
+
+        // target = 1: PoW không thể đạt
+        let mut bad_pow = mk_empty_block(id1, Height(2), 2);
+        bad_pow.header.pow_difficulty_bits = 0x0300_0001;
+        assert_eq!(submit_block(&mut st, bad_pow).unwrap_err().code, egg_rpc::RPC_ERR_INVALID_POW);
+
+        let mut bad_merkle = mk_empty_block(id1, Height(2), 3);
+        bad_merkle.header.merkle_root = Hash256([9u8; 32]);
+        assert_eq!(
+            submit_block(&mut st, bad_merkle).unwrap_err().code,
+            egg_rpc::RPC_ERR_INVALID_MERKLE
+        );
+
+        let orphan = mk_empty_block(Hash256([7u8; 32]), Height(5), 4);
+        assert_eq!(submit_block(&mut st, orphan).unwrap_err().code, egg_rpc::RPC_ERR_ORPHAN_BLOCK);
+        assert_eq!(st.tip.hash, id1);
+    }
+
+    #[test]
+    fn get_block_flags_stale_fork_blocks() {
+        let node = TestNode::new();
+        let mut st = node.state();
+        let g = st.tip.hash;
+
+        let a1 = mk_empty_block(g, Height(1), 1);
+        let (a1_id, _) = st.ingest_block(a1.clone()).unwrap();
+        st.ingest_block(mk_empty_block(a1_id, Height(2), 2)).unwrap();
+        let stale = mk_empty_block(g, Height(1), 101);
+        let (stale_id, _) = st.ingest_block(stale.clone()).unwrap();
+        assert_eq!(st.tip.height, Height(2));
+
+        assert_eq!(
+            get_block(&st, a1_id),
+            Ok(RpcResult::Block {
+                block: a1,
+                on_best_chain: true,
+                confirmations: Some(2),
+            })
+        );
+        assert_eq!(
+            get_block(&st, stale_id),
+            Ok(RpcResult::Block {
+                block: stale,
+                on_best_chain: false,
+                confirmations: None,
+            })
+        );
+        assert!(matches!(
+            get_block(&st, g),
+            Ok(RpcResult::Block { confirmations: Some(3), .. })
+        ));
+        assert_eq!(
+            get_block(&st, Hash256([7u8; 32])).unwrap_err().code,
+            egg_rpc::RPC_ERR_BLOCK_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn handle_request_serves_real_tip_of_built_chain() {
+        let node = TestNode::new();
+        let ids = node.extend(3, 0);
+        let st = node.state();
+        let mut mp = Mempool::new();
+        let mut ask = |id: u64, method: RpcMethod| handle_request(&st, &mut mp, &RpcRequest { id, method });
+
+        assert_eq!(
+            ask(1, RpcMethod::GetTip),
+            RpcResponse::Ok {
+                id: 1,
+                result: RpcResult::Tip {
+                    height: 3,
+                    hash: ids[2],
+                },
+            }
+        );
+
+        let RpcResponse::Ok {
+            result: RpcResult::ChainInfo(info),
+            ..
+        } = ask(2, RpcMethod::GetChainInfo)
+        else {
+            panic!("expected chain info");
+        };
+        assert_eq!(info.chain_id, st.meta.chain_id);
+        assert_eq!(info.genesis_id, st.meta.genesis_id);
+        assert_eq!((info.tip_height, info.tip_hash), (3, ids[2]));
+
+        // cùng block qua id, height và header
+        let by_id = ask(3, RpcMethod::GetBlockById { id: ids[1] });
+        let by_height = ask(3, RpcMethod::GetBlockByHeight { height: 2 });
+        assert_eq!(by_id, by_height);
+        let RpcResponse::Ok {
+            result: RpcResult::Block { block, confirmations, .. },
+            ..
+        } = by_id
+        else {
+            panic!("expected block");
+        };
+        assert_eq!(confirmations, Some(2));
+        assert_eq!(
+            ask(4, RpcMethod::GetHeaderById { id: ids[1] }),
+            RpcResponse::Ok {
+                id: 4,
+                result: RpcResult::Header {
+                    header: block.header,
+                    on_best_chain: true,
+                    confirmations: Some(2),
+                },
+            }
+        );
+
+        for (method, code) in [
+            (RpcMethod::GetBlockByHeight { height: 4 }, egg_rpc::RPC_ERR_BLOCK_NOT_FOUND),
+            (RpcMethod::GetHeaderById { id: Hash256([7u8; 32]) }, egg_rpc::RPC_ERR_BLOCK_NOT_FOUND),
+            (RpcMethod::PeerHealth, egg_rpc::RPC_ERR_METHOD_UNAVAILABLE),
+        ] {
+            let RpcResponse::Err { id: 5, error } = ask(5, method) else {
+                panic!("expected error");
+            };
+            assert_eq!(error.code, code);
+        }
+    }
+
+    #[test]
+    fn submit_tx_feeds_mempool_and_reports_duplicates() {
+        let node = TestNode::new();
+        let st = node.state();
+        let mut mp = Mempool::new();
+        let payload = b"wallet transfer".to_vec();
+        let txid = egg_crypto::tx_id_from_payload(&payload);
+        let req = RpcRequest {
+            id: 1,
+            method: RpcMethod::SubmitTx { payload },
+        };
+
+        assert_eq!(
+            handle_request(&st, &mut mp, &req),
+            RpcResponse::Ok {
+                id: 1,
+                result: RpcResult::SubmitTx { txid, accepted: true },
+            }
+        );
+        assert!(mp.contains(txid));
+        // gửi lại: không lỗi, chỉ báo không nhận thêm
+        assert_eq!(
+            handle_request(&st, &mut mp, &req),
+            RpcResponse::Ok {
+                id: 1,
+                result: RpcResult::SubmitTx { txid, accepted: false },
+            }
+        );
+        assert_eq!(mp.len(), 1);
+
+        let mut tiny = Mempool::with_config(egg_chain::mempool::MempoolConfig {
+            max_txs: 1,
+            max_total_bytes: 8,
+            ..Default::default()
+        });
+        assert_eq!(
+            submit_tx(&mut tiny, vec![0u8; 9]).unwrap_err().code,
+            egg_rpc::RPC_ERR_TX_TOO_LARGE
+        );
+        submit_tx(&mut tiny, b"a".to_vec()).unwrap();
+        assert_eq!(submit_tx(&mut tiny, b"b".to_vec()).unwrap_err().code, egg_rpc::RPC_ERR_MEMPOOL_FULL);
+    }
 }
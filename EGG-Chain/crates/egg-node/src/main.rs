@@ -26,10 +26,13 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
     std::fs::create_dir_all(&db_dir)?;
 
     let kv = SledKv::open(&db_dir)?;
-    let store = DbChainStore::new(kv);
+    let store = DbChainStore::open(kv)?;
 
     let state = ChainState::open_or_init(store, spec)?;
     state.verify_genesis_matches_spec()?;
 
+    // tắt êm: đẩy hết write còn treo trước khi thoát
+    state.flush()?;
+
     Ok(())
 }
@@ -0,0 +1,148 @@
+#![forbid(unsafe_code)]
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use egg_chain::mempool::Mempool;
+use egg_chain::state::SharedChainState;
+use egg_db::store::ChainStore;
+use egg_net::codec::MAX_FRAME_LEN;
+use egg_rpc::{decode_request, encode_response, RpcError, RpcMethod, RpcRequest, RpcResponse};
+
+use crate::{handle_request, submit_block, NodeError, Result, SESSION_IDLE_TIMEOUT};
+
+/// Số kết nối RPC phục vụ đồng thời mặc định; kết nối vượt mức bị đóng ngay.
+pub const DEFAULT_MAX_RPC_CONNS: usize = 32;
+
+/// Phần thân frame được cấp phát dần theo byte thực nhận, mỗi lần tối đa chừng này
+/// (độ dài frame do client khai, chưa tin được).
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Server RPC trên TCP: mỗi request / response là 1 frame `u32_be_len + JSON` (cùng framing
+/// với egg-net). Mỗi kết nối chạy 1 luồng, đọc-trả lời lần lượt tới khi client đóng.
+///
+/// Chỉ trả về khi `accept` lỗi; lỗi của từng kết nối (client ngắt, frame hỏng) không làm dừng server.
+pub fn serve<S: ChainStore + Clone + Send>(
+    listener: TcpListener,
+    state: &SharedChainState<S>,
+    mempool: &Mutex<Mempool>,
+) -> Result<()> {
+    serve_with_limit(listener, state, mempool, DEFAULT_MAX_RPC_CONNS)
+}
+
+/// Như `serve` nhưng tối đa `max_conns` kết nối cùng lúc (mỗi kết nối 1 luồng).
+pub fn serve_with_limit<S: ChainStore + Clone + Send>(
+    listener: TcpListener,
+    state: &SharedChainState<S>,
+    mempool: &Mutex<Mempool>,
+    max_conns: usize,
+) -> Result<()> {
+    let active = AtomicUsize::new(0);
+    let active = &active;
+    thread::scope(|scope| {
+        for conn in listener.incoming() {
+            let stream = conn?;
+            if active.fetch_add(1, Ordering::SeqCst) >= max_conns {
+                active.fetch_sub(1, Ordering::SeqCst);
+                continue; // drop => đóng kết nối
+            }
+            scope.spawn(move || {
+                let _ = serve_conn(stream, state, mempool);
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    })
+}
+
+fn serve_conn<S: ChainStore + Clone>(
+    mut stream: TcpStream,
+    state: &SharedChainState<S>,
+    mempool: &Mutex<Mempool>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(SESSION_IDLE_TIMEOUT))?;
+    loop {
+        let resp = match read_frame(&mut stream)? {
+            Frame::Closed => return Ok(()),
+            Frame::Payload(bytes) => match decode_request(&bytes) {
+                Ok(req) => dispatch(state, mempool, &req),
+                Err(e) => invalid_request(e.to_string()),
+            },
+            Frame::TooLarge(len) => {
+                // không đọc phần thân => mất đồng bộ frame, trả lỗi rồi đóng
+                write_frame(&mut stream, &invalid_request(format!("frame too large: {} bytes", len)))?;
+                return Ok(());
+            }
+        };
+        write_frame(&mut stream, &resp)?;
+    }
+}
+
+/// `SubmitBlock` cần ghi chain nên đi qua `submit_block`; còn lại qua `handle_request`.
+fn dispatch<S: ChainStore + Clone>(
+    state: &SharedChainState<S>,
+    mempool: &Mutex<Mempool>,
+    req: &RpcRequest,
+) -> RpcResponse {
+    if let RpcMethod::SubmitBlock { block } = &req.method {
+        let mut st = state.lock();
+        return match submit_block(&mut st, block.clone()) {
+            Ok(result) => RpcResponse::Ok { id: req.id, result },
+            Err(error) => RpcResponse::Err { id: req.id, error },
+        };
+    }
+    let st = state.lock();
+    let mut mp = mempool.lock().expect("mutex poisoned");
+    handle_request(&st, &mut mp, req)
+}
+
+/// id của request hỏng không đọc được => trả id 0.
+fn invalid_request(message: String) -> RpcResponse {
+    RpcResponse::Err {
+        id: 0,
+        error: RpcError {
+            code: egg_rpc::RPC_ERR_INVALID_REQUEST,
+            message,
+        },
+    }
+}
+
+enum Frame {
+    /// client đóng kết nối giữa 2 frame
+    Closed,
+    TooLarge(u32),
+    Payload(Vec<u8>),
+}
+
+fn read_frame(stream: &mut TcpStream) -> Result<Frame> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(Frame::Closed),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len_buf);
+    if len > MAX_FRAME_LEN {
+        return Ok(Frame::TooLarge(len));
+    }
+    // không cấp phát trước cả `len`: client khai 8 MiB rồi gửi ít byte không chiếm được bộ nhớ
+    let len = len as usize;
+    let mut payload = Vec::with_capacity(len.min(READ_CHUNK));
+    Read::take(&mut *stream, len as u64).read_to_end(&mut payload)?;
+    if payload.len() != len {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Frame::Payload(payload))
+}
+
+fn write_frame(stream: &mut TcpStream, resp: &RpcResponse) -> Result<()> {
+    let payload = encode_response(resp).map_err(|e| NodeError::Protocol(e.to_string()))?;
+    let mut out = Vec::with_capacity(4 + payload.len());
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(&payload);
+    stream.write_all(&out)?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+
+use egg_chain::mempool::Mempool;
+use egg_chain::state::{ChainState, SharedChainState};
+use egg_db::store::DbChainStore;
+use egg_db::MemKv;
+use std::time::{Duration, Instant};
+
+use egg_crypto::{hash_header, merkle::merkle_root_txids};
+use egg_rpc::{
+    decode_response, encode_request, RpcMethod, RpcRequest, RpcResponse, RpcResult, SubmitBlockOutcome,
+};
+use egg_types::{Block, BlockHeader, ChainParams, ChainSpec, GenesisSpec, Height};
+
+fn mk_spec() -> ChainSpec {
+    ChainSpec {
+        spec_version: 1,
+        chain: ChainParams {
+            chain_name: "EGG-MAINNET".to_string(),
+            chain_id: 1,
+        },
+        genesis: GenesisSpec {
+            timestamp_utc: 1_700_000_000,
+            pow_difficulty_bits: 0,
+            nonce: 0,
+        },
+    }
+}
+
+fn send_raw(stream: &mut TcpStream, payload: &[u8]) {
+    stream.write_all(&(payload.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(payload).unwrap();
+}
+
+fn recv(stream: &mut TcpStream) -> RpcResponse {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut payload = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    decode_response(&payload).unwrap()
+}
+
+#[test]
+fn serve_answers_get_tip_over_tcp() {
+    let st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec()).unwrap();
+    let tip = st.tip;
+    let shared = SharedChainState::new(st);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mempool = Mutex::new(Mempool::new());
+        let _ = egg_node::rpc::serve(listener, &shared, &mempool);
+    });
+
+    // client ngắt giữa chừng frame không làm server dừng
+    {
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.write_all(&[0, 0, 0, 50, b'{']).unwrap();
+    }
+
+    let mut s = TcpStream::connect(addr).unwrap();
+    let req = RpcRequest {
+        id: 42,
+        method: RpcMethod::GetTip,
+    };
+    send_raw(&mut s, &encode_request(&req).unwrap());
+    assert_eq!(
+        recv(&mut s),
+        RpcResponse::Ok {
+            id: 42,
+            result: RpcResult::Tip {
+                height: tip.height.0,
+                hash: tip.hash,
+            },
+        }
+    );
+
+    // JSON hỏng => RpcResponse::Err, kết nối vẫn dùng tiếp được
+    send_raw(&mut s, b"not json");
+    let RpcResponse::Err { error, .. } = recv(&mut s) else {
+        panic!("expected error response");
+    };
+    assert_eq!(error.code, egg_rpc::RPC_ERR_INVALID_REQUEST);
+
+    send_raw(&mut s, &encode_request(&req).unwrap());
+    assert!(matches!(recv(&mut s), RpcResponse::Ok { id: 42, .. }));
+}
+
+#[test]
+fn serve_routes_submit_block_to_chain() {
+    let st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec()).unwrap();
+    let parent = st.tip.hash;
+    let shared = SharedChainState::new(st);
+    let server_state = shared.clone();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mempool = Mutex::new(Mempool::new());
+        let _ = egg_node::rpc::serve(listener, &server_state, &mempool);
+    });
+
+    let header = BlockHeader {
+        parent,
+        height: Height(1),
+        timestamp_utc: 1_700_000_000,
+        nonce: 1,
+        merkle_root: merkle_root_txids(&[]),
+        pow_difficulty_bits: 0,
+    };
+    let id = hash_header(&header);
+    let req = RpcRequest {
+        id: 7,
+        method: RpcMethod::SubmitBlock {
+            block: Block { header, txs: vec![] },
+        },
+    };
+    let mut s = TcpStream::connect(addr).unwrap();
+    send_raw(&mut s, &encode_request(&req).unwrap());
+    assert_eq!(
+        recv(&mut s),
+        RpcResponse::Ok {
+            id: 7,
+            result: RpcResult::SubmitBlock {
+                id,
+                outcome: SubmitBlockOutcome::NewTip,
+            },
+        }
+    );
+    assert_eq!(shared.tip().hash, id);
+}
+
+#[test]
+fn serve_with_limit_closes_connections_over_the_cap() {
+    let st = ChainState::open_or_init(DbChainStore::new(MemKv::new()), mk_spec()).unwrap();
+    let shared = SharedChainState::new(st);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mempool = Mutex::new(Mempool::new());
+        let _ = egg_node::rpc::serve_with_limit(listener, &shared, &mempool, 1);
+    });
+    let req = encode_request(&RpcRequest {
+        id: 1,
+        method: RpcMethod::GetTip,
+    })
+    .unwrap();
+
+    let mut first = TcpStream::connect(addr).unwrap();
+    send_raw(&mut first, &req);
+    assert!(matches!(recv(&mut first), RpcResponse::Ok { .. }));
+
+    // kết nối thứ 2 bị đóng không trả lời
+    let mut second = TcpStream::connect(addr).unwrap();
+    second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let _ = second.write_all(&req);
+    let mut buf = [0u8; 1];
+    assert!(matches!(second.read(&mut buf), Ok(0) | Err(_)));
+
+    // kết nối đầu đóng => nhận kết nối mới
+    drop(first);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let mut s = TcpStream::connect(addr).unwrap();
+        s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        // server có thể đóng socket trước khi slot được trả => ghi lỗi cũng là thử lại
+        let sent = s
+            .write_all(&(req.len() as u32).to_be_bytes())
+            .and_then(|_| s.write_all(&req));
+        let mut len = [0u8; 4];
+        if sent.is_ok() && s.read_exact(&mut len).is_ok() {
+            break;
+        }
+        assert!(Instant::now() < deadline, "slot never freed");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
@@ -1,16 +1,20 @@
 #![forbid(unsafe_code)]
 
+use egg_types::{Block, BlockHeader, Hash256};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum RpcCodecError {
     Json(serde_json::Error),
+    /// JSON hợp lệ nhưng sai envelope JSON-RPC 2.0.
+    JsonRpc(String),
 }
 
 impl core::fmt::Display for RpcCodecError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             RpcCodecError::Json(e) => write!(f, "json: {}", e),
+            RpcCodecError::JsonRpc(e) => write!(f, "json-rpc: {}", e),
         }
     }
 }
@@ -25,10 +29,146 @@ impl From<serde_json::Error> for RpcCodecError {
 
 pub type Result<T> = core::result::Result<T, RpcCodecError>;
 
+/// Frame / JSON request không decode được.
+pub const RPC_ERR_INVALID_REQUEST: i32 = 4000;
+/// Mã lỗi `submit_block`.
+pub const RPC_ERR_INVALID_POW: i32 = 4101;
+pub const RPC_ERR_INVALID_MERKLE: i32 = 4102;
+/// Block được lưu nhưng parent chưa biết => không nối được vào chain.
+pub const RPC_ERR_ORPHAN_BLOCK: i32 = 4103;
+/// Vi phạm consensus khác (height, độ khó, coinbase...).
+pub const RPC_ERR_INVALID_BLOCK: i32 = 4104;
+/// `get_block_*` / `get_header_by_id`: node không có block / header được hỏi.
+pub const RPC_ERR_BLOCK_NOT_FOUND: i32 = 4105;
+/// Method cần ngữ cảnh dispatcher không có (peer, quyền ghi chain...).
+pub const RPC_ERR_METHOD_UNAVAILABLE: i32 = 4106;
+/// Mã lỗi `submit_tx` (mempool từ chối tx).
+pub const RPC_ERR_INVALID_TX_ID: i32 = 4201;
+pub const RPC_ERR_MEMPOOL_FULL: i32 = 4202;
+pub const RPC_ERR_TX_TOO_LARGE: i32 = 4203;
+/// Lý do khác (fee thấp, replace không đủ fee...).
+pub const RPC_ERR_TX_REJECTED: i32 = 4204;
+pub const RPC_ERR_INTERNAL: i32 = 5000;
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RpcMethod {
     PeerHealth,
+    ChainStatus,
+    /// Độ khó hiện tại và độ khó bắt buộc cho block kế tiếp.
+    GetDifficulty,
+    /// chain id / genesis / tip (xem `ChainInfo`).
+    GetChainInfo,
+    GetTip,
+    /// Block theo id, kể cả block ở nhánh phụ (xem `RpcResult::Block::on_best_chain`).
+    /// Nhận cả tên cũ `get_block`.
+    #[serde(alias = "get_block")]
+    GetBlockById { id: Hash256 },
+    /// Header theo id; có cả khi node mới sync header chưa có body.
+    GetHeaderById { id: Hash256 },
+    /// Block canonical tại `height`.
+    GetBlockByHeight { height: u64 },
+    /// Block đã đào xong từ pool/miner ngoài; JSON = hex của bytes canonical.
+    SubmitBlock {
+        #[serde(with = "block_hex")]
+        block: Block,
+    },
+    /// Tx từ wallet vào mempool; txid do node tính từ payload. JSON = base64 của payload.
+    SubmitTx {
+        #[serde(with = "payload_b64")]
+        payload: Vec<u8>,
+    },
+}
+
+/// Base64 chuẩn (RFC 4648, có padding).
+mod payload_b64 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    pub fn encode(bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(s: &str) -> Option<Vec<u8>> {
+        let s = s.as_bytes();
+        if s.len() % 4 != 0 {
+            return None;
+        }
+        let mut out = Vec::with_capacity(s.len() / 4 * 3);
+        for (ci, chunk) in s.chunks(4).enumerate() {
+            let last = ci + 1 == s.len() / 4;
+            let pad = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+            if pad > 2 || (pad > 0 && !last) {
+                return None;
+            }
+            let mut n = 0u32;
+            for &c in &chunk[..4 - pad] {
+                let v = ALPHABET.iter().position(|&a| a == c)? as u32;
+                n = (n << 6) | v;
+            }
+            n <<= 6 * pad as u32;
+            let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+            out.extend_from_slice(&bytes[..3 - pad]);
+        }
+        Some(out)
+    }
+
+    pub fn serialize<S: Serializer>(payload: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&encode(payload))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        decode(&s).ok_or_else(|| D::Error::custom("invalid base64"))
+    }
+}
+
+mod block_hex {
+    use egg_types::canonical::{decode_block_strict, encode_block};
+    use egg_types::Block;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(block: &Block, serializer: S) -> Result<S::Ok, S::Error> {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let bytes = encode_block(block);
+        let mut out = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            out.push(DIGITS[(b >> 4) as usize] as char);
+            out.push(DIGITS[(b & 0x0f) as usize] as char);
+        }
+        serializer.serialize_str(&out)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Block, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 {
+            return Err(D::Error::custom("odd hex length"));
+        }
+        let bytes = s
+            .as_bytes()
+            .chunks(2)
+            .map(|p| {
+                let hi = (p[0] as char).to_digit(16)?;
+                let lo = (p[1] as char).to_digit(16)?;
+                Some((hi * 16 + lo) as u8)
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| D::Error::custom("invalid hex char"))?;
+        decode_block_strict(&bytes).map_err(D::Error::custom)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -70,10 +210,106 @@ impl PeerHealth {
     }
 }
 
+/// Trạng thái chain cho monitoring; `tip_stale` = tip quá cũ so với thời điểm hỏi.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainStatus {
+    pub tip_height: u64,
+    pub tip_timestamp_utc: i64,
+    pub tip_stale: bool,
+}
+
+impl ChainStatus {
+    pub fn new(tip_height: u64, tip_timestamp_utc: i64, tip_stale: bool) -> Self {
+        Self {
+            tip_height,
+            tip_timestamp_utc,
+            tip_stale,
+        }
+    }
+}
+
+/// Độ khó cho miner; `blocks_until_retarget` = `None` nếu node không bật retarget.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DifficultyInfo {
+    pub current_bits: u32,
+    pub next_required_bits: u32,
+    pub blocks_until_retarget: Option<u64>,
+}
+
+impl DifficultyInfo {
+    pub fn new(current_bits: u32, next_required_bits: u32, blocks_until_retarget: Option<u64>) -> Self {
+        Self {
+            current_bits,
+            next_required_bits,
+            blocks_until_retarget,
+        }
+    }
+}
+
+/// Thông tin chain cho wallet: chain nào + tip hiện tại.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainInfo {
+    pub chain_name: String,
+    pub chain_id: u32,
+    pub genesis_id: Hash256,
+    pub tip_height: u64,
+    pub tip_hash: Hash256,
+    pub tip_timestamp_utc: i64,
+}
+
+impl ChainInfo {
+    pub fn new(
+        chain_name: String,
+        chain_id: u32,
+        genesis_id: Hash256,
+        tip_height: u64,
+        tip_hash: Hash256,
+        tip_timestamp_utc: i64,
+    ) -> Self {
+        Self {
+            chain_name,
+            chain_id,
+            genesis_id,
+            tip_height,
+            tip_hash,
+            tip_timestamp_utc,
+        }
+    }
+}
+
+/// Kết quả nhận block (`IngestOutcome` của egg-chain; orphan trả lỗi `RPC_ERR_ORPHAN_BLOCK`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmitBlockOutcome {
+    AlreadyKnown,
+    StoredConnected,
+    NewTip,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "method", content = "data", rename_all = "snake_case")]
 pub enum RpcResult {
     PeerHealth(PeerHealth),
+    ChainStatus(ChainStatus),
+    GetDifficulty(DifficultyInfo),
+    ChainInfo(ChainInfo),
+    Tip { height: u64, hash: Hash256 },
+    SubmitBlock { id: Hash256, outcome: SubmitBlockOutcome },
+    /// `accepted` = false nếu mempool đã có tx này (không phải lỗi).
+    SubmitTx { txid: Hash256, accepted: bool },
+    /// `confirmations` = `None` khi block nằm ở nhánh phụ (`on_best_chain` = false).
+    Block {
+        #[serde(with = "block_hex")]
+        block: Block,
+        on_best_chain: bool,
+        confirmations: Option<u64>,
+    },
+    /// Như `Block` nhưng chỉ header.
+    Header {
+        header: BlockHeader,
+        on_best_chain: bool,
+        confirmations: Option<u64>,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -99,6 +335,93 @@ pub fn decode_response(bytes: &[u8]) -> Result<RpcResponse> {
     Ok(serde_json::from_slice(bytes)?)
 }
 
+const JSONRPC_VERSION: &str = "2.0";
+
+#[derive(Serialize, Deserialize)]
+struct JsonRpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    params: serde_json::Value,
+    id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonRpcResponse {
+    jsonrpc: String,
+    /// `null` khi server không đọc được id của request
+    id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<RpcResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+fn check_jsonrpc_version(v: &str) -> Result<()> {
+    if v != JSONRPC_VERSION {
+        return Err(RpcCodecError::JsonRpc(format!("unsupported jsonrpc version {:?}", v)));
+    }
+    Ok(())
+}
+
+/// Envelope JSON-RPC 2.0 cho client có sẵn. `method` = tên snake_case của `RpcMethod`,
+/// `params` = field của variant (bỏ qua nếu không có). `result` giữ nguyên dạng tagged của
+/// `RpcResult` nên 2 format dùng chung 1 bộ serde.
+pub fn encode_request_jsonrpc(req: &RpcRequest) -> Result<Vec<u8>> {
+    // unit variant => "name"; variant có field => {"name": {...}}
+    let (method, params) = match serde_json::to_value(&req.method)? {
+        serde_json::Value::String(name) => (name, serde_json::Value::Null),
+        serde_json::Value::Object(m) if m.len() == 1 => m.into_iter().next().expect("len 1"),
+        v => return Err(RpcCodecError::JsonRpc(format!("unexpected method encoding {}", v))),
+    };
+    Ok(serde_json::to_vec(&JsonRpcRequest {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        method,
+        params,
+        id: req.id,
+    })?)
+}
+
+pub fn decode_request_jsonrpc(bytes: &[u8]) -> Result<RpcRequest> {
+    let env: JsonRpcRequest = serde_json::from_slice(bytes)?;
+    check_jsonrpc_version(&env.jsonrpc)?;
+    let tagged = match env.params {
+        serde_json::Value::Null => serde_json::Value::String(env.method),
+        params => serde_json::Value::Object([(env.method, params)].into_iter().collect()),
+    };
+    Ok(RpcRequest {
+        id: env.id,
+        method: serde_json::from_value(tagged)?,
+    })
+}
+
+pub fn encode_response_jsonrpc(resp: &RpcResponse) -> Result<Vec<u8>> {
+    let (id, result, error) = match resp {
+        RpcResponse::Ok { id, result } => (*id, Some(result.clone()), None),
+        RpcResponse::Err { id, error } => (*id, None, Some(error.clone())),
+    };
+    Ok(serde_json::to_vec(&JsonRpcResponse {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        id: Some(id),
+        result,
+        error,
+    })?)
+}
+
+/// Id `null` (server không đọc được request) => 0, như `RpcResponse` của server egg-node.
+pub fn decode_response_jsonrpc(bytes: &[u8]) -> Result<RpcResponse> {
+    let env: JsonRpcResponse = serde_json::from_slice(bytes)?;
+    check_jsonrpc_version(&env.jsonrpc)?;
+    let id = env.id.unwrap_or(0);
+    match (env.result, env.error) {
+        (Some(result), None) => Ok(RpcResponse::Ok { id, result }),
+        (None, Some(error)) => Ok(RpcResponse::Err { id, error }),
+        _ => Err(RpcCodecError::JsonRpc(
+            "response must have exactly one of result / error".to_string(),
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,6 +451,37 @@ mod tests {
         assert_eq!(got, resp);
     }
 
+    #[test]
+    fn response_ok_chain_status_roundtrip_json() {
+        let resp = RpcResponse::Ok {
+            id: 8,
+            result: RpcResult::ChainStatus(ChainStatus::new(42, 1_700_000_000, true)),
+        };
+
+        let bytes = encode_response(&resp).unwrap();
+        let got = decode_response(&bytes).unwrap();
+        assert_eq!(got, resp);
+    }
+
+    #[test]
+    fn get_difficulty_roundtrip_json() {
+        let req = RpcRequest {
+            id: 10,
+            method: RpcMethod::GetDifficulty,
+        };
+        let bytes = encode_request(&req).unwrap();
+        assert_eq!(decode_request(&bytes).unwrap(), req);
+
+        for info in [DifficultyInfo::new(0x1d00_ffff, 0x1d00_ffff, None), DifficultyInfo::new(3, 4, Some(0))] {
+            let resp = RpcResponse::Ok {
+                id: 10,
+                result: RpcResult::GetDifficulty(info),
+            };
+            let bytes = encode_response(&resp).unwrap();
+            assert_eq!(decode_response(&bytes).unwrap(), resp);
+        }
+    }
+
     #[test]
     fn response_err_roundtrip_json() {
         let resp = RpcResponse::Err {
@@ -142,4 +496,289 @@ mod tests {
         let got = decode_response(&bytes).unwrap();
         assert_eq!(got, resp);
     }
+
+    fn sample_block() -> Block {
+        use egg_types::{BlockHeader, Height, Transaction, CONTENT_TAG_OPAQUE};
+        Block {
+            header: BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(3),
+                timestamp_utc: 1_700_000_000,
+                nonce: 77,
+                merkle_root: Hash256([2u8; 32]),
+                pow_difficulty_bits: 0x2000_ffff,
+            },
+            txs: vec![Transaction {
+                id: Hash256([3u8; 32]),
+                payload: b"coinbase".to_vec(),
+                content_tag: CONTENT_TAG_OPAQUE,
+            }],
+        }
+    }
+
+    #[test]
+    fn submit_block_request_roundtrip_json() {
+        let block = sample_block();
+        let req = RpcRequest {
+            id: 9,
+            method: RpcMethod::SubmitBlock { block: block.clone() },
+        };
+
+        let bytes = encode_request(&req).unwrap();
+        // block nằm trong JSON dưới dạng hex của bytes canonical
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let hex = v["method"]["submit_block"]["block"].as_str().unwrap();
+        assert_eq!(hex.len(), egg_types::canonical::encoded_block_len(&block) * 2);
+        assert_eq!(decode_request(&bytes).unwrap(), req);
+
+        let bad = String::from_utf8(bytes).unwrap().replace(hex, &format!("{hex}00"));
+        assert!(decode_request(bad.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn get_block_by_id_roundtrip_json() {
+        let req = RpcRequest {
+            id: 11,
+            method: RpcMethod::GetBlockById { id: Hash256([4u8; 32]) },
+        };
+        let bytes = encode_request(&req).unwrap();
+        assert_eq!(decode_request(&bytes).unwrap(), req);
+        // client cũ gửi `get_block`
+        let legacy = String::from_utf8(bytes).unwrap().replace("get_block_by_id", "get_block");
+        assert_eq!(decode_request(legacy.as_bytes()).unwrap(), req);
+
+        for (on_best_chain, confirmations) in [(true, Some(3)), (false, None)] {
+            let resp = RpcResponse::Ok {
+                id: 11,
+                result: RpcResult::Block {
+                    block: sample_block(),
+                    on_best_chain,
+                    confirmations,
+                },
+            };
+            let bytes = encode_response(&resp).unwrap();
+            assert_eq!(decode_response(&bytes).unwrap(), resp);
+        }
+    }
+
+    #[test]
+    fn submit_block_responses_roundtrip_json() {
+        let ok = RpcResponse::Ok {
+            id: 9,
+            result: RpcResult::SubmitBlock {
+                id: Hash256([5u8; 32]),
+                outcome: SubmitBlockOutcome::NewTip,
+            },
+        };
+        let bytes = encode_response(&ok).unwrap();
+        assert_eq!(decode_response(&bytes).unwrap(), ok);
+
+        let rejected = RpcResponse::Err {
+            id: 9,
+            error: RpcError {
+                code: RPC_ERR_INVALID_POW,
+                message: "invalid pow for header".to_string(),
+            },
+        };
+        let bytes = encode_response(&rejected).unwrap();
+        assert_eq!(decode_response(&bytes).unwrap(), rejected);
+    }
+
+    fn roundtrip(req: RpcRequest, result: RpcResult) {
+        let bytes = encode_request(&req).unwrap();
+        assert_eq!(decode_request(&bytes).unwrap(), req);
+        let resp = RpcResponse::Ok { id: req.id, result };
+        let bytes = encode_response(&resp).unwrap();
+        assert_eq!(decode_response(&bytes).unwrap(), resp);
+    }
+
+    #[test]
+    fn get_chain_info_roundtrip_json() {
+        let info = ChainInfo::new(
+            "EGG-MAINNET".to_string(),
+            1,
+            Hash256([6u8; 32]),
+            42,
+            Hash256([7u8; 32]),
+            1_700_000_000,
+        );
+        roundtrip(
+            RpcRequest {
+                id: 12,
+                method: RpcMethod::GetChainInfo,
+            },
+            RpcResult::ChainInfo(info),
+        );
+    }
+
+    #[test]
+    fn get_tip_roundtrip_json() {
+        roundtrip(
+            RpcRequest {
+                id: 13,
+                method: RpcMethod::GetTip,
+            },
+            RpcResult::Tip {
+                height: 42,
+                hash: Hash256([7u8; 32]),
+            },
+        );
+    }
+
+    #[test]
+    fn get_header_by_id_roundtrip_json() {
+        for (on_best_chain, confirmations) in [(true, Some(1)), (false, None)] {
+            roundtrip(
+                RpcRequest {
+                    id: 14,
+                    method: RpcMethod::GetHeaderById { id: Hash256([8u8; 32]) },
+                },
+                RpcResult::Header {
+                    header: sample_block().header,
+                    on_best_chain,
+                    confirmations,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn get_block_by_height_roundtrip_json() {
+        roundtrip(
+            RpcRequest {
+                id: 15,
+                method: RpcMethod::GetBlockByHeight { height: 3 },
+            },
+            RpcResult::Block {
+                block: sample_block(),
+                on_best_chain: true,
+                confirmations: Some(1),
+            },
+        );
+    }
+
+    #[test]
+    fn base64_matches_rfc4648_vectors() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ];
+        for (raw, enc) in cases {
+            assert_eq!(payload_b64::encode(raw), enc);
+            assert_eq!(payload_b64::decode(enc).as_deref(), Some(raw));
+        }
+        for bad in ["Zg=", "Z===", "Zg==Zm9v", "Zm9*"] {
+            assert_eq!(payload_b64::decode(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn submit_tx_roundtrip_json() {
+        let payload = vec![0u8, 1, 2, 250, 251, 255, 7];
+        let req = RpcRequest {
+            id: 16,
+            method: RpcMethod::SubmitTx { payload: payload.clone() },
+        };
+        let bytes = encode_request(&req).unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(v["method"]["submit_tx"]["payload"], "AAEC+vv/Bw==");
+        assert_eq!(decode_request(&bytes).unwrap(), req);
+
+        for accepted in [true, false] {
+            let resp = RpcResponse::Ok {
+                id: 16,
+                result: RpcResult::SubmitTx {
+                    txid: Hash256([9u8; 32]),
+                    accepted,
+                },
+            };
+            let bytes = encode_response(&resp).unwrap();
+            assert_eq!(decode_response(&bytes).unwrap(), resp);
+        }
+    }
+
+    #[test]
+    fn jsonrpc_request_roundtrip_and_envelope_shape() {
+        let reqs = [
+            RpcRequest {
+                id: 1,
+                method: RpcMethod::GetTip,
+            },
+            RpcRequest {
+                id: 2,
+                method: RpcMethod::GetBlockByHeight { height: 9 },
+            },
+            RpcRequest {
+                id: 3,
+                method: RpcMethod::SubmitTx { payload: b"tx".to_vec() },
+            },
+        ];
+        for req in reqs {
+            let bytes = encode_request_jsonrpc(&req).unwrap();
+            assert_eq!(decode_request_jsonrpc(&bytes).unwrap(), req);
+        }
+
+        let bytes = encode_request_jsonrpc(&RpcRequest {
+            id: 2,
+            method: RpcMethod::GetBlockByHeight { height: 9 },
+        })
+        .unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            v,
+            serde_json::json!({"jsonrpc": "2.0", "method": "get_block_by_height", "params": {"height": 9}, "id": 2})
+        );
+
+        // request do client ngoài viết tay, không có params
+        let got = decode_request_jsonrpc(br#"{"jsonrpc":"2.0","method":"get_chain_info","id":5}"#).unwrap();
+        assert_eq!(got.method, RpcMethod::GetChainInfo);
+        assert!(decode_request_jsonrpc(br#"{"jsonrpc":"1.0","method":"get_tip","id":5}"#).is_err());
+        assert!(decode_request_jsonrpc(br#"{"jsonrpc":"2.0","method":"no_such","id":5}"#).is_err());
+    }
+
+    #[test]
+    fn jsonrpc_error_response_maps_onto_rpc_error() {
+        let raw = br#"{"jsonrpc":"2.0","id":7,"error":{"code":4105,"message":"block not found"}}"#;
+        assert_eq!(
+            decode_response_jsonrpc(raw).unwrap(),
+            RpcResponse::Err {
+                id: 7,
+                error: RpcError {
+                    code: RPC_ERR_BLOCK_NOT_FOUND,
+                    message: "block not found".to_string(),
+                },
+            }
+        );
+        let raw = br#"{"jsonrpc":"2.0","id":null,"error":{"code":4000,"message":"bad"}}"#;
+        assert!(matches!(
+            decode_response_jsonrpc(raw).unwrap(),
+            RpcResponse::Err { id: 0, error } if error.code == RPC_ERR_INVALID_REQUEST
+        ));
+        assert!(decode_response_jsonrpc(br#"{"jsonrpc":"2.0","id":1}"#).is_err());
+
+        for resp in [
+            RpcResponse::Ok {
+                id: 8,
+                result: RpcResult::Tip {
+                    height: 3,
+                    hash: Hash256([1u8; 32]),
+                },
+            },
+            RpcResponse::Err {
+                id: 9,
+                error: RpcError {
+                    code: RPC_ERR_INTERNAL,
+                    message: "boom".to_string(),
+                },
+            },
+        ] {
+            let bytes = encode_response_jsonrpc(&resp).unwrap();
+            assert_eq!(decode_response_jsonrpc(&bytes).unwrap(), resp);
+        }
+    }
 }
@@ -4,13 +4,85 @@ use serde::{Deserialize, Serialize};
 
 pub const HASH256_LEN: usize = 32;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/// Format human-readable (JSON...) => chuỗi hex 64 ký tự thường; format binary => `[u8; 32]` như cũ.
+/// Thứ tự (`Ord`) = so từng byte từ byte 0 (big-endian, như so chuỗi hex); fork-choice
+/// chọn hash nhỏ hơn khi bằng work và height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Hash256(pub [u8; HASH256_LEN]);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HexError {
+    InvalidLength { len: usize },
+    InvalidChar { at: usize },
+}
+
+impl core::fmt::Display for HexError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HexError::InvalidLength { len } => {
+                write!(f, "invalid hex length {} (expected {})", len, HASH256_LEN * 2)
+            }
+            HexError::InvalidChar { at } => write!(f, "invalid hex char at {}", at),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
 impl Hash256 {
     pub fn zero() -> Self {
         Self([0u8; HASH256_LEN])
     }
+
+    pub fn to_hex(&self) -> String {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut out = String::with_capacity(HASH256_LEN * 2);
+        for b in self.0 {
+            out.push(DIGITS[(b >> 4) as usize] as char);
+            out.push(DIGITS[(b & 0x0f) as usize] as char);
+        }
+        out
+    }
+
+    /// Nhận đúng 64 ký tự hex (hoa hoặc thường), không prefix.
+    pub fn from_hex(s: &str) -> Result<Self, HexError> {
+        let bytes = s.as_bytes();
+        if bytes.len() != HASH256_LEN * 2 {
+            return Err(HexError::InvalidLength { len: bytes.len() });
+        }
+        let nibble = |at: usize| {
+            (bytes[at] as char)
+                .to_digit(16)
+                .map(|d| d as u8)
+                .ok_or(HexError::InvalidChar { at })
+        };
+        let mut out = [0u8; HASH256_LEN];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = (nibble(2 * i)? << 4) | nibble(2 * i + 1)?;
+        }
+        Ok(Self(out))
+    }
+}
+
+impl Serialize for Hash256 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash256 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            Hash256::from_hex(&s).map_err(serde::de::Error::custom)
+        } else {
+            <[u8; HASH256_LEN]>::deserialize(deserializer).map(Hash256)
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -26,11 +98,57 @@ pub struct BlockHeader {
     pub pow_difficulty_bits: u32,
 }
 
+/// Tag 1 byte đầu tx-body cho biết loại payload. 0 = opaque (mặc định, tương thích ngược).
+pub const CONTENT_TAG_OPAQUE: u8 = 0;
+pub const CONTENT_TAG_TRANSFER: u8 = 1;
+pub const CONTENT_TAG_CONTRACT_CALL: u8 = 2;
+pub const CONTENT_TAG_DATA_BLOB: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentType {
+    Opaque,
+    Transfer,
+    ContractCall,
+    DataBlob,
+    Unknown(u8),
+}
+
+impl ContentType {
+    pub fn from_tag(tag: u8) -> Self {
+        match tag {
+            CONTENT_TAG_OPAQUE => ContentType::Opaque,
+            CONTENT_TAG_TRANSFER => ContentType::Transfer,
+            CONTENT_TAG_CONTRACT_CALL => ContentType::ContractCall,
+            CONTENT_TAG_DATA_BLOB => ContentType::DataBlob,
+            other => ContentType::Unknown(other),
+        }
+    }
+
+    pub fn tag(self) -> u8 {
+        match self {
+            ContentType::Opaque => CONTENT_TAG_OPAQUE,
+            ContentType::Transfer => CONTENT_TAG_TRANSFER,
+            ContentType::ContractCall => CONTENT_TAG_CONTRACT_CALL,
+            ContentType::DataBlob => CONTENT_TAG_DATA_BLOB,
+            ContentType::Unknown(t) => t,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Transaction {
-    /// TxID theo chuẩn: hash của tx-body (payload) canonical-encoded, không chứa `id`.
+    /// TxID theo chuẩn: hash của tx-body (content tag + payload) canonical-encoded, không chứa `id`.
     pub id: Hash256,
     pub payload: Vec<u8>,
+    /// Content-type tag nằm trong tx-body nên được bind vào txid.
+    #[serde(default)]
+    pub content_tag: u8,
+}
+
+impl Transaction {
+    pub fn content_type(&self) -> ContentType {
+        ContentType::from_tag(self.content_tag)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -39,6 +157,12 @@ pub struct Block {
     pub txs: Vec<Transaction>,
 }
 
+/// Nguồn header theo thứ tự chain, bắt đầu sau `start` (peer, store local...).
+/// Đặt ở đây để egg-chain (sync) và egg-net (trả `GetHeaders`) dùng chung không phụ thuộc nhau.
+pub trait HeaderProvider {
+    fn get_headers_after(&self, start: Hash256, max: usize) -> Vec<BlockHeader>;
+}
+
 /// ChainSpec định nghĩa tham số mạng + genesis.
 /// Mainnet_Official_Start = thời điểm genesis block (timestamp_utc, UTC).
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -64,12 +188,18 @@ pub struct GenesisSpec {
 
 pub mod canonical {
     use super::{
-        Block, BlockHeader, ChainSpec, GenesisSpec, Hash256, Height, Transaction, HASH256_LEN,
+        Block, BlockHeader, ChainSpec, GenesisSpec, Hash256, Height, Transaction,
+        CONTENT_TAG_OPAQUE, HASH256_LEN,
     };
 
     const MAGIC_HDR: [u8; 8] = *b"EGG_HDR0";
+    // header mở rộng: major(u8) + minor(u8) + field cố định + extra_len(u32) + extra bytes
+    const MAGIC_HDRX: [u8; 8] = *b"EGG_HDRX";
     const MAGIC_TX: [u8; 8] = *b"EGG_TX0\0";
+    // v1: có content tag (chỉ dùng khi tag != opaque, để txid của tx opaque không đổi)
+    const MAGIC_TX1: [u8; 8] = *b"EGG_TX1\0";
     const MAGIC_TBD: [u8; 8] = *b"EGG_TBD0";
+    const MAGIC_TBD1: [u8; 8] = *b"EGG_TBD1";
     const MAGIC_BLK: [u8; 8] = *b"EGG_BLK0";
     const MAGIC_CSP: [u8; 8] = *b"EGG_CSP0";
 
@@ -79,6 +209,9 @@ pub mod canonical {
         InvalidMagic { at: usize },
         InvalidUtf8 { at: usize },
         LengthOverflow { at: usize },
+        UnsupportedVersion { at: usize, major: u8 },
+        LengthMismatch { at: usize, declared: usize, consumed: usize },
+        TrailingBytes { at: usize, remaining: usize },
     }
 
     impl core::fmt::Display for CanonicalError {
@@ -92,6 +225,17 @@ pub mod canonical {
                 CanonicalError::InvalidMagic { at } => write!(f, "invalid magic at {}", at),
                 CanonicalError::InvalidUtf8 { at } => write!(f, "invalid utf8 at {}", at),
                 CanonicalError::LengthOverflow { at } => write!(f, "length overflow at {}", at),
+                CanonicalError::LengthMismatch { at, declared, consumed } => write!(
+                    f,
+                    "length mismatch at {} (declared {}, consumed {})",
+                    at, declared, consumed
+                ),
+                CanonicalError::TrailingBytes { at, remaining } => {
+                    write!(f, "trailing bytes at {} ({} remaining)", at, remaining)
+                }
+                CanonicalError::UnsupportedVersion { at, major } => {
+                    write!(f, "unsupported major version {} at {}", major, at)
+                }
             }
         }
     }
@@ -128,6 +272,10 @@ pub mod canonical {
             Ok(out)
         }
 
+        fn take_u8(&mut self) -> Result<u8> {
+            Ok(self.take(1)?[0])
+        }
+
         fn take_u32_be(&mut self) -> Result<u32> {
             let b = self.take(4)?;
             Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
@@ -183,6 +331,17 @@ pub mod canonical {
         }
     }
 
+    /// Decoder strict: phải dùng hết input, byte thừa ở cuối => `TrailingBytes`.
+    fn ensure_consumed(total: usize, consumed: usize) -> Result<()> {
+        if consumed != total {
+            return Err(CanonicalError::TrailingBytes {
+                at: consumed,
+                remaining: total.saturating_sub(consumed),
+            });
+        }
+        Ok(())
+    }
+
     fn push_u32_be(out: &mut Vec<u8>, v: u32) {
         out.extend_from_slice(&v.to_be_bytes());
     }
@@ -213,18 +372,79 @@ pub mod canonical {
         // Fixed-size: 8 + 32 + 8 + 8 + 8 + 32 + 4 = 100 bytes
         let mut out = Vec::with_capacity(100);
         out.extend_from_slice(&MAGIC_HDR);
-        out.extend_from_slice(&h.parent.0);
-        push_u64_be(&mut out, h.height.0);
-        push_i64_be(&mut out, h.timestamp_utc);
-        push_u64_be(&mut out, h.nonce);
-        out.extend_from_slice(&h.merkle_root.0);
-        push_u32_be(&mut out, h.pow_difficulty_bits);
+        push_header_fields(&mut out, h);
         out
     }
 
-    pub fn decode_block_header(bytes: &[u8]) -> Result<BlockHeader> {
+    /// Định dạng header. V0 = 100 byte cố định (định nghĩa block id hiện tại).
+    /// V1 = mở rộng được: decoder cũ cùng major bỏ qua extra bytes ở cuối.
+    /// Chuyển `hash_header` sang V1 làm đổi block id => chỉ bật kèm consensus version mới.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum HeaderFormat {
+        V0,
+        V1 { minor: u8 },
+    }
+
+    pub const HEADER_V1_MAJOR: u8 = 1;
+
+    /// Header V1: field V0 + vùng `extra` (field tương lai) có độ dài tự mô tả.
+    pub fn encode_block_header_v1(h: &BlockHeader, minor: u8, extra: &[u8]) -> Vec<u8> {
+        let extra_len: u32 = extra.len().try_into().unwrap_or(u32::MAX);
+        let mut out = Vec::with_capacity(8 + 2 + 92 + 4 + extra.len());
+        out.extend_from_slice(&MAGIC_HDRX);
+        out.push(HEADER_V1_MAJOR);
+        out.push(minor);
+        push_header_fields(&mut out, h);
+        push_u32_be(&mut out, extra_len);
+        out.extend_from_slice(extra);
+        out
+    }
+
+    /// Decode cả V0 và V1; trả kèm format + extra bytes (rỗng với V0).
+    /// Chỉ dùng khi consensus đã bật V1 (chưa version nào bật): đường mạng / store dùng
+    /// `decode_block_header*` chỉ nhận V0, tránh cùng 1 header có nhiều dạng byte.
+    pub fn decode_block_header_ext(bytes: &[u8]) -> Result<(BlockHeader, HeaderFormat, Vec<u8>)> {
+        decode_block_header_prefix(bytes, true).map(|(out, _)| out)
+    }
+
+    type DecodedHeader = (BlockHeader, HeaderFormat, Vec<u8>);
+
+    /// Decode header ở đầu `bytes`, trả kèm số byte đã đọc.
+    /// `allow_v1 = false` => header V1 bị từ chối như major chưa hỗ trợ.
+    fn decode_block_header_prefix(bytes: &[u8], allow_v1: bool) -> Result<(DecodedHeader, usize)> {
         let mut c = Cursor::new(bytes);
-        c.expect_magic(&MAGIC_HDR)?;
+        let magic_at = c.pos;
+        let magic = c.take(8)?;
+        if magic == MAGIC_HDR {
+            let h = take_header_fields(&mut c)?;
+            return Ok(((h, HeaderFormat::V0, Vec::new()), c.pos));
+        }
+        if magic != MAGIC_HDRX {
+            return Err(CanonicalError::InvalidMagic { at: magic_at });
+        }
+
+        let major_at = c.pos;
+        let major = c.take_u8()?;
+        if major != HEADER_V1_MAJOR || !allow_v1 {
+            return Err(CanonicalError::UnsupportedVersion { at: major_at, major });
+        }
+        let minor = c.take_u8()?;
+        let h = take_header_fields(&mut c)?;
+        let extra_len = c.take_u32_be()? as usize;
+        let extra = c.take(extra_len)?.to_vec();
+        Ok(((h, HeaderFormat::V1 { minor }, extra), c.pos))
+    }
+
+    fn push_header_fields(out: &mut Vec<u8>, h: &BlockHeader) {
+        out.extend_from_slice(&h.parent.0);
+        push_u64_be(out, h.height.0);
+        push_i64_be(out, h.timestamp_utc);
+        push_u64_be(out, h.nonce);
+        out.extend_from_slice(&h.merkle_root.0);
+        push_u32_be(out, h.pow_difficulty_bits);
+    }
+
+    fn take_header_fields(c: &mut Cursor<'_>) -> Result<BlockHeader> {
         let parent = c.take_hash256()?;
         let height = Height(c.take_u64_be()?);
         let timestamp_utc = c.take_i64_be()?;
@@ -241,25 +461,71 @@ pub mod canonical {
         })
     }
 
+    /// Decode header V0; V1 bị từ chối (`UnsupportedVersion`) khi consensus chưa bật.
+    pub fn decode_block_header(bytes: &[u8]) -> Result<BlockHeader> {
+        decode_block_header_prefix(bytes, false).map(|((h, _, _), _)| h)
+    }
+
+    /// Như `decode_block_header` nhưng không chấp nhận byte thừa sau header.
+    pub fn decode_block_header_strict(bytes: &[u8]) -> Result<BlockHeader> {
+        let ((h, _, _), consumed) = decode_block_header_prefix(bytes, false)?;
+        ensure_consumed(bytes.len(), consumed)?;
+        Ok(h)
+    }
+
     // ---------------- Transaction (wire/storage) ----------------
     // encode_tx bao gồm `id` + `payload` (để truyền/lưu có thể verify).
     // TxID chuẩn phải dùng encode_tx_body (không chứa id).
+    // Tx opaque giữ format v0; tx có content tag dùng format v1 (thêm 1 byte tag sau id).
+
+    /// `encode_tx(tx).len()` mà không encode.
+    pub fn encoded_tx_len(tx: &Transaction) -> usize {
+        let tag = usize::from(tx.content_tag != CONTENT_TAG_OPAQUE);
+        8 + 32 + tag + 4 + tx.payload.len()
+    }
 
     pub fn encode_tx(tx: &Transaction) -> Vec<u8> {
-        // 8 + 32 + 4 + payload
+        // 8 + 32 (+1 tag nếu v1) + 4 + payload
         let payload_len_u32: u32 = tx.payload.len().try_into().unwrap_or(u32::MAX);
-        let mut out = Vec::with_capacity(8 + 32 + 4 + tx.payload.len());
-        out.extend_from_slice(&MAGIC_TX);
-        out.extend_from_slice(&tx.id.0);
+        let mut out = Vec::with_capacity(8 + 32 + 1 + 4 + tx.payload.len());
+        if tx.content_tag == CONTENT_TAG_OPAQUE {
+            out.extend_from_slice(&MAGIC_TX);
+            out.extend_from_slice(&tx.id.0);
+        } else {
+            out.extend_from_slice(&MAGIC_TX1);
+            out.extend_from_slice(&tx.id.0);
+            out.push(tx.content_tag);
+        }
         push_u32_be(&mut out, payload_len_u32);
         out.extend_from_slice(&tx.payload);
         out
     }
 
     pub fn decode_tx(bytes: &[u8]) -> Result<Transaction> {
+        decode_tx_prefix(bytes).map(|(tx, _)| tx)
+    }
+
+    /// Như `decode_tx` nhưng không chấp nhận byte thừa sau tx.
+    pub fn decode_tx_strict(bytes: &[u8]) -> Result<Transaction> {
+        let (tx, consumed) = decode_tx_prefix(bytes)?;
+        ensure_consumed(bytes.len(), consumed)?;
+        Ok(tx)
+    }
+
+    /// Decode tx ở đầu `bytes`, trả kèm số byte đã đọc.
+    fn decode_tx_prefix(bytes: &[u8]) -> Result<(Transaction, usize)> {
         let mut c = Cursor::new(bytes);
-        c.expect_magic(&MAGIC_TX)?;
+        let magic_at = c.pos;
+        let magic = c.take(8)?;
+        let tagged = if magic == MAGIC_TX {
+            false
+        } else if magic == MAGIC_TX1 {
+            true
+        } else {
+            return Err(CanonicalError::InvalidMagic { at: magic_at });
+        };
         let id = c.take_hash256()?;
+        let content_tag = if tagged { c.take_u8()? } else { CONTENT_TAG_OPAQUE };
         let payload_len = c.take_u32_be()? as usize;
 
         let rem = c.remaining();
@@ -271,11 +537,17 @@ pub mod canonical {
             });
         }
         let payload = c.take(payload_len)?.to_vec();
-        Ok(Transaction { id, payload })
+        let tx = Transaction {
+            id,
+            payload,
+            content_tag,
+        };
+        Ok((tx, c.pos))
     }
 
     // ---------------- Transaction Body (for TxID) ----------------
     // encode_tx_body chỉ chứa payload (không chứa id).
+    // Tx có content tag khác opaque: MAGIC_TBD1 + tag(u8) + len + payload.
 
     pub fn encode_tx_body(payload: &[u8]) -> Vec<u8> {
         // 8 + 4 + payload
@@ -287,6 +559,20 @@ pub mod canonical {
         out
     }
 
+    pub fn encode_tx_body_tagged(content_tag: u8, payload: &[u8]) -> Vec<u8> {
+        if content_tag == CONTENT_TAG_OPAQUE {
+            return encode_tx_body(payload);
+        }
+        // 8 + 1 + 4 + payload
+        let len_u32: u32 = payload.len().try_into().unwrap_or(u32::MAX);
+        let mut out = Vec::with_capacity(8 + 1 + 4 + payload.len());
+        out.extend_from_slice(&MAGIC_TBD1);
+        out.push(content_tag);
+        push_u32_be(&mut out, len_u32);
+        out.extend_from_slice(payload);
+        out
+    }
+
     pub fn decode_tx_body(bytes: &[u8]) -> Result<Vec<u8>> {
         let mut c = Cursor::new(bytes);
         c.expect_magic(&MAGIC_TBD)?;
@@ -303,8 +589,25 @@ pub mod canonical {
         Ok(c.take(payload_len)?.to_vec())
     }
 
+    /// Decode tx-body v0 hoặc v1, trả (content_tag, payload).
+    pub fn decode_tx_body_tagged(bytes: &[u8]) -> Result<(u8, Vec<u8>)> {
+        if bytes.starts_with(&MAGIC_TBD) {
+            return Ok((CONTENT_TAG_OPAQUE, decode_tx_body(bytes)?));
+        }
+        let mut c = Cursor::new(bytes);
+        c.expect_magic(&MAGIC_TBD1)?;
+        let content_tag = c.take_u8()?;
+        let payload = c.take_bytes_len_u32()?;
+        Ok((content_tag, payload))
+    }
+
     // ---------------- Block ----------------
 
+    /// `encode_block(b).len()` mà không encode.
+    pub fn encoded_block_len(b: &Block) -> usize {
+        8 + 100 + 4 + b.txs.iter().map(|tx| 4 + encoded_tx_len(tx)).sum::<usize>()
+    }
+
     pub fn encode_block(b: &Block) -> Vec<u8> {
         let mut out = Vec::new();
         out.extend_from_slice(&MAGIC_BLK);
@@ -324,6 +627,18 @@ pub mod canonical {
     }
 
     pub fn decode_block(bytes: &[u8]) -> Result<Block> {
+        decode_block_prefix(bytes).map(|(b, _)| b)
+    }
+
+    /// Như `decode_block` nhưng không chấp nhận byte thừa sau block
+    /// (2 chuỗi byte khác nhau không được decode ra cùng 1 block).
+    pub fn decode_block_strict(bytes: &[u8]) -> Result<Block> {
+        let (b, consumed) = decode_block_prefix(bytes)?;
+        ensure_consumed(bytes.len(), consumed)?;
+        Ok(b)
+    }
+
+    fn decode_block_prefix(bytes: &[u8]) -> Result<(Block, usize)> {
         let mut c = Cursor::new(bytes);
         c.expect_magic(&MAGIC_BLK)?;
 
@@ -342,12 +657,21 @@ pub mod canonical {
                     remaining: c.remaining(),
                 });
             }
+            let at = c.pos;
             let tx_bytes = c.take(tx_len)?;
-            let tx = decode_tx(tx_bytes)?;
+            let (tx, consumed) = decode_tx_prefix(tx_bytes)?;
+            // tx_len phải khớp đúng số byte của tx, không để dư rác trong record
+            if consumed != tx_len {
+                return Err(CanonicalError::LengthMismatch {
+                    at,
+                    declared: tx_len,
+                    consumed,
+                });
+            }
             txs.push(tx);
         }
 
-        Ok(Block { header, txs })
+        Ok((Block { header, txs }, c.pos))
     }
 
     // ---------------- ChainSpec ----------------
@@ -393,7 +717,7 @@ pub mod canonical {
     #[cfg(test)]
     mod tests {
         use super::*;
-        use crate::{ChainParams, GenesisSpec, Hash256, Height};
+        use crate::{ChainParams, GenesisSpec, Hash256, Height, CONTENT_TAG_TRANSFER};
 
         #[test]
         fn block_header_encoding_is_fixed_size() {
@@ -424,11 +748,168 @@ pub mod canonical {
             assert_eq!(h, dec);
         }
 
+        #[test]
+        fn block_header_v0_layout_unchanged() {
+            let h = BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(3),
+                timestamp_utc: 1_700_000_000,
+                nonce: 7,
+                merkle_root: Hash256([2u8; 32]),
+                pow_difficulty_bits: 9,
+            };
+            // layout v0 cố định => block id hiện có không đổi
+            let mut want = b"EGG_HDR0".to_vec();
+            want.extend_from_slice(&[1u8; 32]);
+            want.extend_from_slice(&3u64.to_be_bytes());
+            want.extend_from_slice(&1_700_000_000i64.to_be_bytes());
+            want.extend_from_slice(&7u64.to_be_bytes());
+            want.extend_from_slice(&[2u8; 32]);
+            want.extend_from_slice(&9u32.to_be_bytes());
+            let enc = encode_block_header(&h);
+            assert_eq!(enc, want);
+
+            let (dec, fmt, extra) = decode_block_header_ext(&enc).unwrap();
+            assert_eq!(dec, h);
+            assert_eq!(fmt, HeaderFormat::V0);
+            assert!(extra.is_empty());
+        }
+
+        #[test]
+        fn block_header_v1_roundtrip_and_extra_skipped() {
+            let h = BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(4),
+                timestamp_utc: 1_700_000_500,
+                nonce: 11,
+                merkle_root: Hash256([3u8; 32]),
+                pow_difficulty_bits: 12,
+            };
+            let enc = encode_block_header_v1(&h, 3, &[0xAA, 0xBB, 0xCC]);
+            let (dec, fmt, extra) = decode_block_header_ext(&enc).unwrap();
+            assert_eq!(dec, h);
+            assert_eq!(fmt, HeaderFormat::V1 { minor: 3 });
+            assert_eq!(extra, vec![0xAA, 0xBB, 0xCC]);
+
+            assert_ne!(enc, encode_block_header(&h));
+
+            // major lạ => từ chối
+            let mut bad = enc.clone();
+            bad[8] = HEADER_V1_MAJOR + 1;
+            assert!(matches!(
+                decode_block_header_ext(&bad),
+                Err(CanonicalError::UnsupportedVersion { major, .. }) if major == HEADER_V1_MAJOR + 1
+            ));
+        }
+
+        #[test]
+        fn network_and_storage_decoders_reject_v1_header() {
+            let h = BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(4),
+                timestamp_utc: 1_700_000_500,
+                nonce: 11,
+                merkle_root: Hash256([3u8; 32]),
+                pow_difficulty_bits: 12,
+            };
+            // cùng header, extra tuỳ ý => không được coi là header hợp lệ
+            for extra in [&[][..], &[0xEE; 64][..]] {
+                let enc = encode_block_header_v1(&h, 0, extra);
+                for res in [decode_block_header(&enc), decode_block_header_strict(&enc)] {
+                    assert!(matches!(
+                        res,
+                        Err(CanonicalError::UnsupportedVersion { at: 8, major: HEADER_V1_MAJOR })
+                    ));
+                }
+            }
+        }
+
+        #[test]
+        fn decode_block_rejects_tx_with_understated_payload_len() {
+            let b = Block {
+                header: BlockHeader {
+                    parent: Hash256::zero(),
+                    height: Height(1),
+                    timestamp_utc: 1_700_000_000,
+                    nonce: 0,
+                    merkle_root: Hash256::zero(),
+                    pow_difficulty_bits: 0,
+                },
+                txs: vec![Transaction {
+                    id: Hash256([1u8; 32]),
+                    payload: vec![9, 9, 9, 9],
+                    content_tag: CONTENT_TAG_OPAQUE,
+                }],
+            };
+            let mut enc = encode_block(&b);
+            assert_eq!(decode_block(&enc).unwrap(), b);
+
+            // MAGIC_BLK(8) + header(100) + tx_count(4) + tx_len(4) + MAGIC_TX(8) + id(32) => payload_len
+            let pl_at = 8 + 100 + 4 + 4 + 8 + 32;
+            assert_eq!(&enc[pl_at..pl_at + 4], &4u32.to_be_bytes());
+            enc[pl_at..pl_at + 4].copy_from_slice(&2u32.to_be_bytes());
+            assert!(matches!(
+                decode_block(&enc),
+                Err(CanonicalError::LengthMismatch { declared: 48, consumed: 46, .. })
+            ));
+        }
+
+        #[test]
+        fn strict_decoders_reject_trailing_byte() {
+            let tx = Transaction {
+                id: Hash256([4u8; 32]),
+                payload: b"abc".to_vec(),
+                content_tag: CONTENT_TAG_OPAQUE,
+            };
+            let h = BlockHeader {
+                parent: Hash256([1u8; 32]),
+                height: Height(5),
+                timestamp_utc: 1_700_000_000,
+                nonce: 9,
+                merkle_root: Hash256([2u8; 32]),
+                pow_difficulty_bits: 3,
+            };
+            let b = Block {
+                header: h.clone(),
+                txs: vec![tx.clone()],
+            };
+
+            let mut enc = encode_block_header(&h);
+            assert_eq!(decode_block_header_strict(&enc).unwrap(), h);
+            enc.push(0);
+            assert_eq!(decode_block_header(&enc).unwrap(), h);
+            assert_eq!(
+                decode_block_header_strict(&enc),
+                Err(CanonicalError::TrailingBytes { at: 100, remaining: 1 })
+            );
+
+            let mut enc = encode_tx(&tx);
+            assert_eq!(decode_tx_strict(&enc).unwrap(), tx);
+            enc.push(0);
+            let len = enc.len();
+            assert_eq!(decode_tx(&enc).unwrap(), tx);
+            assert_eq!(
+                decode_tx_strict(&enc),
+                Err(CanonicalError::TrailingBytes { at: len - 1, remaining: 1 })
+            );
+
+            let mut enc = encode_block(&b);
+            assert_eq!(decode_block_strict(&enc).unwrap(), b);
+            enc.push(0);
+            let len = enc.len();
+            assert_eq!(decode_block(&enc).unwrap(), b);
+            assert_eq!(
+                decode_block_strict(&enc),
+                Err(CanonicalError::TrailingBytes { at: len - 1, remaining: 1 })
+            );
+        }
+
         #[test]
         fn tx_roundtrip() {
             let tx = Transaction {
                 id: Hash256::zero(),
                 payload: vec![1, 2, 3, 4, 5],
+                content_tag: CONTENT_TAG_OPAQUE,
             };
             let enc = encode_tx(&tx);
             let dec = decode_tx(&enc).unwrap();
@@ -458,10 +939,12 @@ pub mod canonical {
                     Transaction {
                         id: Hash256::zero(),
                         payload: vec![9, 9, 9],
+                        content_tag: CONTENT_TAG_OPAQUE,
                     },
                     Transaction {
                         id: Hash256::zero(),
                         payload: vec![1, 2, 3, 4],
+                        content_tag: CONTENT_TAG_OPAQUE,
                     },
                 ],
             };
@@ -478,6 +961,31 @@ pub mod canonical {
             assert!(matches!(err, CanonicalError::InvalidMagic { .. }));
         }
 
+        #[test]
+        fn tagged_tx_roundtrip_and_opaque_keeps_v0_format() {
+            let tx = Transaction {
+                id: Hash256([4u8; 32]),
+                payload: vec![1, 2, 3],
+                content_tag: CONTENT_TAG_TRANSFER,
+            };
+            let enc = encode_tx(&tx);
+            assert_eq!(&enc[0..8], b"EGG_TX1\0");
+            assert_eq!(decode_tx(&enc).unwrap(), tx);
+
+            let opaque = Transaction {
+                content_tag: CONTENT_TAG_OPAQUE,
+                ..tx
+            };
+            let enc = encode_tx(&opaque);
+            assert_eq!(&enc[0..8], b"EGG_TX0\0");
+            assert_eq!(decode_tx(&enc).unwrap(), opaque);
+
+            assert_eq!(
+                encode_tx_body_tagged(CONTENT_TAG_OPAQUE, &[7, 7]),
+                encode_tx_body(&[7, 7])
+            );
+        }
+
         #[test]
         fn chainspec_roundtrip() {
             let spec = ChainSpec {
@@ -499,3 +1007,94 @@ pub mod canonical {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Hash256 {
+        let mut b = [0u8; HASH256_LEN];
+        for (i, x) in b.iter_mut().enumerate() {
+            *x = (i as u8).wrapping_mul(37);
+        }
+        Hash256(b)
+    }
+
+    #[test]
+    fn hash256_hex_roundtrip_and_validation() {
+        let h = sample();
+        let hex = h.to_hex();
+        assert_eq!(hex.len(), 64);
+        assert!(hex.starts_with("00254a6f"));
+        assert_eq!(hex, hex.to_lowercase());
+        assert_eq!(Hash256::from_hex(&hex), Ok(h));
+        assert_eq!(Hash256::from_hex(&hex.to_uppercase()), Ok(h));
+
+        assert_eq!(Hash256::from_hex(&hex[..62]), Err(HexError::InvalidLength { len: 62 }));
+        assert_eq!(Hash256::from_hex(""), Err(HexError::InvalidLength { len: 0 }));
+        let mut bad = hex.clone();
+        bad.replace_range(5..6, "g");
+        assert_eq!(Hash256::from_hex(&bad), Err(HexError::InvalidChar { at: 5 }));
+        // ký tự nhiều byte không được lọt qua kiểm tra độ dài theo byte
+        assert!(Hash256::from_hex(&format!("é{}", &hex[2..])).is_err());
+    }
+
+    #[test]
+    fn hash256_json_is_hex_string() {
+        let h = sample();
+        let json = serde_json::to_string(&h).unwrap();
+        assert_eq!(json, format!("\"{}\"", h.to_hex()));
+        assert_eq!(serde_json::from_str::<Hash256>(&json).unwrap(), h);
+        assert!(serde_json::from_str::<Hash256>("\"abc\"").is_err());
+
+        // struct lồng nhau cũng ra hex
+        let hdr = BlockHeader {
+            parent: h,
+            height: Height(1),
+            timestamp_utc: 0,
+            nonce: 0,
+            merkle_root: Hash256::zero(),
+            pow_difficulty_bits: 0,
+        };
+        let v: serde_json::Value = serde_json::to_value(&hdr).unwrap();
+        assert_eq!(v["parent"], serde_json::Value::String(h.to_hex()));
+        assert_eq!(serde_json::from_value::<BlockHeader>(v).unwrap(), hdr);
+    }
+
+    #[test]
+    fn hash256_bincode_keeps_raw_bytes() {
+        let h = sample();
+        let enc = bincode::serialize(&h).unwrap();
+        // [u8; 32] trong bincode = 32 byte thô, không có prefix độ dài
+        assert_eq!(enc, h.0.to_vec());
+        assert_eq!(bincode::deserialize::<Hash256>(&enc).unwrap(), h);
+    }
+
+    #[test]
+    fn hash256_orders_big_endian_bytewise() {
+        let mut hi_first = [0u8; HASH256_LEN];
+        hi_first[0] = 1;
+        let mut lo_last = [0xffu8; HASH256_LEN];
+        lo_last[0] = 0;
+        let mut ids = vec![
+            Hash256([0xff; HASH256_LEN]),
+            Hash256(hi_first),
+            Hash256(lo_last),
+            Hash256::zero(),
+        ];
+        ids.sort();
+        assert_eq!(
+            ids,
+            vec![
+                Hash256::zero(),
+                Hash256(lo_last),
+                Hash256(hi_first),
+                Hash256([0xff; HASH256_LEN]),
+            ]
+        );
+        // cùng thứ tự với chuỗi hex
+        for w in ids.windows(2) {
+            assert!(w[0].to_hex() < w[1].to_hex());
+        }
+    }
+}